@@ -40,6 +40,10 @@ pub enum RpcError {
     // State-related errors,
     NotSynced = -32120,
     UnknownSlot = -32121,
+    SnapshotExportFail = -32122,
+    BlockPruned = -32123,
+    IndexesDisabled = -32124,
+    NotIndexed = -32125,
 
     // Parsing errors
     ParseError = -32190,
@@ -66,6 +70,10 @@ fn to_tuple(e: RpcError) -> (i64, String) {
         // State-related errors
         RpcError::NotSynced => "Blockchain is not synced",
         RpcError::UnknownSlot => "Did not find slot",
+        RpcError::SnapshotExportFail => "Failed exporting snapshot",
+        RpcError::BlockPruned => "Block transactions were pruned by this node",
+        RpcError::IndexesDisabled => "Blockchain indexes are not enabled on this node",
+        RpcError::NotIndexed => "Record is older than the blockchain indexes of this node",
         // Parsing errors
         RpcError::ParseError => "Parse error",
         // Contract-related errors
@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::str::FromStr;
+use std::{path::PathBuf, str::FromStr};
 
 use async_std::sync::{Arc, Mutex};
 use async_trait::async_trait;
@@ -26,7 +26,9 @@ use structopt_toml::{serde::Deserialize, structopt::StructOpt, StructOptToml};
 use url::Url;
 
 use darkfi::{
-    async_daemonize, cli_desc,
+    async_daemonize,
+    blockchain::{Blockchain, Snapshot},
+    cli_desc,
     consensus::{
         constants::{
             MAINNET_BOOTSTRAP_TIMESTAMP, MAINNET_GENESIS_HASH_BYTES, MAINNET_GENESIS_TIMESTAMP,
@@ -34,7 +36,7 @@ use darkfi::{
             TESTNET_GENESIS_TIMESTAMP, TESTNET_INITIAL_DISTRIBUTION,
         },
         proto::{ProtocolProposal, ProtocolSync, ProtocolSyncConsensus, ProtocolTx},
-        task::{block_sync_task, proposal_task},
+        task::{block_sync_task, proposal_task, prune_task, snapshot_blockhash_task},
         validator::ValidatorStatePtr,
         ValidatorState,
     },
@@ -91,10 +93,38 @@ struct Args {
     /// Path to blockchain database
     database: String,
 
+    #[structopt(long)]
+    /// Prune the transactions of blocks older than this many finalized slots
+    prune: Option<u64>,
+
+    #[structopt(long)]
+    /// Keep indexes of transactions, nullifiers and coins, for blockchain lookups
+    index: bool,
+
+    #[structopt(long)]
+    /// Bootstrap an empty blockchain from a snapshot file of a block on the synced header chain
+    snapshot: Option<String>,
+
+    #[structopt(long)]
+    /// Expected content hash of the snapshot to bootstrap from (required with --snapshot)
+    snapshot_hash: Option<String>,
+
+    #[structopt(long)]
+    /// Export a snapshot of the blockchain at its last block to a file, and exit
+    export_snapshot: Option<String>,
+
+    #[structopt(long, default_value = "~/.config/darkfi/darkfid_snapshots")]
+    /// Directory snapshots get exported into over JSON-RPC
+    snapshot_dir: String,
+
     #[structopt(long, default_value = "tcp://127.0.0.1:8340")]
     /// JSON-RPC listen URL
     rpc_listen: Url,
 
+    #[structopt(long)]
+    /// Prometheus metrics listen URL, disabled if not set
+    metrics_listen: Option<Url>,
+
     #[structopt(long)]
     /// P2P accept addresses for the consensus protocol (repeatable flag)
     consensus_p2p_accept: Vec<Url>,
@@ -127,6 +157,10 @@ struct Args {
     /// Prefered transports of outbound connections for the consensus protocol (repeatable flag)
     consensus_p2p_transports: Vec<String>,
 
+    #[structopt(long)]
+    /// Path to the file used to persist known hosts of the consensus protocol
+    consensus_p2p_hosts: Option<String>,
+
     #[structopt(long)]
     /// P2P accept addresses for the syncing protocol (repeatable flag)
     sync_p2p_accept: Vec<Url>,
@@ -151,6 +185,10 @@ struct Args {
     /// Prefered transports of outbound connections for the syncing protocol (repeatable flag)
     sync_p2p_transports: Vec<String>,
 
+    #[structopt(long)]
+    /// Path to the file used to persist known hosts of the syncing protocol
+    sync_p2p_hosts: Option<String>,
+
     #[structopt(long)]
     /// Enable localnet hosts
     localnet: bool,
@@ -182,6 +220,7 @@ pub struct Darkfid {
     sync_p2p: Option<P2pPtr>,
     wallet: WalletPtr,
     validator_state: ValidatorStatePtr,
+    snapshot_dir: PathBuf,
 }
 
 // JSON-RPC methods
@@ -221,14 +260,45 @@ impl RequestHandler for Darkfid {
             Some("blockchain.subscribe_blocks") => {
                 return self.blockchain_subscribe_blocks(req.id, params).await
             }
+            Some("blockchain.subscribe_events") => {
+                return self.blockchain_subscribe_events(req.id, params).await
+            }
+            Some("blockchain.get_events") => {
+                return self.blockchain_get_events(req.id, params).await
+            }
+            Some("blockchain.subscribe_sync") => {
+                return self.blockchain_subscribe_sync(req.id, params).await
+            }
+            Some("blockchain.get_headers") => {
+                return self.blockchain_get_headers(req.id, params).await
+            }
+            Some("blockchain.get_tx_inclusion") => {
+                return self.blockchain_get_tx_inclusion(req.id, params).await
+            }
+            Some("blockchain.get_tx_slot") => {
+                return self.blockchain_get_tx_slot(req.id, params).await
+            }
+            Some("blockchain.get_nullifier_tx") => {
+                return self.blockchain_get_nullifier_tx(req.id, params).await
+            }
+            Some("blockchain.get_coin_location") => {
+                return self.blockchain_get_coin_location(req.id, params).await
+            }
+            Some("blockchain.export_snapshot") => {
+                return self.blockchain_export_snapshot(req.id, params).await
+            }
             Some("blockchain.lookup_zkas") => {
                 return self.blockchain_lookup_zkas(req.id, params).await
             }
+            Some("blockchain.get_contract_nonce") => {
+                return self.blockchain_get_contract_nonce(req.id, params).await
+            }
 
             // ===================
             // Transaction methods
             // ===================
             Some("tx.broadcast") => return self.tx_broadcast(req.id, params).await,
+            Some("tx.estimate_fee") => return self.tx_estimate_fee(req.id, params).await,
 
             // ==============
             // Wallet methods
@@ -255,8 +325,16 @@ impl Darkfid {
         consensus_p2p: Option<P2pPtr>,
         sync_p2p: Option<P2pPtr>,
         wallet: WalletPtr,
+        snapshot_dir: PathBuf,
     ) -> Self {
-        Self { synced: Mutex::new(false), consensus_p2p, sync_p2p, wallet, validator_state }
+        Self {
+            synced: Mutex::new(false),
+            consensus_p2p,
+            sync_p2p,
+            wallet,
+            validator_state,
+            snapshot_dir,
+        }
     }
 }
 
@@ -318,6 +396,33 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
             return Err(Error::UnsupportedChain)
         }
     };
+
+    // Snapshot block headers don't cover the contract states, so we require
+    // the content hash of the snapshot to be pinned.
+    let snapshot = match (args.snapshot, args.snapshot_hash) {
+        (None, _) => None,
+        (Some(path), Some(hash)) => {
+            let Ok(hash) = blake3::Hash::from_hex(&hash) else {
+                error!("Invalid snapshot hash `{}`", hash);
+                return Err(Error::ConfigInvalid)
+            };
+            Some((expand_path(&path)?, hash))
+        }
+        (Some(_), None) => {
+            error!("Bootstrapping from a snapshot requires its expected hash (--snapshot-hash)");
+            return Err(Error::ConfigInvalid)
+        }
+    };
+
+    // Export a snapshot and exit
+    if let Some(path) = args.export_snapshot {
+        let blockchain = Blockchain::new(&sled_db, genesis_ts, genesis_data)?;
+        let (slot, _) = blockchain.last()?;
+        let (_, hash) = Snapshot::export(&blockchain, slot, &expand_path(&path)?)?;
+        info!("Exported snapshot at slot {} with hash {}", slot, hash);
+        return Ok(())
+    }
+
     // Parse faucet addresses
     let mut faucet_pubkeys = vec![];
 
@@ -349,6 +454,11 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
     )
     .await?;
 
+    if args.index {
+        let slot = state.read().await.blockchain.enable_indexes()?;
+        info!("Blockchain indexes are kept from slot {}", slot);
+    }
+
     let sync_p2p = {
         info!("Registering block sync P2P protocols...");
         let sync_network_settings = net::Settings {
@@ -360,10 +470,11 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
             outbound_transports: net::settings::get_outbound_transports(args.sync_p2p_transports),
             localnet: args.localnet,
             channel_log: args.channel_log,
+            hosts_file: args.sync_p2p_hosts,
             ..Default::default()
         };
 
-        let p2p = net::P2p::new(sync_network_settings).await;
+        let p2p = net::P2p::new(sync_network_settings).await?;
         let registry = p2p.protocol_registry();
 
         let _state = state.clone();
@@ -406,9 +517,10 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
                 ),
                 localnet: args.localnet,
                 channel_log: args.channel_log,
+                hosts_file: args.consensus_p2p_hosts,
                 ..Default::default()
             };
-            let p2p = net::P2p::new(consensus_network_settings).await;
+            let p2p = net::P2p::new(consensus_network_settings).await?;
             let registry = p2p.protocol_registry();
 
             let _state = state.clone();
@@ -432,8 +544,16 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
     };
 
     // Initialize program state
-    let darkfid =
-        Darkfid::new(state.clone(), consensus_p2p.clone(), sync_p2p.clone(), wallet.clone()).await;
+    let snapshot_dir = expand_path(&args.snapshot_dir)?;
+    std::fs::create_dir_all(&snapshot_dir)?;
+    let darkfid = Darkfid::new(
+        state.clone(),
+        consensus_p2p.clone(),
+        sync_p2p.clone(),
+        wallet.clone(),
+        snapshot_dir,
+    )
+    .await;
     let darkfid = Arc::new(darkfid);
 
     // JSON-RPC server
@@ -441,6 +561,17 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
     let _ex = ex.clone();
     ex.spawn(listen_and_serve(args.rpc_listen, darkfid.clone(), _ex)).detach();
 
+    // Metrics listener
+    if let Some(metrics_listen) = args.metrics_listen {
+        info!("Starting metrics listener");
+        let mut networks = vec![("sync".to_string(), sync_p2p.clone().unwrap().metrics())];
+        if let Some(p2p) = &consensus_p2p {
+            networks.push(("consensus".to_string(), p2p.metrics()));
+        }
+        let _ex = ex.clone();
+        ex.spawn(net::metrics::listen_and_serve(metrics_listen, networks, _ex)).detach();
+    }
+
     info!("Starting sync P2P network");
     sync_p2p.clone().unwrap().start(ex.clone()).await?;
     let _ex = ex.clone();
@@ -455,11 +586,36 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
     info!("Waiting for sync P2P outbound connections");
     sync_p2p.clone().unwrap().wait_for_outbound(ex.clone()).await?;
 
+    // Bootstrap the blockchain from a snapshot with the expected content
+    // hash, once its block is found in the header chain synced from the network
+    if let Some((path, hash)) = snapshot {
+        if state.read().await.blockchain.len() > 1 {
+            info!("Blockchain database is not empty, skipping snapshot import");
+        } else {
+            let (slot, _, snapshot_hash) = Snapshot::info(&path)?;
+            if snapshot_hash != hash {
+                error!("Snapshot hash {} doesn't match the expected {}", snapshot_hash, hash);
+                return Err(Error::InvalidSnapshot("Content hash mismatch".to_string()))
+            }
+
+            let blockhash =
+                snapshot_blockhash_task(sync_p2p.clone().unwrap(), state.clone(), slot).await?;
+            Snapshot::import(&path, &state.write().await.blockchain, &blockhash, &hash)?;
+            info!("Imported snapshot at slot {} with hash {}", slot, hash);
+        }
+    }
+
     match block_sync_task(sync_p2p.clone().unwrap(), state.clone()).await {
         Ok(()) => *darkfid.synced.lock().await = true,
         Err(e) => error!("Failed syncing blockchain: {}", e),
     }
 
+    // Pruning of old block transactions
+    if let Some(keep) = args.prune {
+        info!("Starting pruning task");
+        ex.spawn(prune_task(state.clone(), keep)).detach();
+    }
+
     // Consensus protocol
     if args.consensus && *darkfid.synced.lock().await {
         info!("Starting consensus P2P network");
@@ -478,7 +634,13 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
 
         info!("Starting consensus protocol task");
         let _ex = ex.clone();
-        ex.spawn(proposal_task(consensus_p2p.unwrap(), sync_p2p.unwrap(), state, _ex)).detach();
+        ex.spawn(proposal_task(
+            consensus_p2p.clone().unwrap(),
+            sync_p2p.clone().unwrap(),
+            state,
+            _ex,
+        ))
+        .detach();
     } else {
         info!("Not starting consensus P2P network");
     }
@@ -488,6 +650,14 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
     print!("\r");
     info!("Caught termination signal, cleaning up and exiting...");
 
+    info!("Stopping P2P networks...");
+    if let Some(p2p) = consensus_p2p {
+        p2p.stop().await;
+    }
+    if let Some(p2p) = sync_p2p {
+        p2p.stop().await;
+    }
+
     info!("Flushing sled database...");
     let flushed_bytes = sled_db.flush_async().await?;
     info!("Flushed {} bytes", flushed_bytes);
@@ -16,14 +16,27 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use darkfi_sdk::{crypto::ContractId, db::SMART_CONTRACT_ZKAS_DB_NAME};
+use std::{
+    path::{Component, Path},
+    str::FromStr,
+};
+
+use darkfi_sdk::{
+    crypto::{Coin, ContractId, Nullifier},
+    db::SMART_CONTRACT_ZKAS_DB_NAME,
+};
 use darkfi_serial::{deserialize, serialize};
 use log::{debug, error};
 use serde_json::{json, Value};
 
-use darkfi::rpc::jsonrpc::{
-    ErrorCode::{InternalError, InvalidParams},
-    JsonError, JsonResponse, JsonResult, JsonSubscriber,
+use darkfi::{
+    blockchain::Snapshot,
+    consensus::event_to_json,
+    rpc::jsonrpc::{
+        ErrorCode::{InternalError, InvalidParams},
+        JsonError, JsonResponse, JsonResult, JsonSubscriber,
+    },
+    Error,
 };
 
 use super::Darkfid;
@@ -32,7 +45,8 @@ use crate::{server_error, RpcError};
 impl Darkfid {
     // RPCAPI:
     // Queries the blockchain database for a block in the given slot.
-    // Returns a readable block upon success.
+    // Returns a readable block upon success, or an error if the node pruned
+    // the block's transactions.
     //
     // --> {"jsonrpc": "2.0", "method": "blockchain.get_slot", "params": [0], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": {...}, "id": 1}
@@ -49,6 +63,7 @@ impl Darkfid {
                 drop(validator_state);
                 v
             }
+            Err(Error::BlockPruned(_)) => return server_error(RpcError::BlockPruned, id, None),
             Err(e) => {
                 error!("[RPC] blockchain.get_slot: Failed fetching block by slot: {}", e);
                 return JsonError::new(InternalError, None, id).into()
@@ -74,7 +89,7 @@ impl Darkfid {
 
         let blockchain = { self.validator_state.read().await.blockchain.clone() };
         let Ok(last_slot) = blockchain.last() else {
-                return JsonError::new(InternalError, None, id).into()
+            return JsonError::new(InternalError, None, id).into()
         };
 
         JsonResponse::new(json!(last_slot.0), id).into()
@@ -98,6 +113,301 @@ impl Darkfid {
         JsonSubscriber::new(blocks_subscriber).into()
     }
 
+    // RPCAPI:
+    // Initializes a subscription to the contract events of new finalized blocks,
+    // optionally filtered by the emitting contract ID and by the event topic.
+    // Either filter can be omitted or `null` to match any value.
+    // Once a subscription is established, `darkfid` will send JSON-RPC notifications
+    // with the slot of the block and each matching event, in block order.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.subscribe_events", "params": ["contract_id", "topic"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "method": "blockchain.subscribe_events", "params": [`slot`, {"tx": "txhash", "contract_id": "contract_id", "topic": "topic", "data": "data"}]}
+    pub async fn blockchain_subscribe_events(&self, id: Value, params: &[Value]) -> JsonResult {
+        let Some((contract_id, topic)) = parse_event_filter(params) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        let events_subscriber =
+            self.validator_state.read().await.event_subscriber(contract_id, topic).await;
+
+        JsonSubscriber::new(events_subscriber).into()
+    }
+
+    // RPCAPI:
+    // Queries the blockchain database for the contract events emitted by the
+    // transactions of the block in the given slot, optionally filtered by the
+    // emitting contract ID and by the event topic. Either filter can be omitted
+    // or `null` to match any value. Events are kept for pruned blocks as well.
+    // Event data is encoded with base58.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_events", "params": [1234, "contract_id", "topic"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": [{"tx": "txhash", "contract_id": "contract_id", "topic": "topic", "data": "data"}, ...], "id": 1}
+    pub async fn blockchain_get_events(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.is_empty() || !params[0].is_u64() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let slot = params[0].as_u64().unwrap();
+        let Some((contract_id, topic)) = parse_event_filter(&params[1..]) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        let blockchain = { self.validator_state.read().await.blockchain.clone() };
+        let events = match blockchain.get_events_by_slot(slot) {
+            Ok(v) => v,
+            Err(Error::SlotNotFound(_)) => return server_error(RpcError::UnknownSlot, id, None),
+            Err(e) => {
+                error!("[RPC] blockchain.get_events: Failed fetching events: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        let ret: Vec<Value> = events
+            .iter()
+            .filter(|(_, event)| contract_id.map_or(true, |x| x == event.contract_id))
+            .filter(|(_, event)| topic.as_ref().map_or(true, |x| *x == event.topic))
+            .map(|(tx_hash, event)| event_to_json(tx_hash, event))
+            .collect();
+
+        JsonResponse::new(json!(ret), id).into()
+    }
+
+    // RPCAPI:
+    // Initializes a subscription to the blockchain sync progress.
+    // Once a subscription is established, `darkfid` will send JSON-RPC notifications
+    // with the last synced slot and the percentage of the sync that is complete.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.subscribe_sync", "params": [], "id": 1}
+    // <-- {"jsonrpc": "2.0", "method": "blockchain.subscribe_sync", "params": [`slot`, `percentage`]}
+    pub async fn blockchain_subscribe_sync(&self, id: Value, params: &[Value]) -> JsonResult {
+        if !params.is_empty() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let sync_subscriber =
+            self.validator_state.read().await.subscribers.get("sync").unwrap().clone();
+
+        JsonSubscriber::new(sync_subscriber).into()
+    }
+
+    // RPCAPI:
+    // Queries the blockchain database for the headers of the blocks after the
+    // given slot, up to the given count, capped at 100. Returns the serialized
+    // headers and blocks, ordered by slot. Headers point to the hash of the
+    // previous block, so clients need both to link them.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_headers", "params": [0, 10], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": [...], "id": 1}
+    pub async fn blockchain_get_headers(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 2 || !params[0].is_u64() || !params[1].is_u64() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let slot = params[0].as_u64().unwrap();
+        let count = params[1].as_u64().unwrap().min(100);
+
+        let blockchain = { self.validator_state.read().await.blockchain.clone() };
+        let headers = match blockchain.get_headers_after(slot, count) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("[RPC] blockchain.get_headers: Failed fetching headers: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        JsonResponse::new(json!(serialize(&headers)), id).into()
+    }
+
+    // RPCAPI:
+    // Builds the proof that the transaction with the given hash is included in the
+    // block of the given slot. The proof holds the block header and the Merkle
+    // authentication path of the transaction, so clients can verify it against
+    // the chain of headers, without the block's transactions. Returns the serialized proof, or `null` if the block doesn't
+    // include the transaction.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_tx_inclusion", "params": [1234, "txhash"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": [...], "id": 1}
+    pub async fn blockchain_get_tx_inclusion(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 2 || !params[0].is_u64() || !params[1].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let slot = params[0].as_u64().unwrap();
+        let Ok(tx_hash) = blake3::Hash::from_hex(params[1].as_str().unwrap()) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        let blockchain = { self.validator_state.read().await.blockchain.clone() };
+        match blockchain.get_tx_inclusion_proof(slot, &tx_hash) {
+            Ok(proof) => JsonResponse::new(json!(proof.map(|x| serialize(&x))), id).into(),
+            Err(Error::SlotNotFound(_)) => server_error(RpcError::UnknownSlot, id, None),
+            Err(e) => {
+                error!("[RPC] blockchain.get_tx_inclusion: Failed building inclusion proof: {}", e);
+                JsonError::new(InternalError, None, id).into()
+            }
+        }
+    }
+
+    // RPCAPI:
+    // Queries the blockchain indexes for the slot of the block that included the
+    // transaction with the given hash. Returns `null` if the transaction is not
+    // in the blockchain, or an error if it might be in a block from before the
+    // node started keeping blockchain indexes, which this method requires.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_tx_slot", "params": ["txhash"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": 1234, "id": 1}
+    pub async fn blockchain_get_tx_slot(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let Ok(tx_hash) = blake3::Hash::from_hex(params[0].as_str().unwrap()) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        let blockchain = { self.validator_state.read().await.blockchain.clone() };
+        let indexed_from = match blockchain.tx_index.indexed_from() {
+            Ok(Some(v)) => v,
+            Ok(None) => return server_error(RpcError::IndexesDisabled, id, None),
+            Err(e) => {
+                error!("[RPC] blockchain.get_tx_slot: Failed reading indexes: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        match blockchain.tx_index.get(&tx_hash) {
+            Ok(None) if indexed_from > 0 => not_indexed(indexed_from, id),
+            Ok(slot) => JsonResponse::new(json!(slot), id).into(),
+            Err(e) => {
+                error!("[RPC] blockchain.get_tx_slot: Failed fetching tx slot: {}", e);
+                JsonError::new(InternalError, None, id).into()
+            }
+        }
+    }
+
+    // RPCAPI:
+    // Queries the blockchain indexes for the hash of the transaction that revealed
+    // the given nullifier, i.e. the one spending its coin. Returns `null` if the
+    // nullifier was not revealed, or an error if it might have been before the
+    // node started keeping blockchain indexes, which this method requires.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_nullifier_tx", "params": ["nullifier"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": "txhash", "id": 1}
+    pub async fn blockchain_get_nullifier_tx(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let Ok(nullifier) = Nullifier::from_str(params[0].as_str().unwrap()) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        let blockchain = { self.validator_state.read().await.blockchain.clone() };
+        let indexed_from = match blockchain.tx_index.indexed_from() {
+            Ok(Some(v)) => v,
+            Ok(None) => return server_error(RpcError::IndexesDisabled, id, None),
+            Err(e) => {
+                error!("[RPC] blockchain.get_nullifier_tx: Failed reading indexes: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        match blockchain.nullifier_index.get(&nullifier) {
+            Ok(None) if indexed_from > 0 => not_indexed(indexed_from, id),
+            Ok(tx_hash) => {
+                let tx_hash = tx_hash.map(|x| x.to_hex().as_str().to_string());
+                JsonResponse::new(json!(tx_hash), id).into()
+            }
+            Err(e) => {
+                error!("[RPC] blockchain.get_nullifier_tx: Failed fetching nullifier tx: {}", e);
+                JsonError::new(InternalError, None, id).into()
+            }
+        }
+    }
+
+    // RPCAPI:
+    // Queries the blockchain indexes for the hash of the transaction that minted
+    // the given coin, the index of the minting call in it, and the coin's index
+    // among the outputs of that call. Returns `null` if the coin was not minted,
+    // or an error if it might have been before the node started keeping
+    // blockchain indexes, which this method requires.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_coin_location", "params": ["coin"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": ["txhash", 0, 1], "id": 1}
+    pub async fn blockchain_get_coin_location(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let Ok(coin) = Coin::from_str(params[0].as_str().unwrap()) else {
+            return JsonError::new(InvalidParams, None, id).into()
+        };
+
+        let blockchain = { self.validator_state.read().await.blockchain.clone() };
+        let indexed_from = match blockchain.tx_index.indexed_from() {
+            Ok(Some(v)) => v,
+            Ok(None) => return server_error(RpcError::IndexesDisabled, id, None),
+            Err(e) => {
+                error!("[RPC] blockchain.get_coin_location: Failed reading indexes: {}", e);
+                return JsonError::new(InternalError, None, id).into()
+            }
+        };
+
+        match blockchain.coin_index.get(&coin) {
+            Ok(None) if indexed_from > 0 => not_indexed(indexed_from, id),
+            Ok(location) => {
+                let location = location
+                    .map(|(x, call_idx, idx)| (x.to_hex().as_str().to_string(), call_idx, idx));
+                JsonResponse::new(json!(location), id).into()
+            }
+            Err(e) => {
+                error!("[RPC] blockchain.get_coin_location: Failed fetching coin location: {}", e);
+                JsonError::new(InternalError, None, id).into()
+            }
+        }
+    }
+
+    // RPCAPI:
+    // Exports a snapshot of the blockchain at the given finalized slot to a file
+    // with the given name, in the node's configured snapshot directory. Returns the
+    // hash of the snapshot block, and the content hash of the snapshot, which nodes
+    // bootstrapping from it can check against.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.export_snapshot", "params": [1234, "snapshot.bin"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": ["blockhash", "hash"], "id": 1}
+    pub async fn blockchain_export_snapshot(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 2 || !params[0].is_u64() || !params[1].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        // Only plain file names are accepted, so snapshots can't be written
+        // outside of the snapshot directory.
+        let slot = params[0].as_u64().unwrap();
+        let name = Path::new(params[1].as_str().unwrap());
+        let mut components = name.components();
+        if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+        let path = self.snapshot_dir.join(name);
+
+        // The export streams the database into the file, so it runs on a blocking
+        // thread, without holding the validator state lock.
+        let blockchain = self.validator_state.read().await.blockchain.clone();
+        let (blockhash, hash) =
+            match smol::unblock(move || Snapshot::export(&blockchain, slot, &path)).await {
+                Ok(v) => v,
+                Err(Error::SlotNotFound(_)) => return server_error(RpcError::UnknownSlot, id, None),
+                Err(e) => {
+                    error!("[RPC] blockchain.export_snapshot: Failed exporting snapshot: {}", e);
+                    return server_error(RpcError::SnapshotExportFail, id, None)
+                }
+            };
+
+        let blockhash = blockhash.to_hex().as_str().to_string();
+        let hash = hash.to_hex().as_str().to_string();
+        JsonResponse::new(json!([blockhash, hash]), id).into()
+    }
+
     // RPCAPI:
     // Performs a lookup of zkas bincodes for a given contract ID and returns all of
     // them, including their namespace.
@@ -119,8 +429,15 @@ impl Darkfid {
 
         let blockchain = { self.validator_state.read().await.blockchain.clone() };
 
-        let Ok(zkas_db) = blockchain.contracts.lookup(&blockchain.sled_db, &contract_id, SMART_CONTRACT_ZKAS_DB_NAME) else {
-            error!("[RPC] blockchain.lookup_zkas: Did not find zkas db for ContractId: {}", contract_id);
+        let Ok(zkas_db) = blockchain.contracts.lookup(
+            &blockchain.sled_db,
+            &contract_id,
+            SMART_CONTRACT_ZKAS_DB_NAME,
+        ) else {
+            error!(
+                "[RPC] blockchain.lookup_zkas: Did not find zkas db for ContractId: {}",
+                contract_id
+            );
             return server_error(RpcError::ContractZkasDbNotFound, id, None)
         };
 
@@ -142,4 +459,66 @@ impl Darkfid {
 
         JsonResponse::new(json!(ret), id).into()
     }
+
+    // RPCAPI:
+    // Returns the number of times the contract with the given ID was deployed
+    // or upgraded, which its next upgrade has to be signed along with.
+    // Contracts never deployed have nonce 0.
+    //
+    // --> {"jsonrpc": "2.0", "method": "blockchain.get_contract_nonce", "params": ["6Ef42L1KLZXBoxBuCDto7coi9DA2D2SRtegNqNU4sd74"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": 1, "id": 1}
+    pub async fn blockchain_get_contract_nonce(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        let contract_id = match ContractId::try_from(params[0].as_str().unwrap()) {
+            Ok(v) => v,
+            Err(e) => {
+                error!(
+                    "[RPC] blockchain.get_contract_nonce: Error decoding string to ContractId: {}",
+                    e
+                );
+                return JsonError::new(InvalidParams, None, id).into()
+            }
+        };
+
+        let blockchain = { self.validator_state.read().await.blockchain.clone() };
+        match blockchain.contract_nonces.get(contract_id) {
+            Ok(nonce) => JsonResponse::new(json!(nonce), id).into(),
+            Err(e) => {
+                error!("[RPC] blockchain.get_contract_nonce: Failed fetching nonce: {}", e);
+                JsonError::new(InternalError, None, id).into()
+            }
+        }
+    }
+}
+
+/// Parse the optional contract ID and topic filtering contract events, given
+/// in that order. Returns `None` if they're invalid.
+fn parse_event_filter(params: &[Value]) -> Option<(Option<ContractId>, Option<String>)> {
+    if params.len() > 2 {
+        return None
+    }
+
+    let contract_id = match params.first() {
+        None | Some(Value::Null) => None,
+        Some(Value::String(v)) => Some(ContractId::try_from(v.as_str()).ok()?),
+        Some(_) => return None,
+    };
+
+    let topic = match params.get(1) {
+        None | Some(Value::Null) => None,
+        Some(Value::String(v)) => Some(v.clone()),
+        Some(_) => return None,
+    };
+
+    Some((contract_id, topic))
+}
+
+/// Error for lookups that miss in the blockchain indexes, when the record
+/// might be in a block from before the given slot they are kept from.
+fn not_indexed(indexed_from: u64, id: Value) -> JsonResult {
+    let msg = format!("Blockchain indexes are only kept from slot {}", indexed_from);
+    server_error(RpcError::NotIndexed, id, Some(&msg))
 }
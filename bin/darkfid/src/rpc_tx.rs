@@ -72,6 +72,53 @@ impl Darkfid {
         JsonResponse::new(json!(true), id).into()
     }
 
+    // RPCAPI:
+    // Compute the fee a transaction has to pay for the gas its calls use.
+    // The transaction is executed without verifying its signatures, ZK proofs
+    // and fee, so it can be sent before signing it. Its fee call has to be
+    // included already, since it uses gas as well, but can pay any amount.
+    //
+    // --> {"jsonrpc": "2.0", "method": "tx.estimate_fee", "params": ["base58encodedTX"], "id": 1}
+    // <-- {"jsonrpc": "2.0", "result": 12345, "id": 1}
+    pub async fn tx_estimate_fee(&self, id: Value, params: &[Value]) -> JsonResult {
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(InvalidParams, None, id).into()
+        }
+
+        if !(*self.synced.lock().await) {
+            error!("[RPC] tx.estimate_fee: Blockchain is not synced");
+            return server_error(RpcError::NotSynced, id, None)
+        }
+
+        // Try to deserialize the transaction
+        let tx_bytes = match bs58::decode(params[0].as_str().unwrap().trim()).into_vec() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("[RPC] tx.estimate_fee: Failed decoding base58 transaction: {}", e);
+                return server_error(RpcError::ParseError, id, None)
+            }
+        };
+
+        let tx: Transaction = match deserialize(&tx_bytes) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("[RPC] tx.estimate_fee: Failed deserializing bytes into Transaction: {}", e);
+                return server_error(RpcError::ParseError, id, None)
+            }
+        };
+
+        let validator_state = self.validator_state.read().await;
+        let gas_used = match validator_state.estimate_gas(&tx).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("[RPC] tx.estimate_fee: Failed to execute transaction: {}", e);
+                return server_error(RpcError::TxSimulationFail, id, None)
+            }
+        };
+
+        JsonResponse::new(json!(gas_used.saturating_mul(validator_state.gas_price)), id).into()
+    }
+
     // RPCAPI:
     // Broadcast a given transaction to the P2P network.
     // The function will first simulate the state transition in order to see
@@ -608,7 +608,7 @@ async fn realmain(args: Args, executor: Arc<smol::Executor<'_>>) -> Result<()> {
     let mut net_settings = args.net.clone();
     net_settings.app_version = Some(option_env!("CARGO_PKG_VERSION").unwrap_or("").to_string());
     let (p2p_tx, p2p_rx) = smol::channel::unbounded::<NetMsg>();
-    let p2p = net::P2p::new(net_settings.into()).await;
+    let p2p = net::P2p::new(net_settings.into()).await?;
     let registry = p2p.protocol_registry();
 
     let raft_node_id = raft.lock().await.id();
@@ -65,6 +65,7 @@ pub struct NodeInfo {
     pub state: String,
     pub children: Vec<SessionInfo>,
     pub external_addr: Option<String>,
+    pub banned: Vec<String>,
     pub is_offline: bool,
 }
 
@@ -75,9 +76,10 @@ impl NodeInfo {
         state: String,
         children: Vec<SessionInfo>,
         external_addr: Option<String>,
+        banned: Vec<String>,
         is_offline: bool,
     ) -> Self {
-        Self { id, name, state, children, external_addr, is_offline }
+        Self { id, name, state, children, external_addr, banned, is_offline }
     }
 }
 
@@ -153,7 +153,8 @@ impl DataParser {
             SessionInfo::new(session_id, name, is_empty, parent, connects, accept_addr, None);
         sessions.push(session_info);
 
-        let node = NodeInfo::new(node_id, node_name, state, sessions.clone(), None, true);
+        let node =
+            NodeInfo::new(node_id, node_name, state, sessions.clone(), None, Vec::new(), true);
 
         self.update_selectables(sessions, node).await?;
         Ok(())
@@ -169,12 +170,14 @@ impl DataParser {
         let _manual = &reply["session_manual"];
         let outbound = &reply["session_outbound"];
         let state = &reply["state"];
+        let banned = reply.get("banned");
 
         let mut sessions: Vec<SessionInfo> = Vec::new();
 
         let node_id = make_node_id(&node_name)?;
 
         let ext_addr = self.parse_external_addr(addr).await?;
+        let banned = self.parse_banned(banned).await?;
         let in_session = self.parse_inbound(inbound, &node_id).await?;
         let out_session = self.parse_outbound(outbound, &node_id).await?;
         //let man_session = self.parse_manual(manual, &node_id).await?;
@@ -189,6 +192,7 @@ impl DataParser {
             state.as_str().unwrap().to_string(),
             sessions.clone(),
             ext_addr,
+            banned,
             false,
         );
 
@@ -305,6 +309,20 @@ impl DataParser {
         }
     }
 
+    async fn parse_banned(&self, banned: Option<&Value>) -> DnetViewResult<Vec<String>> {
+        let mut hosts = Vec::new();
+        // Older nodes don't report bans
+        if let Some(Value::Array(bans)) = banned {
+            for ban in bans {
+                let host = ban["host"].as_str().unwrap_or("?");
+                let expiry = ban["expiry"].as_u64().unwrap_or(0);
+                let reason = ban["reason"].as_str().unwrap_or("");
+                hosts.push(format!("{} (until {}): {}", host, expiry, reason));
+            }
+        }
+        Ok(hosts)
+    }
+
     async fn parse_inbound(
         &self,
         inbound: &Value,
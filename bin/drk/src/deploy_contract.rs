@@ -17,23 +17,28 @@
  */
 
 use std::{
-    env::set_current_dir,
-    fs::{read, read_dir, read_to_string, File},
+    fs::{read, read_dir, read_to_string, OpenOptions},
     io::{ErrorKind, Write},
-    path::{Path, PathBuf},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
     str::FromStr,
 };
 
-use rand::{rngs::OsRng, RngCore};
-
+use anyhow::{anyhow, Context, Result};
 use darkfi::{
-    crypto::keypair::SecretKey,
-    node::{MemoryState, State},
-    runtime::vm_runtime::Runtime,
+    tx::Transaction,
     util::cli::{fg_green, fg_red},
     zkas::ZkBinary,
-    Error, Result,
 };
+use darkfi_sdk::{
+    crypto::{ContractId, PublicKey, SecretKey, DEPLOYOOOR_CONTRACT_ID},
+    deploy::{DeployFunction, DeployParamsV1, LockParamsV1},
+    ContractCall,
+};
+use darkfi_serial::Encodable;
+use rand::{rngs::OsRng, RngCore};
+
+use super::Drk;
 
 const CIRCUIT_DIR_NAME: &str = "proof";
 const CONTRACT_FILE_NAME: &str = "contract.wasm";
@@ -43,9 +48,10 @@ const DEPLOY_KEY_NAME: &str = "deploy.key";
 /// This key allows to update the wasm code and the zk circuits on chain
 /// by creating a signature. When deployed, the contract can be accessed
 /// by requesting the public counterpart of this secret key.
+/// The key file is only readable by its owner.
 pub fn create_deploy_key(mut rng: impl RngCore, path: &Path) -> Result<SecretKey> {
     let secret = SecretKey::random(&mut rng);
-    let mut file = File::create(path)?;
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
     file.write_all(bs58::encode(&secret.to_bytes()).into_string().as_bytes())?;
     Ok(secret)
 }
@@ -54,8 +60,18 @@ pub fn create_deploy_key(mut rng: impl RngCore, path: &Path) -> Result<SecretKey
 fn read_deploy_key(s: &Path) -> core::result::Result<SecretKey, std::io::Error> {
     eprintln!("Trying to read deploy key from file: {:?}", s);
     let contents = read_to_string(s)?;
-    let secret = SecretKey::from_str(&contents).unwrap();
-    Ok(secret)
+    SecretKey::from_str(contents.trim())
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Data needed to deploy a given smart contract on the network.
+pub struct ContractDeployData {
+    /// Deploy key the contract ID is derived from
+    pub deploy_key: SecretKey,
+    /// Compiled wasm bincode of the contract
+    pub wasm_bincode: Vec<u8>,
+    /// Compiled zkas bincodes of the contract's circuits
+    pub zkas_bincodes: Vec<Vec<u8>>,
 }
 
 /// Creates necessary data to deploy a given smart contract on the network.
@@ -68,114 +84,121 @@ fn read_deploy_key(s: &Path) -> core::result::Result<SecretKey, std::io::Error>
 /// ├── deploy.key
 /// ├── Makefile
 /// ├── proof
-/// │   ├── circuit0.zk
-/// │   ├── circuit0.zk.bin
-/// │   ├── circuit1.zk
-/// │   └── circuit1.zk.bin
+/// │   ├── circuit0.zk
+/// │   ├── circuit0.zk.bin
+/// │   ├── circuit1.zk
+/// │   └── circuit1.zk.bin
 /// ├── contract.wasm
 /// ├── src
-/// │   └── lib.rs
+/// │   └── lib.rs
 /// └── tests
 /// ```
-//pub fn create_deploy_data(path: &Path) -> Result<ContractDeploy> {
-pub fn create_deploy_data(path: &Path) -> Result<()> {
-    // Try to chdir into the contract directory
-    if let Err(e) = set_current_dir(path) {
-        eprintln!("Failed to chdir into {:?}", path);
-        return Err(e.into())
-    }
-
-    let deploy_key: SecretKey;
-
-    let deploy_key = match read_deploy_key(&PathBuf::from(DEPLOY_KEY_NAME)) {
-        Ok(v) => deploy_key = v,
-        Err(e) => {
-            if e.kind() == ErrorKind::NotFound {
-                // We didn't find a deploy key, generate a new one.
-                eprintln!("Did not find an existing key, creating a new one.");
-                match create_deploy_key(&mut OsRng, &PathBuf::from(DEPLOY_KEY_NAME)) {
-                    Ok(v) => {
-                        eprintln!("Created new deploy key in \"{}\".", DEPLOY_KEY_NAME);
-                        deploy_key = v;
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to create new deploy key");
-                        return Err(e)
-                    }
-                }
-            }
-            eprintln!("Failed to read deploy key");
-            return Err(e.into())
+/// If there's no deploy key yet and `create_key` is set, a new one is
+/// created in the directory.
+pub fn create_deploy_data(path: &Path, create_key: bool) -> Result<ContractDeployData> {
+    let key_path = path.join(DEPLOY_KEY_NAME);
+    let deploy_key = match read_deploy_key(&key_path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound && create_key => {
+            // We didn't find a deploy key, generate a new one.
+            eprintln!("Did not find an existing key, creating a new one.");
+            let deploy_key = create_deploy_key(&mut OsRng, &key_path)
+                .with_context(|| "Failed to create new deploy key")?;
+            eprintln!("Created new deploy key in {:?}.", key_path);
+            deploy_key
         }
+        Err(e) => return Err(anyhow!("Failed to read deploy key: {}", e)),
     };
 
-    // Search for ZK circuits in the directory. If none are found, we'll bail.
-    // The logic searches for `.zk.bin` files created by zkas.
-    eprintln!("Searching for compiled ZK circuits in \"{}\" ...", CIRCUIT_DIR_NAME);
-    let mut circuits = vec![];
-    for i in read_dir(CIRCUIT_DIR_NAME)? {
-        if let Err(e) = i {
-            eprintln!("Error iterating over \"{}\" directory", CIRCUIT_DIR_NAME);
-            return Err(e.into())
-        }
-
-        let f = i.unwrap();
-        let fname = f.file_name();
-        let fname = fname.to_str().unwrap();
+    // Search for ZK circuits in the directory. The logic searches for
+    // `.zk.bin` files created by zkas. Contracts don't need to have any.
+    let circuit_dir = path.join(CIRCUIT_DIR_NAME);
+    eprintln!("Searching for compiled ZK circuits in {:?} ...", circuit_dir);
+    let mut zkas_bincodes = vec![];
+    if circuit_dir.is_dir() {
+        for entry in read_dir(&circuit_dir)? {
+            let entry = entry.with_context(|| "Error iterating over circuit directory")?;
+            if !entry.file_name().to_string_lossy().ends_with(".zk.bin") {
+                continue
+            }
 
-        if fname.ends_with(".zk.bin") {
             // Validate that the files can be properly decoded
-            eprintln!("{} {}", fg_green("Found:"), f.path().display());
-            let buf = read(f.path())?;
+            eprintln!("{} {}", fg_green("Found:"), entry.path().display());
+            let buf = read(entry.path())?;
             if let Err(e) = ZkBinary::decode(&buf) {
-                eprintln!("{} Failed to decode zkas bincode in {:?}", fg_red("Error:"), f.path());
-                return Err(e)
+                eprintln!(
+                    "{} Failed to decode zkas bincode in {:?}",
+                    fg_red("Error:"),
+                    entry.path()
+                );
+                return Err(e.into())
             }
 
-            circuits.push(buf.clone());
+            zkas_bincodes.push(buf);
         }
     }
 
-    if circuits.is_empty() {
-        return Err(Error::Custom("Found no valid ZK circuits".to_string()))
-    }
+    let wasm_path = path.join(CONTRACT_FILE_NAME);
+    eprintln!("Reading wasm binary from {:?}", wasm_path);
+    let wasm_bincode =
+        read(&wasm_path).with_context(|| format!("Failed to read {:?}", wasm_path))?;
 
-    /* FIXME
-    // Validate wasm binary. We inspect the bincode and try to load it into
-    // the wasm runtime. If loaded, we then look for the `ENTRYPOINT` function
-    // which we hardcode into our sdk and runtime and is the canonical way to
-    // run wasm binaries on chain.
-    eprintln!("Inspecting wasm binary in \"{}\"", CONTRACT_FILE_NAME);
-    let wasm_bytes = read(CONTRACT_FILE_NAME)?;
-    eprintln!("Initializing moch wasm runtime to check validity");
-    let runtime = match Runtime::new(&wasm_bytes, MemoryState::new(State::dummy()?)) {
-        Ok(v) => {
-            eprintln!("Found {} wasm binary", fg_green("valid"));
-            v
-        }
-        Err(e) => {
-            eprintln!("Failed to initialize wasm runtime");
-            return Err(e)
-        }
+    Ok(ContractDeployData { deploy_key, wasm_bincode, zkas_bincodes })
+}
+
+/// Build a transaction deploying the smart contract in the given directory,
+/// or upgrading it if `upgrade` is set. The contract's `__initialize`
+/// function is called with the given payload. Upgrades have to be signed
+/// along with the contract's current nonce, which is queried from darkfid.
+/// The transaction is returned unsigned, along with the deploy key it has
+/// to be signed with.
+pub async fn create_deploy_tx(
+    drk: &Drk,
+    path: &Path,
+    upgrade: bool,
+    ix: Vec<u8>,
+) -> Result<(Transaction, SecretKey)> {
+    let data = create_deploy_data(path, !upgrade)?;
+    let public_key = PublicKey::from_secret(data.deploy_key);
+    let contract_id = ContractId::derive(data.deploy_key);
+    eprintln!("Contract ID: {}", contract_id);
+
+    let (function, nonce) = if upgrade {
+        (DeployFunction::Upgrade, drk.get_contract_nonce(&contract_id).await?)
+    } else {
+        (DeployFunction::Deploy, 0)
     };
 
-    eprintln!("Looking for entrypoint function inside the wasm");
-    let cs = ContractSection::Exec;
-    if let Err(e) = runtime.instance.exports.get_function(cs.name()) {
-        eprintln!("{} Could not find entrypoint function", fg_red("Error:"));
-        return Err(e.into())
-    }
+    let params = DeployParamsV1 {
+        public_key,
+        nonce,
+        wasm_bincode: data.wasm_bincode,
+        zkas_bincodes: data.zkas_bincodes,
+        ix,
+    };
 
-    // TODO: Create a ZK proof enforcing the deploy key relations with their public
-    // counterparts (public key and contract address)
-    let mut total_bytes = 0;
-    total_bytes += wasm_bytes.len();
-    for circuit in circuits {
-        total_bytes += circuit.len();
-    }
-    */
+    let mut call_data = vec![function as u8];
+    params.encode(&mut call_data)?;
+    Ok((deploy_call_tx(call_data), data.deploy_key))
+}
+
+/// Build a transaction locking the smart contract in the given directory,
+/// so its code can't be upgraded anymore. The transaction is returned
+/// unsigned, along with the deploy key it has to be signed with.
+pub fn create_lock_tx(path: &Path) -> Result<(Transaction, SecretKey)> {
+    let deploy_key = read_deploy_key(&path.join(DEPLOY_KEY_NAME))
+        .with_context(|| "Failed to read deploy key")?;
+    eprintln!("Contract ID: {}", ContractId::derive(deploy_key));
+
+    let params = LockParamsV1 { public_key: PublicKey::from_secret(deploy_key) };
+    let mut call_data = vec![DeployFunction::Lock as u8];
+    params.encode(&mut call_data)?;
+    Ok((deploy_call_tx(call_data), deploy_key))
+}
 
-    // TODO: Return the data back to the main function, and work further in creating
-    // a transaction and broadcasting it.
-    Ok(())
+/// Build an unsigned transaction with a single native deployer call
+/// holding the given data.
+fn deploy_call_tx(data: Vec<u8>) -> Transaction {
+    let calls = vec![ContractCall { contract_id: *DEPLOYOOOR_CONTRACT_ID, data }];
+    Transaction { calls, proofs: vec![vec![]], signatures: vec![] }
 }
@@ -18,6 +18,7 @@
 
 use std::{
     io::{stdin, Read},
+    path::PathBuf,
     process::exit,
     str::FromStr,
     time::Instant,
@@ -53,6 +54,9 @@ mod rpc_airdrop;
 /// Payment methods
 mod rpc_transfer;
 
+/// Fee methods
+mod rpc_fee;
+
 /// Swap methods
 mod rpc_swap;
 use rpc_swap::PartialSwapData;
@@ -74,6 +78,10 @@ use wallet_dao::DaoParams;
 /// Wallet functionality related to Money
 mod wallet_money;
 
+/// Smart contract deployment
+mod deploy_contract;
+use deploy_contract::{create_deploy_tx, create_lock_tx};
+
 #[derive(Parser)]
 #[command(about = cli_desc!())]
 struct Args {
@@ -180,6 +188,47 @@ enum Subcmd {
     /// Read a transaction from stdin and broadcast it
     Broadcast,
 
+    /// Verify that a transaction is included in the block of a slot
+    ///
+    /// The check doesn't need the block's transactions: darkfid provides the
+    /// Merkle proof of the transaction against the block header, which is then
+    /// linked to the following headers up to the trusted one. darkfid could
+    /// make up a whole chain, so the trusted header hash has to come from
+    /// somewhere else, e.g. other nodes.
+    Verify {
+        /// Slot of the block including the transaction
+        slot: u64,
+
+        /// Transaction hash
+        tx_hash: String,
+
+        /// Hash of a trusted block header, at or after the slot
+        anchor: String,
+    },
+
+    /// Create a transaction deploying a smart contract
+    ///
+    /// The contract directory has to hold the compiled `contract.wasm`, and
+    /// optionally the compiled zkas circuits in `proof/*.zk.bin`. The contract
+    /// ID is derived from the deploy key in `deploy.key`, which gets created
+    /// on the first deployment. The same key is needed for upgrades.
+    Deploy {
+        /// Path to the contract directory
+        path: PathBuf,
+
+        #[arg(long)]
+        /// Upgrade the code of the already deployed contract
+        upgrade: bool,
+
+        #[arg(long, conflicts_with = "upgrade")]
+        /// Permanently lock the deployed contract against upgrades
+        lock: bool,
+
+        #[arg(long)]
+        /// Base58-encoded payload for the contract's initialize function
+        ix: Option<String>,
+    },
+
     /// Subscribe to incoming blocks from darkfid
     ///
     /// This subscription will listen for incoming blocks from darkfid and look
@@ -659,6 +708,49 @@ async fn main() -> Result<()> {
             Ok(())
         }
 
+        Subcmd::Verify { slot, tx_hash, anchor } => {
+            let tx_hash =
+                blake3::Hash::from_hex(&tx_hash).with_context(|| "Invalid transaction hash")?;
+            let anchor =
+                blake3::Hash::from_hex(&anchor).with_context(|| "Invalid trusted header hash")?;
+
+            let drk = Drk::new(args.endpoint).await?;
+
+            drk.verify_tx_inclusion(slot, &tx_hash, &anchor)
+                .await
+                .with_context(|| "Failed to verify transaction inclusion")?;
+
+            println!("Transaction {} is included in slot {}", tx_hash, slot);
+            println!("Verified up to trusted block header {}", anchor);
+
+            Ok(())
+        }
+
+        Subcmd::Deploy { path, upgrade, lock, ix } => {
+            let drk = Drk::new(args.endpoint).await?;
+
+            let (mut tx, deploy_key) = if lock {
+                create_lock_tx(&path).with_context(|| "Failed to create lock transaction")?
+            } else {
+                let ix = match ix {
+                    Some(ix) => bs58::decode(&ix).into_vec().with_context(|| "Invalid payload")?,
+                    None => vec![],
+                };
+
+                create_deploy_tx(&drk, &path, upgrade, ix)
+                    .await
+                    .with_context(|| "Failed to create deploy transaction")?
+            };
+
+            drk.sign_with_fee(&mut tx, vec![vec![deploy_key]], &[])
+                .await
+                .with_context(|| "Failed to sign deploy transaction")?;
+
+            println!("{}", bs58::encode(&serialize(&tx)).into_string());
+
+            Ok(())
+        }
+
         Subcmd::Subscribe => {
             let drk = Drk::new(args.endpoint.clone()).await?;
 
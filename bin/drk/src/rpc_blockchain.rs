@@ -19,7 +19,7 @@
 use anyhow::{anyhow, Result};
 use async_std::{stream::StreamExt, task};
 use darkfi::{
-    consensus::BlockInfo,
+    consensus::{verify_tx_inclusion, Block, BlockInfo, Header, TxInclusionProof},
     rpc::{
         client::RpcClient,
         jsonrpc::{JsonRequest, JsonResult},
@@ -157,6 +157,17 @@ impl Drk {
         Ok(ret)
     }
 
+    /// Queries darkfid for the number of times the given contract was deployed
+    /// or upgraded, which its next upgrade has to be signed along with.
+    pub async fn get_contract_nonce(&self, contract_id: &ContractId) -> Result<u64> {
+        let params = json!([format!("{}", contract_id)]);
+        let req = JsonRequest::new("blockchain.get_contract_nonce", params);
+        let rep = self.rpc_client.request(req).await?;
+
+        let ret = serde_json::from_value(rep)?;
+        Ok(ret)
+    }
+
     /// Broadcast a given transaction to darkfid and forward onto the network.
     /// Returns the transaction ID upon success
     pub async fn broadcast_tx(&self, tx: &Transaction) -> Result<String> {
@@ -196,6 +207,65 @@ impl Drk {
         }
     }
 
+    /// Queries darkfid for the headers and blocks after the given slot, up to
+    /// the block whose header hashes to `anchor`.
+    async fn get_headers_until(
+        &self,
+        slot: u64,
+        anchor: &blake3::Hash,
+    ) -> Result<(Vec<Header>, Vec<Block>)> {
+        let mut headers: Vec<Header> = vec![];
+        let mut blocks: Vec<Block> = vec![];
+        let mut last = slot;
+        loop {
+            let req = JsonRequest::new("blockchain.get_headers", json!([last, 100]));
+            let rep = self.rpc_client.request(req).await?;
+            let bytes: Vec<u8> = serde_json::from_value(rep)?;
+            let (batch, batch_blocks): (Vec<Header>, Vec<Block>) = deserialize(&bytes)?;
+
+            let Some(header) = batch.last() else {
+                return Err(anyhow!("Trusted header {} not found in the chain", anchor))
+            };
+            last = header.slot;
+            headers.extend(batch);
+            blocks.extend(batch_blocks);
+
+            if let Some(idx) = headers.iter().position(|x| x.headerhash() == *anchor) {
+                headers.truncate(idx + 1);
+                blocks.truncate(idx + 1);
+                return Ok((headers, blocks))
+            }
+        }
+    }
+
+    /// Verify that the transaction with the given hash is included in the
+    /// block of the given slot, without fetching the block's transactions.
+    /// darkfid provides the transaction's inclusion proof, which is checked
+    /// against the chain of headers from that block up to the trusted header
+    /// hashing to `anchor`. darkfid could make up any chain of headers, so the
+    /// check is only as good as the trust in `anchor`.
+    pub async fn verify_tx_inclusion(
+        &self,
+        slot: u64,
+        tx_hash: &blake3::Hash,
+        anchor: &blake3::Hash,
+    ) -> Result<()> {
+        let params = json!([slot, tx_hash.to_hex().as_str()]);
+        let req = JsonRequest::new("blockchain.get_tx_inclusion", params);
+        let rep = self.rpc_client.request(req).await?;
+        let Some(bytes): Option<Vec<u8>> = serde_json::from_value(rep)? else {
+            return Err(anyhow!("Transaction {} is not included in slot {}", tx_hash, slot))
+        };
+        let proof: TxInclusionProof = deserialize(&bytes)?;
+
+        let (headers, blocks) = self.get_headers_until(slot.saturating_sub(1), anchor).await?;
+        if !verify_tx_inclusion(tx_hash, &proof, &headers, &blocks, anchor) {
+            return Err(anyhow!("Invalid inclusion proof for transaction {}", tx_hash))
+        }
+
+        Ok(())
+    }
+
     /// Scans the blockchain starting from the last scanned slot, for relevant
     /// money transfer transactions. If reset flag is provided, Merkle tree state
     /// and coins are reset, and start scanning from beginning. Alternatively,
@@ -68,7 +68,8 @@ impl Drk {
         };
 
         let zkas_bins = self.lookup_zkas(&DAO_CONTRACT_ID).await?;
-        let Some(dao_mint_zkbin) = zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_MINT_NS) else {
+        let Some(dao_mint_zkbin) = zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_MINT_NS)
+        else {
             return Err(anyhow!("DAO Mint circuit not found"));
         };
 
@@ -87,8 +88,7 @@ impl Drk {
         let calls = vec![ContractCall { contract_id: *DAO_CONTRACT_ID, data }];
         let proofs = vec![proofs];
         let mut tx = Transaction { calls, proofs, signatures: vec![] };
-        let sigs = tx.create_sigs(&mut OsRng, &[dao.secret_key])?;
-        tx.signatures = vec![sigs];
+        self.sign_with_fee(&mut tx, vec![vec![dao.secret_key]], &[]).await?;
 
         Ok(tx)
     }
@@ -140,7 +140,8 @@ impl Drk {
 
         // FIXME: Here we're looking for a coin == proposer_limit but this shouldn't have to
         // be the case {
-        let Some(gov_coin) = gov_owncoins.iter().find(|x| x.note.value == dao.proposer_limit) else {
+        let Some(gov_coin) = gov_owncoins.iter().find(|x| x.note.value == dao.proposer_limit)
+        else {
             return Err(anyhow!("Did not find a single gov coin of value {}", dao.proposer_limit));
         };
         // }
@@ -148,14 +149,14 @@ impl Drk {
         // Lookup the zkas bins
         let zkas_bins = self.lookup_zkas(&DAO_CONTRACT_ID).await?;
         let Some(propose_burn_zkbin) =
-            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_PROPOSE_BURN_NS) else
-        {
+            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_PROPOSE_BURN_NS)
+        else {
             return Err(anyhow!("Propose Burn circuit not found"))
         };
 
         let Some(propose_main_zkbin) =
-            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_PROPOSE_MAIN_NS) else
-        {
+            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_PROPOSE_MAIN_NS)
+        else {
             return Err(anyhow!("Propose Main circuit not found"))
         };
 
@@ -241,8 +242,7 @@ impl Drk {
         let calls = vec![ContractCall { contract_id: *DAO_CONTRACT_ID, data }];
         let proofs = vec![proofs];
         let mut tx = Transaction { calls, proofs, signatures: vec![] };
-        let sigs = tx.create_sigs(&mut OsRng, &vec![signature_secret])?;
-        tx.signatures = vec![sigs];
+        self.sign_with_fee(&mut tx, vec![vec![signature_secret]], &[]).await?;
 
         Ok(tx)
     }
@@ -338,14 +338,14 @@ impl Drk {
 
         let zkas_bins = self.lookup_zkas(&DAO_CONTRACT_ID).await?;
         let Some(dao_vote_burn_zkbin) =
-            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_VOTE_BURN_NS) else
-        {
+            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_VOTE_BURN_NS)
+        else {
             return Err(anyhow!("DAO Vote Burn circuit not found"))
         };
 
         let Some(dao_vote_main_zkbin) =
-            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_VOTE_MAIN_NS) else
-        {
+            zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_VOTE_MAIN_NS)
+        else {
             return Err(anyhow!("DAO Vote Main circuit not found"))
         };
 
@@ -375,8 +375,7 @@ impl Drk {
         let calls = vec![ContractCall { contract_id: *DAO_CONTRACT_ID, data }];
         let proofs = vec![proofs];
         let mut tx = Transaction { calls, proofs, signatures: vec![] };
-        let sigs = tx.create_sigs(&mut OsRng, &input_secrets)?;
-        tx.signatures = vec![sigs];
+        self.sign_with_fee(&mut tx, vec![input_secrets], &[]).await?;
 
         Ok(tx)
     }
@@ -480,10 +479,12 @@ impl Drk {
         };
 
         let zkas_bins = self.lookup_zkas(&MONEY_CONTRACT_ID).await?;
-        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1) else {
+        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1)
+        else {
             return Err(anyhow!("Money Mint circuit not found"))
         };
-        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1) else {
+        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1)
+        else {
             return Err(anyhow!("Money Burn circuit not found"))
         };
         let mint_zkbin = ZkBinary::decode(&mint_zkbin.1)?;
@@ -504,7 +505,8 @@ impl Drk {
         let xfer_call = ContractCall { contract_id: *MONEY_CONTRACT_ID, data };
 
         let zkas_bins = self.lookup_zkas(&DAO_CONTRACT_ID).await?;
-        let Some(exec_zkbin) = zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_EXEC_NS) else {
+        let Some(exec_zkbin) = zkas_bins.iter().find(|x| x.0 == DAO_CONTRACT_ZKAS_DAO_EXEC_NS)
+        else {
             return Err(anyhow!("DAO Exec circuit not found"))
         };
         let exec_zkbin = ZkBinary::decode(&exec_zkbin.1)?;
@@ -581,9 +583,8 @@ impl Drk {
             signatures: vec![],
         };
 
-        let xfer_sigs = tx.create_sigs(&mut OsRng, &xfer_signature_secrets)?;
-        let exec_sigs = tx.create_sigs(&mut OsRng, &[exec_signature_secret])?;
-        tx.signatures = vec![xfer_sigs, exec_sigs];
+        let secrets = vec![xfer_signature_secrets, vec![exec_signature_secret]];
+        self.sign_with_fee(&mut tx, secrets, &[]).await?;
 
         Ok(tx)
     }
@@ -0,0 +1,147 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use anyhow::{anyhow, Result};
+use darkfi::{
+    rpc::jsonrpc::JsonRequest,
+    tx::Transaction,
+    zk::{halo2::Field, proof::ProvingKey, vm::ZkCircuit, vm_stack::empty_witnesses},
+    zkas::ZkBinary,
+};
+use darkfi_money_contract::{
+    client::{build_fee_tx, OwnCoin},
+    MoneyFunction, MONEY_CONTRACT_ZKAS_BURN_NS_V1, MONEY_CONTRACT_ZKAS_MINT_NS_V1,
+};
+use darkfi_sdk::{
+    crypto::{contract_id::MONEY_CONTRACT_ID, Keypair, SecretKey, DARK_TOKEN_ID},
+    pasta::pallas,
+    tx::ContractCall,
+};
+use darkfi_serial::{serialize, Encodable};
+use rand::rngs::OsRng;
+use serde_json::json;
+
+use super::Drk;
+
+impl Drk {
+    /// Append a money fee call to the given unsigned transaction, paying for
+    /// the gas it uses, and sign it. `secrets` holds the signature secrets of
+    /// each of the transaction's calls. The fee is paid with one of our native
+    /// token coins, other than the `spent_coins` the transaction already spends,
+    /// which then gets marked as spent.
+    pub async fn sign_with_fee(
+        &self,
+        tx: &mut Transaction,
+        mut secrets: Vec<Vec<SecretKey>>,
+        spent_coins: &[OwnCoin],
+    ) -> Result<()> {
+        let owncoins = self.get_coins(false).await?;
+        let mut owncoins: Vec<OwnCoin> = owncoins.into_iter().map(|x| x.0).collect();
+        owncoins.retain(|x| x.note.token_id == *DARK_TOKEN_ID);
+        owncoins.retain(|x| x.note.spend_hook == pallas::Base::zero());
+        owncoins.retain(|x| !spent_coins.iter().any(|y| y.coin == x.coin));
+        if owncoins.is_empty() {
+            return Err(anyhow!("Did not find any native token coins to pay the fee with"))
+        }
+
+        let tree = self.get_money_tree().await?;
+
+        // TODO: Which keypair to actually use?
+        let wallet_secrets = self.get_money_secrets().await?;
+        let keypair = Keypair::new(wallet_secrets[0]);
+
+        let zkas_bins = self.lookup_zkas(&MONEY_CONTRACT_ID).await?;
+
+        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1)
+        else {
+            return Err(anyhow!("Mint circuit not found"))
+        };
+
+        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1)
+        else {
+            return Err(anyhow!("Burn circuit not found"))
+        };
+
+        let mint_zkbin = ZkBinary::decode(&mint_zkbin.1)?;
+        let burn_zkbin = ZkBinary::decode(&burn_zkbin.1)?;
+
+        let k = 13;
+        let mint_circuit = ZkCircuit::new(empty_witnesses(&mint_zkbin), mint_zkbin.clone());
+        let burn_circuit = ZkCircuit::new(empty_witnesses(&burn_zkbin), burn_zkbin.clone());
+
+        eprintln!("Creating Mint circuit proving key");
+        let mint_pk = ProvingKey::build(k, &mint_circuit);
+        eprintln!("Creating Burn circuit proving key");
+        let burn_pk = ProvingKey::build(k, &burn_circuit);
+
+        // The fee call uses gas as well, so one paying nothing is appended
+        // first to find out the fee, and then replaced by one paying it.
+        // They only differ in values, so they use the same amount of gas.
+        let mut fee = 0;
+        for pass in 0..2 {
+            let (params, proofs, fee_secrets, fee_coin) = build_fee_tx(
+                &keypair,
+                fee,
+                &owncoins,
+                &tree,
+                &mint_zkbin,
+                &mint_pk,
+                &burn_zkbin,
+                &burn_pk,
+            )?;
+
+            let mut data = vec![MoneyFunction::Fee as u8];
+            params.encode(&mut data)?;
+            let call = ContractCall { contract_id: *MONEY_CONTRACT_ID, data };
+
+            if pass == 0 {
+                tx.calls.push(call);
+                tx.proofs.push(proofs);
+                eprintln!("Estimating the transaction fee");
+                fee = self.estimate_fee(tx).await?;
+                eprintln!("Paying a fee of {}", fee);
+                continue
+            }
+
+            *tx.calls.last_mut().unwrap() = call;
+            *tx.proofs.last_mut().unwrap() = proofs;
+            secrets.push(fee_secrets);
+
+            // Every call signs the whole transaction, fee call included
+            let mut signatures = Vec::with_capacity(secrets.len());
+            for call_secrets in &secrets {
+                signatures.push(tx.create_sigs(&mut OsRng, call_secrets)?);
+            }
+            tx.signatures = signatures;
+
+            self.mark_spent_coin(&fee_coin.coin).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Query darkfid for the fee the given transaction has to pay
+    async fn estimate_fee(&self, tx: &Transaction) -> Result<u64> {
+        let params = json!([bs58::encode(&serialize(tx)).into_string()]);
+        let req = JsonRequest::new("tx.estimate_fee", params);
+        let rep = self.rpc_client.request(req).await?;
+
+        let fee = serde_json::from_value(rep)?;
+        Ok(fee)
+    }
+}
@@ -96,11 +96,13 @@ impl Drk {
         // We also do this through the RPC.
         let zkas_bins = self.lookup_zkas(&contract_id).await?;
 
-        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1) else {
+        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1)
+        else {
             return Err(anyhow!("Mint circuit not found"))
         };
 
-        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1) else {
+        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1)
+        else {
             return Err(anyhow!("Burn circuit not found"))
         };
 
@@ -182,11 +184,13 @@ impl Drk {
         // We also do this through the RPC.
         let zkas_bins = self.lookup_zkas(&contract_id).await?;
 
-        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1) else {
+        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1)
+        else {
             return Err(anyhow!("Mint circuit not found"))
         };
 
-        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1) else {
+        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1)
+        else {
             return Err(anyhow!("Burn circuit not found"))
         };
 
@@ -215,7 +219,7 @@ impl Drk {
                 partial.token_pair.0,
                 &partial.value_blinds,
                 &partial.token_blinds,
-                &[burn_coin],
+                &[burn_coin.clone()],
                 &tree,
                 &mint_zkbin,
                 &mint_pk,
@@ -243,9 +247,10 @@ impl Drk {
             proofs: vec![full_proofs],
             signatures: vec![],
         };
+        // The joining party pays the fee, and the other party's signature
+        // gets prepended in `sign_swap()`.
         eprintln!("Signing swap transaction");
-        let sigs = tx.create_sigs(&mut OsRng, &half_keys)?;
-        tx.signatures = vec![sigs];
+        self.sign_with_fee(&mut tx, vec![half_keys], &[burn_coin]).await?;
 
         Ok(tx)
     }
@@ -108,11 +108,13 @@ impl Drk {
         // We also do this through the RPC.
         let zkas_bins = self.lookup_zkas(&contract_id).await?;
 
-        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1) else {
+        let Some(mint_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_MINT_NS_V1)
+        else {
             return Err(anyhow!("Mint circuit not found"))
         };
 
-        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1) else {
+        let Some(burn_zkbin) = zkas_bins.iter().find(|x| x.0 == MONEY_CONTRACT_ZKAS_BURN_NS_V1)
+        else {
             return Err(anyhow!("Burn circuit not found"))
         };
 
@@ -146,14 +148,13 @@ impl Drk {
             false,
         )?;
 
-        // Encode and sign the transaction
+        // Encode the transaction, and sign it along with its fee call
         let mut data = vec![MoneyFunction::Transfer as u8];
         params.encode(&mut data)?;
         let calls = vec![ContractCall { contract_id, data }];
         let proofs = vec![proofs];
         let mut tx = Transaction { calls, proofs, signatures: vec![] };
-        let sigs = tx.create_sigs(&mut OsRng, &secrets)?;
-        tx.signatures = vec![sigs];
+        self.sign_with_fee(&mut tx, vec![secrets], &spent_coins).await?;
 
         // We need to mark the coins we've spent in our wallet
         for spent_coin in spent_coins {
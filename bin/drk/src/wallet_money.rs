@@ -30,7 +30,7 @@ use darkfi_money_contract::{
         MONEY_KEYS_COL_IS_DEFAULT, MONEY_KEYS_COL_KEY_ID, MONEY_KEYS_COL_PUBLIC,
         MONEY_KEYS_COL_SECRET, MONEY_KEYS_TABLE, MONEY_TREE_COL_TREE, MONEY_TREE_TABLE,
     },
-    model::{MoneyTransferParams, Output},
+    model::{MoneyFeeParams, MoneyTransferParams, Output},
     MoneyFunction,
 };
 use darkfi_sdk::{
@@ -516,6 +516,14 @@ impl Drk {
 
                 continue
             }
+
+            if call.contract_id == cid && call.data[0] == MoneyFunction::Fee as u8 {
+                eprintln!("Found Money::Fee in call {}", i);
+                let params: MoneyFeeParams = deserialize(&call.data[1..])?;
+                nullifiers.push(params.input.nullifier);
+                outputs.push(params.output);
+                continue
+            }
         }
 
         let secrets = self.get_money_secrets().await?;
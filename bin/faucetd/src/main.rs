@@ -592,7 +592,7 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
         ..Default::default()
     };
 
-    let sync_p2p = net::P2p::new(network_settings).await;
+    let sync_p2p = net::P2p::new(network_settings).await?;
     let registry = sync_p2p.protocol_registry();
 
     info!("Registering block sync P2P protocols...");
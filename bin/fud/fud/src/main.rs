@@ -408,7 +408,7 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
         ..Default::default()
     };
 
-    let p2p = net::P2p::new(network_settings).await;
+    let p2p = net::P2p::new(network_settings).await?;
 
     // Initialize daemon dht
     let dht = Dht::new(None, p2p.clone(), shutdown.clone(), ex.clone()).await?;
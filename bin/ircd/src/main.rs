@@ -162,7 +162,7 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'_>>) -> Result<(
     net_settings.app_version = Some(option_env!("CARGO_PKG_VERSION").unwrap_or("").to_string());
     let (p2p_send_channel, p2p_recv_channel) = smol::channel::unbounded::<Privmsg>();
 
-    let p2p = net::P2p::new(net_settings.into()).await;
+    let p2p = net::P2p::new(net_settings.into()).await?;
     let p2p2 = p2p.clone();
 
     let registry = p2p.protocol_registry();
@@ -190,6 +190,13 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'_>>) -> Result<(
         .spawn(async move { listen_and_serve(rpc_listen_addr, rpc_interface, _ex).await })
         .detach();
 
+    // Metrics listener
+    if let Some(metrics_listen) = settings.metrics_listen.clone() {
+        let networks = vec![("ircd".to_string(), p2p.metrics())];
+        let _ex = executor.clone();
+        executor.spawn(net::metrics::listen_and_serve(metrics_listen, networks, _ex)).detach();
+    }
+
     //
     // IRC instance
     //
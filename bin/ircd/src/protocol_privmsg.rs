@@ -103,4 +103,8 @@ impl net::Message for Privmsg {
     fn name() -> &'static str {
         "privmsg"
     }
+
+    fn rate_limit() -> Option<net::RateLimit> {
+        Some(net::RateLimit::new(100, 20.0))
+    }
 }
@@ -366,6 +366,10 @@ impl net::Message for Event {
     fn name() -> &'static str {
         "event"
     }
+
+    fn rate_limit() -> Option<net::RateLimit> {
+        Some(net::RateLimit::new(100, 20.0))
+    }
 }
 
 impl net::Message for Inv {
@@ -68,6 +68,10 @@ pub struct Args {
     #[structopt(long = "rpc", default_value = "tcp://127.0.0.1:25550")]
     pub rpc_listen: Url,
 
+    /// Prometheus metrics listen URL, disabled if not set
+    #[structopt(long = "metrics")]
+    pub metrics_listen: Option<Url>,
+
     /// IRC listen URL
     #[structopt(long = "irc", default_value = "tcp://127.0.0.1:6667")]
     pub irc_listen: Url,
@@ -33,6 +33,10 @@ pub struct Args {
     /// JSON-RPC listen URL
     pub rpc_listen: Url,
 
+    #[structopt(long)]
+    /// Prometheus metrics listen URL, disabled if not set
+    pub metrics_listen: Option<Url>,
+
     #[structopt(short, long)]
     /// Configuration file to use
     pub config: Option<String>,
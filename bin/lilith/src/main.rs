@@ -202,7 +202,7 @@ async fn spawn_network(
         ..Default::default()
     };
 
-    let p2p = net::P2p::new(network_settings).await;
+    let p2p = net::P2p::new(network_settings).await?;
 
     // Setting saved hosts
     match saved_hosts {
@@ -347,6 +347,15 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'_>>) -> Result<()> {
     let _ex = ex.clone();
     ex.spawn(listen_and_serve(args.rpc_listen, lilith.clone(), _ex)).detach();
 
+    // Metrics listener
+    if let Some(metrics_listen) = args.metrics_listen.clone() {
+        info!("Starting metrics listener");
+        let networks =
+            lilith.spawns.iter().map(|spawn| (spawn.name.clone(), spawn.p2p.metrics())).collect();
+        let _ex = ex.clone();
+        ex.spawn(net::metrics::listen_and_serve(metrics_listen, networks, _ex)).detach();
+    }
+
     // JSON-RPC notifications simulation
     ex.spawn(simulate_blocks(subscriber)).detach();
 
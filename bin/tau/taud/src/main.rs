@@ -247,7 +247,7 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'_>>) -> Result<(
     net_settings.app_version = Some(option_env!("CARGO_PKG_VERSION").unwrap_or("").to_string());
     let (p2p_send_channel, p2p_recv_channel) = smol::channel::unbounded::<NetMsg>();
 
-    let p2p = net::P2p::new(net_settings.into()).await;
+    let p2p = net::P2p::new(net_settings.into()).await?;
     let p2p = p2p.clone();
     let registry = p2p.protocol_registry();
 
@@ -279,6 +279,15 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'_>>) -> Result<(
     let _ex = executor.clone();
     executor.spawn(listen_and_serve(settings.rpc_listen.clone(), rpc_interface, _ex)).detach();
 
+    //
+    // Metrics listener
+    //
+    if let Some(metrics_listen) = settings.metrics_listen.clone() {
+        let networks = vec![("taud".to_string(), p2p.metrics())];
+        let _ex = executor.clone();
+        executor.spawn(net::metrics::listen_and_serve(metrics_listen, networks, _ex)).detach();
+    }
+
     //
     // Waiting Exit signal
     //
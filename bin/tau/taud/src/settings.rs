@@ -37,6 +37,9 @@ pub struct Args {
     /// JSON-RPC listen URL
     #[structopt(long = "rpc", default_value = "tcp://127.0.0.1:23330")]
     pub rpc_listen: Url,
+    /// Prometheus metrics listen URL, disabled if not set
+    #[structopt(long = "metrics")]
+    pub metrics_listen: Option<Url>,
     /// Sets Datastore Path
     #[structopt(long, default_value = "~/.tau")]
     pub datastore: String,
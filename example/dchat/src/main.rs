@@ -233,7 +233,7 @@ async fn main() -> Result<()> {
 
     let settings = settings?.clone();
 
-    let p2p = net::P2p::new(settings.net).await;
+    let p2p = net::P2p::new(settings.net).await?;
 
     let ex = Arc::new(Executor::new());
     let ex2 = ex.clone();
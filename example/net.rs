@@ -29,7 +29,7 @@ use darkfi::{
 };
 
 async fn start(executor: Arc<Executor<'_>>, options: ProgramOptions) -> Result<()> {
-    let p2p = net::P2p::new(options.network_settings).await;
+    let p2p = net::P2p::new(options.network_settings).await?;
 
     p2p.clone().start(executor.clone()).await?;
     p2p.run(executor).await?;
@@ -109,7 +109,7 @@ impl MockP2p {
 
                     let net_settings =
                         net::Settings { inbound: address.clone(), peers, ..Default::default() };
-                    let p2p = net::P2p::new(net_settings).await;
+                    let p2p = net::P2p::new(net_settings).await?;
 
                     broadcast = false;
                     state = State::Seed;
@@ -128,7 +128,7 @@ impl MockP2p {
                         ..Default::default()
                     };
 
-                    let p2p = net::P2p::new(net_settings).await;
+                    let p2p = net::P2p::new(net_settings).await?;
 
                     state = State::Inbound;
 
@@ -144,7 +144,7 @@ impl MockP2p {
                         ..Default::default()
                     };
 
-                    let p2p = net::P2p::new(net_settings).await;
+                    let p2p = net::P2p::new(net_settings).await?;
                     state = State::Outbound;
 
                     p2p
@@ -156,7 +156,7 @@ impl MockP2p {
             let net_settings =
                 net::Settings { peers: vec![Url::parse(&connect.unwrap())?], ..Default::default() };
 
-            let p2p = net::P2p::new(net_settings).await;
+            let p2p = net::P2p::new(net_settings).await?;
             state = State::Outbound;
 
             p2p
@@ -247,7 +247,9 @@ impl MockP2p {
         let rpc_interface =
             Arc::new(rpc::JsonRpcInterface { addr: rpc_addr.clone(), p2p: p2p.clone() });
         let _ex = executor.clone();
-        executor.spawn(async move { listen_and_serve(rpc_addr, rpc_interface, _ex).await }).detach();
+        executor
+            .spawn(async move { listen_and_serve(rpc_addr, rpc_interface, _ex).await })
+            .detach();
 
         p2p.clone().start(executor.clone()).await?;
         p2p.run(executor).await
@@ -40,9 +40,9 @@ use darkfi::{
     },
     util::{
         cli::{get_log_config, get_log_level, spawn_config},
+        expand_path,
         path::get_config_path,
         serial::serialize,
-        expand_path,
     },
     Result,
 };
@@ -288,7 +288,7 @@ async fn realmain(args: Args, ex: Arc<Executor<'_>>) -> Result<()> {
         ..Default::default()
     };
 
-    let p2p = net::P2p::new(network_settings).await;
+    let p2p = net::P2p::new(network_settings).await?;
 
     // Initialize daemon dht
     let dht = Dht::new(None, p2p.clone(), shutdown.clone(), ex.clone()).await?;
@@ -154,7 +154,7 @@ async fn start(args: Args, executor: Arc<Executor<'_>>) -> Result<()> {
 
     let (p2p_send_channel, p2p_recv_channel) = async_channel::unbounded::<NetMsg>();
 
-    let p2p = net::P2p::new(net_settings).await;
+    let p2p = net::P2p::new(net_settings).await?;
     let p2p = p2p.clone();
 
     let registry = p2p.protocol_registry();
@@ -31,7 +31,7 @@ const SLED_BLOCK_ORDER_TREE: &[u8] = b"_block_order";
 /// The `HeaderStore` is a `sled` tree storing all the blockchain's blocks' headers
 /// where the key is the headers' hash, and value is the serialized header.
 #[derive(Clone)]
-pub struct HeaderStore(sled::Tree);
+pub struct HeaderStore(pub sled::Tree);
 
 impl HeaderStore {
     /// Opens a new or existing `HeaderStore` on the given sled database.
@@ -54,6 +54,15 @@ impl HeaderStore {
     /// the key, while value is the serialized [`Header`] itself.
     /// On success, the function returns the header hashes in the same order.
     pub fn insert(&self, headers: &[Header]) -> Result<Vec<blake3::Hash>> {
+        let (ret, batch) = self.insert_batch(headers);
+        self.0.apply_batch(batch)?;
+        Ok(ret)
+    }
+
+    /// Generate the sled batch corresponding to an insert, so the caller
+    /// can apply it along with other writes.
+    /// Returns the header hashes in the same order, and the batch.
+    pub fn insert_batch(&self, headers: &[Header]) -> (Vec<blake3::Hash>, sled::Batch) {
         let mut ret = Vec::with_capacity(headers.len());
         let mut batch = sled::Batch::default();
 
@@ -64,8 +73,7 @@ impl HeaderStore {
             ret.push(headerhash);
         }
 
-        self.0.apply_batch(batch)?;
-        Ok(ret)
+        (ret, batch)
     }
 
     /// Check if the headerstore contains a given headerhash.
@@ -117,7 +125,7 @@ impl HeaderStore {
 /// The `BlockStore` is a `sled` tree storing all the blockchain's blocks
 /// where the key is the blocks' hash, and value is the serialized block.
 #[derive(Clone)]
-pub struct BlockStore(sled::Tree);
+pub struct BlockStore(pub sled::Tree);
 
 impl BlockStore {
     /// Opens a new or existing `BlockStore` on the given sled database.
@@ -139,6 +147,15 @@ impl BlockStore {
     /// the key, while value is the serialized [`Block`] itself.
     /// On success, the function returns the block hashes in the same order.
     pub fn insert(&self, blocks: &[Block]) -> Result<Vec<blake3::Hash>> {
+        let (ret, batch) = self.insert_batch(blocks);
+        self.0.apply_batch(batch)?;
+        Ok(ret)
+    }
+
+    /// Generate the sled batch corresponding to an insert, so the caller
+    /// can apply it along with other writes.
+    /// Returns the block hashes in the same order, and the batch.
+    pub fn insert_batch(&self, blocks: &[Block]) -> (Vec<blake3::Hash>, sled::Batch) {
         let mut ret = Vec::with_capacity(blocks.len());
         let mut batch = sled::Batch::default();
 
@@ -149,8 +166,7 @@ impl BlockStore {
             ret.push(blockhash);
         }
 
-        self.0.apply_batch(batch)?;
-        Ok(ret)
+        (ret, batch)
     }
 
     /// Check if the blockstore contains a given blockhash.
@@ -207,7 +223,7 @@ impl BlockStore {
 /// blockchain's slots, where the key is the slot uid, and the value is
 /// the blocks' hash. [`BlockStore`] can be queried with this hash.
 #[derive(Clone)]
-pub struct BlockOrderStore(sled::Tree);
+pub struct BlockOrderStore(pub sled::Tree);
 
 impl BlockOrderStore {
     /// Opens a new or existing `BlockOrderStore` on the given sled database.
@@ -228,6 +244,14 @@ impl BlockOrderStore {
     /// operation is done as a batch.
     /// The block slot is used as the key, and the blockhash is used as value.
     pub fn insert(&self, slots: &[u64], hashes: &[blake3::Hash]) -> Result<()> {
+        let batch = self.insert_batch(slots, hashes);
+        self.0.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// Generate the sled batch corresponding to an insert, so the caller
+    /// can apply it along with other writes.
+    pub fn insert_batch(&self, slots: &[u64], hashes: &[blake3::Hash]) -> sled::Batch {
         assert_eq!(slots.len(), hashes.len());
         let mut batch = sled::Batch::default();
 
@@ -235,8 +259,7 @@ impl BlockOrderStore {
             batch.insert(&sl.to_be_bytes(), hashes[i].as_bytes());
         }
 
-        self.0.apply_batch(batch)?;
-        Ok(())
+        batch
     }
 
     /// Check if the blockorderstore contains a given slot.
@@ -20,15 +20,18 @@ use darkfi_sdk::crypto::ContractId;
 use darkfi_serial::{deserialize, serialize};
 use log::{debug, error};
 
+use super::{StateOverlay, TreeWrites};
 use crate::{Error, Result};
 
 const SLED_CONTRACTS_TREE: &[u8] = b"_contracts";
 const SLED_BINCODE_TREE: &[u8] = b"_wasm_bincode";
+const SLED_CONTRACT_LOCKS_TREE: &[u8] = b"_contract_locks";
+const SLED_CONTRACT_NONCES_TREE: &[u8] = b"_contract_nonces";
 
 /// The `WasmStore` is a `sled` tree that stores the wasm bincode for deployed
 /// contracts.
 #[derive(Clone)]
-pub struct WasmStore(sled::Tree);
+pub struct WasmStore(pub sled::Tree);
 
 impl WasmStore {
     /// Opens or creates a `WasmStore`. This tree holds the wasm bincode.
@@ -52,6 +55,23 @@ impl WasmStore {
         Err(Error::WasmBincodeNotFound)
     }
 
+    /// Fetches the bincode for a given ContractId, taking the pending writes
+    /// of the given overlay into account.
+    /// Returns an error if the bincode is not found.
+    pub fn get_pending(&self, overlay: &StateOverlay, contract_id: ContractId) -> Result<Vec<u8>> {
+        if let Some(bincode) = overlay.get(&self.0, &serialize(&contract_id))? {
+            return Ok(bincode)
+        }
+
+        Err(Error::WasmBincodeNotFound)
+    }
+
+    /// Generate the write inserting or replacing the bincode for a given
+    /// ContractId, so the caller can stage it along with other writes.
+    pub fn insert_writes(&self, contract_id: ContractId, bincode: &[u8]) -> TreeWrites {
+        TreeWrites::from([(serialize(&contract_id), Some(bincode.to_vec()))])
+    }
+
     /// Inserts or replaces the bincode for a given ContractId
     pub fn insert(&self, contract_id: ContractId, bincode: &[u8]) -> Result<()> {
         if let Err(e) = self.0.insert(&serialize(&contract_id), bincode) {
@@ -66,7 +86,7 @@ impl WasmStore {
 /// The `ContractStateStore` is a `sled` tree that stores pointers to contracts'
 /// databases. See the rustdoc for the impl functions for more info.
 #[derive(Clone)]
-pub struct ContractStateStore(sled::Tree);
+pub struct ContractStateStore(pub sled::Tree);
 
 impl ContractStateStore {
     /// Opens or creates a `ContractStateStore`. This main tree holds the links
@@ -135,6 +155,85 @@ impl ContractStateStore {
         Ok(tree)
     }
 
+    /// Like `init()`, but instead of writing the new state pointer right away,
+    /// the write registering it is returned, so it can be staged along with
+    /// the other state changes of an on-chain deployment. Existing pointers are
+    /// read through the given overlay, and then through `pending`, which holds
+    /// the writes returned by previous calls that were not staged yet.
+    pub fn init_writes(
+        &self,
+        db: &sled::Db,
+        overlay: &StateOverlay,
+        pending: &TreeWrites,
+        contract_id: &ContractId,
+        tree_name: &str,
+    ) -> Result<(sled::Tree, TreeWrites)> {
+        debug!(target: "blockchain::contractstore", "Initializing pending state tree for {}:{}", contract_id, tree_name);
+
+        let contract_id_bytes = serialize(contract_id);
+        let ptr = contract_id.hash_state_id(tree_name);
+
+        let mut state_pointers =
+            self.pending_pointers(overlay, pending, &contract_id_bytes)?.unwrap_or_default();
+
+        if state_pointers.contains(&ptr) {
+            return Err(Error::ContractAlreadyInitialized)
+        }
+
+        state_pointers.push(ptr);
+
+        let tree = db.open_tree(ptr)?;
+        let writes = TreeWrites::from([(contract_id_bytes, Some(serialize(&state_pointers)))]);
+        Ok((tree, writes))
+    }
+
+    /// Like `lookup()`, but taking into account the pending writes of the
+    /// given overlay, and the ones in `pending` returned by `init_writes()`.
+    pub fn lookup_pending(
+        &self,
+        db: &sled::Db,
+        overlay: &StateOverlay,
+        pending: &TreeWrites,
+        contract_id: &ContractId,
+        tree_name: &str,
+    ) -> Result<sled::Tree> {
+        debug!(target: "blockchain::contractstore", "Looking up pending state tree for {}:{}", contract_id, tree_name);
+
+        let contract_id_bytes = serialize(contract_id);
+        let ptr = contract_id.hash_state_id(tree_name);
+
+        let Some(state_pointers) = self.pending_pointers(overlay, pending, &contract_id_bytes)?
+        else {
+            return Err(Error::ContractNotFound(contract_id.to_string()))
+        };
+
+        if !state_pointers.contains(&ptr) {
+            return Err(Error::ContractStateNotFound)
+        }
+
+        let tree = db.open_tree(ptr)?;
+        Ok(tree)
+    }
+
+    /// Fetch the state pointers of a contract from `pending`, or from the
+    /// tree through the given overlay.
+    fn pending_pointers(
+        &self,
+        overlay: &StateOverlay,
+        pending: &TreeWrites,
+        contract_id_bytes: &[u8],
+    ) -> Result<Option<Vec<[u8; 32]>>> {
+        let bytes = match pending.get(contract_id_bytes) {
+            Some(v) => v.clone(),
+            None => overlay.get(&self.0, contract_id_bytes)?,
+        };
+
+        match bytes {
+            Some(bytes) => Ok(Some(deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
     /// Do a lookup of an existing contract state. In order to succeed, the
     /// state must have been previously initialized with `init()`. If the
     /// state has been found, a handle to it will be returned. Otherwise, we
@@ -204,3 +303,78 @@ impl ContractStateStore {
         Ok(())
     }
 }
+
+/// The `ContractLockStore` is a `sled` tree holding the IDs of the deployed
+/// contracts that were locked, so their code can't be upgraded anymore.
+#[derive(Clone)]
+pub struct ContractLockStore(pub sled::Tree);
+
+impl ContractLockStore {
+    /// Opens or creates a `ContractLockStore`.
+    /// The layout looks like this:
+    /// ```plaintext
+    ///  tree: "_contract_locks"
+    ///   key: ContractId
+    /// value: ()
+    /// ```
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_CONTRACT_LOCKS_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Check if the given contract is locked, taking the pending writes of
+    /// the given overlay into account.
+    pub fn is_locked(&self, overlay: &StateOverlay, contract_id: ContractId) -> Result<bool> {
+        overlay.contains_key(&self.0, &serialize(&contract_id))
+    }
+
+    /// Generate the write locking the given contract, so the caller can stage
+    /// it along with other writes.
+    pub fn insert_writes(&self, contract_id: ContractId) -> TreeWrites {
+        TreeWrites::from([(serialize(&contract_id), Some(vec![]))])
+    }
+}
+
+/// The `ContractNonceStore` is a `sled` tree holding the number of times each
+/// deployed contract was deployed or upgraded, which deploy calls have to
+/// match, so they can't be replayed.
+#[derive(Clone)]
+pub struct ContractNonceStore(pub sled::Tree);
+
+impl ContractNonceStore {
+    /// Opens or creates a `ContractNonceStore`.
+    /// The layout looks like this:
+    /// ```plaintext
+    ///  tree: "_contract_nonces"
+    ///   key: ContractId
+    /// value: u64
+    /// ```
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_CONTRACT_NONCES_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Fetch the nonce of the given contract. Contracts never deployed have
+    /// nonce 0.
+    pub fn get(&self, contract_id: ContractId) -> Result<u64> {
+        match self.0.get(&serialize(&contract_id))? {
+            Some(bytes) => Ok(deserialize(&bytes)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Fetch the nonce of the given contract, taking the pending writes of
+    /// the given overlay into account. Contracts never deployed have nonce 0.
+    pub fn get_pending(&self, overlay: &StateOverlay, contract_id: ContractId) -> Result<u64> {
+        match overlay.get(&self.0, &serialize(&contract_id))? {
+            Some(bytes) => Ok(deserialize(&bytes)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Generate the write setting the nonce of the given contract, so the
+    /// caller can stage it along with other writes.
+    pub fn insert_writes(&self, contract_id: ContractId, nonce: u64) -> TreeWrites {
+        TreeWrites::from([(serialize(&contract_id), Some(serialize(&nonce)))])
+    }
+}
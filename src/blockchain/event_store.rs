@@ -0,0 +1,56 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi_sdk::event::ContractEvent;
+use darkfi_serial::{deserialize, serialize};
+
+use super::TreeWrites;
+use crate::Result;
+
+const SLED_EVENT_TREE: &[u8] = b"_contract_events";
+
+/// The `EventStore` is a `sled` tree mapping the hashes of the transactions
+/// to the events emitted by their contract calls, in the order they were
+/// emitted. Transactions without events have no record.
+#[derive(Clone)]
+pub struct EventStore(pub sled::Tree);
+
+impl EventStore {
+    /// Opens a new or existing `EventStore` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_EVENT_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Generate the writes storing the events emitted by the given
+    /// transaction, so the caller can apply them along with other writes.
+    pub fn insert_writes(&self, tx: &blake3::Hash, events: &[ContractEvent]) -> TreeWrites {
+        let mut writes = TreeWrites::new();
+        if !events.is_empty() {
+            writes.insert(tx.as_bytes().to_vec(), Some(serialize(&events.to_vec())));
+        }
+
+        writes
+    }
+
+    /// Fetch the events emitted by the given transaction.
+    pub fn get(&self, tx: &blake3::Hash) -> Result<Vec<ContractEvent>> {
+        let Some(found) = self.0.get(tx.as_bytes())? else { return Ok(vec![]) };
+        Ok(deserialize(&found)?)
+    }
+}
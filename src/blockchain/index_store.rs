@@ -0,0 +1,155 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi_sdk::crypto::{Coin, Nullifier};
+use darkfi_serial::{deserialize, serialize};
+
+use super::TreeWrites;
+use crate::Result;
+
+const SLED_TX_INDEX_TREE: &[u8] = b"_tx_index";
+const SLED_NULLIFIER_INDEX_TREE: &[u8] = b"_nullifier_index";
+const SLED_COIN_INDEX_TREE: &[u8] = b"_coin_index";
+
+/// Key of the `TxIndexStore` holding the slot from which the indexes are
+/// kept. Transaction hashes are 32 bytes long, so it can't collide with them.
+const SLED_INDEXED_FROM_KEY: &[u8] = b"indexed_from";
+
+/// The `TxIndexStore` is a `sled` tree mapping the hashes of the
+/// transactions to the slot of the block that included them.
+/// It also records the slot from which the indexes are kept, if they
+/// are enabled.
+#[derive(Clone)]
+pub struct TxIndexStore(pub sled::Tree);
+
+impl TxIndexStore {
+    /// Opens a new or existing `TxIndexStore` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_TX_INDEX_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Record that the indexes are kept from the given slot onwards.
+    /// Does nothing if they were already enabled.
+    pub fn enable(&self, slot: u64) -> Result<()> {
+        // The swap fails if the key is already set, which is fine
+        let _ = self.0.compare_and_swap(
+            SLED_INDEXED_FROM_KEY,
+            None as Option<&[u8]>,
+            Some(slot.to_be_bytes().to_vec()),
+        )?;
+        Ok(())
+    }
+
+    /// Fetch the slot from which the indexes are kept, or `None` if they
+    /// are not enabled.
+    pub fn indexed_from(&self) -> Result<Option<u64>> {
+        let Some(found) = self.0.get(SLED_INDEXED_FROM_KEY)? else { return Ok(None) };
+        let slot_bytes: [u8; 8] = found.as_ref().try_into().unwrap();
+        Ok(Some(u64::from_be_bytes(slot_bytes)))
+    }
+
+    /// Generate the writes indexing the given transaction hashes under the
+    /// given slot, so the caller can apply them along with other writes.
+    pub fn insert_writes(&self, txs: &[blake3::Hash], slot: u64) -> TreeWrites {
+        let mut writes = TreeWrites::new();
+        for tx in txs {
+            writes.insert(tx.as_bytes().to_vec(), Some(slot.to_be_bytes().to_vec()));
+        }
+
+        writes
+    }
+
+    /// Fetch the slot of the block that included the given transaction, if
+    /// it's indexed.
+    pub fn get(&self, tx: &blake3::Hash) -> Result<Option<u64>> {
+        let Some(found) = self.0.get(tx.as_bytes())? else { return Ok(None) };
+        let slot_bytes: [u8; 8] = found.as_ref().try_into().unwrap();
+        Ok(Some(u64::from_be_bytes(slot_bytes)))
+    }
+}
+
+/// The `NullifierIndexStore` is a `sled` tree mapping the nullifiers
+/// revealed by money contract calls to the hash of the transaction that
+/// revealed them, i.e. the one spending the coin.
+#[derive(Clone)]
+pub struct NullifierIndexStore(pub sled::Tree);
+
+impl NullifierIndexStore {
+    /// Opens a new or existing `NullifierIndexStore` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_NULLIFIER_INDEX_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Generate the writes indexing the nullifiers revealed by the given
+    /// transaction, so the caller can apply them along with other writes.
+    pub fn insert_writes(&self, tx: &blake3::Hash, nullifiers: &[Nullifier]) -> TreeWrites {
+        let mut writes = TreeWrites::new();
+        for nullifier in nullifiers {
+            writes.insert(nullifier.to_bytes().to_vec(), Some(tx.as_bytes().to_vec()));
+        }
+
+        writes
+    }
+
+    /// Fetch the hash of the transaction that revealed the given nullifier,
+    /// if it's indexed.
+    pub fn get(&self, nullifier: &Nullifier) -> Result<Option<blake3::Hash>> {
+        let Some(found) = self.0.get(nullifier.to_bytes())? else { return Ok(None) };
+        let hash_bytes: [u8; 32] = found.as_ref().try_into().unwrap();
+        Ok(Some(blake3::Hash::from(hash_bytes)))
+    }
+}
+
+/// The `CoinIndexStore` is a `sled` tree mapping the coins minted by money
+/// contract calls to the hash of the transaction that minted them, the index
+/// of the minting call in it, and the index of the coin among the outputs of
+/// that call.
+#[derive(Clone)]
+pub struct CoinIndexStore(pub sled::Tree);
+
+impl CoinIndexStore {
+    /// Opens a new or existing `CoinIndexStore` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_COIN_INDEX_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Generate the writes indexing the coins minted by each call of the
+    /// given transaction, so the caller can apply them along with other writes.
+    pub fn insert_writes(&self, tx: &blake3::Hash, coins: &[Vec<Coin>]) -> TreeWrites {
+        let mut writes = TreeWrites::new();
+        for (call_idx, call_coins) in coins.iter().enumerate() {
+            for (output_idx, coin) in call_coins.iter().enumerate() {
+                let location = (*tx, call_idx as u64, output_idx as u64);
+                writes.insert(coin.to_bytes().to_vec(), Some(serialize(&location)));
+            }
+        }
+
+        writes
+    }
+
+    /// Fetch the hash of the transaction that minted the given coin, the
+    /// index of the minting call in it, and the coin's output index in that
+    /// call, if it's indexed.
+    pub fn get(&self, coin: &Coin) -> Result<Option<(blake3::Hash, u64, u64)>> {
+        let Some(found) = self.0.get(coin.to_bytes())? else { return Ok(None) };
+        Ok(Some(deserialize(&found)?))
+    }
+}
@@ -16,12 +16,23 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use log::debug;
+use std::{collections::HashSet, ops::Bound};
+
+use darkfi_sdk::{
+    crypto::{Coin, Nullifier},
+    event::ContractEvent,
+};
+use darkfi_serial::serialize;
+use log::{debug, info, warn};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Transactional,
+};
 
 use crate::{
-    consensus::{Block, BlockInfo, SlotCheckpoint},
+    consensus::{Block, BlockInfo, Header, SlotCheckpoint, TxInclusionProof},
     util::time::Timestamp,
-    Result,
+    Error, Result,
 };
 
 pub mod block_store;
@@ -34,7 +45,38 @@ pub mod tx_store;
 pub use tx_store::TxStore;
 
 pub mod contract_store;
-pub use contract_store::{ContractStateStore, WasmStore};
+pub use contract_store::{ContractLockStore, ContractNonceStore, ContractStateStore, WasmStore};
+
+pub mod state_overlay;
+pub use state_overlay::{writes_batch, StateOverlay, TreeWrites};
+
+pub mod state_diff_store;
+pub use state_diff_store::{StateDiff, StateDiffStore};
+
+pub mod pruned_store;
+pub use pruned_store::PrunedStore;
+
+pub mod index_store;
+pub use index_store::{CoinIndexStore, NullifierIndexStore, TxIndexStore};
+
+pub mod event_store;
+pub use event_store::EventStore;
+
+pub mod snapshot;
+pub use snapshot::Snapshot;
+
+pub mod version_store;
+pub use version_store::VersionStore;
+
+/// Current version of the database layout. Databases of older versions get
+/// migrated when opened.
+/// * 1: blocks are written atomically, so partially written blocks left by
+///   older versions are repaired once.
+const DB_VERSION: u64 = 1;
+
+/// Number of slots after which blocks are considered final, and can't be
+/// rolled back anymore. The state diffs of older blocks are dropped.
+pub const FINALITY_DEPTH: u64 = 100;
 
 /// Structure holding all sled trees that define the concept of Blockchain.
 #[derive(Clone)]
@@ -55,11 +97,34 @@ pub struct Blockchain {
     pub contracts: ContractStateStore,
     /// Wasm bincodes
     pub wasm_bincode: WasmStore,
+    /// Contracts locked against upgrades
+    pub contract_locks: ContractLockStore,
+    /// Deploy call nonces of contracts
+    pub contract_nonces: ContractNonceStore,
+    /// Reversible contract state diffs
+    pub state_diffs: StateDiffStore,
+    /// Last slot with pruned transactions
+    pub pruned: PrunedStore,
+    /// Transaction to slot index
+    pub tx_index: TxIndexStore,
+    /// Nullifier to spending transaction index
+    pub nullifier_index: NullifierIndexStore,
+    /// Coin to minting transaction index
+    pub coin_index: CoinIndexStore,
+    /// Contract events emitted by each transaction
+    pub events: EventStore,
+    /// Database layout version
+    pub version: VersionStore,
+    /// Contract state changes pending to be written along with their blocks
+    pub overlay: StateOverlay,
 }
 
 impl Blockchain {
     /// Instantiate a new `Blockchain` with the given `sled` database.
+    /// Databases left by an interrupted snapshot import are reset first.
     pub fn new(db: &sled::Db, genesis_ts: Timestamp, genesis_data: blake3::Hash) -> Result<Self> {
+        snapshot::clear_interrupted_import(db)?;
+
         let headers = HeaderStore::new(db, genesis_ts, genesis_data)?;
         let blocks = BlockStore::new(db, genesis_ts, genesis_data)?;
         let order = BlockOrderStore::new(db, genesis_ts, genesis_data)?;
@@ -67,8 +132,17 @@ impl Blockchain {
         let transactions = TxStore::new(db)?;
         let contracts = ContractStateStore::new(db)?;
         let wasm_bincode = WasmStore::new(db)?;
+        let contract_locks = ContractLockStore::new(db)?;
+        let contract_nonces = ContractNonceStore::new(db)?;
+        let state_diffs = StateDiffStore::new(db)?;
+        let pruned = PrunedStore::new(db)?;
+        let tx_index = TxIndexStore::new(db)?;
+        let nullifier_index = NullifierIndexStore::new(db)?;
+        let coin_index = CoinIndexStore::new(db)?;
+        let events = EventStore::new(db)?;
+        let version = VersionStore::new(db)?;
 
-        Ok(Self {
+        let blockchain = Self {
             sled_db: db.clone(),
             headers,
             blocks,
@@ -77,37 +151,481 @@ impl Blockchain {
             transactions,
             contracts,
             wasm_bincode,
-        })
+            contract_locks,
+            contract_nonces,
+            state_diffs,
+            pruned,
+            tx_index,
+            nullifier_index,
+            coin_index,
+            events,
+            version,
+            overlay: StateOverlay::default(),
+        };
+
+        blockchain.migrate()?;
+        Ok(blockchain)
+    }
+
+    /// Bring a database written by an older version up to [`DB_VERSION`].
+    /// Migrations run once, and the new version is recorded after they finish,
+    /// so an interrupted migration runs again on the next open.
+    fn migrate(&self) -> Result<()> {
+        let version = self.version.get()?;
+        if version >= DB_VERSION {
+            return Ok(())
+        }
+
+        info!(target: "blockchain", "Migrating database from version {} to {}", version, DB_VERSION);
+        if version < 1 {
+            let removed = self.check_consistency()?;
+            if removed > 0 {
+                warn!(target: "blockchain", "Removed {} partially written records from the database", removed);
+            }
+        }
+
+        self.version.set(DB_VERSION)?;
+        self.sled_db.flush()?;
+        Ok(())
     }
 
     /// Insert a given slice of [`BlockInfo`] into the blockchain database.
     /// This functions wraps all the logic of separating the block into specific
     /// data that can be fed into the different trees of the database.
+    /// The blocks are written in a single sled transaction, along with the
+    /// contract state changes pending in the [`StateOverlay`], so either all
+    /// of them end up in the database, or none. On failure, the pending state
+    /// changes are dropped.
+    /// The inverse of the state changes is stored as a [`StateDiff`], so they
+    /// can be undone with [`Blockchain::rollback_to`]. Blocks given together
+    /// share a single diff, so they can only be rolled back together. Diffs of
+    /// the blocks becoming final, [`FINALITY_DEPTH`] slots behind the last
+    /// one, are dropped in the same transaction.
+    /// If the indexes are enabled, the transactions get indexed by slot, and
+    /// the index writes staged with [`Blockchain::stage_tx_indexes`] are
+    /// written along with the blocks as well.
     /// Upon success, the functions returns a vector of the block hashes that
     /// were given and appended to the ledger.
     pub fn add(&self, blocks: &[BlockInfo]) -> Result<Vec<blake3::Hash>> {
-        let mut ret = Vec::with_capacity(blocks.len());
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else { return Ok(vec![]) };
+        let (first_slot, last_slot) = (first.header.slot, last.header.slot);
 
-        // TODO: Make db writes here completely atomic
+        let mut txs = vec![];
+        let mut headers = Vec::with_capacity(blocks.len());
+        let mut blks = Vec::with_capacity(blocks.len());
+        let mut slots = Vec::with_capacity(blocks.len());
         for block in blocks {
-            // Store transactions
-            self.transactions.insert(&block.txs)?;
+            txs.extend_from_slice(&block.txs);
+            headers.push(block.header.clone());
+            blks.push(Block::from(block.clone()));
+            slots.push(block.header.slot);
+        }
+
+        let (_, txs_batch) = self.transactions.insert_batch(&txs);
+        let (_, headers_batch) = self.headers.insert_batch(&headers);
+        let (ret, blocks_batch) = self.blocks.insert_batch(&blks);
+        let order_batch = self.order.insert_batch(&slots, &ret);
+
+        // Index writes go through the overlay, so they get undone along with
+        // the contract state changes on rollback.
+        if self.tx_index.indexed_from()?.is_some() {
+            for (block, slot) in blks.iter().zip(slots.iter()) {
+                self.overlay
+                    .insert(&self.tx_index.0, self.tx_index.insert_writes(&block.txs, *slot));
+            }
+        }
+
+        // The contract state changes produced by executing the blocks come
+        // first, and the block order last, as it's what links the blocks
+        // into the chain.
+        let state = self.overlay.take();
+        let mut trees: Vec<sled::Tree> = state.iter().map(|(tree, _)| tree.clone()).collect();
+        let mut batches: Vec<sled::Batch> = state.iter().map(|(_, w)| writes_batch(w)).collect();
+        trees.extend([
+            self.transactions.0.clone(),
+            self.headers.0.clone(),
+            self.blocks.0.clone(),
+            self.order.0.clone(),
+        ]);
+        batches.extend([txs_batch, headers_batch, blocks_batch, order_batch]);
+
+        // Diffs of final blocks won't be needed anymore
+        let final_slot = last_slot.saturating_sub(FINALITY_DEPTH);
+        let mut pruned_batch = sled::Batch::default();
+        let mut diffs_batch = sled::Batch::default();
+        if final_slot > self.pruned.get_diffs()?.unwrap_or(0) {
+            pruned_batch = self.pruned.insert_diffs_batch(final_slot);
+            diffs_batch = self.state_diffs.remove_until_batch(final_slot)?;
+        }
+        trees.extend([self.pruned.0.clone(), self.state_diffs.0.clone()]);
+        batches.extend([pruned_batch, diffs_batch]);
+
+        let result = trees.as_slice().transaction(|trees| {
+            // Record the previous values of the changed keys, so the state
+            // changes can be undone
+            let mut diff = StateDiff { first_slot, trees: Vec::with_capacity(state.len()) };
+            for (tree, (sled_tree, writes)) in trees.iter().zip(state.iter()) {
+                let mut previous = Vec::with_capacity(writes.len());
+                for key in writes.keys() {
+                    previous.push((key.clone(), tree.get(key)?.map(|v| v.to_vec())));
+                }
+                diff.trees.push((sled_tree.name().to_vec(), previous));
+            }
 
-            // Store header
-            self.headers.insert(&[block.header.clone()])?;
+            for (tree, batch) in trees.iter().zip(batches.iter()) {
+                tree.apply_batch(batch)?;
+                #[cfg(test)]
+                if tests::fail_after_write() {
+                    let e = Error::Custom("Injected failure".to_string());
+                    return Err(ConflictableTransactionError::Abort(e))
+                }
+            }
 
-            // Store block
-            let blk: Block = Block::from(block.clone());
-            let blockhash = self.blocks.insert(&[blk])?;
-            ret.push(blockhash[0]);
+            if !diff.trees.is_empty() {
+                trees.last().unwrap().insert(&last_slot.to_be_bytes(), serialize(&diff))?;
+            }
 
-            // Store block order
-            self.order.insert(&[block.header.slot], &[blockhash[0]])?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Abort(e)) => return Err(e),
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
         }
 
+        self.sled_db.flush()?;
         Ok(ret)
     }
 
+    /// Enable the secondary indexes of transactions, nullifiers and coins.
+    /// Only the blocks added from then on get indexed, so lookups missing in
+    /// the indexes can't tell apart older records from nonexistent ones, unless
+    /// they're kept from genesis. Once enabled, the indexes are kept up to date
+    /// until the database is removed.
+    /// Returns the slot from which the indexes are kept.
+    pub fn enable_indexes(&self) -> Result<u64> {
+        let slot = if self.len() <= 1 { 0 } else { self.last()?.0 + 1 };
+        self.tx_index.enable(slot)?;
+        self.sled_db.flush()?;
+        Ok(self.tx_index.indexed_from()?.unwrap())
+    }
+
+    /// Stage the index writes for the nullifiers revealed and the coins
+    /// minted by each call of the given transaction, so they get written
+    /// along with its block in [`Blockchain::add`]. Does nothing if the
+    /// indexes are disabled.
+    pub fn stage_tx_indexes(
+        &self,
+        tx: &blake3::Hash,
+        nullifiers: &[Nullifier],
+        coins: &[Vec<Coin>],
+    ) -> Result<()> {
+        if self.tx_index.indexed_from()?.is_none() {
+            return Ok(())
+        }
+
+        self.overlay
+            .insert(&self.nullifier_index.0, self.nullifier_index.insert_writes(tx, nullifiers));
+        self.overlay.insert(&self.coin_index.0, self.coin_index.insert_writes(tx, coins));
+        Ok(())
+    }
+
+    /// Stage the events emitted by the given transaction, so they get written
+    /// along with its block in [`Blockchain::add`].
+    pub fn stage_tx_events(&self, tx: &blake3::Hash, events: &[ContractEvent]) {
+        if events.is_empty() {
+            return
+        }
+
+        self.overlay.insert(&self.events.0, self.events.insert_writes(tx, events));
+    }
+
+    /// Retrieve the [`StateDiff`]s undoing the contract state changes of the
+    /// blocks after the given slot, ordered from the newest to the oldest.
+    /// Fails if the blockchain can't be rolled back to the slot, because its
+    /// block was added along with later ones, or the diffs after it were
+    /// dropped as final or pruned.
+    pub fn get_rollback_diffs(&self, slot: u64) -> Result<Vec<(u64, StateDiff)>> {
+        let dropped = self.pruned.get_diffs()?.max(self.pruned.get()?);
+        if dropped.map_or(false, |dropped| dropped > slot) {
+            return Err(Error::RollbackNotPossible(slot))
+        }
+
+        let diffs = self.state_diffs.get_after(slot)?;
+        if let Some((_, diff)) = diffs.last() {
+            if diff.first_slot <= slot {
+                return Err(Error::RollbackNotPossible(slot))
+            }
+        }
+
+        Ok(diffs)
+    }
+
+    /// Roll the blockchain back to the given slot, removing all the blocks
+    /// and slot checkpoints after it and undoing the contract state changes
+    /// applied with them, in a single sled transaction. Contract state changes
+    /// pending in the [`StateOverlay`] are dropped, as they were executed on
+    /// top of the state being rolled back. Blocks older than
+    /// [`FINALITY_DEPTH`] slots, or pruned ones, can't be rolled back.
+    /// Returns the hashes of the removed blocks, ordered by slot.
+    pub fn rollback_to(&self, slot: u64) -> Result<Vec<blake3::Hash>> {
+        info!(target: "blockchain", "Rolling back to slot {}", slot);
+        self.overlay.clear();
+
+        // Diffs are ordered from the newest to the oldest, so applying them in
+        // order leaves each key with its value from before the oldest change.
+        let diffs = self.get_rollback_diffs(slot)?;
+        let checkpoints_batch = self.slot_checkpoints.remove_after_batch(slot)?;
+
+        let range = (Bound::Excluded(slot.to_be_bytes()), Bound::Unbounded);
+        let mut order_keys = vec![];
+        let mut hashes = vec![];
+        for record in self.order.0.range(range) {
+            let (key, value) = record?;
+            let hash_bytes: [u8; 32] = value.as_ref().try_into().unwrap();
+            order_keys.push(key);
+            hashes.push(blake3::Hash::from(hash_bytes));
+        }
+
+        let mut headers = vec![];
+        let mut txs = vec![];
+        for block in self.blocks.get(&hashes, false)?.into_iter().flatten() {
+            headers.push(block.header);
+            txs.extend(block.txs);
+        }
+
+        // Contract state trees touched by the diffs, by name
+        let mut names: Vec<&Vec<u8>> = vec![];
+        for (_, diff) in &diffs {
+            for (name, _) in &diff.trees {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        let mut trees = vec![
+            self.order.0.clone(),
+            self.blocks.0.clone(),
+            self.headers.0.clone(),
+            self.transactions.0.clone(),
+            self.state_diffs.0.clone(),
+            self.slot_checkpoints.0.clone(),
+        ];
+        for name in &names {
+            trees.push(self.sled_db.open_tree(name)?);
+        }
+
+        let result: std::result::Result<(), TransactionError<Error>> =
+            trees.as_slice().transaction(|trees| {
+                for key in &order_keys {
+                    trees[0].remove(key)?;
+                }
+                for hash in &hashes {
+                    trees[1].remove(hash.as_bytes())?;
+                }
+                for hash in &headers {
+                    trees[2].remove(hash.as_bytes())?;
+                }
+                for hash in &txs {
+                    trees[3].remove(hash.as_bytes())?;
+                }
+
+                trees[5].apply_batch(&checkpoints_batch)?;
+
+                for (diff_slot, diff) in &diffs {
+                    trees[4].remove(&diff_slot.to_be_bytes())?;
+                    for (name, previous) in &diff.trees {
+                        let tree = &trees[6 + names.iter().position(|x| *x == name).unwrap()];
+                        for (key, value) in previous {
+                            match value {
+                                Some(value) => tree.insert(key.as_slice(), value.as_slice())?,
+                                None => tree.remove(key.as_slice())?,
+                            };
+                        }
+                    }
+                }
+
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Abort(e)) => return Err(e),
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+        }
+
+        self.sled_db.flush()?;
+        info!(target: "blockchain", "Rolled back {} blocks and {} state diffs", hashes.len(), diffs.len());
+        Ok(hashes)
+    }
+
+    /// Prune the transactions of the blocks older than the last `keep` slots,
+    /// to save disk space. Headers, blocks, slot checkpoints and contract
+    /// states are kept, so the chain can still be followed and verified, but
+    /// pruned blocks can't be served anymore: fetching them fails with
+    /// [`Error::BlockPruned`]. Their state diffs are dropped as well, so they
+    /// can't be rolled back.
+    /// Returns the number of pruned transactions.
+    pub fn prune(&self, keep: u64) -> Result<usize> {
+        let (last, _) = self.last()?;
+        let Some(prune_to) = last.checked_sub(keep).filter(|x| *x > 0) else { return Ok(0) };
+
+        let start = match self.pruned.get()? {
+            Some(pruned) if pruned >= prune_to => return Ok(0),
+            Some(pruned) => Bound::Excluded(pruned.to_be_bytes()),
+            None => Bound::Unbounded,
+        };
+
+        let mut hashes = vec![];
+        for record in self.order.0.range((start, Bound::Included(prune_to.to_be_bytes()))) {
+            let (_, value) = record?;
+            let hash_bytes: [u8; 32] = value.as_ref().try_into().unwrap();
+            hashes.push(blake3::Hash::from(hash_bytes));
+        }
+
+        let mut txs_batch = sled::Batch::default();
+        let mut pruned = 0;
+        for block in self.blocks.get(&hashes, true)?.into_iter().flatten() {
+            for tx in block.txs {
+                txs_batch.remove(tx.as_bytes());
+                pruned += 1;
+            }
+        }
+
+        let mut pruned_batches = vec![self.pruned.insert_batch(prune_to)];
+        let mut diffs_batch = sled::Batch::default();
+        if prune_to > self.pruned.get_diffs()?.unwrap_or(0) {
+            pruned_batches.push(self.pruned.insert_diffs_batch(prune_to));
+            diffs_batch = self.state_diffs.remove_until_batch(prune_to)?;
+        }
+
+        let trees =
+            [self.transactions.0.clone(), self.pruned.0.clone(), self.state_diffs.0.clone()];
+        let result: std::result::Result<(), TransactionError<Error>> =
+            trees.as_slice().transaction(|trees| {
+                trees[0].apply_batch(&txs_batch)?;
+                for batch in &pruned_batches {
+                    trees[1].apply_batch(batch)?;
+                }
+                trees[2].apply_batch(&diffs_batch)?;
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Abort(e)) => return Err(e),
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+        }
+
+        self.sled_db.flush()?;
+        debug!(target: "blockchain", "Pruned {} transactions up to slot {}", pruned, prune_to);
+        Ok(pruned)
+    }
+
+    /// Check that the block trees are consistent with each other, and repair
+    /// them otherwise. Databases written before block insertion was atomic
+    /// may contain partially written blocks, in case the node was stopped in
+    /// the middle of `add()`. These are found by walking the block order, and
+    /// get removed along with all the blocks ordered after them. Headers,
+    /// blocks and transactions not referenced by any ordered block are
+    /// leftovers of such writes, so they're removed as well.
+    /// This scans the whole database, so it's only run once when migrating
+    /// databases written by older versions. It can be run again by hand to
+    /// repair a database.
+    /// Returns the number of removed records.
+    pub fn check_consistency(&self) -> Result<usize> {
+        let mut headers = HashSet::new();
+        let mut blocks = HashSet::new();
+        let mut txs = HashSet::new();
+
+        let mut order_batch = sled::Batch::default();
+        let mut removed = 0;
+        let mut truncate = false;
+
+        for record in self.order.0.iter() {
+            let (key, value) = record?;
+
+            if !truncate {
+                let slot_bytes: [u8; 8] = key.as_ref().try_into().unwrap();
+                let hash_bytes: [u8; 32] = value.as_ref().try_into().unwrap();
+                let slot = u64::from_be_bytes(slot_bytes);
+                let blockhash = blake3::Hash::from(hash_bytes);
+
+                if let Some(block) = self.complete_block(slot, &blockhash)? {
+                    headers.insert(block.header);
+                    txs.extend(block.txs);
+                    blocks.insert(blockhash);
+                    continue
+                }
+
+                warn!(target: "blockchain", "Block {} in slot {} is incomplete, truncating the chain", blockhash, slot);
+                truncate = true;
+            }
+
+            order_batch.remove(key);
+            removed += 1;
+        }
+        self.order.0.apply_batch(order_batch)?;
+
+        removed += Self::remove_unreferenced(&self.blocks.0, &blocks)?;
+        removed += Self::remove_unreferenced(&self.headers.0, &headers)?;
+        removed += Self::remove_unreferenced(&self.transactions.0, &txs)?;
+
+        if removed > 0 {
+            self.sled_db.flush()?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Retrieve the [`Block`] ordered in the given slot, if it and all the
+    /// data it points to exist in the database.
+    fn complete_block(&self, slot: u64, blockhash: &blake3::Hash) -> Result<Option<Block>> {
+        let Some(block) = self.blocks.get(&[*blockhash], false)?.remove(0) else { return Ok(None) };
+
+        let Some(header) = self.headers.get(&[block.header], false)?.remove(0) else {
+            return Ok(None)
+        };
+
+        if header.slot != slot {
+            return Ok(None)
+        }
+
+        // Transactions of pruned blocks are expected to be missing
+        if self.pruned.is_pruned(slot)? {
+            return Ok(Some(block))
+        }
+
+        for tx in &block.txs {
+            if !self.transactions.contains(tx)? {
+                return Ok(None)
+            }
+        }
+
+        Ok(Some(block))
+    }
+
+    /// Remove all the keys of the given tree that are not in `referenced`.
+    /// Returns the number of removed keys.
+    fn remove_unreferenced(tree: &sled::Tree, referenced: &HashSet<blake3::Hash>) -> Result<usize> {
+        let mut batch = sled::Batch::default();
+        let mut removed = 0;
+
+        for key in tree.iter().keys() {
+            let key = key?;
+            let hash_bytes: [u8; 32] = key.as_ref().try_into().unwrap();
+            if !referenced.contains(&blake3::Hash::from(hash_bytes)) {
+                batch.remove(key);
+                removed += 1;
+            }
+        }
+
+        tree.apply_batch(batch)?;
+        Ok(removed)
+    }
+
     /// Check if the given [`BlockInfo`] is in the database and all trees.
     pub fn has_block(&self, block: &BlockInfo) -> Result<bool> {
         let blockhash = match self.order.get(&[block.header.slot], true) {
@@ -121,7 +639,8 @@ impl Blockchain {
         Ok(blockhash == block.blockhash())
     }
 
-    /// Retrieve [`BlockInfo`]s by given hashes. Fails if any of them are not found.
+    /// Retrieve [`BlockInfo`]s by given hashes. Fails if any of them are not found,
+    /// or with [`Error::BlockPruned`] if the transactions of any of them were pruned.
     pub fn get_blocks_by_hash(&self, hashes: &[blake3::Hash]) -> Result<Vec<BlockInfo>> {
         let mut ret = Vec::with_capacity(hashes.len());
 
@@ -134,6 +653,10 @@ impl Blockchain {
             // Since we used strict get, its safe to unwrap here
             let header = headers[0].clone().unwrap();
 
+            if !block.txs.is_empty() && self.pruned.is_pruned(header.slot)? {
+                return Err(Error::BlockPruned(header.slot))
+            }
+
             let txs = self.transactions.get(&block.txs, true)?;
             let txs = txs.iter().map(|x| x.clone().unwrap()).collect();
 
@@ -164,6 +687,56 @@ impl Blockchain {
         self.get_blocks_by_hash(&hashes)
     }
 
+    /// Retrieve the headers and [`Block`]s of n blocks after given start slot.
+    pub fn get_headers_after(&self, slot: u64, n: u64) -> Result<(Vec<Header>, Vec<Block>)> {
+        debug!(target: "blockchain", "get_headers_after(): {} -> {}", slot, n);
+        let hashes = self.order.get_after(slot, n)?;
+        let blocks: Vec<Block> =
+            self.blocks.get(&hashes, true)?.into_iter().map(|x| x.unwrap()).collect();
+
+        let headerhashes: Vec<blake3::Hash> = blocks.iter().map(|x| x.header).collect();
+        let headers = self.headers.get(&headerhashes, true)?.into_iter().map(|x| x.unwrap());
+
+        Ok((headers.collect(), blocks))
+    }
+
+    /// Retrieve the events emitted by the transactions of the block in the
+    /// given slot, along with the hash of the transaction emitting each of
+    /// them, in block order. Only the transaction hashes of the block are
+    /// needed, so events can be retrieved for pruned blocks as well.
+    pub fn get_events_by_slot(&self, slot: u64) -> Result<Vec<(blake3::Hash, ContractEvent)>> {
+        let Some(blockhash) = self.order.get(&[slot], false)?.remove(0) else {
+            return Err(Error::SlotNotFound(slot))
+        };
+        let block = self.blocks.get(&[blockhash], true)?.remove(0).unwrap();
+
+        let mut ret = vec![];
+        for tx_hash in block.txs {
+            ret.extend(self.events.get(&tx_hash)?.into_iter().map(|event| (tx_hash, event)));
+        }
+
+        Ok(ret)
+    }
+
+    /// Build the proof that the transaction with the given hash is included
+    /// in the block of the given slot. Only the transaction hashes of the block
+    /// are needed, so proofs can be built for pruned blocks as well.
+    /// Returns `None` if the block doesn't include the transaction.
+    pub fn get_tx_inclusion_proof(
+        &self,
+        slot: u64,
+        tx_hash: &blake3::Hash,
+    ) -> Result<Option<TxInclusionProof>> {
+        let Some(blockhash) = self.order.get(&[slot], false)?.remove(0) else {
+            return Err(Error::SlotNotFound(slot))
+        };
+        let block = self.blocks.get(&[blockhash], true)?.remove(0).unwrap();
+        let header = self.headers.get(&[block.header], true)?.remove(0).unwrap();
+
+        let Some(position) = block.txs.iter().position(|x| x == tx_hash) else { return Ok(None) };
+        Ok(TxInclusionProof::new(header, &block.txs, position))
+    }
+
     /// Retrieve stored blocks count
     pub fn len(&self) -> usize {
         self.order.len()
@@ -178,6 +751,15 @@ impl Blockchain {
         self.order.get_last()
     }
 
+    /// Retrieve the header of the last block.
+    pub fn last_header(&self) -> Result<Header> {
+        let (_, hash) = self.last()?;
+        // Since we used strict get, its safe to unwrap here
+        let block = self.blocks.get(&[hash], true)?[0].clone().unwrap();
+        let header = self.headers.get(&[block.header], true)?[0].clone().unwrap();
+        Ok(header)
+    }
+
     /// Retrieve the last slot checkpoint.
     pub fn last_slot_checkpoint(&self) -> Result<SlotCheckpoint> {
         self.slot_checkpoints.get_last()
@@ -217,3 +799,509 @@ impl Blockchain {
         Ok(!vec.is_empty())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::Cell;
+
+    use darkfi_sdk::{crypto::ContractId, pasta::pallas, tx::ContractCall};
+
+    use super::*;
+    use crate::{
+        consensus::{verify_tx_inclusion, Header, LeadInfo},
+        tx::Transaction,
+    };
+
+    thread_local! {
+        /// Number of trees [`Blockchain::add`] writes before the one after
+        /// which it aborts its transaction, to inject failures.
+        static FAIL_AFTER: Cell<Option<usize>> = Cell::new(None);
+    }
+
+    /// Count a tree written by [`Blockchain::add`], returning `true` if its
+    /// transaction must be aborted after it.
+    pub(super) fn fail_after_write() -> bool {
+        FAIL_AFTER.with(|x| match x.get() {
+            Some(0) => {
+                x.set(None);
+                true
+            }
+            Some(n) => {
+                x.set(Some(n - 1));
+                false
+            }
+            None => false,
+        })
+    }
+
+    /// Open a [`Blockchain`] on a temporary database, with a fixed genesis
+    /// block so blockchains opened separately share it.
+    pub(crate) fn test_blockchain() -> Result<Blockchain> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Blockchain::new(&db, Timestamp(0), blake3::hash(b"genesis"))
+    }
+
+    fn test_block(slot: u64) -> BlockInfo {
+        let header = Header { slot, ..Header::default() };
+        let call = ContractCall {
+            contract_id: ContractId::from(pallas::Base::from(slot)),
+            data: slot.to_be_bytes().to_vec(),
+        };
+        let tx = Transaction { calls: vec![call], proofs: vec![vec![]], signatures: vec![vec![]] };
+        BlockInfo::new(header, vec![tx], LeadInfo::default())
+    }
+
+    fn stage_state(blockchain: &Blockchain, state: &sled::Tree) {
+        let mut writes = TreeWrites::new();
+        writes.insert(b"key".to_vec(), Some(b"value".to_vec()));
+        blockchain.overlay.insert(state, writes);
+    }
+
+    #[test]
+    fn add_blocks_atomically() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let db = blockchain.sled_db.clone();
+        let state = db.open_tree("contract_state")?;
+        let blocks = vec![test_block(1), test_block(2)];
+        let txs: Vec<blake3::Hash> = Block::from(blocks[0].clone()).txs;
+
+        // The state tree and the 4 block trees, fail after writing each of them
+        for step in 0..5 {
+            stage_state(&blockchain, &state);
+            FAIL_AFTER.with(|x| x.set(Some(step)));
+            assert!(blockchain.add(&blocks).is_err());
+
+            // Nothing must have been written, and the state changes are dropped
+            assert!(blockchain.overlay.is_empty());
+            assert!(state.is_empty());
+            assert_eq!(blockchain.len(), 1);
+            assert!(!blockchain.has_slot(1)?);
+            assert!(!blockchain.blocks.contains(&blocks[0].blockhash())?);
+            assert!(!blockchain.headers.contains(&blocks[0].header.headerhash())?);
+            assert!(!blockchain.transactions.contains(&txs[0])?);
+        }
+
+        stage_state(&blockchain, &state);
+        let hashes = blockchain.add(&blocks)?;
+        assert!(blockchain.overlay.is_empty());
+        assert_eq!(state.get(b"key")?.unwrap().as_ref(), b"value");
+        assert_eq!(blockchain.len(), 3);
+        assert_eq!(blockchain.last()?, (2, hashes[1]));
+        assert_eq!(blockchain.last_header()?, blocks[1].header);
+        assert_eq!(blockchain.get_blocks_by_hash(&hashes)?.len(), 2);
+        assert_eq!(blockchain.check_consistency()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlay_reads_pending_writes() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let db = blockchain.sled_db.clone();
+        let state = db.open_tree("contract_state")?;
+        state.insert(b"removed", b"value")?;
+
+        let mut writes = TreeWrites::new();
+        writes.insert(b"key".to_vec(), Some(b"value".to_vec()));
+        writes.insert(b"removed".to_vec(), None);
+        blockchain.overlay.insert(&state, writes);
+
+        assert_eq!(blockchain.overlay.get(&state, b"key")?, Some(b"value".to_vec()));
+        assert!(blockchain.overlay.contains_key(&state, b"key")?);
+        assert!(!blockchain.overlay.contains_key(&state, b"removed")?);
+        assert!(state.contains_key(b"removed")?);
+        assert!(!state.contains_key(b"key")?);
+
+        blockchain.add(&[test_block(1)])?;
+        assert!(state.contains_key(b"key")?);
+        assert!(!state.contains_key(b"removed")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlay_ranges_over_pending_writes() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let db = blockchain.sled_db.clone();
+        let state = db.open_tree("contract_state")?;
+        state.insert(b"a", b"1")?;
+        state.insert(b"c", b"3")?;
+        state.insert(b"d", b"4")?;
+
+        let mut writes = TreeWrites::new();
+        writes.insert(b"b".to_vec(), Some(b"2".to_vec()));
+        writes.insert(b"c".to_vec(), None);
+        writes.insert(b"d".to_vec(), Some(b"5".to_vec()));
+        writes.insert(b"e".to_vec(), Some(b"6".to_vec()));
+        blockchain.overlay.insert(&state, writes);
+
+        let record = |key: &[u8], value: &[u8]| (key.to_vec(), value.to_vec());
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        assert_eq!(
+            blockchain.overlay.range(&state, all.clone(), 10)?,
+            vec![record(b"a", b"1"), record(b"b", b"2"), record(b"d", b"5"), record(b"e", b"6")]
+        );
+        assert_eq!(
+            blockchain.overlay.range(&state, all, 2)?,
+            vec![record(b"a", b"1"), record(b"b", b"2")]
+        );
+
+        let after_b = (Bound::Excluded(b"b".to_vec()), Bound::Excluded(b"e".to_vec()));
+        assert_eq!(blockchain.overlay.range(&state, after_b, 10)?, vec![record(b"d", b"5")]);
+
+        let inverted = (Bound::Excluded(b"e".to_vec()), Bound::Excluded(b"e".to_vec()));
+        assert!(blockchain.overlay.range(&state, inverted, 10)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn repair_partial_writes() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let (db, genesis_data) = (blockchain.sled_db.clone(), blake3::hash(b"genesis"));
+        blockchain.add(&[test_block(1)])?;
+
+        // Replay the non-atomic writes of a block, interrupted after each tree
+        for step in 1..=4 {
+            let block = test_block(2);
+            let blk = Block::from(block.clone());
+            if step >= 1 {
+                blockchain.transactions.insert(&block.txs)?;
+            }
+            if step >= 2 {
+                blockchain.headers.insert(&[block.header.clone()])?;
+            }
+            if step >= 3 {
+                blockchain.blocks.insert(&[blk.clone()])?;
+            }
+            if step >= 4 {
+                // The block order gets written, but a transaction is lost
+                blockchain.order.insert(&[2], &[blk.blockhash()])?;
+                blockchain.transactions.0.remove(blk.txs[0].as_bytes())?;
+            }
+
+            // Databases of the current version are opened as they are
+            let reopened = Blockchain::new(&db, Timestamp(0), genesis_data)?;
+            assert_eq!(reopened.transactions.contains(&blk.txs[0])?, step < 4);
+
+            // Reopening a database of an older version repairs the trees once
+            blockchain.version.set(0)?;
+            let blockchain = Blockchain::new(&db, Timestamp(0), genesis_data)?;
+            assert_eq!(blockchain.version.get()?, DB_VERSION);
+            assert_eq!(blockchain.check_consistency()?, 0);
+            assert_eq!(blockchain.len(), 2);
+            assert_eq!(blockchain.last()?.0, 1);
+            assert!(!blockchain.blocks.contains(&blk.blockhash())?);
+            assert!(!blockchain.headers.contains(&blk.header)?);
+            assert!(!blockchain.transactions.contains(&blk.txs[0])?);
+            assert_eq!(blockchain.get_blocks_after(0, 10)?.len(), 1);
+        }
+
+        // A block ordered after an incomplete one gets removed as well
+        let block = test_block(2);
+        let blk = Block::from(block.clone());
+        blockchain.headers.insert(&[block.header.clone()])?;
+        blockchain.blocks.insert(&[blk.clone()])?;
+        blockchain.order.insert(&[2], &[blk.blockhash()])?;
+        blockchain.add(&[test_block(3)])?;
+        assert_eq!(blockchain.check_consistency()?, 7);
+        assert_eq!(blockchain.len(), 2);
+        assert_eq!(blockchain.last()?.0, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune_transactions() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let mut hashes = vec![];
+        for slot in 1..=4 {
+            hashes.extend(blockchain.add(&[test_block(slot)])?);
+        }
+
+        assert_eq!(blockchain.prune(4)?, 0);
+        assert_eq!(blockchain.pruned.get()?, None);
+
+        // Blocks 1 and 2 are older than the last 2 slots
+        assert_eq!(blockchain.prune(2)?, 2);
+        assert_eq!(blockchain.pruned.get()?, Some(2));
+        assert_eq!(blockchain.transactions.0.len(), 2);
+        assert!(matches!(blockchain.get_blocks_by_hash(&hashes[..1]), Err(Error::BlockPruned(1))));
+        assert!(matches!(blockchain.get_blocks_after(0, 10), Err(Error::BlockPruned(1))));
+        assert_eq!(blockchain.get_blocks_by_hash(&hashes[2..])?.len(), 2);
+        assert_eq!(blockchain.get_headers_after(0, 10)?.0.len(), 4);
+        assert_eq!(blockchain.prune(2)?, 0);
+
+        // Pruned blocks are still part of a consistent chain
+        assert_eq!(blockchain.check_consistency()?, 0);
+        assert_eq!(blockchain.len(), 5);
+
+        // Pruned blocks can't be rolled back, but the ones after them can
+        assert!(matches!(blockchain.rollback_to(1), Err(Error::RollbackNotPossible(1))));
+        assert_eq!(blockchain.pruned.get_diffs()?, Some(2));
+        assert_eq!(blockchain.rollback_to(2)?, hashes[2..]);
+        let hashes = blockchain.add(&[test_block(3)])?;
+        assert_eq!(blockchain.get_blocks_by_hash(&hashes)?[0].txs.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_transactions() -> Result<()> {
+        let blockchain = test_blockchain()?;
+
+        // Blocks added before enabling the indexes don't get indexed
+        let unindexed = blockchain.add(&[test_block(1)])?;
+        let unindexed = blockchain.blocks.get(&unindexed, true)?[0].clone().unwrap().txs[0];
+        assert_eq!(blockchain.enable_indexes()?, 2);
+        assert_eq!(blockchain.enable_indexes()?, 2);
+
+        let nullifier = Nullifier::from(pallas::Base::from(2));
+        let coins = [Coin::from(pallas::Base::from(3)), Coin::from(pallas::Base::from(4))];
+        let block = test_block(2);
+        let tx = Block::from(block.clone()).txs[0];
+        blockchain.stage_tx_indexes(
+            &tx,
+            &[nullifier],
+            &[vec![coins[0]], vec![], vec![coins[1]]],
+        )?;
+        blockchain.add(&[block])?;
+
+        assert_eq!(blockchain.tx_index.get(&unindexed)?, None);
+        assert_eq!(blockchain.tx_index.get(&tx)?, Some(2));
+        assert_eq!(blockchain.nullifier_index.get(&nullifier)?, Some(tx));
+        assert_eq!(blockchain.coin_index.get(&coins[0])?, Some((tx, 0, 0)));
+        assert_eq!(blockchain.coin_index.get(&coins[1])?, Some((tx, 2, 0)));
+
+        // Rolled back blocks get removed from the indexes
+        blockchain.rollback_to(1)?;
+        assert_eq!(blockchain.tx_index.get(&tx)?, None);
+        assert_eq!(blockchain.nullifier_index.get(&nullifier)?, None);
+        assert_eq!(blockchain.coin_index.get(&coins[0])?, None);
+        assert_eq!(blockchain.tx_index.indexed_from()?, Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_tx_events() -> Result<()> {
+        let blockchain = test_blockchain()?;
+
+        let event = ContractEvent {
+            contract_id: ContractId::from(pallas::Base::from(1)),
+            topic: "transfer".to_string(),
+            data: vec![1, 2, 3],
+        };
+        let events = vec![event.clone(), ContractEvent { topic: "mint".to_string(), ..event }];
+
+        let block = test_block(1);
+        let tx = Block::from(block.clone()).txs[0];
+        blockchain.stage_tx_events(&tx, &events);
+        blockchain.add(&[block])?;
+        assert_eq!(blockchain.events.get(&tx)?, events);
+        let by_slot: Vec<_> = events.iter().map(|event| (tx, event.clone())).collect();
+        assert_eq!(blockchain.get_events_by_slot(1)?, by_slot);
+
+        // Rolled back blocks get their events removed
+        blockchain.rollback_to(0)?;
+        assert!(blockchain.events.get(&tx)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tx_inclusion_proofs() -> Result<()> {
+        let blockchain = test_blockchain()?;
+
+        // Blocks get linked the way proposals are, to the last block hash
+        let mut chain = vec![];
+        for slot in 1..=3 {
+            let mut block = test_block(slot);
+            block.header.previous = blockchain.last()?.1;
+            block.header.root = block.txs_root();
+            blockchain.add(&[block.clone()])?;
+            chain.push(block);
+        }
+        let block = &chain[0];
+
+        let tx = Block::from(block.clone()).txs[0];
+        let proof = blockchain.get_tx_inclusion_proof(1, &tx)?.unwrap();
+        assert_eq!(proof.header, block.header);
+        assert!(proof.verify(&tx));
+
+        // The proof verifies against the stored chain up to any later header
+        let (headers, blocks) = blockchain.get_headers_after(0, 10)?;
+        assert_eq!(headers, chain.iter().map(|x| x.header.clone()).collect::<Vec<_>>());
+        for i in 0..headers.len() {
+            let anchor = headers[i].headerhash();
+            assert!(verify_tx_inclusion(&tx, &proof, &headers[..=i], &blocks[..=i], &anchor));
+        }
+
+        // But not when anchored to a header other than the chain's last one
+        let anchor = headers[1].headerhash();
+        assert!(!verify_tx_inclusion(&tx, &proof, &headers, &blocks, &anchor));
+
+        assert!(blockchain.get_tx_inclusion_proof(1, &blake3::hash(b"other"))?.is_none());
+        assert!(matches!(blockchain.get_tx_inclusion_proof(2, &tx), Err(Error::SlotNotFound(2))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stage_contract_deployment() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let db = blockchain.sled_db.clone();
+        let contract_id = ContractId::from(pallas::Base::from(42));
+        let overlay = &blockchain.overlay;
+
+        // Trees initialized by a pending deployment can be looked up through
+        // the overlay only, and can't be initialized twice
+        let mut pointers = TreeWrites::new();
+        let (_, writes) =
+            blockchain.contracts.init_writes(&db, overlay, &pointers, &contract_id, "a")?;
+        pointers.extend(writes);
+        let (_, writes) =
+            blockchain.contracts.init_writes(&db, overlay, &pointers, &contract_id, "b")?;
+        pointers.extend(writes);
+        assert!(matches!(
+            blockchain.contracts.init_writes(&db, overlay, &pointers, &contract_id, "a"),
+            Err(Error::ContractAlreadyInitialized)
+        ));
+        assert!(blockchain
+            .contracts
+            .lookup_pending(&db, overlay, &pointers, &contract_id, "b")
+            .is_ok());
+
+        overlay.insert(&blockchain.contracts.0, pointers);
+        overlay.insert(
+            &blockchain.wasm_bincode.0,
+            blockchain.wasm_bincode.insert_writes(contract_id, b"wasm"),
+        );
+        overlay.insert(
+            &blockchain.contract_locks.0,
+            blockchain.contract_locks.insert_writes(contract_id),
+        );
+        overlay.insert(
+            &blockchain.contract_nonces.0,
+            blockchain.contract_nonces.insert_writes(contract_id, 1),
+        );
+
+        let empty = TreeWrites::new();
+        assert!(blockchain
+            .contracts
+            .lookup_pending(&db, overlay, &empty, &contract_id, "a")
+            .is_ok());
+        assert!(blockchain.contracts.lookup(&db, &contract_id, "a").is_err());
+        assert_eq!(blockchain.wasm_bincode.get_pending(overlay, contract_id)?, b"wasm");
+        assert!(blockchain.wasm_bincode.get(contract_id).is_err());
+        assert!(blockchain.contract_locks.is_locked(overlay, contract_id)?);
+        assert_eq!(blockchain.contract_nonces.get_pending(overlay, contract_id)?, 1);
+        assert_eq!(blockchain.contract_nonces.get(contract_id)?, 0);
+
+        // The deployment gets written along with its block, and undone with it
+        blockchain.add(&[test_block(1)])?;
+        assert!(blockchain.contracts.lookup(&db, &contract_id, "a").is_ok());
+        assert!(blockchain.contracts.lookup(&db, &contract_id, "b").is_ok());
+        assert_eq!(blockchain.wasm_bincode.get(contract_id)?, b"wasm");
+        assert_eq!(blockchain.contract_nonces.get(contract_id)?, 1);
+
+        blockchain.rollback_to(0)?;
+        assert!(blockchain.contracts.lookup(&db, &contract_id, "a").is_err());
+        assert!(blockchain.wasm_bincode.get(contract_id).is_err());
+        assert!(!blockchain.contract_locks.is_locked(overlay, contract_id)?);
+        assert_eq!(blockchain.contract_nonces.get(contract_id)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_blocks_and_state() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let db = blockchain.sled_db.clone();
+        let state = db.open_tree("contract_state")?;
+        state.insert(b"a", b"0")?;
+        state.insert(b"b", b"0")?;
+
+        // Block 1 changes a, block 2 removes b and adds c, block 3 changes a and c
+        let changes: [&[(&str, Option<&str>)]; 3] = [
+            &[("a", Some("1"))],
+            &[("b", None), ("c", Some("2"))],
+            &[("a", Some("3")), ("c", Some("3"))],
+        ];
+
+        let mut hashes = vec![];
+        for (slot, writes) in (1..).zip(changes) {
+            let writes =
+                writes.iter().map(|(k, v)| (k.as_bytes().to_vec(), v.map(|v| v.into()))).collect();
+            blockchain.overlay.insert(&state, writes);
+            hashes.extend(blockchain.add(&[test_block(slot)])?);
+        }
+        assert_eq!(blockchain.state_diffs.len(), 3);
+
+        let value = |key: &[u8]| state.get(key).unwrap().map(|v| v.to_vec());
+
+        assert_eq!(blockchain.rollback_to(1)?, hashes[1..]);
+        assert_eq!(blockchain.last()?, (1, hashes[0]));
+        assert_eq!(value(b"a"), Some(b"1".to_vec()));
+        assert_eq!(value(b"b"), Some(b"0".to_vec()));
+        assert_eq!(value(b"c"), None);
+        assert!(!blockchain.blocks.contains(&hashes[1])?);
+        assert_eq!(blockchain.state_diffs.len(), 1);
+        assert_eq!(blockchain.check_consistency()?, 0);
+
+        assert_eq!(blockchain.rollback_to(0)?, hashes[..1]);
+        assert_eq!(blockchain.len(), 1);
+        assert_eq!(value(b"a"), Some(b"0".to_vec()));
+        assert!(blockchain.state_diffs.is_empty());
+        assert_eq!(blockchain.check_consistency()?, 0);
+
+        // Blocks added together can only be rolled back together
+        blockchain.overlay.insert(&state, [(b"a".to_vec(), Some(b"4".to_vec()))].into());
+        let hashes = blockchain.add(&[test_block(1), test_block(2)])?;
+        assert!(blockchain.rollback_to(1).is_err());
+        assert_eq!(blockchain.last()?, (2, hashes[1]));
+        assert_eq!(blockchain.rollback_to(0)?, hashes);
+        assert_eq!(value(b"a"), Some(b"0".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_slot_checkpoints() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let zero = pallas::Base::from(0);
+        let checkpoints: Vec<SlotCheckpoint> =
+            (1..=3).map(|slot| SlotCheckpoint::new(slot, zero, zero, zero)).collect();
+        blockchain.add_slot_checkpoints(&checkpoints)?;
+        for slot in 1..=3 {
+            blockchain.add(&[test_block(slot)])?;
+        }
+
+        blockchain.rollback_to(1)?;
+        assert_eq!(blockchain.last_slot_checkpoint()?.slot, 1);
+        assert!(!blockchain.slot_checkpoints.contains(2)?);
+        assert!(!blockchain.slot_checkpoints.contains(3)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn drop_final_state_diffs() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let state = blockchain.sled_db.open_tree("contract_state")?;
+
+        for slot in 1..=FINALITY_DEPTH + 2 {
+            stage_state(&blockchain, &state);
+            blockchain.add(&[test_block(slot)])?;
+        }
+
+        // Only the diffs of the last FINALITY_DEPTH blocks are kept
+        assert_eq!(blockchain.state_diffs.len() as u64, FINALITY_DEPTH);
+        assert_eq!(blockchain.pruned.get_diffs()?, Some(2));
+        assert!(matches!(blockchain.rollback_to(1), Err(Error::RollbackNotPossible(1))));
+        assert_eq!(blockchain.rollback_to(2)?.len() as u64, FINALITY_DEPTH);
+        assert!(blockchain.state_diffs.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::Result;
+
+const SLED_PRUNED_TREE: &[u8] = b"_pruned";
+const SLED_PRUNED_SLOT_KEY: &[u8] = b"pruned_slot";
+const SLED_PRUNED_DIFFS_KEY: &[u8] = b"pruned_diffs_slot";
+
+/// The `PrunedStore` is a `sled` tree recording the last slot whose block
+/// transactions were pruned. The transactions of all the blocks up to and
+/// including that slot are deleted from the `TxStore`. It also records the
+/// last slot whose state diffs were dropped from the `StateDiffStore`, as
+/// the blockchain can't be rolled back before it.
+#[derive(Clone)]
+pub struct PrunedStore(pub sled::Tree);
+
+impl PrunedStore {
+    /// Opens a new or existing `PrunedStore` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_PRUNED_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Generate the sled batch recording the given slot as the last pruned
+    /// one, so the caller can apply it along with other writes.
+    pub fn insert_batch(&self, slot: u64) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+        batch.insert(SLED_PRUNED_SLOT_KEY, &slot.to_be_bytes());
+        batch
+    }
+
+    /// Fetch the last slot whose block transactions were pruned, if any.
+    pub fn get(&self) -> Result<Option<u64>> {
+        let Some(found) = self.0.get(SLED_PRUNED_SLOT_KEY)? else { return Ok(None) };
+        let slot_bytes: [u8; 8] = found.as_ref().try_into().unwrap();
+        Ok(Some(u64::from_be_bytes(slot_bytes)))
+    }
+
+    /// Generate the sled batch recording the given slot as the last one whose
+    /// state diffs were dropped, so the caller can apply it along with other
+    /// writes.
+    pub fn insert_diffs_batch(&self, slot: u64) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+        batch.insert(SLED_PRUNED_DIFFS_KEY, &slot.to_be_bytes());
+        batch
+    }
+
+    /// Fetch the last slot whose state diffs were dropped, if any.
+    pub fn get_diffs(&self) -> Result<Option<u64>> {
+        let Some(found) = self.0.get(SLED_PRUNED_DIFFS_KEY)? else { return Ok(None) };
+        let slot_bytes: [u8; 8] = found.as_ref().try_into().unwrap();
+        Ok(Some(u64::from_be_bytes(slot_bytes)))
+    }
+
+    /// Check if the transactions of the block in the given slot were pruned.
+    pub fn is_pruned(&self, slot: u64) -> Result<bool> {
+        Ok(self.get()?.map_or(false, |pruned| slot <= pruned))
+    }
+}
@@ -16,6 +16,8 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::ops::Bound;
+
 use darkfi_serial::{deserialize, serialize};
 
 use crate::{consensus::SlotCheckpoint, Error, Result};
@@ -26,7 +28,7 @@ const SLED_SLOT_CHECKPOINT_TREE: &[u8] = b"_slot_checkpoints";
 /// blockchain's slots, where the key is the slot uid, and the value is
 /// is the serialized checkpoint.
 #[derive(Clone)]
-pub struct SlotCheckpointStore(sled::Tree);
+pub struct SlotCheckpointStore(pub sled::Tree);
 
 impl SlotCheckpointStore {
     /// Opens a new or existing `SlotCheckpointStore` on the given sled database.
@@ -123,6 +125,19 @@ impl SlotCheckpointStore {
         Ok(ret)
     }
 
+    /// Generate the sled batch removing all the slot checkpoints after the
+    /// given slot, so the caller can apply it along with other writes.
+    pub fn remove_after_batch(&self, slot: u64) -> Result<sled::Batch> {
+        let mut batch = sled::Batch::default();
+
+        let range = (Bound::Excluded(slot.to_be_bytes()), Bound::Unbounded);
+        for key in self.0.range(range).keys() {
+            batch.remove(key?);
+        }
+
+        Ok(batch)
+    }
+
     /// Fetch the last slot checkpoint in the tree, based on the `Ord`
     /// implementation for `Vec<u8>`. This should not be able to
     /// fail because we initialize the store with the genesis slot checkpoint.
@@ -0,0 +1,685 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use darkfi_serial::{deserialize, Decodable, Encodable};
+use log::{info, warn};
+
+use super::{Blockchain, StateDiff};
+use crate::{
+    consensus::{Block, Header},
+    Error, Result,
+};
+
+const SNAPSHOT_VERSION: u8 = 2;
+
+/// Name of sled's default tree, which isn't part of the blockchain
+const SLED_DEFAULT_TREE: &[u8] = b"__sled__default";
+
+/// Key of sled's default tree marking a snapshot import in progress
+const SLED_IMPORT_MARKER: &[u8] = b"snapshot_import";
+
+/// Number of records written to the database at once when importing
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+/// Number of times an export is retried when blocks get added meanwhile
+const EXPORT_ATTEMPTS: usize = 3;
+
+/// Records of a sled tree, by key
+type Records = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A `Snapshot` holds the contents of all the sled trees of a [`Blockchain`]
+/// at a given finalized slot, including the contract states, wasm bincodes
+/// and slot checkpoints, so new nodes can start from it instead of executing
+/// every block.
+///
+/// Snapshot files start with the version, slot, block hash and content hash.
+/// Each tree then follows as `Some(name)`, its records as `Some((key, value))`
+/// in key order, and `None`. A final `None` ends the trees. The content hash
+/// covers everything after it.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Snapshot version
+    pub version: u8,
+    /// Slot of the last block in the snapshot
+    pub slot: u64,
+    /// Hash of the last block in the snapshot
+    pub blockhash: blake3::Hash,
+    /// Content hash of the snapshot trees
+    pub hash: blake3::Hash,
+    /// Records of each sled tree, by tree name
+    pub trees: Vec<(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)>,
+}
+
+impl Snapshot {
+    /// Export a snapshot of the given blockchain at the given slot, which
+    /// must hold a block, into a file. The state changes of the blocks after
+    /// it are undone using their [`StateDiff`]s, leaving the database
+    /// untouched. Records are streamed from the database into the file, so
+    /// this is blocking, and doesn't need exclusive access to the blockchain:
+    /// the export is retried if blocks got added or pruned meanwhile.
+    /// Returns the hash of the snapshot block and the snapshot content hash.
+    pub fn export(
+        blockchain: &Blockchain,
+        slot: u64,
+        path: &Path,
+    ) -> Result<(blake3::Hash, blake3::Hash)> {
+        for _ in 0..EXPORT_ATTEMPTS {
+            let before = (blockchain.last()?, blockchain.pruned.get()?);
+            let ret = Self::write_export(blockchain, slot, path)?;
+            if (blockchain.last()?, blockchain.pruned.get()?) == before {
+                return Ok(ret)
+            }
+            info!(target: "blockchain::snapshot", "Blockchain changed while exporting snapshot, retrying");
+        }
+
+        Err(Error::InvalidSnapshot("Blockchain kept changing during export".to_string()))
+    }
+
+    /// Single attempt of [`Snapshot::export`].
+    fn write_export(
+        blockchain: &Blockchain,
+        slot: u64,
+        path: &Path,
+    ) -> Result<(blake3::Hash, blake3::Hash)> {
+        let blockhash = blockchain.order.get(&[slot], true)?[0].unwrap();
+        info!(target: "blockchain::snapshot", "Exporting snapshot at slot {} ({})", slot, blockhash);
+
+        // Previous values of the keys changed by the blocks after the slot.
+        // Diffs come latest first, so the earliest one gets applied last.
+        let diffs = blockchain.get_rollback_diffs(slot)?;
+        let mut undo: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>> = BTreeMap::new();
+        for (_, diff) in diffs {
+            apply_diff(&mut undo, diff);
+        }
+
+        // The blocks after the slot get dropped, along with their headers and
+        // transactions. Slot checkpoints and state diffs are keyed by slot.
+        let after = (slot + 1).to_be_bytes().to_vec();
+        let mut dropped: HashSet<Vec<u8>> = HashSet::new();
+        for record in blockchain.order.0.range(after.clone()..) {
+            let hash = record?.1;
+            if let Some(block) = blockchain.blocks.0.get(&hash)? {
+                let block: Block = deserialize(&block)?;
+                dropped.insert(block.header.as_bytes().to_vec());
+                dropped.extend(block.txs.iter().map(|tx| tx.as_bytes().to_vec()));
+            }
+            dropped.insert(hash.to_vec());
+        }
+        let by_slot = [
+            blockchain.order.0.name(),
+            blockchain.slot_checkpoints.0.name(),
+            blockchain.state_diffs.0.name(),
+        ];
+        let by_hash = [
+            blockchain.blocks.0.name(),
+            blockchain.headers.0.name(),
+            blockchain.transactions.0.name(),
+        ];
+
+        let mut file = BufWriter::new(File::create(path)?);
+        let hash_offset = (SNAPSHOT_VERSION, slot, blockhash).encode(&mut file)?;
+        blake3::Hash::from([0; 32]).encode(&mut file)?;
+
+        let mut names = blockchain.sled_db.tree_names();
+        names.retain(|name| name.as_ref() != SLED_DEFAULT_TREE);
+        names.sort();
+
+        let mut writer = TreesWriter::new(&mut file);
+        for name in names {
+            let slot_keyed = by_slot.contains(&name);
+            let hash_keyed = by_hash.contains(&name);
+            let keep = |key: &[u8]| {
+                !(slot_keyed && key >= after.as_slice()) && !(hash_keyed && dropped.contains(key))
+            };
+
+            writer.tree(&name)?;
+
+            // Merge the tree records with the undone changes, in key order
+            let mut records = blockchain.sled_db.open_tree(&name)?.iter();
+            let mut undone = undo.remove(name.as_ref()).unwrap_or_default().into_iter();
+            let mut next_record = records.next().transpose()?;
+            let mut next_undo = undone.next();
+            loop {
+                let order = match (&next_record, &next_undo) {
+                    (None, None) => break,
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (Some((key, _)), Some((undo_key, _))) => key.as_ref().cmp(undo_key.as_slice()),
+                };
+
+                // Records without undone changes are written as they are
+                let (key, value) = if order == Ordering::Less {
+                    let (key, value) = next_record.take().unwrap();
+                    next_record = records.next().transpose()?;
+                    (key.to_vec(), Some(value.to_vec()))
+                } else {
+                    // Undone changes replace the record with the same key, if any
+                    if order == Ordering::Equal {
+                        next_record = records.next().transpose()?;
+                    }
+                    let undone_change = next_undo.take().unwrap();
+                    next_undo = undone.next();
+                    undone_change
+                };
+
+                if let Some(value) = value.filter(|_| keep(&key)) {
+                    writer.record(&key, &value)?;
+                }
+            }
+
+            writer.end()?;
+        }
+        writer.end()?;
+
+        let hash = writer.finalize();
+        file.seek(SeekFrom::Start(hash_offset as u64))?;
+        hash.encode(&mut file)?;
+        file.flush()?;
+
+        Ok((blockhash, hash))
+    }
+
+    /// Compute the content hash of the given snapshot trees.
+    pub fn content_hash(trees: &[(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)]) -> blake3::Hash {
+        let mut writer = TreesWriter::new(std::io::sink());
+        // Writing into a sink can't fail
+        writer.write_trees(trees).unwrap();
+        writer.finalize()
+    }
+
+    /// Verify the snapshot content hash, and that its blocks form a valid
+    /// chain from the genesis block of the given blockchain to the snapshot
+    /// block.
+    pub fn verify(&self, blockchain: &Blockchain) -> Result<()> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(Error::InvalidSnapshot(format!("Unsupported version {}", self.version)))
+        }
+
+        if Self::content_hash(&self.trees) != self.hash {
+            return Err(Error::InvalidSnapshot("Content hash mismatch".to_string()))
+        }
+
+        let find = |tree: &sled::Tree| -> Result<Records> {
+            let Some((_, records)) =
+                self.trees.iter().find(|(name, _)| tree.name().as_ref() == name.as_slice())
+            else {
+                return Err(Error::InvalidSnapshot("Missing blockchain tree".to_string()))
+            };
+            Ok(records.iter().cloned().collect())
+        };
+        let order = find(&blockchain.order.0)?;
+        let blocks = find(&blockchain.blocks.0)?;
+        let headers = find(&blockchain.headers.0)?;
+
+        let genesis = blockchain.order.get(&[0], true)?[0].unwrap();
+        verify_chain(
+            &genesis,
+            (self.slot, self.blockhash),
+            order.into_iter().map(Ok),
+            |key| Ok(blocks.get(key).cloned()),
+            |key| Ok(headers.get(key).cloned()),
+        )
+    }
+
+    /// Import the snapshot file at the given path into the given blockchain,
+    /// which must only hold the genesis block. The snapshot block must match
+    /// `blockhash`, the hash of the block in the snapshot slot of the header
+    /// chain synced from the network, and the snapshot content must match the
+    /// trusted content hash `hash`, as headers don't cover the contract states.
+    /// The whole file gets hashed before anything is written. Records are then
+    /// streamed from the file and written in batches, replacing all existing
+    /// records, so snapshots don't need to fit in memory. The content hash and
+    /// the chain of blocks get verified again once all of them are written, in
+    /// case the file changed meanwhile. If the import fails, the database is
+    /// reset to the genesis block, and so it is on the next start if the
+    /// import gets interrupted.
+    /// Returns the snapshot slot.
+    pub fn import(
+        path: &Path,
+        blockchain: &Blockchain,
+        blockhash: &blake3::Hash,
+        hash: &blake3::Hash,
+    ) -> Result<u64> {
+        if blockchain.len() > 1 || !blockchain.overlay.is_empty() {
+            return Err(Error::SnapshotImportNotEmpty)
+        }
+
+        let mut file = BufReader::new(File::open(path)?);
+        let (_, snapshot_blockhash, snapshot_hash) = read_info(&mut file)?;
+        if snapshot_blockhash != *blockhash {
+            let e = format!("Snapshot block {} is not on the synced chain", snapshot_blockhash);
+            return Err(Error::InvalidSnapshot(e))
+        }
+        if snapshot_hash != *hash {
+            return Err(Error::InvalidSnapshot("Content hash mismatch".to_string()))
+        }
+        let mut reader = HashReader::new(file);
+        std::io::copy(&mut reader, &mut std::io::sink())?;
+        if reader.finalize() != *hash {
+            return Err(Error::InvalidSnapshot("Content hash mismatch".to_string()))
+        }
+
+        let genesis_hash = blockchain.order.get(&[0], true)?[0].unwrap();
+        let genesis = blockchain.get_blocks_by_hash(&[genesis_hash])?.remove(0).header;
+
+        let db = &blockchain.sled_db;
+        db.insert(SLED_IMPORT_MARKER, vec![1u8])?;
+        db.flush()?;
+
+        let result = Self::write_import(path, blockchain, &genesis_hash, blockhash, hash);
+        if let Err(e) = &result {
+            warn!(target: "blockchain::snapshot", "Snapshot import failed, resetting the database: {}", e);
+            // Opening the blockchain clears the unfinished import
+            Blockchain::new(db, genesis.timestamp, genesis.previous)?;
+            return result
+        }
+
+        db.remove(SLED_IMPORT_MARKER)?;
+        db.flush()?;
+        result
+    }
+
+    /// Writes of [`Snapshot::import`].
+    fn write_import(
+        path: &Path,
+        blockchain: &Blockchain,
+        genesis: &blake3::Hash,
+        blockhash: &blake3::Hash,
+        hash: &blake3::Hash,
+    ) -> Result<u64> {
+        let mut file = BufReader::new(File::open(path)?);
+        let (slot, snapshot_blockhash, snapshot_hash) = read_info(&mut file)?;
+        if snapshot_blockhash != *blockhash || snapshot_hash != *hash {
+            return Err(Error::InvalidSnapshot("Snapshot file changed".to_string()))
+        }
+
+        info!(target: "blockchain::snapshot", "Importing snapshot at slot {} ({})", slot, blockhash);
+        let db = &blockchain.sled_db;
+        clear_trees(db)?;
+
+        let mut reader = HashReader::new(file);
+        while let Some(name) = Option::<Vec<u8>>::decode(&mut reader)? {
+            if name == SLED_DEFAULT_TREE {
+                return Err(Error::InvalidSnapshot("Invalid tree name".to_string()))
+            }
+
+            let tree = db.open_tree(&name)?;
+            let mut batch = sled::Batch::default();
+            let mut batched = 0;
+            while let Some((key, value)) = Option::<(Vec<u8>, Vec<u8>)>::decode(&mut reader)? {
+                batch.insert(key, value);
+                batched += 1;
+                if batched == IMPORT_BATCH_SIZE {
+                    tree.apply_batch(std::mem::take(&mut batch))?;
+                    batched = 0;
+                }
+            }
+            tree.apply_batch(batch)?;
+        }
+
+        if reader.finalize() != *hash {
+            return Err(Error::InvalidSnapshot("Content hash mismatch".to_string()))
+        }
+
+        let order = blockchain.order.0.iter().map(|record| -> Result<(Vec<u8>, Vec<u8>)> {
+            let (key, value) = record?;
+            Ok((key.to_vec(), value.to_vec()))
+        });
+        verify_chain(
+            genesis,
+            (slot, *blockhash),
+            order,
+            |key| Ok(blockchain.blocks.0.get(key)?.map(|v| v.to_vec())),
+            |key| Ok(blockchain.headers.0.get(key)?.map(|v| v.to_vec())),
+        )?;
+
+        db.flush()?;
+        Ok(slot)
+    }
+
+    /// Read the slot, block hash and content hash of a snapshot file,
+    /// without loading its trees.
+    pub fn info(path: &Path) -> Result<(u64, blake3::Hash, blake3::Hash)> {
+        read_info(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Write the snapshot to a file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        (self.version, self.slot, self.blockhash, self.hash).encode(&mut file)?;
+        TreesWriter::new(&mut file).write_trees(&self.trees)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Read a snapshot from a file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let (slot, blockhash, hash) = read_info(&mut file)?;
+
+        let mut trees = vec![];
+        while let Some(name) = Option::<Vec<u8>>::decode(&mut file)? {
+            let mut records = vec![];
+            while let Some(record) = Option::<(Vec<u8>, Vec<u8>)>::decode(&mut file)? {
+                records.push(record);
+            }
+            trees.push((name, records));
+        }
+
+        Ok(Self { version: SNAPSHOT_VERSION, slot, blockhash, hash, trees })
+    }
+}
+
+/// Writer of the trees of a snapshot file, see [`Snapshot`], computing their
+/// content hash along the way.
+struct TreesWriter<W: Write> {
+    writer: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> TreesWriter<W> {
+    fn new(writer: W) -> Self {
+        Self { writer, hasher: blake3::Hasher::new() }
+    }
+
+    fn write<T: Encodable>(&mut self, item: &T) -> Result<()> {
+        let mut bytes = vec![];
+        item.encode(&mut bytes)?;
+        self.hasher.update(&bytes);
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Start a tree with the given name
+    fn tree(&mut self, name: &[u8]) -> Result<()> {
+        self.write(&Some(name.to_vec()))
+    }
+
+    /// Write a record of the current tree
+    fn record(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write(&Some((key.to_vec(), value.to_vec())))
+    }
+
+    /// End the current tree, or the trees if none was started
+    fn end(&mut self) -> Result<()> {
+        self.write(&None::<Vec<u8>>)
+    }
+
+    /// Write the given trees and end them
+    fn write_trees(&mut self, trees: &[(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)]) -> Result<()> {
+        for (name, records) in trees {
+            self.tree(name)?;
+            for (key, value) in records {
+                self.record(key, value)?;
+            }
+            self.end()?;
+        }
+        self.end()
+    }
+
+    fn finalize(&self) -> blake3::Hash {
+        self.hasher.finalize()
+    }
+}
+
+/// Reader of the trees of a snapshot file, computing their content hash
+/// along the way.
+struct HashReader<R: Read> {
+    reader: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> HashReader<R> {
+    fn new(reader: R) -> Self {
+        Self { reader, hasher: blake3::Hasher::new() }
+    }
+
+    fn finalize(&self) -> blake3::Hash {
+        self.hasher.finalize()
+    }
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Read the version, slot, block hash and content hash a snapshot file
+/// starts with, failing on unsupported versions.
+fn read_info(reader: &mut impl Read) -> Result<(u64, blake3::Hash, blake3::Hash)> {
+    let (version, slot, blockhash, hash): (u8, u64, blake3::Hash, blake3::Hash) =
+        Decodable::decode(reader)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(Error::InvalidSnapshot(format!("Unsupported version {}", version)))
+    }
+
+    Ok((slot, blockhash, hash))
+}
+
+/// Verify the block order records, in key order, link the blocks from the
+/// `genesis` block to the `last` one, in the form of a tuple (`slot`,
+/// `blockhash`). Blocks and headers are looked up by their hash.
+fn verify_chain(
+    genesis: &blake3::Hash,
+    last: (u64, blake3::Hash),
+    order: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+    blocks: impl Fn(&[u8]) -> Result<Option<Vec<u8>>>,
+    headers: impl Fn(&[u8]) -> Result<Option<Vec<u8>>>,
+) -> Result<()> {
+    let mut previous: Option<(u64, blake3::Hash)> = None;
+    for record in order {
+        let (key, value) = record?;
+        let e = || Error::InvalidSnapshot("Invalid block chain".to_string());
+
+        let slot = u64::from_be_bytes(key.as_slice().try_into().map_err(|_| e())?);
+        let hash: [u8; 32] = value.as_slice().try_into().map_err(|_| e())?;
+        let hash = blake3::Hash::from(hash);
+
+        // Blocks and headers are stored serialized, keyed by their hash
+        let block = blocks(&value)?.ok_or_else(e)?;
+        if blake3::hash(&block) != hash {
+            return Err(e())
+        }
+        let block: Block = deserialize(&block)?;
+
+        let header = headers(block.header.as_bytes())?.ok_or_else(e)?;
+        if blake3::hash(&header) != block.header {
+            return Err(e())
+        }
+        let header: Header = deserialize(&header)?;
+
+        if header.slot != slot {
+            return Err(e())
+        }
+
+        match previous {
+            None if slot != 0 || hash != *genesis => return Err(e()),
+            Some((previous_slot, previous_hash))
+                if header.previous != previous_hash || slot <= previous_slot =>
+            {
+                return Err(e())
+            }
+            _ => {}
+        }
+
+        previous = Some((slot, hash));
+    }
+
+    if previous != Some(last) {
+        return Err(Error::InvalidSnapshot("Snapshot block is not the last one".to_string()))
+    }
+
+    Ok(())
+}
+
+/// Reset the database if a snapshot import got interrupted, removing all
+/// the records written, so the blockchain gets initialized from its genesis
+/// block again.
+pub(super) fn clear_interrupted_import(db: &sled::Db) -> Result<()> {
+    if !db.contains_key(SLED_IMPORT_MARKER)? {
+        return Ok(())
+    }
+
+    warn!(target: "blockchain::snapshot", "Found an unfinished snapshot import, resetting the database");
+    clear_trees(db)?;
+    db.remove(SLED_IMPORT_MARKER)?;
+    db.flush()?;
+    Ok(())
+}
+
+/// Remove the records of all the trees of the database, but sled's default
+/// one.
+fn clear_trees(db: &sled::Db) -> Result<()> {
+    for name in db.tree_names() {
+        if name.as_ref() != SLED_DEFAULT_TREE {
+            db.open_tree(name)?.clear()?;
+        }
+    }
+    Ok(())
+}
+
+/// Collect the previous values of the keys changed by the given diff, by
+/// tree name, replacing the ones collected from later diffs.
+fn apply_diff(undo: &mut BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>, diff: StateDiff) {
+    for (name, previous) in diff.trees {
+        undo.entry(name).or_default().extend(previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blockchain::tests::test_blockchain,
+        consensus::{BlockInfo, LeadInfo},
+        util::time::Timestamp,
+    };
+
+    #[test]
+    fn export_and_import_snapshot() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let state = blockchain.sled_db.open_tree("contract_state")?;
+
+        let mut previous = blockchain.last()?.1;
+        for slot in 1..=3u64 {
+            let header = Header { previous, slot, ..Header::default() };
+            let block = BlockInfo::new(header, vec![], LeadInfo::default());
+            previous = block.blockhash();
+            let writes = [(b"key".to_vec(), Some(slot.to_be_bytes().to_vec()))].into();
+            blockchain.overlay.insert(&state, writes);
+            blockchain.add(&[block])?;
+        }
+
+        // The snapshot undoes the changes of the block after it
+        let path = std::env::temp_dir().join("darkfi_snapshot_test.bin");
+        let (blockhash, hash) = Snapshot::export(&blockchain, 2, &path)?;
+        assert_eq!(blockhash, blockchain.order.get(&[2], true)?[0].unwrap());
+        assert_eq!(blockchain.last()?, (3, previous));
+        assert_eq!(state.get(b"key")?.unwrap().as_ref(), 3u64.to_be_bytes());
+
+        let snapshot = Snapshot::load(&path)?;
+        assert_eq!((snapshot.blockhash, snapshot.hash), (blockhash, hash));
+        assert_eq!(Snapshot::content_hash(&snapshot.trees), hash);
+
+        // Saving it back gives the same file
+        let exported = std::fs::read(&path)?;
+        snapshot.save(&path)?;
+        assert_eq!(std::fs::read(&path)?, exported);
+        assert_eq!(Snapshot::info(&path)?, (2, blockhash, hash));
+
+        // Snapshots of blocks other than the synced one are rejected, and the
+        // blockchain is left with just the genesis block
+        let imported = test_blockchain()?;
+        let other_block = blockchain.order.get(&[1], true)?[0].unwrap();
+        assert!(Snapshot::import(&path, &imported, &other_block, &hash).is_err());
+        assert_eq!(imported.len(), 1);
+
+        // So are snapshots with another content hash than the trusted one
+        let other_hash = blake3::hash(b"other");
+        assert!(Snapshot::import(&path, &imported, &blockhash, &other_hash).is_err());
+        assert_eq!(imported.len(), 1);
+
+        assert_eq!(Snapshot::import(&path, &imported, &blockhash, &hash)?, 2);
+        assert_eq!(imported.last()?, (2, snapshot.blockhash));
+        assert_eq!(imported.get_blocks_after(0, 10)?.len(), 2);
+        assert_eq!(imported.state_diffs.len(), 2);
+        assert_eq!(imported.check_consistency()?, 0);
+        let state = imported.sled_db.open_tree("contract_state")?;
+        assert_eq!(state.get(b"key")?.unwrap().as_ref(), 2u64.to_be_bytes());
+
+        // Snapshots are only imported into an empty blockchain
+        assert!(matches!(
+            Snapshot::import(&path, &imported, &blockhash, &hash),
+            Err(Error::SnapshotImportNotEmpty)
+        ));
+
+        // Modified snapshots are rejected before writing anything, even with
+        // a valid chain of blocks and a matching content hash in the file
+        let empty = test_blockchain()?;
+        let mut modified = snapshot.clone();
+        modified.trees.pop();
+        modified.save(&path)?;
+        assert!(Snapshot::import(&path, &empty, &blockhash, &hash).is_err());
+        let mut modified = snapshot.clone();
+        let (_, records) =
+            modified.trees.iter_mut().find(|(name, _)| name == b"contract_state").unwrap();
+        records[0].1 = 100u64.to_be_bytes().to_vec();
+        modified.hash = Snapshot::content_hash(&modified.trees);
+        modified.save(&path)?;
+        assert!(modified.verify(&empty).is_ok());
+        assert!(Snapshot::import(&path, &empty, &blockhash, &hash).is_err());
+        assert_eq!(empty.len(), 1);
+        assert!(empty.sled_db.open_tree("contract_state")?.is_empty());
+
+        // Snapshots failing after being written reset the written records
+        let mut modified = snapshot.clone();
+        modified.slot = 1;
+        modified.save(&path)?;
+        assert!(Snapshot::import(&path, &empty, &blockhash, &hash).is_err());
+        assert_eq!(empty.len(), 1);
+        assert!(empty.sled_db.open_tree("contract_state")?.is_empty());
+        std::fs::remove_file(&path)?;
+
+        // Interrupted imports are reset on the next start
+        let db = empty.sled_db.clone();
+        db.insert(SLED_IMPORT_MARKER, vec![1u8])?;
+        db.open_tree("contract_state")?.insert(b"key", b"leftover")?;
+        let reopened = Blockchain::new(&db, Timestamp(0), blake3::hash(b"genesis"))?;
+        assert!(db.open_tree("contract_state")?.is_empty());
+        assert!(!db.contains_key(SLED_IMPORT_MARKER)?);
+        assert_eq!(reopened.last()?, empty.last()?);
+
+        // Snapshots of another chain are rejected
+        let db = sled::Config::new().temporary(true).open()?;
+        let other = Blockchain::new(&db, Timestamp(0), blake3::hash(b"other"))?;
+        assert!(snapshot.verify(&other).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::ops::Bound;
+
+use darkfi_serial::{deserialize, SerialDecodable, SerialEncodable};
+
+use crate::Result;
+
+const SLED_STATE_DIFF_TREE: &[u8] = b"_state_diffs";
+
+/// The inverse of the contract state changes applied along with a set of
+/// blocks. Applying it restores the contract state trees to what they were
+/// before the blocks were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct StateDiff {
+    /// Slot of the first block the changes were applied with. Blocks added
+    /// together share a single diff, keyed by the slot of the last of them.
+    pub first_slot: u64,
+    /// Changed sled trees, by name, along with the previous value of each
+    /// changed key, where `None` means the key didn't exist.
+    pub trees: Vec<(Vec<u8>, Vec<(Vec<u8>, Option<Vec<u8>>)>)>,
+}
+
+/// The `StateDiffStore` is a `sled` tree storing the [`StateDiff`] of the
+/// blocks, where the key is the slot of the last block the changes were
+/// applied with, and the value is the serialized diff.
+#[derive(Clone)]
+pub struct StateDiffStore(pub sled::Tree);
+
+impl StateDiffStore {
+    /// Opens a new or existing `StateDiffStore` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_STATE_DIFF_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Fetch all the diffs stored after the given slot, ordered from the
+    /// newest to the oldest, in the form of a tuple (`slot`, `diff`).
+    pub fn get_after(&self, slot: u64) -> Result<Vec<(u64, StateDiff)>> {
+        let mut ret = vec![];
+
+        let range = (Bound::Excluded(slot.to_be_bytes()), Bound::Unbounded);
+        for record in self.0.range(range).rev() {
+            let (key, value) = record?;
+            let slot_bytes: [u8; 8] = key.as_ref().try_into().unwrap();
+            ret.push((u64::from_be_bytes(slot_bytes), deserialize(&value)?));
+        }
+
+        Ok(ret)
+    }
+
+    /// Generate the sled batch removing all the diffs stored up to and
+    /// including the given slot, so the caller can apply it along with other
+    /// writes.
+    pub fn remove_until_batch(&self, slot: u64) -> Result<sled::Batch> {
+        let mut batch = sled::Batch::default();
+
+        for key in self.0.range(..=slot.to_be_bytes()).keys() {
+            batch.remove(key?);
+        }
+
+        Ok(batch)
+    }
+
+    /// Retrieve records count
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.len() == 0
+    }
+}
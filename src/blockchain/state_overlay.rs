@@ -0,0 +1,214 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    ops::Bound,
+    sync::{Arc, Mutex},
+};
+
+use crate::Result;
+
+/// Pending writes to a single sled tree, where the key maps to the new
+/// value, or to `None` if the key is removed.
+pub type TreeWrites = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Build a `sled::Batch` performing the given writes.
+pub fn writes_batch(writes: &TreeWrites) -> sled::Batch {
+    let mut batch = sled::Batch::default();
+    for (key, value) in writes {
+        match value {
+            Some(value) => batch.insert(key.as_slice(), value.as_slice()),
+            None => batch.remove(key.as_slice()),
+        }
+    }
+
+    batch
+}
+
+/// The `StateOverlay` holds contract state changes that were executed, but
+/// not yet written to the database. Reads done by the wasm runtime go through
+/// the overlay first, so calls see the changes made by the transactions and
+/// blocks executed before them. The changes are then written in the same sled
+/// transaction as the blocks that produced them, in `Blockchain::add()`.
+///
+/// The overlay is shared between all clones of a `Blockchain`. Changes that
+/// may still get discarded can be staged in a [`StateOverlay::child`].
+#[derive(Clone, Default)]
+pub struct StateOverlay {
+    writes: Arc<Mutex<BTreeMap<sled::IVec, (sled::Tree, TreeWrites)>>>,
+    /// Overlay this one stages writes on top of
+    parent: Option<Arc<StateOverlay>>,
+}
+
+impl StateOverlay {
+    /// Create an overlay staging writes on top of this one. Reads through it
+    /// see the pending writes of both, while this one is left untouched until
+    /// they're moved into it with [`StateOverlay::merge`].
+    pub fn child(&self) -> Self {
+        Self { writes: Arc::default(), parent: Some(Arc::new(self.clone())) }
+    }
+
+    /// Move the pending writes of a child overlay into its parent.
+    pub fn merge(&self) {
+        let Some(parent) = &self.parent else { return };
+        for (tree, writes) in self.take() {
+            parent.insert(&tree, writes);
+        }
+    }
+
+    /// Pending write of `key` in the given tree, looking into the parent
+    /// overlays if there's none in this one.
+    fn pending(&self, tree: &sled::IVec, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        if let Some((_, writes)) = self.writes.lock().unwrap().get(tree) {
+            if let Some(value) = writes.get(key) {
+                return Some(value.clone())
+            }
+        }
+
+        self.parent.as_ref().and_then(|parent| parent.pending(tree, key))
+    }
+
+    /// Pending writes of the given tree within the given range, including
+    /// the ones of the parent overlays.
+    fn pending_range(
+        &self,
+        tree: &sled::IVec,
+        range: &(Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> TreeWrites {
+        let mut pending = match &self.parent {
+            Some(parent) => parent.pending_range(tree, range),
+            None => TreeWrites::new(),
+        };
+
+        if let Some((_, writes)) = self.writes.lock().unwrap().get(tree) {
+            let writes = writes.range(range.clone()).map(|(k, v)| (k.clone(), v.clone()));
+            pending.extend(writes);
+        }
+
+        pending
+    }
+
+    /// Fetch the value of `key` in the given tree, taking pending writes
+    /// into account.
+    pub fn get(&self, tree: &sled::Tree, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.pending(&tree.name(), key) {
+            return Ok(value)
+        }
+
+        Ok(tree.get(key)?.map(|v| v.to_vec()))
+    }
+
+    /// Check if the given tree contains `key`, taking pending writes into account.
+    pub fn contains_key(&self, tree: &sled::Tree, key: &[u8]) -> Result<bool> {
+        if let Some(value) = self.pending(&tree.name(), key) {
+            return Ok(value.is_some())
+        }
+
+        Ok(tree.contains_key(key)?)
+    }
+
+    /// Fetch up to `limit` records of the given tree with keys in the given
+    /// range, in key order, taking pending writes into account.
+    pub fn range(
+        &self,
+        tree: &sled::Tree,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        // `BTreeMap::range` panics on inverted ranges, which are empty anyway
+        if let (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) = &range
+        {
+            let empty = match &range {
+                (Bound::Included(_), Bound::Included(_)) => start > end,
+                _ => start >= end,
+            };
+            if empty {
+                return Ok(vec![])
+            }
+        }
+
+        let writes = self.pending_range(&tree.name(), &range);
+
+        let mut tree_iter = tree.range(range);
+        let mut writes_iter = writes.iter();
+        let mut next_record = tree_iter.next().transpose()?;
+        let mut next_write = writes_iter.next();
+
+        let mut ret = vec![];
+        while ret.len() < limit {
+            let order = match (&next_record, &next_write) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((key, _)), Some((write_key, _))) => key.as_ref().cmp(write_key.as_slice()),
+            };
+
+            // Records without pending writes are returned as they are
+            if order == Ordering::Less {
+                let (key, value) = next_record.take().unwrap();
+                ret.push((key.to_vec(), value.to_vec()));
+                next_record = tree_iter.next().transpose()?;
+                continue
+            }
+
+            // Pending writes replace the record with the same key, if any
+            if order == Ordering::Equal {
+                next_record = tree_iter.next().transpose()?;
+            }
+
+            let (key, value) = next_write.take().unwrap();
+            if let Some(value) = value {
+                ret.push((key.clone(), value.clone()));
+            }
+            next_write = writes_iter.next();
+        }
+
+        Ok(ret)
+    }
+
+    /// Stage writes to the given tree on top of the already pending ones.
+    pub fn insert(&self, tree: &sled::Tree, writes: TreeWrites) {
+        if writes.is_empty() {
+            return
+        }
+
+        let mut overlay = self.writes.lock().unwrap();
+        let (_, pending) =
+            overlay.entry(tree.name()).or_insert_with(|| (tree.clone(), TreeWrites::new()));
+        pending.extend(writes);
+    }
+
+    /// Take all the pending writes, leaving the overlay empty.
+    pub fn take(&self) -> Vec<(sled::Tree, TreeWrites)> {
+        std::mem::take(&mut *self.writes.lock().unwrap()).into_values().collect()
+    }
+
+    /// Drop all the pending writes.
+    pub fn clear(&self) {
+        self.writes.lock().unwrap().clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.lock().unwrap().is_empty()
+    }
+}
@@ -26,7 +26,7 @@ const SLED_TX_TREE: &[u8] = b"_transactions";
 /// transactions where the key is the transaction hash, and the value is
 /// the serialized transaction.
 #[derive(Clone)]
-pub struct TxStore(sled::Tree);
+pub struct TxStore(pub sled::Tree);
 
 impl TxStore {
     /// Opens a new or existing `TxStore` on the given sled database.
@@ -42,6 +42,15 @@ impl TxStore {
     /// On success, the function returns the transaction hashes in the same
     /// order as the input transactions.
     pub fn insert(&self, transactions: &[Transaction]) -> Result<Vec<blake3::Hash>> {
+        let (ret, batch) = self.insert_batch(transactions);
+        self.0.apply_batch(batch)?;
+        Ok(ret)
+    }
+
+    /// Generate the sled batch corresponding to an insert, so the caller
+    /// can apply it along with other writes.
+    /// Returns the transaction hashes in the same order, and the batch.
+    pub fn insert_batch(&self, transactions: &[Transaction]) -> (Vec<blake3::Hash>, sled::Batch) {
         let mut ret = Vec::with_capacity(transactions.len());
         let mut batch = sled::Batch::default();
 
@@ -52,8 +61,7 @@ impl TxStore {
             ret.push(txhash);
         }
 
-        self.0.apply_batch(batch)?;
-        Ok(ret)
+        (ret, batch)
     }
 
     /// Check if the txstore contains a given transaction hash.
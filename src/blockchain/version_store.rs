@@ -0,0 +1,50 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::Result;
+
+const SLED_VERSION_TREE: &[u8] = b"_version";
+const SLED_VERSION_KEY: &[u8] = b"version";
+
+/// The `VersionStore` is a `sled` tree recording the version of the database
+/// layout, so migrations run only once, when opening a database written by an
+/// older version.
+#[derive(Clone)]
+pub struct VersionStore(pub sled::Tree);
+
+impl VersionStore {
+    /// Opens a new or existing `VersionStore` on the given sled database.
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree(SLED_VERSION_TREE)?;
+        Ok(Self(tree))
+    }
+
+    /// Record the given database version.
+    pub fn set(&self, version: u64) -> Result<()> {
+        self.0.insert(SLED_VERSION_KEY, &version.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Fetch the database version. Databases written before the version was
+    /// recorded are version 0.
+    pub fn get(&self) -> Result<u64> {
+        let Some(found) = self.0.get(SLED_VERSION_KEY)? else { return Ok(0) };
+        let version_bytes: [u8; 8] = found.as_ref().try_into().unwrap();
+        Ok(u64::from_be_bytes(version_bytes))
+    }
+}
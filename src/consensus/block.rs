@@ -27,6 +27,7 @@ use darkfi_serial::{serialize, SerialDecodable, SerialEncodable};
 
 use super::{
     constants::{BLOCK_MAGIC_BYTES, BLOCK_VERSION},
+    tx_merkle::txs_root,
     LeadInfo,
 };
 use crate::{net, tx::Transaction, util::time::Timestamp};
@@ -190,6 +191,14 @@ impl BlockInfo {
         let block: Block = self.clone().into();
         block.blockhash()
     }
+
+    /// Calculate the root of the transactions Merkle tree, which the
+    /// header has to commit to.
+    pub fn txs_root(&self) -> MerkleNode {
+        let tx_hashes: Vec<blake3::Hash> =
+            self.txs.iter().map(|x| blake3::hash(&serialize(x))).collect();
+        txs_root(&tx_hashes)
+    }
 }
 
 impl From<BlockInfo> for Block {
@@ -209,6 +218,9 @@ impl From<BlockInfo> for Block {
 pub struct BlockResponse {
     /// Response blocks.
     pub blocks: Vec<BlockInfo>,
+    /// Set if the responding node pruned some of the requested blocks,
+    /// to the slot of the first of them. No blocks are sent in that case.
+    pub pruned: Option<u64>,
 }
 
 impl net::Message for BlockResponse {
@@ -217,6 +229,50 @@ impl net::Message for BlockResponse {
     }
 }
 
+/// Auxiliary structure used for headers-first blockchain syncing.
+#[derive(Debug, SerialEncodable, SerialDecodable)]
+pub struct HeaderRequest {
+    /// Slot UID after which headers are requested
+    pub slot: u64,
+}
+
+impl net::Message for HeaderRequest {
+    fn name() -> &'static str {
+        "headerrequest"
+    }
+}
+
+/// Auxiliary structure used for headers-first blockchain syncing.
+/// Each header is sent along with its [`Block`], which holds the leader
+/// info and transaction hashes needed to produce the block hash.
+#[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
+pub struct HeaderResponse {
+    /// Response headers
+    pub headers: Vec<Header>,
+    /// Blocks of the response headers, in the same order
+    pub blocks: Vec<Block>,
+}
+
+impl net::Message for HeaderResponse {
+    fn name() -> &'static str {
+        "headerresponse"
+    }
+}
+
+/// Auxiliary structure used for blockchain syncing, requesting the full
+/// data of the given blocks. Answered with a [`BlockResponse`].
+#[derive(Debug, SerialEncodable, SerialDecodable)]
+pub struct BlockBodyRequest {
+    /// Requested block hashes
+    pub hashes: Vec<blake3::Hash>,
+}
+
+impl net::Message for BlockBodyRequest {
+    fn name() -> &'static str {
+        "blockbodyrequest"
+    }
+}
+
 /// This struct represents a block proposal, used for consensus.
 #[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
 pub struct BlockProposal {
@@ -265,6 +321,10 @@ impl net::Message for BlockProposal {
     fn name() -> &'static str {
         "proposal"
     }
+
+    fn rate_limit() -> Option<net::RateLimit> {
+        Some(net::RateLimit::new(50, 5.0))
+    }
 }
 
 impl From<BlockProposal> for BlockInfo {
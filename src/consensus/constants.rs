@@ -95,15 +95,45 @@ pub const SYNC_RETRIES_DURATION: u64 = 2;
 /// Max resync retries
 pub const SYNC_MAX_RETRIES: u64 = 10;
 
+/// Seconds to wait for a peer's response to a block sync request
+pub const SYNC_REQUEST_TIMEOUT: u64 = 30;
+
+/// Block bodies requested from a peer at once during block sync
+pub const SYNC_BODIES_BATCH: usize = 10;
+
 /// Transactions included in a block cap
 pub const TXS_CAP: usize = 50;
 
+/// Maximum serialized size of the transactions included in a block
+pub const BLOCK_TXS_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// Maximum gas used by the transactions included in a block
+pub const BLOCK_GAS_LIMIT: u64 = 2_000_000_000;
+
+/// Maximum number of transactions kept in the mempool
+pub const MEMPOOL_MAX_TXS: usize = 10000;
+
+/// Maximum serialized size of all transactions kept in the mempool
+pub const MEMPOOL_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Mempool transactions older than this many seconds are dropped
+pub const MEMPOOL_TX_EXPIRY: u64 = 60 * 60 * 3;
+
+/// Price of a unit of gas, in the smallest unit of the native token.
+/// Transaction fees have to cover the gas used by their contract calls
+/// times this price.
+pub const GAS_PRICE: u64 = 1;
+
 /// Block leader reward
 pub const REWARD: u64 = 1;
 
 /// Leader proofs k for zk proof rows (rows=2^k)
 pub const LEADER_PROOF_K: u32 = 13;
 
+/// Contract circuits k for zk proof rows (rows=2^k)
+// FIXME: This k=13 man...
+pub const CONTRACT_PROOF_K: u32 = 13;
+
 // TODO: Describe these constants
 pub const RADIX_BITS: usize = 76;
 
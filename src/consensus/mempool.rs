@@ -0,0 +1,459 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    io::Cursor,
+};
+
+use darkfi_sdk::{
+    crypto::{contract_id::MONEY_CONTRACT_ID, Coin, Nullifier},
+    money::MoneyFunction,
+};
+use darkfi_serial::{serialize, Decodable};
+use log::debug;
+
+use super::constants::{MEMPOOL_MAX_BYTES, MEMPOOL_MAX_TXS, MEMPOOL_TX_EXPIRY};
+use crate::{tx::Transaction, util::time::Timestamp, Error, Result};
+
+/// Extract the nullifiers a transaction reveals, given the state updates
+/// its calls produced. All money contract state updates start with the
+/// function byte, followed by the revealed nullifiers.
+pub fn spent_nullifiers(tx: &Transaction, updates: &[Vec<u8>]) -> Vec<Nullifier> {
+    let mut nullifiers = vec![];
+    for (call, update) in tx.calls.iter().zip(updates.iter()) {
+        if call.contract_id != *MONEY_CONTRACT_ID || update.is_empty() {
+            continue
+        }
+
+        match Vec::<Nullifier>::decode(Cursor::new(&update[1..])) {
+            Ok(revealed) => nullifiers.extend(revealed),
+            Err(e) => {
+                debug!(target: "consensus::mempool", "Failed decoding money state update: {}", e)
+            }
+        }
+    }
+    nullifiers
+}
+
+/// Extract the coins each call of a transaction mints, in call order, given
+/// the state updates they produced. All money contract state updates hold the
+/// minted coins right after the revealed nullifiers. Other calls mint none.
+pub fn minted_coins(tx: &Transaction, updates: &[Vec<u8>]) -> Vec<Vec<Coin>> {
+    let mut coins = vec![vec![]; tx.calls.len()];
+    for ((call, update), call_coins) in tx.calls.iter().zip(updates.iter()).zip(coins.iter_mut()) {
+        if call.contract_id != *MONEY_CONTRACT_ID || update.is_empty() {
+            continue
+        }
+
+        let mut decoder = Cursor::new(&update[1..]);
+        let minted =
+            Vec::<Nullifier>::decode(&mut decoder).and_then(|_| Vec::<Coin>::decode(&mut decoder));
+        match minted {
+            Ok(minted) => *call_coins = minted,
+            Err(e) => {
+                debug!(target: "consensus::mempool", "Failed decoding money state update: {}", e)
+            }
+        }
+    }
+    coins
+}
+
+/// Sum the fees paid by a transaction, given the state updates its calls
+/// produced. Fee calls of the money contract produce an update holding the
+/// spent nullifiers, the change coins and the paid fee.
+pub fn paid_fee(tx: &Transaction, updates: &[Vec<u8>]) -> u64 {
+    let mut fee = 0_u64;
+    for (call, update) in tx.calls.iter().zip(updates.iter()) {
+        if call.contract_id != *MONEY_CONTRACT_ID ||
+            update.first() != Some(&(MoneyFunction::Fee as u8))
+        {
+            continue
+        }
+
+        let mut decoder = Cursor::new(&update[1..]);
+        let paid = Vec::<Nullifier>::decode(&mut decoder)
+            .and_then(|_| Vec::<Coin>::decode(&mut decoder))
+            .and_then(|_| u64::decode(&mut decoder));
+        match paid {
+            Ok(v) => fee = fee.saturating_add(v),
+            Err(e) => {
+                debug!(target: "consensus::mempool", "Failed decoding money fee update: {}", e)
+            }
+        }
+    }
+    fee
+}
+
+/// Check if a transaction is a faucet airdrop, given the state updates its
+/// calls produced. Airdrops are money transfers with only clear inputs, which
+/// have to be signed by a faucet key, and mint coins out of nothing. They're
+/// exempt from fees, since nobody holds any coins to pay with before them.
+pub fn is_faucet_airdrop(tx: &Transaction, updates: &[Vec<u8>]) -> bool {
+    !tx.calls.is_empty() &&
+        tx.calls.iter().zip(updates.iter()).all(|(call, update)| {
+            call.contract_id == *MONEY_CONTRACT_ID &&
+                update.first() == Some(&(MoneyFunction::Transfer as u8)) &&
+                matches!(Vec::<Nullifier>::decode(Cursor::new(&update[1..])), Ok(v) if v.is_empty())
+        })
+}
+
+/// Mempool limits.
+#[derive(Clone, Debug)]
+pub struct MempoolConfig {
+    /// Maximum number of transactions
+    pub max_txs: usize,
+    /// Maximum serialized size of all transactions
+    pub max_bytes: usize,
+    /// Seconds after which a transaction gets dropped
+    pub expiry: u64,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self { max_txs: MEMPOOL_MAX_TXS, max_bytes: MEMPOOL_MAX_BYTES, expiry: MEMPOOL_TX_EXPIRY }
+    }
+}
+
+/// A transaction waiting in the mempool.
+#[derive(Clone, Debug)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    /// Serialized size of the transaction
+    pub size: usize,
+    /// Fee paid by the transaction
+    pub fee: u64,
+    /// Gas used by the transaction's calls
+    pub gas_used: u64,
+    /// Nullifiers revealed by the transaction
+    pub nullifiers: Vec<Nullifier>,
+    /// When the transaction entered the mempool
+    pub added: Timestamp,
+    /// Arrival order, used to break fee ties
+    seq: u64,
+}
+
+impl MempoolEntry {
+    /// Compare by fee per byte, older transactions first on ties.
+    /// Greater means the entry should be included first.
+    fn priority_cmp(&self, other: &Self) -> Ordering {
+        let a = self.fee as u128 * other.size as u128;
+        let b = other.fee as u128 * self.size as u128;
+        a.cmp(&b).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Pool of valid transactions waiting to be included in a block.
+/// Transactions are indexed by hash and by the nullifiers they reveal, so
+/// double spends of a mempool transaction get rejected right away. Once
+/// the pool is full, the lowest paying transactions get evicted to make
+/// room for better ones.
+#[derive(Clone, Debug)]
+pub struct Mempool {
+    config: MempoolConfig,
+    txs: HashMap<blake3::Hash, MempoolEntry>,
+    nullifiers: HashMap<[u8; 32], blake3::Hash>,
+    bytes: usize,
+    next_seq: u64,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(MempoolConfig::default())
+    }
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self { config, txs: HashMap::new(), nullifiers: HashMap::new(), bytes: 0, next_seq: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    /// Serialized size of all transactions in the pool.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn contains(&self, tx_hash: &blake3::Hash) -> bool {
+        self.txs.contains_key(tx_hash)
+    }
+
+    pub fn get(&self, tx_hash: &blake3::Hash) -> Option<&MempoolEntry> {
+        self.txs.get(tx_hash)
+    }
+
+    /// All transactions in the pool, in arrival order.
+    pub fn txs(&self) -> Vec<Transaction> {
+        let mut entries: Vec<&MempoolEntry> = self.txs.values().collect();
+        entries.sort_by_key(|e| e.seq);
+        entries.into_iter().map(|e| e.tx.clone()).collect()
+    }
+
+    /// Add a verified transaction. Evicts lower priority transactions if
+    /// the pool is full, or fails if the transaction doesn't beat them.
+    pub fn insert(
+        &mut self,
+        tx: Transaction,
+        fee: u64,
+        gas_used: u64,
+        nullifiers: Vec<Nullifier>,
+        now: Timestamp,
+    ) -> Result<()> {
+        let bytes = serialize(&tx);
+        let tx_hash = blake3::hash(&bytes);
+        if self.txs.contains_key(&tx_hash) {
+            return Err(Error::TxAlreadyInMempool)
+        }
+
+        for nullifier in &nullifiers {
+            if let Some(other) = self.nullifiers.get(&nullifier.to_bytes()) {
+                return Err(Error::TxConflictsWithMempool(other.to_string()))
+            }
+        }
+
+        let entry = MempoolEntry {
+            tx,
+            size: bytes.len(),
+            fee,
+            gas_used,
+            nullifiers,
+            added: now,
+            seq: self.next_seq,
+        };
+        if entry.size > self.config.max_bytes || self.config.max_txs == 0 {
+            return Err(Error::MempoolFull)
+        }
+
+        // Find out what to evict before touching anything, so a rejected
+        // transaction leaves the pool as it was.
+        let mut evict = vec![];
+        let mut count = self.txs.len() + 1;
+        let mut size = self.bytes + entry.size;
+        if count > self.config.max_txs || size > self.config.max_bytes {
+            let mut candidates: Vec<(&blake3::Hash, &MempoolEntry)> = self.txs.iter().collect();
+            candidates.sort_by(|a, b| a.1.priority_cmp(b.1));
+            for (hash, candidate) in candidates {
+                if count <= self.config.max_txs && size <= self.config.max_bytes {
+                    break
+                }
+                if candidate.priority_cmp(&entry) != Ordering::Less {
+                    return Err(Error::MempoolFull)
+                }
+                evict.push(*hash);
+                count -= 1;
+                size -= candidate.size;
+            }
+        }
+
+        for hash in evict {
+            debug!(target: "consensus::mempool", "Evicting tx {}", hash);
+            self.remove(&hash);
+        }
+
+        for nullifier in &entry.nullifiers {
+            self.nullifiers.insert(nullifier.to_bytes(), tx_hash);
+        }
+        self.bytes += entry.size;
+        self.next_seq += 1;
+        self.txs.insert(tx_hash, entry);
+        Ok(())
+    }
+
+    /// Remove a transaction from the pool.
+    pub fn remove(&mut self, tx_hash: &blake3::Hash) -> Option<MempoolEntry> {
+        let entry = self.txs.remove(tx_hash)?;
+        for nullifier in &entry.nullifiers {
+            self.nullifiers.remove(&nullifier.to_bytes());
+        }
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// Remove transactions that got into a block, along with any pool
+    /// transaction revealing the same nullifiers, since those can no
+    /// longer be valid.
+    pub fn remove_confirmed(&mut self, txs: &[Transaction], nullifiers: &[Nullifier]) {
+        for tx in txs {
+            self.remove(&blake3::hash(&serialize(tx)));
+        }
+
+        for nullifier in nullifiers {
+            if let Some(hash) = self.nullifiers.get(&nullifier.to_bytes()).cloned() {
+                debug!(target: "consensus::mempool", "Dropping tx {} spending a confirmed nullifier", hash);
+                self.remove(&hash);
+            }
+        }
+    }
+
+    /// Drop transactions that stayed in the pool for longer than the
+    /// configured expiry. Returns the number of dropped transactions.
+    pub fn expire(&mut self, now: Timestamp) -> usize {
+        let expiry = self.config.expiry as i64;
+        let expired: Vec<blake3::Hash> = self
+            .txs
+            .iter()
+            .filter(|(_, e)| now.0 - e.added.0 > expiry)
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        for hash in &expired {
+            debug!(target: "consensus::mempool", "Expiring tx {}", hash);
+            self.remove(hash);
+        }
+
+        expired.len()
+    }
+
+    /// Pick the highest priority transactions fitting in a block, within
+    /// its transaction count, byte and gas budgets, skipping the `exclude`d
+    /// ones.
+    pub fn select(
+        &self,
+        max_txs: usize,
+        max_bytes: usize,
+        max_gas: u64,
+        exclude: &HashSet<blake3::Hash>,
+    ) -> Vec<Transaction> {
+        let mut entries: Vec<(&blake3::Hash, &MempoolEntry)> =
+            self.txs.iter().filter(|(hash, _)| !exclude.contains(hash)).collect();
+        entries.sort_by(|a, b| b.1.priority_cmp(a.1));
+
+        let mut selected = vec![];
+        let mut size = 0;
+        let mut gas = 0_u64;
+        for (_, entry) in entries {
+            if selected.len() == max_txs {
+                break
+            }
+            if size + entry.size > max_bytes || gas.saturating_add(entry.gas_used) > max_gas {
+                continue
+            }
+            size += entry.size;
+            gas += entry.gas_used;
+            selected.push(entry.tx.clone());
+        }
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkfi_sdk::{pasta::pallas, tx::ContractCall};
+    use darkfi_serial::Encodable;
+
+    fn tx(n: u8, len: usize) -> Transaction {
+        let call = ContractCall { contract_id: *MONEY_CONTRACT_ID, data: vec![n; len] };
+        Transaction { calls: vec![call], proofs: vec![], signatures: vec![] }
+    }
+
+    fn hash(tx: &Transaction) -> blake3::Hash {
+        blake3::hash(&serialize(tx))
+    }
+
+    #[test]
+    fn mempool_limits_and_conflicts() {
+        let config = MempoolConfig { max_txs: 2, max_bytes: 1024, expiry: 10 };
+        let mut mempool = Mempool::new(config);
+        let nullifier = Nullifier::from(pallas::Base::from(1));
+
+        mempool.insert(tx(0, 10), 10, 100, vec![nullifier], Timestamp(0)).unwrap();
+        assert!(matches!(
+            mempool.insert(tx(0, 10), 10, 100, vec![], Timestamp(0)),
+            Err(Error::TxAlreadyInMempool)
+        ));
+        assert!(matches!(
+            mempool.insert(tx(1, 10), 10, 100, vec![nullifier], Timestamp(0)),
+            Err(Error::TxConflictsWithMempool(_))
+        ));
+
+        mempool.insert(tx(1, 10), 5, 100, vec![], Timestamp(1)).unwrap();
+
+        // Full, and not paying more than anyone
+        assert!(matches!(
+            mempool.insert(tx(2, 10), 1, 100, vec![], Timestamp(2)),
+            Err(Error::MempoolFull)
+        ));
+        assert_eq!(mempool.len(), 2);
+
+        // Paying more evicts the cheapest one
+        mempool.insert(tx(3, 10), 20, 300, vec![], Timestamp(3)).unwrap();
+        assert!(!mempool.contains(&hash(&tx(1, 10))));
+
+        // Highest fee rate first, within the byte budget
+        let selected = mempool.select(10, 1024, 1000, &HashSet::new());
+        assert_eq!(selected, vec![tx(3, 10), tx(0, 10)]);
+        let size = mempool.get(&hash(&tx(3, 10))).unwrap().size;
+        let selected = mempool.select(10, size + 1, 1000, &HashSet::new());
+        assert_eq!(selected, vec![tx(3, 10)]);
+
+        // And within the gas budget
+        let selected = mempool.select(10, 1024, 399, &HashSet::new());
+        assert_eq!(selected, vec![tx(3, 10)]);
+        let selected = mempool.select(10, 1024, 299, &HashSet::new());
+        assert_eq!(selected, vec![tx(0, 10)]);
+
+        // Confirming a tx spending the same nullifier drops the pool one
+        mempool.remove_confirmed(&[], &[nullifier]);
+        assert!(!mempool.contains(&hash(&tx(0, 10))));
+
+        assert_eq!(mempool.expire(Timestamp(20)), 1);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.bytes(), 0);
+    }
+
+    #[test]
+    fn fee_and_nullifiers_from_updates() {
+        let nullifier = Nullifier::from(pallas::Base::from(7));
+        let coin = Coin::from(pallas::Base::from(8));
+
+        let mut fee_update = vec![MoneyFunction::Fee as u8];
+        vec![nullifier].encode(&mut fee_update).unwrap();
+        vec![coin].encode(&mut fee_update).unwrap();
+        42_u64.encode(&mut fee_update).unwrap();
+
+        // Transfers spending nothing are airdrops
+        let mut airdrop_update = vec![MoneyFunction::Transfer as u8];
+        Vec::<Nullifier>::new().encode(&mut airdrop_update).unwrap();
+        vec![coin].encode(&mut airdrop_update).unwrap();
+        assert!(is_faucet_airdrop(&tx(0, 10), &[airdrop_update.clone()]));
+
+        // A transfer update has no fee
+        let mut transfer_update = vec![MoneyFunction::Transfer as u8];
+        vec![nullifier].encode(&mut transfer_update).unwrap();
+        vec![coin].encode(&mut transfer_update).unwrap();
+
+        let mut tx = tx(0, 10);
+        tx.calls.push(tx.calls[0].clone());
+        let updates = vec![transfer_update, fee_update];
+
+        assert_eq!(paid_fee(&tx, &updates), 42);
+        assert_eq!(spent_nullifiers(&tx, &updates).len(), 2);
+        assert!(!is_faucet_airdrop(&tx, &updates));
+        assert!(!is_faucet_airdrop(&tx, &[airdrop_update, updates[1].clone()]));
+    }
+}
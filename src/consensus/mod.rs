@@ -35,9 +35,19 @@ pub use lead_info::{LeadInfo, LeadProof};
 pub mod state;
 pub use state::SlotCheckpoint;
 
+/// Transactions Merkle tree of block headers
+pub mod tx_merkle;
+pub use tx_merkle::{verify_tx_inclusion, TxInclusionProof};
+
+/// Pending transactions pool
+pub mod mempool;
+pub use mempool::{Mempool, MempoolConfig};
+
 /// Consensus validator state
 pub mod validator;
-pub use validator::{ValidatorState, ValidatorStatePtr};
+pub use validator::{
+    event_to_json, EventSubscriber, ValidatorState, ValidatorStatePtr, VerifiedTx,
+};
 
 /// P2P net protocols
 pub mod proto;
@@ -25,13 +25,14 @@ use url::Url;
 use crate::{
     consensus::{BlockProposal, ValidatorStatePtr},
     net::{
-        ChannelPtr, MessageSubscription, P2pPtr, ProtocolBase, ProtocolBasePtr,
-        ProtocolJobsManager, ProtocolJobsManagerPtr,
+        constants::BAN_SCORE_INVALID_PROPOSAL, ChannelPtr, MessageSubscription, P2pPtr,
+        ProtocolBase, ProtocolBasePtr, ProtocolJobsManager, ProtocolJobsManagerPtr,
     },
-    Result,
+    Error, Result,
 };
 
 pub struct ProtocolProposal {
+    channel: ChannelPtr,
     proposal_sub: MessageSubscription<BlockProposal>,
     jobsman: ProtocolJobsManagerPtr,
     state: ValidatorStatePtr,
@@ -54,6 +55,7 @@ impl ProtocolProposal {
         let channel_address = channel.address();
 
         Ok(Arc::new(Self {
+            channel: channel.clone(),
             proposal_sub,
             jobsman: ProtocolJobsManager::new("ProposalProtocol", channel),
             state,
@@ -121,6 +123,21 @@ impl ProtocolProposal {
                         "receive_proposal error: {}",
                         e
                     );
+
+                    // Proposals that can't be valid no matter our own
+                    // state mean the peer relayed them without checking
+                    if matches!(
+                        e,
+                        Error::InvalidSignature |
+                            Error::ProposalHashesMissmatchError |
+                            Error::ProposalHeadersMissmatchError |
+                            Error::ProposalTxsExceedCapError |
+                            Error::LeaderProofVerification
+                    ) {
+                        drop(lock);
+                        let reason = format!("invalid proposal: {}", e);
+                        self.channel.misbehaving(BAN_SCORE_INVALID_PROPOSAL, &reason).await;
+                    }
                     continue
                 }
             }
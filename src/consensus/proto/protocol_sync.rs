@@ -23,7 +23,9 @@ use smol::Executor;
 
 use crate::{
     consensus::{
-        block::{BlockInfo, BlockOrder, BlockResponse},
+        block::{
+            BlockBodyRequest, BlockInfo, BlockOrder, BlockResponse, HeaderRequest, HeaderResponse,
+        },
         state::{SlotCheckpoint, SlotCheckpointRequest, SlotCheckpointResponse},
         ValidatorStatePtr,
     },
@@ -31,15 +33,19 @@ use crate::{
         ChannelPtr, MessageSubscription, P2pPtr, ProtocolBase, ProtocolBasePtr,
         ProtocolJobsManager, ProtocolJobsManagerPtr,
     },
-    Result,
+    Error, Result,
 };
 
 // Constant defining how many blocks we send during syncing.
 const BATCH: u64 = 10;
+// Constant defining how many headers we send during syncing.
+const HEADERS_BATCH: u64 = 100;
 
 pub struct ProtocolSync {
     channel: ChannelPtr,
     request_sub: MessageSubscription<BlockOrder>,
+    header_request_sub: MessageSubscription<HeaderRequest>,
+    body_request_sub: MessageSubscription<BlockBodyRequest>,
     slot_checkpoin_request_sub: MessageSubscription<SlotCheckpointRequest>,
     block_sub: MessageSubscription<BlockInfo>,
     slot_checkpoints_sub: MessageSubscription<SlotCheckpoint>,
@@ -58,11 +64,15 @@ impl ProtocolSync {
     ) -> Result<ProtocolBasePtr> {
         let msg_subsystem = channel.get_message_subsystem();
         msg_subsystem.add_dispatch::<BlockOrder>().await;
+        msg_subsystem.add_dispatch::<HeaderRequest>().await;
+        msg_subsystem.add_dispatch::<BlockBodyRequest>().await;
         msg_subsystem.add_dispatch::<SlotCheckpointRequest>().await;
         msg_subsystem.add_dispatch::<BlockInfo>().await;
         msg_subsystem.add_dispatch::<SlotCheckpoint>().await;
 
         let request_sub = channel.subscribe_msg::<BlockOrder>().await?;
+        let header_request_sub = channel.subscribe_msg::<HeaderRequest>().await?;
+        let body_request_sub = channel.subscribe_msg::<BlockBodyRequest>().await?;
         let slot_checkpoin_request_sub = channel.subscribe_msg::<SlotCheckpointRequest>().await?;
         let block_sub = channel.subscribe_msg::<BlockInfo>().await?;
         let slot_checkpoints_sub = channel.subscribe_msg::<SlotCheckpoint>().await?;
@@ -70,6 +80,8 @@ impl ProtocolSync {
         Ok(Arc::new(Self {
             channel: channel.clone(),
             request_sub,
+            header_request_sub,
+            body_request_sub,
             slot_checkpoin_request_sub,
             block_sub,
             slot_checkpoints_sub,
@@ -106,8 +118,11 @@ impl ProtocolSync {
 
             // Extra validations can be added here
             let key = order.slot;
-            let blocks = match self.state.read().await.blockchain.get_blocks_after(key, BATCH) {
-                Ok(v) => v,
+            let response = match self.state.read().await.blockchain.get_blocks_after(key, BATCH) {
+                Ok(blocks) => BlockResponse { blocks, pruned: None },
+                Err(Error::BlockPruned(slot)) => {
+                    BlockResponse { blocks: vec![], pruned: Some(slot) }
+                }
                 Err(e) => {
                     error!(
                         target: "consensus::protocol_sync::handle_receive_request()",
@@ -120,10 +135,9 @@ impl ProtocolSync {
             debug!(
                 target: "consensus::protocol_sync::handle_receive_request()",
                 "Found {} blocks",
-                blocks.len()
+                response.blocks.len()
             );
 
-            let response = BlockResponse { blocks };
             if let Err(e) = self.channel.send(response).await {
                 error!(
                     target: "consensus::protocol_sync::handle_receive_request()",
@@ -134,6 +148,117 @@ impl ProtocolSync {
         }
     }
 
+    async fn handle_receive_header_request(self: Arc<Self>) -> Result<()> {
+        debug!(
+            target: "consensus::protocol_sync::handle_receive_header_request()",
+            "START"
+        );
+        loop {
+            let request = match self.header_request_sub.receive().await {
+                Ok(v) => v,
+                Err(e) => {
+                    debug!(
+                        target: "consensus::protocol_sync::handle_receive_header_request()",
+                        "recv fail: {}",
+                        e
+                    );
+                    continue
+                }
+            };
+
+            debug!(
+                target: "consensus::protocol_sync::handle_receive_header_request()",
+                "received {:?}",
+                request
+            );
+
+            let (headers, blocks) = match self
+                .state
+                .read()
+                .await
+                .blockchain
+                .get_headers_after(request.slot, HEADERS_BATCH)
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    // We reply with no headers, so the peer doesn't wait for them
+                    error!(
+                        target: "consensus::protocol_sync::handle_receive_header_request()",
+                        "get_headers_after fail: {}",
+                        e
+                    );
+                    (vec![], vec![])
+                }
+            };
+            debug!(
+                target: "consensus::protocol_sync::handle_receive_header_request()",
+                "Found {} headers",
+                headers.len()
+            );
+
+            let response = HeaderResponse { headers, blocks };
+            if let Err(e) = self.channel.send(response).await {
+                error!(
+                    target: "consensus::protocol_sync::handle_receive_header_request()",
+                    "channel send fail: {}",
+                    e
+                )
+            };
+        }
+    }
+
+    async fn handle_receive_body_request(self: Arc<Self>) -> Result<()> {
+        debug!(
+            target: "consensus::protocol_sync::handle_receive_body_request()",
+            "START"
+        );
+        loop {
+            let request = match self.body_request_sub.receive().await {
+                Ok(v) => v,
+                Err(e) => {
+                    debug!(
+                        target: "consensus::protocol_sync::handle_receive_body_request()",
+                        "recv fail: {}",
+                        e
+                    );
+                    continue
+                }
+            };
+
+            debug!(
+                target: "consensus::protocol_sync::handle_receive_body_request()",
+                "received request for {} blocks",
+                request.hashes.len()
+            );
+
+            // We only serve up to a batch of blocks per request
+            let hashes = &request.hashes[..request.hashes.len().min(BATCH as usize)];
+            let response = match self.state.read().await.blockchain.get_blocks_by_hash(hashes) {
+                Ok(blocks) => BlockResponse { blocks, pruned: None },
+                Err(Error::BlockPruned(slot)) => {
+                    BlockResponse { blocks: vec![], pruned: Some(slot) }
+                }
+                Err(e) => {
+                    // We reply with no blocks, so the peer doesn't wait for them
+                    error!(
+                        target: "consensus::protocol_sync::handle_receive_body_request()",
+                        "get_blocks_by_hash fail: {}",
+                        e
+                    );
+                    BlockResponse { blocks: vec![], pruned: None }
+                }
+            };
+
+            if let Err(e) = self.channel.send(response).await {
+                error!(
+                    target: "consensus::protocol_sync::handle_receive_body_request()",
+                    "channel send fail: {}",
+                    e
+                )
+            };
+        }
+    }
+
     async fn handle_receive_block(self: Arc<Self>) -> Result<()> {
         debug!(target: "consensus::protocol_sync::handle_receive_block()", "START");
         let exclude_list = vec![self.channel.address()];
@@ -364,6 +489,14 @@ impl ProtocolBase for ProtocolSync {
         debug!(target: "consensus::protocol_sync::start()", "START");
         self.jobsman.clone().start(executor.clone());
         self.jobsman.clone().spawn(self.clone().handle_receive_request(), executor.clone()).await;
+        self.jobsman
+            .clone()
+            .spawn(self.clone().handle_receive_header_request(), executor.clone())
+            .await;
+        self.jobsman
+            .clone()
+            .spawn(self.clone().handle_receive_body_request(), executor.clone())
+            .await;
         self.jobsman
             .clone()
             .spawn(self.clone().handle_receive_slot_checkpoint_request(), executor.clone())
@@ -98,7 +98,7 @@ impl ProtocolSyncConsensus {
             for fork in &lock.consensus.forks {
                 forks.push(fork.clone().into());
             }
-            let unconfirmed_txs = lock.unconfirmed_txs.clone();
+            let unconfirmed_txs = lock.unconfirmed_txs.txs();
             let slot_checkpoints = lock.consensus.slot_checkpoints.clone();
             let previous_leaders = lock.consensus.previous_leaders.clone();
             let nullifiers = lock.consensus.nullifiers.clone();
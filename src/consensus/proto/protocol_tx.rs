@@ -45,6 +45,10 @@ impl net::Message for Transaction {
     fn name() -> &'static str {
         "tx"
     }
+
+    fn rate_limit() -> Option<net::RateLimit> {
+        Some(net::RateLimit::new(100, 20.0))
+    }
 }
 
 impl ProtocolTx {
@@ -93,7 +97,7 @@ impl ProtocolTx {
 
             let tx_copy = (*tx).clone();
 
-            // Nodes use unconfirmed_txs vector as seen_txs pool.
+            // Nodes use the unconfirmed_txs mempool as seen_txs pool.
             if self.state.write().await.append_tx(tx_copy.clone()).await {
                 if let Err(e) = self.p2p.broadcast_with_exclude(tx_copy, &exclude_list).await {
                     error!(
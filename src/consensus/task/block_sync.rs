@@ -16,108 +16,548 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use async_std::{future::timeout, sync::Arc};
+use futures::future::join_all;
+use log::{debug, info, warn};
+use rand::Rng;
+use serde_json::json;
+
 use crate::{
     consensus::{
-        block::{BlockOrder, BlockResponse},
-        state::{SlotCheckpointRequest, SlotCheckpointResponse},
-        ValidatorStatePtr,
+        block::{BlockBodyRequest, BlockInfo, BlockResponse, HeaderRequest, HeaderResponse},
+        constants::{
+            BLOCK_MAGIC_BYTES, PI_MU_RHO_INDEX, PI_MU_Y_INDEX, PI_SIGMA1_INDEX, PI_SIGMA2_INDEX,
+            SYNC_BODIES_BATCH, SYNC_MAX_RETRIES, SYNC_REQUEST_TIMEOUT,
+        },
+        lead_coin::LeadCoin,
+        state::{SlotCheckpoint, SlotCheckpointRequest, SlotCheckpointResponse},
+        LeadInfo, ValidatorState, ValidatorStatePtr,
     },
-    net, Result,
+    net::{self, constants::BAN_SCORE_INVALID_SYNC_RESPONSE, ChannelPtr, MessageSubscription},
+    rpc::jsonrpc::JsonNotification,
+    zk::ProofBatch,
+    Error, Result,
 };
-use log::{debug, info, warn};
+
+/// A peer the blockchain is synced from, along with its subscriptions
+/// to the sync responses.
+struct SyncPeer {
+    channel: ChannelPtr,
+    /// Last slot the peer reported as pruned, if any
+    pruned: Option<u64>,
+    slot_checkpoint_sub: MessageSubscription<SlotCheckpointResponse>,
+    header_sub: MessageSubscription<HeaderResponse>,
+    block_sub: MessageSubscription<BlockResponse>,
+}
+
+impl SyncPeer {
+    async fn new(channel: ChannelPtr) -> Result<Self> {
+        let msg_subsystem = channel.get_message_subsystem();
+        msg_subsystem.add_dispatch::<SlotCheckpointResponse>().await;
+        msg_subsystem.add_dispatch::<HeaderResponse>().await;
+        msg_subsystem.add_dispatch::<BlockResponse>().await;
+
+        let slot_checkpoint_sub = channel.subscribe_msg::<SlotCheckpointResponse>().await?;
+        let header_sub = channel.subscribe_msg::<HeaderResponse>().await?;
+        let block_sub = channel.subscribe_msg::<BlockResponse>().await?;
+
+        Ok(Self { channel, pruned: None, slot_checkpoint_sub, header_sub, block_sub })
+    }
+
+    /// Check if the peer can serve the block of the given slot, based on
+    /// what it reported as pruned.
+    fn serves(&self, slot: u64) -> bool {
+        self.pruned.map_or(true, |pruned| slot > pruned)
+    }
+
+    async fn unsubscribe(&self) {
+        self.slot_checkpoint_sub.unsubscribe().await;
+        self.header_sub.unsubscribe().await;
+        self.block_sub.unsubscribe().await;
+    }
+
+    /// Request the slot checkpoints after given slot.
+    async fn slot_checkpoints_after(&self, slot: u64) -> Result<SlotCheckpointResponse> {
+        self.channel.send(SlotCheckpointRequest { slot }).await?;
+        let resp =
+            timeout(Duration::from_secs(SYNC_REQUEST_TIMEOUT), self.slot_checkpoint_sub.receive())
+                .await??;
+
+        Ok((*resp).clone())
+    }
+
+    /// Request the headers after given slot.
+    async fn headers_after(&self, slot: u64) -> Result<HeaderResponse> {
+        self.channel.send(HeaderRequest { slot }).await?;
+        let resp =
+            timeout(Duration::from_secs(SYNC_REQUEST_TIMEOUT), self.header_sub.receive()).await??;
+
+        Ok((*resp).clone())
+    }
+
+    /// Request the full data of the blocks with given hashes, and check the
+    /// response produces the same hashes.
+    async fn bodies(&self, hashes: &[blake3::Hash]) -> Result<Vec<BlockInfo>> {
+        self.channel.send(BlockBodyRequest { hashes: hashes.to_vec() }).await?;
+        let resp =
+            timeout(Duration::from_secs(SYNC_REQUEST_TIMEOUT), self.block_sub.receive()).await??;
+
+        if let Some(slot) = resp.pruned {
+            return Err(Error::BlockPruned(slot))
+        }
+
+        // Peers that failed serving the blocks reply with none of them
+        if resp.blocks.is_empty() && !hashes.is_empty() {
+            return Err(Error::BlockNotFound(hashes[0].to_string()))
+        }
+
+        if resp.blocks.len() != hashes.len() {
+            let e = format!("Received {} blocks, expected {}", resp.blocks.len(), hashes.len());
+            return Err(Error::InvalidSyncResponse(e))
+        }
+
+        for (block, hash) in resp.blocks.iter().zip(hashes) {
+            if block.blockhash() != *hash {
+                let e = format!("Received block doesn't match requested {}", hash);
+                return Err(Error::InvalidSyncResponse(e))
+            }
+        }
+
+        Ok(resp.blocks.clone())
+    }
+}
 
 /// async task used for block syncing.
+/// The node first syncs the slot checkpoints, then the headers of the
+/// blocks after its last one, keeping the longest chain its peers reply
+/// with after verifying their hash links and leader signatures and proofs,
+/// whose public inputs are checked against the synced slot checkpoints.
+/// Once the header chain is known, the block bodies are downloaded from all
+/// connected peers in parallel, and applied in order. Peers that time out
+/// are dropped from the sync, and peers sending invalid data, or blocks that
+/// fail to apply, are also penalized. Peers on a competing fork are not.
+/// Peers that pruned some blocks are only asked for the blocks after them.
+/// Progress is reported through the "sync" subscriber.
 pub async fn block_sync_task(p2p: net::P2pPtr, state: ValidatorStatePtr) -> Result<()> {
     info!(target: "consensus::block_sync", "Starting blockchain sync...");
-    // Getting a random connected channel to ask from peers
-    match p2p.clone().random_channel().await {
-        Some(channel) => {
-            let msg_subsystem = channel.get_message_subsystem();
-
-            // Communication setup for slot checkpoints
-            msg_subsystem.add_dispatch::<SlotCheckpointResponse>().await;
-            let slot_checkpoint_response_sub =
-                channel.subscribe_msg::<SlotCheckpointResponse>().await?;
-
-            // Communication setup for blocks
-            msg_subsystem.add_dispatch::<BlockResponse>().await;
-            let block_response_sub = channel.subscribe_msg::<BlockResponse>().await?;
-
-            // Node loops until both slot checkpoints and blocks have been synced
-            let mut slot_checkpoints_synced = false;
-            let mut blocks_synced = false;
-            loop {
-                // Node sends the last known slot checkpoint of the canonical blockchain
-                // and loops until the response is the same slot (used to utilize batch requests).
-                let mut last = state.read().await.blockchain.last_slot_checkpoint()?;
-                info!(target: "consensus::block_sync", "Last known slot checkpoint: {:?}", last.slot);
-
-                loop {
-                    // Node creates a `SlotCheckpointRequest` and sends it
-                    let request = SlotCheckpointRequest { slot: last.slot };
-                    channel.send(request).await?;
-
-                    // Node stores response data.
-                    let resp = slot_checkpoint_response_sub.receive().await?;
-
-                    // Verify and store retrieved checkpoints
-                    debug!(target: "consensus::block_sync", "block_sync_task(): Processing received slot checkpoints");
-                    state.write().await.receive_slot_checkpoints(&resp.slot_checkpoints).await?;
-
-                    let last_received = state.read().await.blockchain.last_slot_checkpoint()?;
-                    info!(target: "consensus::block_sync", "Last received slot checkpoint: {:?}", last_received.slot);
-
-                    if last.slot == last_received.slot {
-                        break
-                    }
 
-                    blocks_synced = false;
-                    last = last_received;
-                }
+    let mut peers = sync_peers(&p2p).await?;
+    if peers.is_empty() {
+        warn!(target: "consensus::block_sync", "Node is not connected to other nodes");
+        info!(target: "consensus::block_sync", "Blockchain synced!");
+        return Ok(())
+    }
 
-                // We force a recheck of slot checkpoints after blocks have been synced
-                if blocks_synced {
-                    slot_checkpoints_synced = true;
+    let result = sync(&state, &mut peers).await;
+
+    for peer in &peers {
+        peer.unsubscribe().await;
+    }
+    result?;
+
+    info!(target: "consensus::block_sync", "Blockchain synced!");
+    Ok(())
+}
+
+/// Sync the slot checkpoints and the headers of the blocks, without their
+/// bodies, and return the hash of the block in the given slot of the synced
+/// header chain. Used to check a snapshot of that slot belongs to the chain
+/// the network agrees on before importing it.
+pub async fn snapshot_blockhash_task(
+    p2p: net::P2pPtr,
+    state: ValidatorStatePtr,
+    slot: u64,
+) -> Result<blake3::Hash> {
+    info!(target: "consensus::block_sync", "Syncing headers up to snapshot slot {}...", slot);
+
+    let mut peers = sync_peers(&p2p).await?;
+    if peers.is_empty() {
+        warn!(target: "consensus::block_sync", "Node is not connected to other nodes");
+        return Err(Error::SyncPeersExhausted)
+    }
+
+    let result = async {
+        sync_slot_checkpoints(&state, &mut peers).await?;
+        sync_headers(&state, &mut peers).await
+    }
+    .await;
+
+    for peer in &peers {
+        peer.unsubscribe().await;
+    }
+
+    match result?.into_iter().find(|(block_slot, _)| *block_slot == slot) {
+        Some((_, blockhash)) => Ok(blockhash),
+        None => Err(Error::InvalidSnapshot(format!("No synced block in slot {}", slot))),
+    }
+}
+
+/// Subscribe to the sync responses of the connected channels, excluding
+/// seeds.
+async fn sync_peers(p2p: &net::P2pPtr) -> Result<Vec<SyncPeer>> {
+    let channels: Vec<ChannelPtr> = {
+        let settings = p2p.settings();
+        let channels = p2p.channels().lock().await;
+        channels
+            .iter()
+            .filter(|(url, _)| !settings.seeds.contains(url))
+            .map(|(_, c)| c.clone())
+            .collect()
+    };
+
+    let mut peers = Vec::with_capacity(channels.len());
+    for channel in channels {
+        peers.push(SyncPeer::new(channel).await?);
+    }
+
+    Ok(peers)
+}
+
+/// Node loops until both slot checkpoints and blocks have been synced.
+async fn sync(state: &ValidatorStatePtr, peers: &mut Vec<SyncPeer>) -> Result<()> {
+    loop {
+        let slot_checkpoints_changed = sync_slot_checkpoints(state, peers).await?;
+
+        let expected = sync_headers(state, peers).await?;
+        let blocks_changed = !expected.is_empty();
+        sync_bodies(state, peers, &expected).await?;
+
+        if !slot_checkpoints_changed && !blocks_changed {
+            return Ok(())
+        }
+    }
+}
+
+/// Sync the slot checkpoints after the last known one. Returns `true` if
+/// new slot checkpoints were received.
+async fn sync_slot_checkpoints(
+    state: &ValidatorStatePtr,
+    peers: &mut Vec<SyncPeer>,
+) -> Result<bool> {
+    let mut last = state.read().await.blockchain.last_slot_checkpoint()?;
+    info!(target: "consensus::block_sync", "Last known slot checkpoint: {:?}", last.slot);
+
+    let mut changed = false;
+    loop {
+        let idx = random_peer(peers)?;
+        let resp = match peers[idx].slot_checkpoints_after(last.slot).await {
+            Ok(v) => v,
+            Err(e) => {
+                drop_peers(peers, vec![(idx, e)]).await;
+                continue
+            }
+        };
+
+        debug!(target: "consensus::block_sync", "sync_slot_checkpoints(): Processing received slot checkpoints");
+        state.write().await.receive_slot_checkpoints(&resp.slot_checkpoints).await?;
+
+        let last_received = state.read().await.blockchain.last_slot_checkpoint()?;
+        info!(target: "consensus::block_sync", "Last received slot checkpoint: {:?}", last_received.slot);
+
+        if last.slot == last_received.slot {
+            return Ok(changed)
+        }
+
+        changed = true;
+        last = last_received;
+    }
+}
+
+/// Fetch, verify and store the headers of the blocks after the last known
+/// one. All peers are asked for the headers after it, and the longest valid
+/// chain they reply with is kept, until none of them knows of more blocks.
+/// Only headers of slots we have the checkpoint of can be verified, the
+/// ones after them are fetched once the slot checkpoints are synced again.
+/// Returns the slot and hash of each new block, in order.
+async fn sync_headers(
+    state: &ValidatorStatePtr,
+    peers: &mut Vec<SyncPeer>,
+) -> Result<Vec<(u64, blake3::Hash)>> {
+    let mut last = state.read().await.blockchain.last()?;
+    info!(target: "consensus::block_sync", "Last known block: {:?} - {:?}", last.0, last.1);
+
+    let mut expected = vec![];
+    loop {
+        if peers.is_empty() {
+            return Err(Error::SyncPeersExhausted)
+        }
+
+        let requests = peers.iter().map(|peer| peer.headers_after(last.0));
+        let results = join_all(requests).await;
+
+        // Verify all the replies, keeping the one extending the chain the most.
+        // Peers with nothing after our last block reply with no headers, and
+        // peers on a competing fork with headers that don't extend it.
+        let mut best: Option<(HeaderResponse, Vec<(u64, blake3::Hash)>)> = None;
+        let mut failed = vec![];
+        {
+            let state = state.read().await;
+            for (idx, result) in results.into_iter().enumerate() {
+                let verified = result.and_then(|resp| {
+                    let verified = verify_headers(&state, last, &resp)?;
+                    Ok((resp, verified))
+                });
+                match verified {
+                    Ok((resp, Some(verified))) => {
+                        if verified.len() > best.as_ref().map_or(0, |(_, best)| best.len()) {
+                            best = Some((resp, verified));
+                        }
+                    }
+                    Ok((_, None)) => {
+                        let address = peers[idx].channel.address();
+                        debug!(target: "consensus::block_sync", "sync_headers(): Peer {} is on a competing fork", address);
+                    }
+                    Err(e) => failed.push((idx, e)),
                 }
+            }
+        }
+        drop_peers(peers, failed).await;
+
+        let Some((resp, verified)) = best else {
+            if peers.is_empty() {
+                return Err(Error::SyncPeersExhausted)
+            }
+            return Ok(expected)
+        };
+
+        state.write().await.blockchain.headers.insert(&resp.headers[..verified.len()])?;
+
+        last = *verified.last().unwrap();
+        info!(target: "consensus::block_sync", "Last received header: {:?} - {:?}", last.0, last.1);
+        expected.extend(verified);
+    }
+}
+
+/// Verify the received headers extend the chain ending at the block `last`,
+/// in the form of a tuple (`slot`, `blockhash`), and were produced by valid
+/// leaders. Returns the slot and hash of each block, up to the last one we
+/// have the slot checkpoint of, or `None` if the headers are on a competing
+/// fork, not extending our chain.
+fn verify_headers(
+    state: &ValidatorState,
+    last: (u64, blake3::Hash),
+    resp: &HeaderResponse,
+) -> Result<Option<Vec<(u64, blake3::Hash)>>> {
+    if resp.headers.len() != resp.blocks.len() {
+        let e = format!("Received {} headers for {} blocks", resp.headers.len(), resp.blocks.len());
+        return Err(Error::InvalidSyncResponse(e))
+    }
 
-                // Node sends the last known block hash of the canonical blockchain
-                // and loops until the response is the same block (used to utilize
-                // batch requests).
-                let mut last = state.read().await.blockchain.last()?;
-                info!(target: "consensus::block_sync", "Last known block: {:?} - {:?}", last.0, last.1);
+    if resp.headers.first().map_or(false, |header| header.previous != last.1) {
+        return Ok(None)
+    }
 
-                loop {
-                    // Node creates a `BlockOrder` and sends it
-                    let order = BlockOrder { slot: last.0, block: last.1 };
-                    channel.send(order).await?;
+    let last_checkpoint = state.blockchain.last_slot_checkpoint()?.slot;
+    let mut ret = Vec::with_capacity(resp.headers.len());
+    let mut leader_proofs = ProofBatch::new();
+    let (mut last_slot, mut last_hash) = last;
+    for (header, block) in resp.headers.iter().zip(resp.blocks.iter()) {
+        if block.magic != BLOCK_MAGIC_BYTES || block.header != header.headerhash() {
+            let e = format!("Block of slot {} doesn't match its header", header.slot);
+            return Err(Error::InvalidSyncResponse(e))
+        }
+
+        if header.previous != last_hash || header.slot <= last_slot {
+            let e = format!("Header of slot {} doesn't extend the chain", header.slot);
+            return Err(Error::InvalidSyncResponse(e))
+        }
+
+        // Leaders can't be verified without the slot checkpoint
+        if header.slot > last_checkpoint {
+            break
+        }
+
+        // Ignore leader validations if we oporate in single-node mode
+        if !state.single_node {
+            let lf = &block.lead_info;
+            if !lf.public_key.verify(block.header.as_bytes(), &lf.signature) {
+                warn!(target: "consensus::block_sync", "verify_headers(): Leader {} signature could not be verified", lf.public_key);
+                return Err(Error::InvalidSignature)
+            }
+            let checkpoint = state.consensus.get_slot_checkpoint(header.slot)?;
+            verify_leader_public_inputs(&checkpoint, lf)?;
+            leader_proofs.add(&lf.proof.proof, &lf.public_inputs);
+        }
 
-                    // Node stores response data.
-                    let resp = block_response_sub.receive().await?;
+        last_slot = header.slot;
+        last_hash = block.blockhash();
+        ret.push((last_slot, last_hash));
+    }
 
-                    // Verify and store retrieved blocks
-                    debug!(target: "consensus::block_sync", "block_sync_task(): Processing received blocks");
-                    state.write().await.receive_sync_blocks(&resp.blocks).await?;
+    if !state.single_node && !leader_proofs.verify(&state.lead_verifying_key) {
+        return Err(Error::LeaderProofVerification)
+    }
+
+    Ok(Some(ret))
+}
+
+/// Verify the public inputs of a leader proof were derived from the given
+/// slot checkpoint, so the proof attests a leader of the slot, instead of
+/// values of the peer's choosing.
+fn verify_leader_public_inputs(checkpoint: &SlotCheckpoint, lf: &LeadInfo) -> Result<()> {
+    if lf.public_inputs.len() <= PI_SIGMA2_INDEX {
+        let e = format!("Leader of slot {} has too few public inputs", checkpoint.slot);
+        return Err(Error::InvalidSyncResponse(e))
+    }
+
+    let (mu_y, mu_rho) = LeadCoin::election_seeds_u64(checkpoint.eta, checkpoint.slot);
+    if lf.public_inputs[PI_MU_Y_INDEX] != mu_y ||
+        lf.public_inputs[PI_MU_RHO_INDEX] != mu_rho ||
+        lf.public_inputs[PI_SIGMA1_INDEX] != checkpoint.sigma1 ||
+        lf.public_inputs[PI_SIGMA2_INDEX] != checkpoint.sigma2
+    {
+        warn!(target: "consensus::block_sync", "verify_leader_public_inputs(): Leader of slot {} public inputs don't match our slot checkpoint", checkpoint.slot);
+        return Err(Error::ProposalPublicValuesMismatched)
+    }
+
+    Ok(())
+}
 
-                    let last_received = state.read().await.blockchain.last()?;
-                    info!(target: "consensus::block_sync", "Last received block: {:?} - {:?}", last_received.0, last_received.1);
+/// Download the bodies of the `expected` blocks, in the form of tuples
+/// (`slot`, `blockhash`), and apply them in order. Chunks of the blocks
+/// are requested from all peers in parallel, and failed requests are
+/// retried with the remaining peers.
+async fn sync_bodies(
+    state: &ValidatorStatePtr,
+    peers: &mut Vec<SyncPeer>,
+    expected: &[(u64, blake3::Hash)],
+) -> Result<()> {
+    let start = state.read().await.blockchain.last()?.0;
+    let target = expected.last().map_or(start, |(slot, _)| *slot);
+    notify_progress(state, start, start, target).await;
 
-                    if last == last_received {
-                        blocks_synced = true;
-                        break
+    let chunks: Vec<&[(u64, blake3::Hash)]> = expected.chunks(SYNC_BODIES_BATCH).collect();
+
+    let mut pending: BTreeSet<usize> = (0..chunks.len()).collect();
+    let mut retries = vec![0; chunks.len()];
+    // Downloaded chunks, along with the peer that served them
+    let mut downloaded = BTreeMap::new();
+    let mut next = 0;
+    while next < chunks.len() {
+        if peers.is_empty() {
+            return Err(Error::SyncPeersExhausted)
+        }
+
+        // Request from each peer the first pending chunk it didn't prune
+        let mut round = vec![];
+        for (peer_idx, peer) in peers.iter().enumerate() {
+            let chunk = pending.iter().copied().find(|chunk| peer.serves(chunks[*chunk][0].0));
+            if let Some(chunk) = chunk {
+                pending.remove(&chunk);
+                round.push((peer_idx, chunk));
+            }
+        }
+
+        if round.is_empty() {
+            // All the remaining peers pruned the blocks we're missing
+            let chunk = pending.iter().next().unwrap();
+            return Err(Error::BlockPruned(chunks[*chunk][0].0))
+        }
+
+        let requests = round.iter().map(|(peer_idx, chunk)| {
+            let hashes: Vec<blake3::Hash> = chunks[*chunk].iter().map(|(_, hash)| *hash).collect();
+            let peer = &peers[*peer_idx];
+            async move { peer.bodies(&hashes).await }
+        });
+        let results = join_all(requests).await;
+
+        let mut failed = vec![];
+        for ((peer_idx, chunk), result) in round.into_iter().zip(results) {
+            match result {
+                Ok(blocks) => {
+                    downloaded.insert(chunk, (peers[peer_idx].channel.clone(), blocks));
+                }
+                Err(Error::BlockPruned(slot)) => {
+                    // The peer can still serve the blocks after the pruned ones
+                    peers[peer_idx].pruned = Some(slot.max(chunks[chunk][0].0));
+                    pending.insert(chunk);
+                }
+                Err(e) => {
+                    retries[chunk] += 1;
+                    if retries[chunk] > SYNC_MAX_RETRIES {
+                        return Err(e)
                     }
+                    pending.insert(chunk);
+                    failed.push((peer_idx, e));
+                }
+            }
+        }
+
+        drop_peers(peers, failed).await;
 
-                    slot_checkpoints_synced = false;
-                    last = last_received;
+        // Apply the downloaded chunks that continue the chain. Chunks that
+        // fail to apply are retried with another peer, and the one that
+        // served them gets penalized.
+        while let Some((channel, blocks)) = downloaded.remove(&next) {
+            debug!(target: "consensus::block_sync", "sync_bodies(): Processing received blocks");
+            if let Err(e) = state.write().await.receive_sync_blocks(&blocks).await {
+                retries[next] += 1;
+                if retries[next] > SYNC_MAX_RETRIES {
+                    return Err(e)
                 }
+                pending.insert(next);
 
-                if slot_checkpoints_synced && blocks_synced {
-                    break
+                let e = Error::InvalidSyncResponse(format!("Blocks failed to apply: {}", e));
+                match peers.iter().position(|peer| Arc::ptr_eq(&peer.channel, &channel)) {
+                    Some(idx) => drop_peers(peers, vec![(idx, e)]).await,
+                    None => {
+                        channel.misbehaving(BAN_SCORE_INVALID_SYNC_RESPONSE, &e.to_string()).await
+                    }
                 }
+                break
             }
+
+            let last_received = state.read().await.blockchain.last()?;
+            info!(target: "consensus::block_sync", "Last received block: {:?} - {:?}", last_received.0, last_received.1);
+            notify_progress(state, last_received.0, start, target).await;
+            next += 1;
         }
-        None => warn!(target: "consensus::block_sync", "Node is not connected to other nodes"),
-    };
+    }
 
-    info!(target: "consensus::block_sync", "Blockchain synced!");
     Ok(())
 }
+
+/// Pick a random peer, failing if none are left.
+fn random_peer(peers: &[SyncPeer]) -> Result<usize> {
+    if peers.is_empty() {
+        return Err(Error::SyncPeersExhausted)
+    }
+
+    Ok(rand::thread_rng().gen_range(0..peers.len()))
+}
+
+/// Remove the peers that failed a request, given by index in ascending order,
+/// from the sync. Peers that sent invalid data are also penalized.
+async fn drop_peers(peers: &mut Vec<SyncPeer>, failed: Vec<(usize, Error)>) {
+    for (idx, e) in failed.into_iter().rev() {
+        let peer = peers.remove(idx);
+        peer.unsubscribe().await;
+        warn!(target: "consensus::block_sync", "Dropping sync peer {}: {}", peer.channel.address(), e);
+
+        if matches!(
+            e,
+            Error::InvalidSyncResponse(_) |
+                Error::InvalidSignature |
+                Error::LeaderProofVerification |
+                Error::ProposalPublicValuesMismatched
+        ) {
+            peer.channel.misbehaving(BAN_SCORE_INVALID_SYNC_RESPONSE, &e.to_string()).await;
+        }
+    }
+}
+
+/// Notify the "sync" subscriber about the last synced slot and the
+/// percentage of the blocks from `start` to `target` synced.
+async fn notify_progress(state: &ValidatorStatePtr, slot: u64, start: u64, target: u64) {
+    let percentage = if target > start { (slot - start) * 100 / (target - start) } else { 100 };
+
+    let params = json!([slot, percentage]);
+    let notif = JsonNotification::new("blockchain.subscribe_sync", params);
+    let sync_subscriber = state.read().await.subscribers.get("sync").unwrap().clone();
+    sync_subscriber.notify(notif).await;
+}
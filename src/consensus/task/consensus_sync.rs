@@ -24,7 +24,7 @@ use crate::{
             ConsensusRequest, ConsensusResponse, ConsensusSlotCheckpointsRequest,
             ConsensusSlotCheckpointsResponse,
         },
-        ValidatorStatePtr,
+        Mempool, ValidatorStatePtr,
     },
     net::P2pPtr,
     util::async_util::sleep,
@@ -142,7 +142,11 @@ pub async fn consensus_sync_task(p2p: P2pPtr, state: ValidatorStatePtr) -> Resul
     }
     lock.consensus.bootstrap_slot = response.bootstrap_slot;
     lock.consensus.forks = forks;
-    lock.unconfirmed_txs = response.unconfirmed_txs.clone();
+    // Pending transactions go through the usual mempool validations
+    lock.unconfirmed_txs = Mempool::default();
+    for tx in &response.unconfirmed_txs {
+        lock.append_tx(tx.clone()).await;
+    }
     lock.consensus.slot_checkpoints = response.slot_checkpoints.clone();
     lock.consensus.previous_leaders = response.previous_leaders.clone();
     lock.consensus.nullifiers = response.nullifiers.clone();
@@ -19,10 +19,13 @@
 // TODO: Handle ? with matches in these files. They should be robust.
 
 mod block_sync;
-pub use block_sync::block_sync_task;
+pub use block_sync::{block_sync_task, snapshot_blockhash_task};
 
 mod consensus_sync;
 pub use consensus_sync::consensus_sync_task;
 
 mod proposal;
 pub use proposal::proposal_task;
+
+mod prune;
+pub use prune::prune_task;
@@ -0,0 +1,41 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use log::{error, info};
+
+use crate::{
+    consensus::{constants, ValidatorStatePtr},
+    util::async_util::sleep,
+};
+
+/// async task used for pruning the transactions of the finalized blocks
+/// older than the last `keep` slots, once every slot.
+pub async fn prune_task(state: ValidatorStatePtr, keep: u64) {
+    info!(target: "consensus::prune", "Pruning transactions older than {} slots", keep);
+    loop {
+        // Blocks get written while holding the write lock, so we hold it too
+        let result = state.write().await.blockchain.prune(keep);
+        match result {
+            Ok(0) => {}
+            Ok(pruned) => info!(target: "consensus::prune", "Pruned {} transactions", pruned),
+            Err(e) => error!(target: "consensus::prune", "Failed pruning transactions: {}", e),
+        }
+
+        sleep(constants::SLOT_TIME).await;
+    }
+}
@@ -0,0 +1,224 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The transactions Merkle tree of a block is built over the BLAKE3 hashes
+//! of its serialized transactions, in block order, and its root is committed
+//! to in the block [`Header`]. Each leaf is the hash truncated to its first
+//! 31 bytes, read as a little-endian integer, so it always fits in the base
+//! field. The tree has the same depth and hash function as the money
+//! contract's coins tree.
+
+use darkfi_sdk::{
+    crypto::{constants::MERKLE_DEPTH, MerkleNode, MerkleTree},
+    incrementalmerkletree::{Altitude, Hashable, Tree},
+    pasta::{group::ff::PrimeField, pallas},
+};
+use darkfi_serial::{SerialDecodable, SerialEncodable};
+
+use super::{Block, Header};
+
+/// Convert a transaction hash into its leaf in the transactions Merkle tree.
+pub fn tx_leaf(tx_hash: &blake3::Hash) -> MerkleNode {
+    let mut repr = [0_u8; 32];
+    repr[..31].copy_from_slice(&tx_hash.as_bytes()[..31]);
+    MerkleNode::from(pallas::Base::from_repr(repr).unwrap())
+}
+
+/// Compute the root of the transactions Merkle tree over the given
+/// transaction hashes.
+pub fn txs_root(tx_hashes: &[blake3::Hash]) -> MerkleNode {
+    let mut tree = MerkleTree::new(1);
+    for tx_hash in tx_hashes {
+        tree.append(&tx_leaf(tx_hash));
+    }
+
+    tree.root(0).unwrap()
+}
+
+/// Proof that a transaction is included in the block with the given
+/// [`Header`], consisting of the transaction's position in the block and
+/// its authentication path in the transactions Merkle tree.
+#[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
+pub struct TxInclusionProof {
+    /// Header of the block including the transaction
+    pub header: Header,
+    /// Position of the transaction in the block
+    pub position: u64,
+    /// Sibling nodes from the leaf up to the root
+    pub path: Vec<MerkleNode>,
+}
+
+impl TxInclusionProof {
+    /// Build the inclusion proof of the transaction at `position` among the
+    /// given transaction hashes of the block with the given header.
+    /// Returns `None` if the position is out of bounds.
+    pub fn new(header: Header, tx_hashes: &[blake3::Hash], position: usize) -> Option<Self> {
+        if position >= tx_hashes.len() {
+            return None
+        }
+
+        let mut tree = MerkleTree::new(1);
+        let mut leaf_position = None;
+        for (idx, tx_hash) in tx_hashes.iter().enumerate() {
+            tree.append(&tx_leaf(tx_hash));
+            if idx == position {
+                leaf_position = tree.witness();
+            }
+        }
+
+        let root = tree.root(0).unwrap();
+        let path = tree.authentication_path(leaf_position?, &root)?;
+        Some(Self { header, position: position as u64, path })
+    }
+
+    /// Compute the transactions Merkle root the proof leads to, starting
+    /// from the given transaction hash.
+    pub fn root(&self, tx_hash: &blake3::Hash) -> MerkleNode {
+        let mut node = tx_leaf(tx_hash);
+        for (level, sibling) in self.path.iter().enumerate() {
+            let altitude = Altitude::from(level as u8);
+            node = match (self.position >> level) & 1 {
+                0 => MerkleNode::combine(altitude, &node, sibling),
+                _ => MerkleNode::combine(altitude, sibling, &node),
+            };
+        }
+
+        node
+    }
+
+    /// Verify that the transaction with the given hash is included in the
+    /// block of the proof's header.
+    pub fn verify(&self, tx_hash: &blake3::Hash) -> bool {
+        if self.path.len() != MERKLE_DEPTH as usize || self.position >> MERKLE_DEPTH != 0 {
+            return false
+        }
+
+        self.root(tx_hash) == self.header.root
+    }
+}
+
+/// Verify that the transaction with the given hash is included in a block of
+/// the given chain, without needing any transaction data. The chain is given
+/// as the headers and [`Block`]s of consecutive blocks, ordered by slot. Each
+/// header has to point to the hash of the previous [`Block`], the way the
+/// blockchain links them, and the proof's header has to be one of them.
+///
+/// A valid chain of headers is easy to make up, so on its own it proves
+/// nothing. The chain has to end with the header hashing to `anchor`, which
+/// the caller has to trust, e.g. because it was checked against other nodes.
+pub fn verify_tx_inclusion(
+    tx_hash: &blake3::Hash,
+    proof: &TxInclusionProof,
+    headers: &[Header],
+    blocks: &[Block],
+    anchor: &blake3::Hash,
+) -> bool {
+    if headers.len() != blocks.len() {
+        return false
+    }
+
+    let Some(last) = headers.last() else { return false };
+    if last.headerhash() != *anchor {
+        return false
+    }
+
+    for (header, block) in headers.iter().zip(blocks) {
+        if block.header != header.headerhash() {
+            return false
+        }
+    }
+
+    for (pair, block) in headers.windows(2).zip(blocks) {
+        if pair[1].previous != block.blockhash() || pair[1].slot <= pair[0].slot {
+            return false
+        }
+    }
+
+    headers.contains(&proof.header) && proof.verify(tx_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::time::Timestamp;
+
+    #[test]
+    fn tx_inclusion_proofs() {
+        let tx_hashes: Vec<blake3::Hash> = (0..5_u8).map(|i| blake3::hash(&[i])).collect();
+
+        let genesis_ts = Timestamp::current_time();
+        let genesis_data = blake3::hash(b"genesis");
+        let genesis = Header::genesis_header(genesis_ts, genesis_data);
+        let genesis_block = Block::genesis_block(genesis_ts, genesis_data);
+        let header = Header {
+            previous: genesis_block.blockhash(),
+            slot: 1,
+            root: txs_root(&tx_hashes),
+            ..genesis.clone()
+        };
+        let block =
+            Block { header: header.headerhash(), txs: tx_hashes.clone(), ..genesis_block.clone() };
+        let headers = [genesis.clone(), header.clone()];
+        let blocks = [genesis_block, block];
+        let anchor = header.headerhash();
+
+        for (position, tx_hash) in tx_hashes.iter().enumerate() {
+            let proof = TxInclusionProof::new(header.clone(), &tx_hashes, position).unwrap();
+            assert!(proof.verify(tx_hash));
+            assert!(verify_tx_inclusion(tx_hash, &proof, &headers, &blocks, &anchor));
+
+            // The proof doesn't hold for other transactions or positions
+            let other = blake3::hash(b"other");
+            assert!(!proof.verify(&other));
+            let moved = TxInclusionProof { position: (position as u64 + 1) % 5, ..proof.clone() };
+            assert!(!moved.verify(tx_hash));
+
+            // Nor for headers outside the given chain
+            let genesis_anchor = genesis.headerhash();
+            assert!(!verify_tx_inclusion(
+                tx_hash,
+                &proof,
+                &headers[..1],
+                &blocks[..1],
+                &genesis_anchor
+            ));
+
+            // Nor for chains not ending with the trusted header
+            assert!(!verify_tx_inclusion(tx_hash, &proof, &headers, &blocks, &genesis_anchor));
+        }
+
+        // Headers have to point to the previous block hash, not its header hash
+        let unlinked = Header { previous: genesis.headerhash(), ..header.clone() };
+        let unlinked_block = Block { header: unlinked.headerhash(), ..blocks[1].clone() };
+        let proof = TxInclusionProof::new(unlinked.clone(), &tx_hashes, 0).unwrap();
+        let headers = [genesis, unlinked.clone()];
+        let blocks = [blocks[0].clone(), unlinked_block];
+        assert!(!verify_tx_inclusion(
+            &tx_hashes[0],
+            &proof,
+            &headers,
+            &blocks,
+            &unlinked.headerhash()
+        ));
+
+        assert!(TxInclusionProof::new(header, &tx_hashes, 5).is_none());
+
+        // Blocks without transactions have the empty tree root
+        assert_eq!(txs_root(&[]), MerkleTree::new(1).root(0).unwrap());
+    }
+}
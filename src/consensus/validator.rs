@@ -16,19 +16,24 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashMap, io::Cursor};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    ops::Bound,
+};
 
-use async_std::sync::{Arc, RwLock};
+use async_std::sync::{Arc, Mutex, RwLock};
 use darkfi_sdk::{
     crypto::{
-        constants::MERKLE_DEPTH,
-        contract_id::{DAO_CONTRACT_ID, MONEY_CONTRACT_ID},
+        contract_id::{DAO_CONTRACT_ID, DEPLOYOOOR_CONTRACT_ID, MONEY_CONTRACT_ID},
         schnorr::{SchnorrPublic, SchnorrSecret},
-        MerkleNode, PublicKey, SecretKey,
+        ContractId, Nullifier, PublicKey, SecretKey,
     },
     db::SMART_CONTRACT_ZKAS_DB_NAME,
-    incrementalmerkletree::{bridgetree::BridgeTree, Tree},
-    pasta::{group::ff::PrimeField, pallas},
+    deploy::{DeployFunction, DeployParamsV1, LockParamsV1},
+    event::ContractEvent,
+    pasta::pallas,
+    ContractCall,
 };
 use darkfi_serial::{deserialize, serialize, Decodable, Encodable, WriteExt};
 use halo2_proofs::arithmetic::Field;
@@ -39,16 +44,21 @@ use serde_json::json;
 use super::{
     constants,
     lead_coin::LeadCoin,
+    mempool::{is_faucet_airdrop, minted_coins, paid_fee, spent_nullifiers, Mempool},
     state::{ConsensusState, Fork, SlotCheckpoint, StateCheckpoint},
+    tx_merkle::txs_root,
     BlockInfo, BlockProposal, Header, LeadInfo, LeadProof,
 };
 
 use crate::{
-    blockchain::Blockchain,
+    blockchain::{Blockchain, TreeWrites},
     rpc::jsonrpc::JsonNotification,
-    runtime::vm_runtime::Runtime,
+    runtime::{
+        gas::{db_write_cost, GAS_DB_READ},
+        vm_runtime::{BlockContext, Runtime},
+    },
     system::{Subscriber, SubscriberPtr},
-    tx::Transaction,
+    tx::{verify_batch, Transaction},
     util::time::Timestamp,
     wallet::WalletPtr,
     zk::{
@@ -57,7 +67,7 @@ use crate::{
         vm_stack::empty_witnesses,
     },
     zkas::ZkBinary,
-    Error, Result,
+    Error, Result, VerifyFailed,
 };
 
 /// Atomic pointer to validator state.
@@ -65,6 +75,90 @@ pub type ValidatorStatePtr = Arc<RwLock<ValidatorState>>;
 
 type VerifyingKeyMap = Arc<RwLock<HashMap<[u8; 32], Vec<(String, VerifyingKey)>>>>;
 
+/// Outcome of a successful transaction verification.
+#[derive(Clone, Debug)]
+pub struct VerifiedTx {
+    /// State updates produced by the transaction's calls
+    pub updates: Vec<Vec<u8>>,
+    /// Gas used by the transaction's calls
+    pub gas_used: u64,
+    /// Fee paid by the transaction
+    pub fee: u64,
+    /// Events emitted by the transaction's calls
+    pub events: Vec<ContractEvent>,
+}
+
+/// Subscriber to the contract events matching a filter.
+pub struct EventSubscriber {
+    /// Only events of this contract get sent, if set
+    pub contract_id: Option<ContractId>,
+    /// Only events with this topic get sent, if set
+    pub topic: Option<String>,
+    /// Subscriber the matching events get sent to
+    pub subscriber: SubscriberPtr<JsonNotification>,
+}
+
+impl EventSubscriber {
+    /// Check if the given event passes the subscriber's filter.
+    pub fn matches(&self, event: &ContractEvent) -> bool {
+        self.contract_id.map_or(true, |x| x == event.contract_id) &&
+            self.topic.as_ref().map_or(true, |x| *x == event.topic)
+    }
+}
+
+/// Remove the event subscribers nobody holds anymore. Their subscriptions
+/// hold them while listening, so these have no listeners, and filters don't
+/// pile up as clients come and go.
+fn drop_unused_event_subscribers(event_subscribers: &mut Vec<EventSubscriber>) {
+    event_subscribers.retain(|x| Arc::strong_count(&x.subscriber) > 1);
+}
+
+/// Readable representation of a contract event emitted by the transaction
+/// with the given hash, as returned by the JSON-RPC API.
+pub fn event_to_json(tx_hash: &blake3::Hash, event: &ContractEvent) -> serde_json::Value {
+    json!({
+        "tx": tx_hash.to_hex().as_str(),
+        "contract_id": event.contract_id.to_string(),
+        "topic": event.topic,
+        "data": bs58::encode(&event.data).into_string(),
+    })
+}
+
+/// Build the ZK proof verifying key of the given contract circuit, failing
+/// if it doesn't fit in the rows contract circuits are given.
+fn contract_verifying_key(zkbin: ZkBinary) -> Result<VerifyingKey> {
+    let circuit = ZkCircuit::new(empty_witnesses(&zkbin), zkbin);
+    Ok(VerifyingKey::try_build(constants::CONTRACT_PROOF_K, &circuit)?)
+}
+
+/// Decode the zkas circuits of a contract deployment into their namespaces
+/// and bincodes, making sure verifying keys can be built for them. A bad
+/// circuit gets the deploy call rejected, instead of failing every later
+/// verification of proofs against it.
+fn deploy_zkas_bincodes(bincodes: Vec<Vec<u8>>) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut zkas_bincodes = Vec::with_capacity(bincodes.len());
+    for bincode in bincodes {
+        let zkbin = ZkBinary::decode(&bincode)?;
+        let namespace = zkbin.namespace.clone();
+        contract_verifying_key(zkbin)?;
+        zkas_bincodes.push((namespace, bincode));
+    }
+
+    Ok(zkas_bincodes)
+}
+
+/// Outcome of executing a call to the native deployer.
+struct DeployCall {
+    /// Contract deployed, upgraded or locked by the call
+    contract_id: ContractId,
+    /// Runtime that ran the contract's `__initialize`, holding its state changes
+    runtime: Option<Runtime>,
+    /// Writes to the blockchain's contract trees
+    writes: Vec<(sled::Tree, TreeWrites)>,
+    /// Gas used by the call
+    gas_used: u64,
+}
+
 /// This struct represents the state of a validator node.
 pub struct ValidatorState {
     /// Leader proof proving key
@@ -76,18 +170,23 @@ pub struct ValidatorState {
     /// Canonical (finalized) blockchain
     pub blockchain: Blockchain,
     /// Pending transactions
-    pub unconfirmed_txs: Vec<Transaction>,
+    pub unconfirmed_txs: Mempool,
     /// A map of various subscribers exporting live info from the blockchain
     /// TODO: Instead of JsonNotification, it can be an enum of internal objects,
     ///       and then we don't have to deal with json in this module but only
     //        externally.
     pub subscribers: HashMap<&'static str, SubscriberPtr<JsonNotification>>,
+    /// Subscribers to the contract events of finalized blocks, one per filter
+    pub event_subscribers: Mutex<Vec<EventSubscriber>>,
     /// ZK proof verifying keys for smart contract calls
     pub verifying_keys: VerifyingKeyMap,
     /// Wallet interface
     pub wallet: WalletPtr,
     /// Flag to enable single-node mode
     pub single_node: bool,
+    /// Price of a unit of gas that transaction fees have to cover,
+    /// `constants::GAS_PRICE` unless changed.
+    pub gas_price: u64,
 }
 
 impl ValidatorState {
@@ -137,7 +236,7 @@ impl ValidatorState {
             single_node,
         )?;
 
-        let unconfirmed_txs = vec![];
+        let unconfirmed_txs = Mempool::default();
 
         // -----NATIVE WASM CONTRACTS-----
         // This is the current place where native contracts are being deployed.
@@ -186,26 +285,7 @@ impl ValidatorState {
             // When deployed, we can do a lookup for the zkas circuits and
             // initialize verifying keys for them.
             info!(target: "consensus::validator", "Creating ZK verifying keys for {} zkas circuits", nc.0);
-            info!(target: "consensus::validator", "Looking up zkas db for {} (ContractID: {})", nc.0, nc.1);
-            let zkas_db = blockchain.contracts.lookup(
-                &blockchain.sled_db,
-                &nc.1,
-                SMART_CONTRACT_ZKAS_DB_NAME,
-            )?;
-
-            let mut vks = vec![];
-            for i in zkas_db.iter() {
-                info!(target: "consensus::validator", "Iterating over zkas db");
-                let (zkas_ns, zkas_bincode) = i?;
-                info!(target: "consensus::validator", "Deserializing namespace");
-                let zkas_ns: String = deserialize(&zkas_ns)?;
-                info!(target: "consensus::validator", "Creating VerifyingKey for zkas circuit with namespace {}", zkas_ns);
-                let zkbin = ZkBinary::decode(&zkas_bincode)?;
-                let circuit = ZkCircuit::new(empty_witnesses(&zkbin), zkbin);
-                // FIXME: This k=13 man...
-                let vk = VerifyingKey::build(13, &circuit);
-                vks.push((zkas_ns, vk));
-            }
+            let vks = Self::build_verifying_keys(&blockchain, &nc.1)?;
 
             info!(target: "consensus::validator", "Finished creating VerifyingKey objects for {} (ContractID: {})", nc.0, nc.1);
             verifying_keys.insert(nc.1.to_bytes(), vks);
@@ -217,6 +297,8 @@ impl ValidatorState {
         let mut subscribers = HashMap::new();
         let block_subscriber = Subscriber::new();
         subscribers.insert("blocks", block_subscriber);
+        let sync_subscriber = Subscriber::new();
+        subscribers.insert("sync", sync_subscriber);
 
         let state = Arc::new(RwLock::new(ValidatorState {
             lead_proving_key,
@@ -225,16 +307,18 @@ impl ValidatorState {
             blockchain,
             unconfirmed_txs,
             subscribers,
+            event_subscribers: Mutex::new(vec![]),
             verifying_keys: Arc::new(RwLock::new(verifying_keys)),
             wallet,
             single_node,
+            gas_price: constants::GAS_PRICE,
         }));
 
         Ok(state)
     }
 
     /// The node retrieves a transaction, validates its state transition,
-    /// and appends it to the unconfirmed transactions list.
+    /// and appends it to the mempool.
     pub async fn append_tx(&mut self, tx: Transaction) -> bool {
         let tx_hash = blake3::hash(&serialize(&tx));
         let tx_in_txstore = match self.blockchain.transactions.contains(&tx_hash) {
@@ -245,19 +329,34 @@ impl ValidatorState {
             }
         };
 
-        if self.unconfirmed_txs.contains(&tx) || tx_in_txstore {
+        if self.unconfirmed_txs.contains(&tx_hash) || tx_in_txstore {
             info!(target: "consensus::validator", "append_tx(): We have already seen this tx.");
             return false
         }
 
         info!(target: "consensus::validator", "append_tx(): Starting state transition validation");
-        if let Err(e) = self.verify_transactions(&[tx.clone()], false).await {
-            error!(target: "consensus::validator", "append_tx(): Failed to verify transaction: {}", e);
-            return false
+        let verified = match self.verify_transactions(&[tx.clone()], false).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!(target: "consensus::validator", "append_tx(): Failed to verify transaction: {}", e);
+                return false
+            }
         };
 
+        let now = Timestamp::current_time();
+        let expired = self.unconfirmed_txs.expire(now);
+        if expired > 0 {
+            info!(target: "consensus::validator", "append_tx(): Dropped {} expired txs from mempool", expired);
+        }
+
+        let nullifiers = spent_nullifiers(&tx, &verified[0].updates);
+        let (fee, gas_used) = (verified[0].fee, verified[0].gas_used);
+        if let Err(e) = self.unconfirmed_txs.insert(tx, fee, gas_used, nullifiers, now) {
+            warn!(target: "consensus::validator", "append_tx(): Rejected by mempool: {}", e);
+            return false
+        }
+
         info!(target: "consensus::validator", "append_tx(): Appended tx to mempool");
-        self.unconfirmed_txs.push(tx);
         true
     }
 
@@ -280,14 +379,9 @@ impl ValidatorState {
 
         // Generate proposal
         let unproposed_txs = self.unproposed_txs(fork_index);
-        let mut tree = BridgeTree::<MerkleNode, MERKLE_DEPTH>::new(100);
-        // The following is pretty weird, so something better should be done.
-        for tx in &unproposed_txs {
-            let mut hash = [0_u8; 32];
-            hash[0..31].copy_from_slice(&blake3::hash(&serialize(tx)).as_bytes()[0..31]);
-            tree.append(&MerkleNode::from(pallas::Base::from_repr(hash).unwrap()));
-        }
-        let root = tree.root(0).unwrap();
+        let tx_hashes: Vec<blake3::Hash> =
+            unproposed_txs.iter().map(|tx| blake3::hash(&serialize(tx))).collect();
+        let root = txs_root(&tx_hashes);
 
         // Checking if extending a fork or canonical
         let (prev_hash, coin) = if fork_index == -1 {
@@ -336,35 +430,29 @@ impl ValidatorState {
         Ok(Some((BlockProposal::new(header, unproposed_txs, lead_info), coin, derived_blind)))
     }
 
-    /// Retrieve all unconfirmed transactions not proposed in previous blocks
-    /// of provided index chain.
+    /// Retrieve the highest paying unconfirmed transactions not proposed in
+    /// previous blocks of provided index chain, up to the block limits.
     pub fn unproposed_txs(&self, index: i64) -> Vec<Transaction> {
-        let unproposed_txs = if index == -1 {
-            // If index is -1 (canonical blockchain) a new fork will be generated,
-            // therefore all unproposed transactions can be included in the proposal.
-            self.unconfirmed_txs.clone()
-        } else {
-            // We iterate over the fork chain proposals to find already proposed
-            // transactions and remove them from the local unproposed_txs vector.
-            let mut filtered_txs = self.unconfirmed_txs.clone();
+        // If index is -1 (canonical blockchain) a new fork will be generated,
+        // therefore all unproposed transactions can be included in the proposal.
+        // Otherwise, we iterate over the fork chain proposals to find already
+        // proposed transactions and exclude them.
+        let mut proposed = HashSet::new();
+        if index != -1 {
             let chain = &self.consensus.forks[index as usize];
             for state_checkpoint in &chain.sequence {
                 for tx in &state_checkpoint.proposal.block.txs {
-                    if let Some(pos) = filtered_txs.iter().position(|txs| *txs == *tx) {
-                        filtered_txs.remove(pos);
-                    }
+                    proposed.insert(blake3::hash(&serialize(tx)));
                 }
             }
-            filtered_txs
-        };
-
-        // Check if transactions exceed configured cap
-        let cap = constants::TXS_CAP;
-        if unproposed_txs.len() > cap {
-            return unproposed_txs[0..cap].to_vec()
         }
 
-        unproposed_txs
+        self.unconfirmed_txs.select(
+            constants::TXS_CAP,
+            constants::BLOCK_TXS_MAX_BYTES,
+            constants::BLOCK_GAS_LIMIT,
+            &proposed,
+        )
     }
 
     /// Given a proposal, the node verify its sender (slot leader) and finds which blockchain
@@ -435,6 +523,23 @@ impl ValidatorState {
             return Err(Error::ProposalTxsExceedCapError)
         }
 
+        let txs_size: usize = proposal.block.txs.iter().map(|tx| serialize(tx).len()).sum();
+        if txs_size > constants::BLOCK_TXS_MAX_BYTES {
+            warn!(
+                target: "consensus::validator",
+                "receive_proposal(): Received proposal transactions exceed size limit: {} - {}",
+                txs_size,
+                constants::BLOCK_TXS_MAX_BYTES
+            );
+            return Err(Error::ProposalTxsExceedCapError)
+        }
+
+        // Check that the header commits to the proposal transactions
+        if hdr.root != proposal.block.txs_root() {
+            warn!(target: "consensus::validator", "receive_proposal(): Proposal transactions don't match the header root");
+            return Err(Error::InvalidTxsRoot)
+        }
+
         // Verify proposal signature is valid based on producer public key
         // TODO: derive public key from proof
         if !lf.public_key.verify(proposal.header.as_bytes(), &lf.signature) {
@@ -559,7 +664,10 @@ impl ValidatorState {
         // Validate state transition against canonical state
         // TODO: This should be validated against fork state
         info!(target: "consensus::validator", "receive_proposal(): Starting state transition validation");
-        if let Err(e) = self.verify_transactions(&proposal.block.txs, false).await {
+        let block_context = self.block_context(hdr)?;
+        if let Err(e) =
+            self.verify_transactions_with_context(&proposal.block.txs, block_context, false).await
+        {
             error!(target: "consensus::validator", "receive_proposal(): Transaction verifications failed: {}", e);
             return Err(e)
         };
@@ -606,14 +714,14 @@ impl ValidatorState {
         Ok(true)
     }
 
-    /// Remove provided transactions vector from unconfirmed_txs if they exist.
-    pub fn remove_txs(&mut self, transactions: &Vec<Transaction>) -> Result<()> {
-        for tx in transactions {
-            if let Some(pos) = self.unconfirmed_txs.iter().position(|txs| txs == tx) {
-                self.unconfirmed_txs.remove(pos);
-            }
-        }
-
+    /// Remove provided transactions vector from unconfirmed_txs if they exist,
+    /// along with any pending transaction spending the given nullifiers.
+    pub fn remove_txs(
+        &mut self,
+        transactions: &Vec<Transaction>,
+        nullifiers: &[Nullifier],
+    ) -> Result<()> {
+        self.unconfirmed_txs.remove_confirmed(transactions, nullifiers);
         Ok(())
     }
 
@@ -677,32 +785,51 @@ impl ValidatorState {
             finalized.push(state_checkpoint.proposal.clone().into());
         }
 
-        // Adding finalized proposals to canonical
+        // Validating state transitions and adding finalized proposals to canonical.
+        // The resulting state changes are kept in the blockchain overlay, and
+        // written along with each block, so they can be rolled back per block.
         info!(target: "consensus::validator", "consensus: Adding {} finalized block to canonical chain.", finalized.len());
-        match self.blockchain.add(&finalized) {
-            Ok(v) => v,
-            Err(e) => {
-                error!(target: "consensus::validator", "consensus: Failed appending finalized blocks to canonical chain: {}", e);
+        let mut finalized_nullifiers = Vec::with_capacity(finalized.len());
+        for proposal in &finalized {
+            info!(target: "consensus::validator", "Applying state transition for finalized block");
+            let block_context = self.block_context(&proposal.header)?;
+            let verified = match self
+                .verify_transactions_with_context(&proposal.txs, block_context, true)
+                .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "consensus::validator", "Finalized block transaction verifications failed: {}", e);
+                    self.discard_pending_state().await;
+                    return Err(e)
+                }
+            };
+
+            if let Err(e) = self.stage_tx_indexes(&proposal.txs, &verified) {
+                self.discard_pending_state().await;
                 return Err(e)
             }
-        };
 
-        let blocks_subscriber = self.subscribers.get("blocks").unwrap().clone();
-
-        // Validating state transitions
-        for proposal in &finalized {
-            // TODO: Is this the right place? We're already doing this in protocol_sync.
-            // TODO: These state transitions have already been checked. (I wrote this, but where?)
-            // TODO: FIXME: The state transitions have already been written, they have to be in memory
-            //              until this point.
-            info!(target: "consensus::validator", "Applying state transition for finalized block");
-            if let Err(e) = self.verify_transactions(&proposal.txs, true).await {
-                error!(target: "consensus::validator", "Finalized block transaction verifications failed: {}", e);
+            if let Err(e) = self.blockchain.add(&[proposal.clone()]) {
+                error!(target: "consensus::validator", "consensus: Failed appending finalized blocks to canonical chain: {}", e);
                 return Err(e)
             }
 
-            // Remove proposal transactions from memory pool
-            if let Err(e) = self.remove_txs(&proposal.txs) {
+            let nullifiers: Vec<Nullifier> = proposal
+                .txs
+                .iter()
+                .zip(verified.iter())
+                .flat_map(|(tx, verified)| spent_nullifiers(tx, &verified.updates))
+                .collect();
+            finalized_nullifiers.push(nullifiers);
+        }
+
+        let blocks_subscriber = self.subscribers.get("blocks").unwrap().clone();
+
+        for (proposal, nullifiers) in finalized.iter().zip(finalized_nullifiers) {
+            // Remove proposal transactions, and the ones conflicting with
+            // them, from memory pool
+            if let Err(e) = self.remove_txs(&proposal.txs, &nullifiers) {
                 error!(target: "consensus::validator", "Removing finalized block transactions failed: {}", e);
                 return Err(e)
             }
@@ -712,6 +839,7 @@ impl ValidatorState {
             let notif = JsonNotification::new("blockchain.subscribe_blocks", params);
             info!(target: "consensus::validator", "consensus: Sending notification about finalized block");
             blocks_subscriber.notify(notif).await;
+            self.notify_events(proposal).await;
         }
 
         // Setting leaders history to last proposal leaders count
@@ -765,18 +893,48 @@ impl ValidatorState {
     /// Validate and append to canonical state received blocks.
     pub async fn receive_blocks(&mut self, blocks: &[BlockInfo]) -> Result<()> {
         // Verify state transitions for all blocks and their respective transactions.
+        // The state changes are kept in the blockchain overlay until each block
+        // gets appended, so they're written along with it.
         info!(target: "consensus::validator", "receive_blocks(): Starting state transition validations");
         for block in blocks {
-            if let Err(e) = self.verify_transactions(&block.txs, true).await {
-                error!(target: "consensus::validator", "receive_blocks(): Transaction verifications failed: {}", e);
+            if block.header.root != block.txs_root() {
+                error!(target: "consensus::validator", "receive_blocks(): Block transactions don't match the header root");
+                self.discard_pending_state().await;
+                return Err(Error::InvalidTxsRoot)
+            }
+
+            let block_context = self.block_context(&block.header)?;
+            let verified = match self
+                .verify_transactions_with_context(&block.txs, block_context, true)
+                .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "consensus::validator", "receive_blocks(): Transaction verifications failed: {}", e);
+                    self.discard_pending_state().await;
+                    return Err(e)
+                }
+            };
+
+            if let Err(e) = self.stage_tx_indexes(&block.txs, &verified) {
+                self.discard_pending_state().await;
                 return Err(e)
             }
+
+            info!(target: "consensus::validator", "receive_blocks(): Appending block to ledger");
+            self.blockchain.add(&[block.clone()])?;
+
+            // Pending transactions spending the same nullifiers can't be valid anymore
+            let nullifiers: Vec<Nullifier> = block
+                .txs
+                .iter()
+                .zip(verified.iter())
+                .flat_map(|(tx, verified)| spent_nullifiers(tx, &verified.updates))
+                .collect();
+            self.remove_txs(&block.txs, &nullifiers)?;
         }
 
         info!(target: "consensus::validator", "receive_blocks(): All state transitions passed");
-        info!(target: "consensus::validator", "receive_blocks(): Appending blocks to ledger");
-        self.blockchain.add(blocks)?;
-
         Ok(())
     }
 
@@ -805,9 +963,7 @@ impl ValidatorState {
         let notif = JsonNotification::new("blockchain.subscribe_blocks", params);
         info!(target: "consensus::validator", "consensus: Sending notification about finalized block");
         blocks_subscriber.notify(notif).await;
-
-        info!(target: "consensus::validator", "receive_finalized_block(): Removing block transactions from unconfirmed_txs");
-        self.remove_txs(&block.txs)?;
+        self.notify_events(&block).await;
 
         Ok(true)
     }
@@ -847,36 +1003,183 @@ impl ValidatorState {
             let notif = JsonNotification::new("blockchain.subscribe_blocks", params);
             info!(target: "consensus::validator", "consensus: Sending notification about finalized block");
             blocks_subscriber.notify(notif).await;
+            self.notify_events(&block).await;
         }
 
         Ok(())
     }
 
+    /// Get the subscriber to the contract events of finalized blocks matching
+    /// the given contract ID and topic, where `None` matches any of them.
+    /// Subscriptions with the same filter share a subscriber.
+    pub async fn event_subscriber(
+        &self,
+        contract_id: Option<ContractId>,
+        topic: Option<String>,
+    ) -> SubscriberPtr<JsonNotification> {
+        let mut event_subscribers = self.event_subscribers.lock().await;
+        drop_unused_event_subscribers(&mut event_subscribers);
+        if let Some(sub) =
+            event_subscribers.iter().find(|x| x.contract_id == contract_id && x.topic == topic)
+        {
+            return sub.subscriber.clone()
+        }
+
+        let subscriber = Subscriber::new();
+        event_subscribers.push(EventSubscriber {
+            contract_id,
+            topic,
+            subscriber: subscriber.clone(),
+        });
+        subscriber
+    }
+
+    /// Notify the event subscribers about the contract events emitted by the
+    /// transactions of the given finalized block.
+    async fn notify_events(&self, block: &BlockInfo) {
+        let mut event_subscribers = self.event_subscribers.lock().await;
+        drop_unused_event_subscribers(&mut event_subscribers);
+        if event_subscribers.is_empty() {
+            return
+        }
+
+        let events = match self.blockchain.get_events_by_slot(block.header.slot) {
+            Ok(v) => v,
+            Err(e) => {
+                error!(target: "consensus::validator", "notify_events(): Failed fetching block events: {}", e);
+                return
+            }
+        };
+
+        for (tx_hash, event) in events {
+            let params = json!([block.header.slot, event_to_json(&tx_hash, &event)]);
+            let notif = JsonNotification::new("blockchain.subscribe_events", params);
+            for sub in event_subscribers.iter().filter(|x| x.matches(&event)) {
+                sub.subscriber.notify(notif.clone()).await;
+            }
+        }
+    }
+
     /// Validate signatures, wasm execution, and zk proofs for given transactions.
     /// If all of those succeed, try to execute a state update for the contract calls.
-    /// Currently the verifications are sequential, and the function will fail if any
-    /// of the verifications fail.
+    /// The verification happens in three stages:
+    /// * The "metadata" call of each contract call is executed to get the public
+    ///   inputs of the ZK proofs and the public keys of the signatures.
+    /// * The signatures and ZK proofs of the entire batch are verified in parallel,
+    ///   see [`verify_batch`].
+    /// * The "exec" and "update" calls are executed, in order, for every transaction.
+    /// Calls to the native deployer, which deploy and upgrade contracts, have
+    /// no wasm code, and are executed by [`ValidatorState::exec_deploy_call`].
+    /// The function will fail if any of the verifications fail.
+    /// Each transaction has to pay a fee covering the gas its calls used, times
+    /// the gas price.
     /// The function takes a boolean called `write` which tells it to actually write
-    /// the state transitions to the database.
-    // TODO: This should be paralellized as if even one tx in the batch fails to verify,
-    //       we can drop everything.
-    pub async fn verify_transactions(&self, txs: &[Transaction], write: bool) -> Result<()> {
+    /// the state transitions to the database. Each transaction's state transitions
+    /// are written before the next transaction is executed.
+    /// Returns the state updates, gas used and fee paid by each transaction.
+    /// The transactions are executed in the context of the current slot, see
+    /// [`ValidatorState::verify_transactions_with_context`].
+    pub async fn verify_transactions(
+        &self,
+        txs: &[Transaction],
+        write: bool,
+    ) -> Result<Vec<VerifiedTx>> {
+        let block_context = self.current_block_context()?;
+        self.verify_transactions_with_context(txs, block_context, write).await
+    }
+
+    /// Same as [`ValidatorState::verify_transactions`], executing the
+    /// transactions in the given blockchain context. Transactions of a block
+    /// have to be verified in the context of that block, so that their
+    /// execution doesn't depend on when the block is verified.
+    pub async fn verify_transactions_with_context(
+        &self,
+        txs: &[Transaction],
+        block_context: BlockContext,
+        write: bool,
+    ) -> Result<Vec<VerifiedTx>> {
+        self.execute_transactions(txs, block_context, write, true).await
+    }
+
+    /// Execute the given transaction in the context of the current slot,
+    /// without verifying its signatures, ZK proofs and fee, and return the
+    /// gas it uses. Clients use it to find out the fee a transaction has to
+    /// pay, before signing it. Nothing gets written.
+    pub async fn estimate_gas(&self, tx: &Transaction) -> Result<u64> {
+        let block_context = self.current_block_context()?;
+        let txs = std::slice::from_ref(tx);
+        let verified = self.execute_transactions(txs, block_context, false, false).await?;
+        Ok(verified[0].gas_used)
+    }
+
+    /// Shared implementation of [`ValidatorState::verify_transactions_with_context`]
+    /// and [`ValidatorState::estimate_gas`]. The signatures, ZK proofs and
+    /// fees only get verified if `verify` is set.
+    /// Transactions calling the native deployer end a batch, so the following
+    /// ones get their metadata and ZK proofs checked against the new code and
+    /// circuits of the contracts it deployed or upgraded.
+    async fn execute_transactions(
+        &self,
+        txs: &[Transaction],
+        block_context: BlockContext,
+        write: bool,
+        verify: bool,
+    ) -> Result<Vec<VerifiedTx>> {
+        let mut verified = Vec::with_capacity(txs.len());
+        let mut start = 0;
+        for (idx, tx) in txs.iter().enumerate() {
+            let deploys = tx.calls.iter().any(|call| call.contract_id == *DEPLOYOOOR_CONTRACT_ID);
+            if deploys || idx == txs.len() - 1 {
+                let batch = &txs[start..=idx];
+                verified.extend(self.execute_batch(batch, block_context, write, verify).await?);
+                start = idx + 1;
+            }
+        }
+
+        Ok(verified)
+    }
+
+    /// Execute a batch of transactions, see [`ValidatorState::execute_transactions`].
+    async fn execute_batch(
+        &self,
+        txs: &[Transaction],
+        block_context: BlockContext,
+        write: bool,
+        verify: bool,
+    ) -> Result<Vec<VerifiedTx>> {
         info!(target: "consensus::validator", "Verifying {} transaction(s)", txs.len());
+
+        // Tables of public inputs used for ZK proof verification
+        let mut zkp_tables = Vec::with_capacity(txs.len());
+        // Tables of public keys used for signature verification
+        let mut sig_tables = Vec::with_capacity(txs.len());
+        // Runtimes of each transaction's calls, reused for execution
+        let mut tx_runtimes = Vec::with_capacity(txs.len());
+
         for tx in txs {
             let tx_hash = blake3::hash(&serialize(tx));
-            info!(target: "consensus::validator", "Verifying transaction {}", tx_hash);
+            info!(target: "consensus::validator", "Retrieving metadata of transaction {}", tx_hash);
 
-            // Table of public inputs used for ZK proof verification
             let mut zkp_table = vec![];
-            // Table of public keys used for signature verification
             let mut sig_table = vec![];
-            // State updates produced by contract execcution
-            let mut updates = vec![];
+            let mut runtimes = vec![];
 
             // Iterate over all calls to get the metadata
             for (idx, call) in tx.calls.iter().enumerate() {
                 info!(target: "consensus::validator", "Executing contract call {}", idx);
-                let wasm = match self.blockchain.wasm_bincode.get(call.contract_id) {
+
+                // Calls to the native deployer have no wasm code, and just
+                // need to be signed with the deploy key.
+                if call.contract_id == *DEPLOYOOOR_CONTRACT_ID {
+                    zkp_table.push(vec![]);
+                    sig_table.push(vec![Self::deploy_call_signer(call)?]);
+                    runtimes.push(None);
+                    continue
+                }
+
+                let wasm_store = &self.blockchain.wasm_bincode;
+                let wasm = match wasm_store.get_pending(&self.blockchain.overlay, call.contract_id)
+                {
                     Ok(v) => {
                         info!(target: "consensus::validator", "Found wasm bincode for {}", call.contract_id);
                         v
@@ -891,11 +1194,6 @@ impl ValidatorState {
                     }
                 };
 
-                // Write the actual payload data
-                let mut payload = vec![];
-                payload.write_u32(idx as u32)?; // Call index
-                tx.calls.encode(&mut payload)?; // Actual call data
-
                 // Instantiate the wasm runtime
                 let mut runtime =
                     match Runtime::new(&wasm, self.blockchain.clone(), call.contract_id) {
@@ -909,9 +1207,10 @@ impl ValidatorState {
                             return Err(e)
                         }
                     };
+                runtime.set_block_context(block_context);
 
                 info!(target: "consensus::validator", "Executing \"metadata\" call");
-                let metadata = match runtime.metadata(&payload) {
+                let metadata = match runtime.metadata(&Self::call_payload(tx, idx)?) {
                     Ok(v) => v,
                     Err(e) => {
                         error!(target: "consensus::validator", "Failed to execute \"metadata\" call: {}", e);
@@ -943,14 +1242,80 @@ impl ValidatorState {
                 info!(target: "consensus::validator", "Successfully executed \"metadata\" call");
                 zkp_table.push(zkp_pub);
                 sig_table.push(sig_pub);
+                runtimes.push(Some(runtime));
+            }
+
+            zkp_tables.push(zkp_table);
+            sig_tables.push(sig_table);
+            tx_runtimes.push(runtimes);
+        }
+
+        // With the metadata at hand, we verify the signatures and ZK proofs of
+        // the entire batch. If even one of them fails, we drop everything.
+        // NOTE: When it comes to the ZK proofs, we first do a lookup of the
+        // verifying keys, but if we do not find them, we'll generate them
+        // from the contract's zkas db. This can be kinda expensive, so open
+        // to alternatives.
+        if verify {
+            self.verify_signatures_and_proofs(txs, sig_tables, zkp_tables).await?;
+        }
+
+        // The state transitions must be applied in order, so the execution is
+        // done one transaction at a time.
+        let mut verified = Vec::with_capacity(txs.len());
+        for (tx, mut runtimes) in txs.iter().zip(tx_runtimes) {
+            let tx_hash = blake3::hash(&serialize(tx));
+            info!(target: "consensus::validator", "Executing transaction {}", tx_hash);
+
+            // State updates produced by contract execution
+            let mut updates = vec![];
+            // Gas used by all the calls
+            let mut gas_used = 0_u64;
+            // Events emitted by all the calls
+            let mut events = vec![];
+            // Contracts deployed, upgraded or locked, and the resulting writes
+            let mut deployed = vec![];
+            let mut deploy_writes = vec![];
+            // Contracts whose code and circuits got replaced
+            let mut initialized = vec![];
+
+            for (idx, (call, runtime)) in tx.calls.iter().zip(runtimes.iter_mut()).enumerate() {
+                let Some(runtime) = runtime else {
+                    info!(target: "consensus::validator", "Executing native deployer call");
+                    let deploy_call = match self.exec_deploy_call(call, block_context) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!(target: "consensus::validator", "Failed to execute deployer call: {}", e);
+                            return Err(e)
+                        }
+                    };
+
+                    // Pending changes aren't visible to the following calls,
+                    // so a contract can only be handled once per transaction.
+                    if deployed.contains(&deploy_call.contract_id) {
+                        let e = Error::DuplicateDeployCall(deploy_call.contract_id.to_string());
+                        error!(target: "consensus::validator", "{}", e);
+                        return Err(e)
+                    }
+
+                    gas_used = gas_used.saturating_add(deploy_call.gas_used);
+                    deployed.push(deploy_call.contract_id);
+                    if deploy_call.runtime.is_some() {
+                        initialized.push(deploy_call.contract_id);
+                    }
+                    deploy_writes.extend(deploy_call.writes);
+                    *runtime = deploy_call.runtime;
+                    updates.push(vec![]);
+                    continue
+                };
 
                 // After getting the metadata, we run the "exec" function with the same
                 // runtime and the same payload.
                 info!(target: "consensus::validator", "Executing \"exec\" call");
-                match runtime.exec(&payload) {
+                let update = match runtime.exec(&Self::call_payload(tx, idx)?) {
                     Ok(v) => {
                         info!(target: "consensus::validator", "Successfully executed \"exec\" call");
-                        updates.push(v);
+                        v
                     }
                     Err(e) => {
                         error!(
@@ -961,83 +1326,50 @@ impl ValidatorState {
                         return Err(e)
                     }
                 };
+
+                // The state update is run right away so its gas gets accounted
+                // for. Its changes are only written once the whole transaction
+                // is verified, and we're told to write.
+                info!(target: "consensus::validator", "Executing \"update\" call");
+                if let Err(e) = runtime.update(&update) {
+                    error!(target: "consensus::validator", "Failed to execute \"update\" call: {}", e);
+                    return Err(e)
+                }
+
+                gas_used = gas_used.saturating_add(runtime.gas_used());
+                events.extend(runtime.take_events());
+                updates.push(update);
                 // At this point we're done with the call and move on to the next one.
             }
 
-            // When we're done looping and executing over the tx's contract calls, we
-            // move on with verification. First we verify the signatures as that's
-            // cheaper, and then finally we verify the ZK proofs.
-            info!(target: "consensus::validator", "Verifying signatures for transaction {}", tx_hash);
-            if sig_table.len() != tx.signatures.len() {
-                error!(target: "consensus::validator", "Incorrect number of signatures in tx {}", tx_hash);
-                return Err(Error::InvalidSignature)
+            // Calls only see the nullifiers committed before the transaction,
+            // so we make sure they don't spend the same input among themselves.
+            let mut spent = HashSet::new();
+            if !spent_nullifiers(tx, &updates).iter().all(|n| spent.insert(n.to_bytes())) {
+                error!(target: "consensus::validator", "Transaction {} spends an input more than once", tx_hash);
+                return Err(Error::DuplicateNullifier)
             }
 
-            match tx.verify_sigs(sig_table) {
-                Ok(()) => {
-                    info!(target: "consensus::validator", "Signatures verification for tx {} successful", tx_hash)
-                }
-                Err(e) => {
-                    error!(target: "consensus::validator", "Signature verification for tx {} failed: {}", tx_hash, e);
-                    return Err(e)
-                }
-            };
-
-            // NOTE: When it comes to the ZK proofs, we first do a lookup of the
-            // verifying keys, but if we do not find them, we'll generate them
-            // inside of this function. This can be kinda expensive, so open to
-            // alternatives.
-            info!(target: "consensus::validator", "Verifying ZK proofs for transaction {}", tx_hash);
-            match tx.verify_zkps(self.verifying_keys.clone(), zkp_table).await {
-                Ok(()) => {
-                    info!(target: "consensus::validator", "ZK proof verification for tx {} successful", tx_hash)
-                }
-                Err(e) => {
-                    error!(target: "consensus::validator", "ZK proof verification for tx {} failed: {}", tx_hash, e);
-                    return Err(e)
-                }
-            };
+            // Check that the transaction pays for the gas it used
+            let fee = paid_fee(tx, &updates);
+            let required_fee = gas_used.saturating_mul(self.gas_price);
+            info!(target: "consensus::validator", "Transaction {} used {} gas and paid a fee of {}", tx_hash, gas_used, fee);
+            if verify && fee < required_fee && !is_faucet_airdrop(tx, &updates) {
+                error!(
+                    target: "consensus::validator",
+                    "Fee {} of tx {} doesn't cover the required {}",
+                    fee, tx_hash, required_fee
+                );
+                return Err(Error::InsufficientFee(fee, required_fee))
+            }
 
             // After the verifications stage passes, if we're told to write, we
             // apply the state updates.
             assert!(tx.calls.len() == updates.len());
             if write {
                 info!(target: "consensus::validator", "Performing state updates");
-                for (call, update) in tx.calls.iter().zip(updates.iter()) {
-                    // For this we instantiate the runtimes again.
-                    // TODO: Optimize this
-                    // TODO: Sum up the gas costs of previous calls during execution
-                    //       and verification and these.
-                    let wasm = match self.blockchain.wasm_bincode.get(call.contract_id) {
-                        Ok(v) => {
-                            info!(target: "consensus::validator", "Found wasm bincode for {}", call.contract_id);
-                            v
-                        }
-                        Err(e) => {
-                            error!(
-                                target: "consensus::validator",
-                                "Could not find wasm bincode for contract {}: {}",
-                                call.contract_id, e
-                            );
-                            return Err(Error::ContractNotFound(call.contract_id.to_string()))
-                        }
-                    };
-
-                    let mut runtime =
-                        match Runtime::new(&wasm, self.blockchain.clone(), call.contract_id) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!(
-                                    target: "consensus::validator",
-                                    "Failed to instantiate WASM runtime for contract {}",
-                                    call.contract_id
-                                );
-                                return Err(e)
-                            }
-                        };
-
-                    info!(target: "consensus::validator", "Executing \"apply\" call");
-                    match runtime.apply(update) {
+                for runtime in runtimes.iter_mut().flatten() {
+                    match runtime.commit() {
                         // TODO: FIXME: This should be done in an atomic tx/batch
                         Ok(()) => {
                             info!(target: "consensus::validator", "State update applied successfully")
@@ -1048,16 +1380,342 @@ impl ValidatorState {
                         }
                     };
                 }
+
+                for (tree, writes) in deploy_writes {
+                    self.blockchain.overlay.insert(&tree, writes);
+                }
+
+                self.blockchain.stage_tx_events(&tx_hash, &events);
+
+                // Verifying keys of upgraded contracts get rebuilt once
+                // their new circuits are written along with the block.
+                let mut verifying_keys = self.verifying_keys.write().await;
+                for contract_id in &initialized {
+                    verifying_keys.remove(&contract_id.to_bytes());
+                }
             } else {
                 info!(target: "consensus::validator", "Skipping apply of state updates because write=false");
             }
 
             info!(target: "consensus::validator", "Transaction {} verified successfully", tx_hash);
+            verified.push(VerifiedTx { updates, gas_used, fee, events });
+        }
+
+        Ok(verified)
+    }
+
+    /// Drop the state changes pending in the blockchain overlay. Verifying
+    /// keys built from circuits of contracts deployed or upgraded by them get
+    /// dropped as well, so they're rebuilt from the stored circuits. Native
+    /// contracts can't be upgraded, so their keys are kept.
+    async fn discard_pending_state(&self) {
+        self.blockchain.overlay.clear();
+
+        let native = [MONEY_CONTRACT_ID.to_bytes(), DAO_CONTRACT_ID.to_bytes()];
+        self.verifying_keys.write().await.retain(|id, _| native.contains(id));
+    }
+
+    /// Verify the signatures and ZK proofs of the given transactions in one
+    /// batch, against the public keys and inputs their calls' metadata gave.
+    async fn verify_signatures_and_proofs(
+        &self,
+        txs: &[Transaction],
+        sig_tables: Vec<Vec<Vec<PublicKey>>>,
+        zkp_tables: Vec<Vec<Vec<(String, Vec<pallas::Base>)>>>,
+    ) -> Result<()> {
+        // Make sure the tables line up with what's attached to the transactions,
+        // so the batch verification doesn't have to.
+        for ((tx, sig_table), zkp_table) in txs.iter().zip(&sig_tables).zip(&zkp_tables) {
+            let tx_hash = blake3::hash(&serialize(tx));
+            if sig_table.len() != tx.signatures.len() {
+                error!(target: "consensus::validator", "Incorrect number of signatures in tx {}", tx_hash);
+                return Err(Error::InvalidSignature)
+            }
+
+            if zkp_table.len() != tx.proofs.len() ||
+                zkp_table.iter().zip(tx.proofs.iter()).any(|(x, y)| x.len() != y.len())
+            {
+                let e = format!("Incorrect number of ZK proofs in tx {}", tx_hash);
+                error!(target: "consensus::validator", "{}", e);
+                return Err(VerifyFailed::ProofVerifyFailed(e).into())
+            }
+        }
+
+        self.load_verifying_keys(txs).await?;
+        info!(target: "consensus::validator", "Verifying signatures and ZK proofs of {} transaction(s)", txs.len());
+        if let Err(e) = verify_batch(txs, sig_tables, zkp_tables, self.verifying_keys.clone()).await
+        {
+            error!(target: "consensus::validator", "Batch verification failed: {}", e);
+            return Err(e)
+        }
+        info!(target: "consensus::validator", "Batch verification successful");
+
+        Ok(())
+    }
+
+    /// Stage the blockchain index writes for the nullifiers and coins of the
+    /// given verified transactions, so they get written along with their block.
+    fn stage_tx_indexes(&self, txs: &[Transaction], verified: &[VerifiedTx]) -> Result<()> {
+        for (tx, verified) in txs.iter().zip(verified.iter()) {
+            let tx_hash = blake3::hash(&serialize(tx));
+            let nullifiers = spent_nullifiers(tx, &verified.updates);
+            let coins = minted_coins(tx, &verified.updates);
+            self.blockchain.stage_tx_indexes(&tx_hash, &nullifiers, &coins)?;
+        }
+
+        Ok(())
+    }
+
+    /// Build the ZK proof verifying keys for the circuits found in the zkas
+    /// db of the given contract, taking the pending writes of the blockchain
+    /// overlay into account. Contracts without a zkas db have none.
+    fn build_verifying_keys(
+        blockchain: &Blockchain,
+        contract_id: &ContractId,
+    ) -> Result<Vec<(String, VerifyingKey)>> {
+        info!(target: "consensus::validator", "Looking up zkas db for ContractID: {}", contract_id);
+        let zkas_db = match blockchain.contracts.lookup_pending(
+            &blockchain.sled_db,
+            &blockchain.overlay,
+            &TreeWrites::new(),
+            contract_id,
+            SMART_CONTRACT_ZKAS_DB_NAME,
+        ) {
+            Ok(v) => v,
+            Err(Error::ContractNotFound(_)) | Err(Error::ContractStateNotFound) => {
+                return Ok(vec![])
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut vks = vec![];
+        let range = (Bound::Unbounded, Bound::Unbounded);
+        for (zkas_ns, zkas_bincode) in blockchain.overlay.range(&zkas_db, range, usize::MAX)? {
+            info!(target: "consensus::validator", "Iterating over zkas db");
+            info!(target: "consensus::validator", "Deserializing namespace");
+            let zkas_ns: String = deserialize(&zkas_ns)?;
+            info!(target: "consensus::validator", "Creating VerifyingKey for zkas circuit with namespace {}", zkas_ns);
+            let zkbin = ZkBinary::decode(&zkas_bincode)?;
+            let vk = contract_verifying_key(zkbin)?;
+            vks.push((zkas_ns, vk));
+        }
+
+        Ok(vks)
+    }
+
+    /// Make sure the verifying keys of the contracts called with ZK proofs in
+    /// the given transactions are available, building the missing ones. These
+    /// are the contracts deployed on-chain, or upgraded since their keys were
+    /// last built.
+    async fn load_verifying_keys(&self, txs: &[Transaction]) -> Result<()> {
+        let mut verifying_keys = self.verifying_keys.write().await;
+        for tx in txs {
+            for (call, proofs) in tx.calls.iter().zip(tx.proofs.iter()) {
+                if proofs.is_empty() || verifying_keys.contains_key(&call.contract_id.to_bytes()) {
+                    continue
+                }
+
+                info!(target: "consensus::validator", "Creating ZK verifying keys for contract {}", call.contract_id);
+                let vks = Self::build_verifying_keys(&self.blockchain, &call.contract_id)?;
+                verifying_keys.insert(call.contract_id.to_bytes(), vks);
+            }
         }
 
         Ok(())
     }
 
+    /// Get the public key a call to the native deployer has to be signed with.
+    /// Its parameters always start with it, whatever the function.
+    fn deploy_call_signer(call: &ContractCall) -> Result<PublicKey> {
+        let Some((func, params)) = call.data.split_first() else {
+            return Err(Error::ContractError(darkfi_sdk::error::ContractError::InvalidFunction))
+        };
+
+        DeployFunction::try_from(*func)?;
+        Ok(PublicKey::decode(Cursor::new(params))?)
+    }
+
+    /// Blockchain context for executing transactions in the current slot,
+    /// on top of the canonical chain.
+    fn current_block_context(&self) -> Result<BlockContext> {
+        let slot = self.consensus.current_slot();
+        Ok(BlockContext {
+            slot,
+            epoch: self.consensus.slot_epoch(slot),
+            last_block_timestamp: self.blockchain.last_header()?.timestamp,
+        })
+    }
+
+    /// Blockchain context for executing the transactions of the block with
+    /// the given header. Its parent is either a block of the canonical chain,
+    /// or a proposal extending a fork.
+    fn block_context(&self, header: &Header) -> Result<BlockContext> {
+        let blocks = self.blockchain.blocks.get(&[header.previous], false)?;
+        let parent = match &blocks[0] {
+            // Since we used strict get, its safe to unwrap here
+            Some(block) => self.blockchain.headers.get(&[block.header], true)?[0].clone().unwrap(),
+            None => self
+                .consensus
+                .forks
+                .iter()
+                .flat_map(|fork| &fork.sequence)
+                .find(|checkpoint| checkpoint.proposal.hash == header.previous)
+                .map(|checkpoint| checkpoint.proposal.block.header.clone())
+                .ok_or_else(|| Error::BlockNotFound(header.previous.to_string()))?,
+        };
+
+        Ok(BlockContext {
+            slot: header.slot,
+            epoch: self.consensus.slot_epoch(header.slot),
+            last_block_timestamp: parent.timestamp,
+        })
+    }
+
+    /// Execute a call to the native deployer, which deploys a new contract,
+    /// upgrades the code of a deployed one, or locks one so it can't be
+    /// upgraded anymore. The contract ID is derived from the deploy key the
+    /// call is signed with, see [`ContractId::derive`].
+    /// On deployments and upgrades, the contract's `__initialize` function is
+    /// run with the given payload, on top of its existing state for upgrades.
+    /// Nothing gets written: the resulting state changes are returned, to be
+    /// staged once the whole transaction is verified.
+    fn exec_deploy_call(
+        &self,
+        call: &ContractCall,
+        block_context: BlockContext,
+    ) -> Result<DeployCall> {
+        let overlay = &self.blockchain.overlay;
+        let wasm_store = &self.blockchain.wasm_bincode;
+        let lock_store = &self.blockchain.contract_locks;
+        let nonce_store = &self.blockchain.contract_nonces;
+
+        let (func, params) = (DeployFunction::try_from(call.data[0])?, &call.data[1..]);
+        match func {
+            DeployFunction::Deploy | DeployFunction::Upgrade => {
+                let params: DeployParamsV1 = deserialize(params)?;
+                let contract_id = ContractId::derive_public(params.public_key);
+
+                let deployed = wasm_store.get_pending(overlay, contract_id).is_ok();
+                match func {
+                    DeployFunction::Deploy if deployed => {
+                        return Err(Error::ContractAlreadyDeployed(contract_id.to_string()))
+                    }
+                    DeployFunction::Upgrade if !deployed => {
+                        return Err(Error::ContractNotFound(contract_id.to_string()))
+                    }
+                    DeployFunction::Upgrade if lock_store.is_locked(overlay, contract_id)? => {
+                        return Err(Error::ContractLocked(contract_id.to_string()))
+                    }
+                    _ => {}
+                }
+
+                // Deploy calls are signed along with the nonce, so they can't
+                // be replayed to downgrade the contract after an upgrade.
+                let nonce = nonce_store.get_pending(overlay, contract_id)?;
+                if params.nonce != nonce {
+                    return Err(Error::ContractNonceMismatch(
+                        contract_id.to_string(),
+                        params.nonce,
+                        nonce,
+                    ))
+                }
+
+                // The circuits are stored under their namespace, like the
+                // ones native contracts set up.
+                let zkas_bincodes = deploy_zkas_bincodes(params.zkas_bincodes)?;
+                let bytes = params.wasm_bincode.len() +
+                    zkas_bincodes.iter().map(|(_, bincode)| bincode.len()).sum::<usize>();
+
+                // Circuits of the previous code that the new one doesn't
+                // provide anymore get removed.
+                let stale_zkas = match func {
+                    DeployFunction::Upgrade => {
+                        self.stale_zkas_writes(&contract_id, &zkas_bincodes)?
+                    }
+                    _ => None,
+                };
+
+                info!(target: "consensus::validator", "Initializing contract {}", contract_id);
+                let mut runtime =
+                    Runtime::new(&params.wasm_bincode, self.blockchain.clone(), contract_id)?;
+                runtime.set_block_context(block_context);
+                runtime.initialize(&zkas_bincodes, &params.ix)?;
+
+                let gas_used = runtime.gas_used().saturating_add(db_write_cost(bytes + 8));
+                let mut writes = vec![
+                    (
+                        wasm_store.0.clone(),
+                        wasm_store.insert_writes(contract_id, &params.wasm_bincode),
+                    ),
+                    (nonce_store.0.clone(), nonce_store.insert_writes(contract_id, nonce + 1)),
+                ];
+                writes.extend(stale_zkas);
+
+                Ok(DeployCall { contract_id, runtime: Some(runtime), writes, gas_used })
+            }
+
+            DeployFunction::Lock => {
+                let params: LockParamsV1 = deserialize(params)?;
+                let contract_id = ContractId::derive_public(params.public_key);
+
+                if wasm_store.get_pending(overlay, contract_id).is_err() {
+                    return Err(Error::ContractNotFound(contract_id.to_string()))
+                }
+
+                if lock_store.is_locked(overlay, contract_id)? {
+                    return Err(Error::ContractLocked(contract_id.to_string()))
+                }
+
+                let writes = vec![(lock_store.0.clone(), lock_store.insert_writes(contract_id))];
+                let gas_used = 2 * GAS_DB_READ + db_write_cost(32);
+                Ok(DeployCall { contract_id, runtime: None, writes, gas_used })
+            }
+        }
+    }
+
+    /// Generate the writes removing the circuits of the given contract's zkas
+    /// db, taking pending writes into account, whose namespaces aren't among
+    /// the given ones. Returns `None` if the contract has no zkas db.
+    fn stale_zkas_writes(
+        &self,
+        contract_id: &ContractId,
+        zkas_bincodes: &[(String, Vec<u8>)],
+    ) -> Result<Option<(sled::Tree, TreeWrites)>> {
+        let blockchain = &self.blockchain;
+        let zkas_db = match blockchain.contracts.lookup_pending(
+            &blockchain.sled_db,
+            &blockchain.overlay,
+            &TreeWrites::new(),
+            contract_id,
+            SMART_CONTRACT_ZKAS_DB_NAME,
+        ) {
+            Ok(v) => v,
+            Err(Error::ContractNotFound(_)) | Err(Error::ContractStateNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let namespaces: Vec<Vec<u8>> = zkas_bincodes.iter().map(|(ns, _)| serialize(ns)).collect();
+        let range = (Bound::Unbounded, Bound::Unbounded);
+        let writes: TreeWrites = blockchain
+            .overlay
+            .range(&zkas_db, range, usize::MAX)?
+            .into_iter()
+            .filter(|(key, _)| !namespaces.contains(key))
+            .map(|(key, _)| (key, None))
+            .collect();
+
+        Ok(Some((zkas_db, writes)))
+    }
+
+    /// Build the payload passed to the wasm runtime for the call at `idx`
+    /// in the given transaction.
+    fn call_payload(tx: &Transaction, idx: usize) -> Result<Vec<u8>> {
+        let mut payload = vec![];
+        payload.write_u32(idx as u32)?; // Call index
+        tx.calls.encode(&mut payload)?; // Actual call data
+        Ok(payload)
+    }
+
     /// Append to canonical state received finalized slot checkpoints from block sync task.
     pub async fn receive_slot_checkpoints(
         &mut self,
@@ -1094,3 +1752,111 @@ impl ValidatorState {
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use darkfi_serial::VarInt;
+
+    use super::*;
+    use crate::zkas::{
+        compiler::{BINARY_VERSION, MAGIC_BYTES},
+        types::StackType,
+        Opcode, VarType,
+    };
+
+    /// Assemble a zkas bincode with the given witnesses and opcodes.
+    fn zkas_bincode(
+        witnesses: &[VarType],
+        opcodes: &[(Opcode, Vec<(StackType, usize)>)],
+    ) -> Vec<u8> {
+        let mut bincode = MAGIC_BYTES.to_vec();
+        bincode.push(BINARY_VERSION);
+        bincode.extend_from_slice(&serialize(&String::from("Test")));
+        bincode.extend_from_slice(b".constant");
+        bincode.extend_from_slice(b".literal");
+        bincode.extend_from_slice(b".contract");
+        bincode.extend(witnesses.iter().map(|x| *x as u8));
+        bincode.extend_from_slice(b".circuit");
+        for (opcode, args) in opcodes {
+            bincode.push(*opcode as u8);
+            bincode.extend_from_slice(&serialize(&VarInt(args.len() as u64)));
+            for (stack_type, index) in args {
+                bincode.push(stack_type.clone() as u8);
+                bincode.extend_from_slice(&serialize(&VarInt(*index as u64)));
+            }
+        }
+
+        bincode
+    }
+
+    fn sum_bincode() -> Vec<u8> {
+        zkas_bincode(
+            &[VarType::Base, VarType::Base],
+            &[
+                (Opcode::BaseAdd, vec![(StackType::Var, 0), (StackType::Var, 1)]),
+                (Opcode::ConstrainInstance, vec![(StackType::Var, 2)]),
+            ],
+        )
+    }
+
+    #[test]
+    fn deploy_accepts_valid_zkas() {
+        let zkas_bincodes = deploy_zkas_bincodes(vec![sum_bincode()]).unwrap();
+        assert_eq!(zkas_bincodes, vec![(String::from("Test"), sum_bincode())]);
+    }
+
+    #[test]
+    fn deploy_rejects_short_zkas() {
+        let bincode = sum_bincode();
+        for len in 0..=MAGIC_BYTES.len() {
+            assert!(deploy_zkas_bincodes(vec![bincode[..len].to_vec()]).is_err());
+        }
+
+        // Opcode announcing arguments the binary ends before
+        let mut bincode = zkas_bincode(&[VarType::Base], &[]);
+        bincode.push(Opcode::ConstrainInstance as u8);
+        bincode.extend_from_slice(&serialize(&VarInt(2)));
+        assert!(deploy_zkas_bincodes(vec![bincode]).is_err());
+    }
+
+    #[test]
+    fn deploy_rejects_invalid_zkas() {
+        // Reference past the end of the stack
+        let bincode = zkas_bincode(
+            &[VarType::Base, VarType::Base],
+            &[(Opcode::BaseAdd, vec![(StackType::Var, 0), (StackType::Var, 5)])],
+        );
+        assert!(deploy_zkas_bincodes(vec![bincode]).is_err());
+
+        // Argument of the wrong type
+        let bincode = zkas_bincode(
+            &[VarType::Base, VarType::Base],
+            &[(Opcode::EcAdd, vec![(StackType::Var, 0), (StackType::Var, 1)])],
+        );
+        assert!(deploy_zkas_bincodes(vec![bincode]).is_err());
+
+        // Missing argument
+        let bincode =
+            zkas_bincode(&[VarType::Base], &[(Opcode::BaseAdd, vec![(StackType::Var, 0)])]);
+        assert!(deploy_zkas_bincodes(vec![bincode]).is_err());
+
+        // Literal that isn't declared
+        let bincode = zkas_bincode(&[], &[(Opcode::WitnessBase, vec![(StackType::Lit, 0)])]);
+        assert!(deploy_zkas_bincodes(vec![bincode]).is_err());
+
+        // Witness type the zkvm can't witness
+        let bincode = zkas_bincode(&[VarType::BaseArray], &[]);
+        assert!(deploy_zkas_bincodes(vec![bincode]).is_err());
+    }
+
+    #[test]
+    fn deploy_rejects_oversized_zkas() {
+        // Variable-base multiplications take a few hundred rows each,
+        // way more than 2^CONTRACT_PROOF_K rows in total.
+        let mul = (Opcode::EcMulVarBase, vec![(StackType::Var, 0), (StackType::Var, 1)]);
+        let opcodes = vec![mul; 128];
+        let bincode = zkas_bincode(&[VarType::Base, VarType::EcNiPoint], &opcodes);
+        assert!(ZkBinary::decode(&bincode).is_ok());
+        assert!(deploy_zkas_bincodes(vec![bincode]).is_err());
+    }
+}
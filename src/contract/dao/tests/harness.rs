@@ -132,6 +132,10 @@ impl DaoTestHarness {
         )
         .await?;
 
+        // Transaction fees are tested in the money contract, so the DAO
+        // tests can build transactions without fee calls.
+        alice_state.write().await.gas_price = 0;
+
         let money_contract_id = *MONEY_CONTRACT_ID;
         let dao_contract_id = *DAO_CONTRACT_ID;
 
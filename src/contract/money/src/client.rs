@@ -39,6 +39,7 @@ use darkfi_sdk::crypto::{
     pasta_prelude::*,
     pedersen_commitment_base, pedersen_commitment_u64, poseidon_hash, Keypair, MerkleNode,
     MerklePosition, MerkleTree, Nullifier, PublicKey, SecretKey, TokenId, ValueBlind, ValueCommit,
+    DARK_TOKEN_ID,
 };
 use darkfi_serial::{serialize, Decodable, Encodable, SerialDecodable, SerialEncodable};
 use halo2_proofs::circuit::Value;
@@ -46,8 +47,8 @@ use log::{debug, error, info};
 use rand::rngs::OsRng;
 
 use crate::model::{
-    ClearInput, Input, MoneyStakeParams, MoneyTransferParams, MoneyUnstakeParams, Output,
-    StakedInput, StakedOutput,
+    ClearInput, Input, MoneyFeeParams, MoneyStakeParams, MoneyTransferParams, MoneyUnstakeParams,
+    Output, StakedInput, StakedOutput,
 };
 
 // Wallet SQL table constant names. These have to represent the SQL schema.
@@ -671,7 +672,9 @@ pub fn build_half_swap_tx(
 
     debug!(target: "money", "Money::build_half_swap_tx(): Building anonymous inputs");
     // We'll take any coin that has correct value
-    let Some(coin) = coins.iter().find(|x| x.note.value == value_send && x.note.token_id == token_id_send) else {
+    let Some(coin) =
+        coins.iter().find(|x| x.note.value == value_send && x.note.token_id == token_id_send)
+    else {
         error!(target: "money", "Money::build_half_swap_tx(): Did not find a coin with enough value to swap");
         return Err(ClientFailed::NotEnoughValue(value_send).into())
     };
@@ -1059,6 +1062,125 @@ pub fn build_transfer_tx(
     Ok((params, zk_proofs, signature_secrets, spent_coins))
 }
 
+/// Build money contract fee call parameters with the given data:
+/// * `keypair` - Caller's keypair, receiving the change
+/// * `fee` - Fee to pay
+/// * `coins` - Set of native token coins we're able to spend
+/// * `tree` - Current Merkle tree of coins
+/// * `mint_zkbin` - ZkBinary of the mint circuit
+/// * `mint_pk` - Proving key for the ZK mint proof
+/// * `burn_zkbin` - ZkBinary of the burn circuit
+/// * `burn_pk` - Proving key for the ZK burn proof
+///
+/// A single coin holding at least `fee` is spent, and the rest is returned
+/// in a change output.
+#[allow(clippy::too_many_arguments)]
+pub fn build_fee_tx(
+    keypair: &Keypair,
+    fee: u64,
+    coins: &[OwnCoin],
+    tree: &MerkleTree,
+    mint_zkbin: &ZkBinary,
+    mint_pk: &ProvingKey,
+    burn_zkbin: &ZkBinary,
+    burn_pk: &ProvingKey,
+) -> Result<(MoneyFeeParams, Vec<Proof>, Vec<SecretKey>, OwnCoin)> {
+    debug!(target: "money", "Building money contract fee call");
+    let token_id = *DARK_TOKEN_ID;
+
+    let Some(coin) = coins.iter().find(|c| c.note.token_id == token_id && c.note.value >= fee)
+    else {
+        error!(target: "money", "Money::build_fee_tx(): No coin holding enough value to pay the fee");
+        let max = coins.iter().filter(|c| c.note.token_id == token_id).map(|c| c.note.value).max();
+        return Err(ClientFailed::NotEnoughValue(max.unwrap_or(0)).into())
+    };
+
+    let token_blind = ValueBlind::random(&mut OsRng);
+    let input_blind = ValueBlind::random(&mut OsRng);
+    let output_blind = ValueBlind::random(&mut OsRng);
+    // The fee commitment is the difference of the input and output ones
+    let fee_value_blind = input_blind - output_blind;
+
+    let root = tree.root(0).unwrap();
+    let merkle_path = tree.authentication_path(coin.leaf_position, &root).unwrap();
+    let signature_secret = SecretKey::random(&mut OsRng);
+
+    info!(target: "money", "Creating fee burn proof");
+    let (burn_proof, burn_revealed) = create_transfer_burn_proof(
+        burn_zkbin,
+        burn_pk,
+        coin.note.value,
+        token_id,
+        input_blind,
+        token_blind,
+        coin.note.serial,
+        pallas::Base::zero(),
+        pallas::Base::zero(),
+        pallas::Base::random(&mut OsRng),
+        coin.note.coin_blind,
+        coin.secret,
+        coin.leaf_position,
+        merkle_path,
+        signature_secret,
+    )?;
+
+    let change_value = coin.note.value - fee;
+    let serial = pallas::Base::random(&mut OsRng);
+    let coin_blind = pallas::Base::random(&mut OsRng);
+
+    info!(target: "money", "Creating fee change mint proof");
+    let (mint_proof, mint_revealed) = create_transfer_mint_proof(
+        mint_zkbin,
+        mint_pk,
+        change_value,
+        token_id,
+        output_blind,
+        token_blind,
+        serial,
+        pallas::Base::zero(),
+        pallas::Base::zero(),
+        coin_blind,
+        keypair.public,
+    )?;
+
+    let note = Note {
+        serial,
+        value: change_value,
+        token_id,
+        spend_hook: pallas::Base::zero(),
+        user_data: pallas::Base::zero(),
+        coin_blind,
+        value_blind: output_blind,
+        token_blind,
+        memo: vec![],
+    };
+    let encrypted_note = note.encrypt(&keypair.public)?;
+
+    let params = MoneyFeeParams {
+        input: Input {
+            value_commit: burn_revealed.value_commit,
+            token_commit: burn_revealed.token_commit,
+            nullifier: burn_revealed.nullifier,
+            merkle_root: burn_revealed.merkle_root,
+            spend_hook: burn_revealed.spend_hook,
+            user_data_enc: burn_revealed.user_data_enc,
+            signature_public: burn_revealed.signature_public,
+        },
+        output: Output {
+            value_commit: mint_revealed.value_commit,
+            token_commit: mint_revealed.token_commit,
+            coin: mint_revealed.coin.inner(),
+            ciphertext: encrypted_note.ciphertext,
+            ephem_public: encrypted_note.ephem_public,
+        },
+        fee_value: fee,
+        fee_value_blind,
+        token_blind,
+    };
+
+    Ok((params, vec![burn_proof, mint_proof], vec![signature_secret], coin.clone()))
+}
+
 pub fn build_stake_tx(
     //pubkey: &PublicKey,
     coins: &[OwnCoin],
@@ -28,43 +28,22 @@ use darkfi_sdk::{
     msg, set_return_data, ContractCall,
 };
 
+#[cfg(not(feature = "no-entrypoint"))]
 use darkfi_sdk::error::ContractError;
 
 #[cfg(not(feature = "no-entrypoint"))]
 use darkfi_serial::{deserialize, serialize, Encodable, WriteExt};
 
 /// Functions we allow in this contract
-#[repr(u8)]
-pub enum MoneyFunction {
-    Transfer = 0x00,
-    OtcSwap = 0x01,
-    Stake = 0x02,
-    Unstake = 0x03,
-    Mint = 0x04,
-}
-
-impl TryFrom<u8> for MoneyFunction {
-    type Error = ContractError;
-
-    fn try_from(b: u8) -> core::result::Result<MoneyFunction, Self::Error> {
-        match b {
-            0x00 => Ok(Self::Transfer),
-            0x01 => Ok(Self::OtcSwap),
-            0x02 => Ok(Self::Stake),
-            0x03 => Ok(Self::Unstake),
-            0x04 => Ok(Self::Mint),
-            _ => Err(ContractError::InvalidFunction),
-        }
-    }
-}
+pub use darkfi_sdk::money::MoneyFunction;
 
 /// Structures and object definitions
 pub mod model;
 
 #[cfg(not(feature = "no-entrypoint"))]
 use model::{
-    MoneyStakeParams, MoneyStakeUpdate, MoneyTransferParams, MoneyTransferUpdate,
-    MoneyUnstakeParams,
+    MoneyFeeParams, MoneyFeeUpdate, MoneyStakeParams, MoneyStakeUpdate, MoneyTransferParams,
+    MoneyTransferUpdate, MoneyUnstakeParams,
 };
 
 #[cfg(feature = "client")]
@@ -357,6 +336,56 @@ fn get_metadata(_cid: ContractId, ix: &[u8]) -> ContractResult {
             set_return_data(&metadata)?;
             Ok(())
         }
+
+        MoneyFunction::Fee => {
+            let params: MoneyFeeParams = deserialize(&self_.data[1..])?;
+            let input = &params.input;
+            let output = &params.output;
+
+            let mut zk_public_values: Vec<(String, Vec<pallas::Base>)> = vec![];
+
+            let value_coords = input.value_commit.to_affine().coordinates().unwrap();
+            let token_coords = input.token_commit.to_affine().coordinates().unwrap();
+            let (sig_x, sig_y) = input.signature_public.xy();
+            zk_public_values.push((
+                MONEY_CONTRACT_ZKAS_BURN_NS_V1.to_string(),
+                vec![
+                    input.nullifier.inner(),
+                    *value_coords.x(),
+                    *value_coords.y(),
+                    *token_coords.x(),
+                    *token_coords.y(),
+                    input.merkle_root.inner(),
+                    input.user_data_enc,
+                    sig_x,
+                    sig_y,
+                ],
+            ));
+
+            let value_coords = output.value_commit.to_affine().coordinates().unwrap();
+            let token_coords = output.token_commit.to_affine().coordinates().unwrap();
+            zk_public_values.push((
+                MONEY_CONTRACT_ZKAS_MINT_NS_V1.to_string(),
+                vec![
+                    output.coin,
+                    *value_coords.x(),
+                    *value_coords.y(),
+                    *token_coords.x(),
+                    *token_coords.y(),
+                ],
+            ));
+
+            let signature_pubkeys: Vec<PublicKey> = vec![input.signature_public];
+
+            let mut metadata = vec![];
+            zk_public_values.encode(&mut metadata)?;
+            signature_pubkeys.encode(&mut metadata)?;
+
+            // Using this, we pass the above data to the host.
+            set_return_data(&metadata)?;
+            Ok(())
+        }
+
         MoneyFunction::Mint => {
             msg!("[Mint] Entered match arm");
             unimplemented!();
@@ -385,7 +414,8 @@ fn process_instruction(cid: ContractId, ix: &[u8]) -> ContractResult {
             let nullifiers_db = db_lookup(cid, MONEY_CONTRACT_NULLIFIERS_TREE)?;
             let coin_roots_db = db_lookup(cid, MONEY_CONTRACT_COIN_ROOTS_TREE)?;
 
-            let Some(faucet_pubkeys) = db_get(info_db, &serialize(&MONEY_CONTRACT_FAUCET_PUBKEYS))? else {
+            let Some(faucet_pubkeys) = db_get(info_db, &serialize(&MONEY_CONTRACT_FAUCET_PUBKEYS))?
+            else {
                 msg!("[Transfer] Error: Missing faucet pubkeys from info db");
                 return Err(ContractError::Internal);
             };
@@ -741,6 +771,58 @@ fn process_instruction(cid: ContractId, ix: &[u8]) -> ContractResult {
             Ok(())
         }
 
+        MoneyFunction::Fee => {
+            msg!("[Fee] Entered match arm");
+            let params: MoneyFeeParams = deserialize(&self_.data[1..])?;
+            let input = &params.input;
+            let output = &params.output;
+
+            let nullifiers_db = db_lookup(cid, MONEY_CONTRACT_NULLIFIERS_TREE)?;
+            let coin_roots_db = db_lookup(cid, MONEY_CONTRACT_COIN_ROOTS_TREE)?;
+
+            // Fees are paid in the native token
+            let tokcom = pedersen_commitment_base(DARK_TOKEN_ID.inner(), params.token_blind);
+            if input.token_commit != tokcom || output.token_commit != tokcom {
+                msg!("[Fee] Error: Fee is not paid in the native token");
+                return Err(ContractError::Custom(27))
+            }
+
+            // The Merkle root is used to know whether this is a coin that existed
+            // in a previous state.
+            if !db_contains_key(coin_roots_db, &serialize(&input.merkle_root))? {
+                msg!("[Fee] Error: Merkle root not found in previous state");
+                return Err(ContractError::Custom(21))
+            }
+
+            // The nullifier should not already exist. It is the double-spend protection.
+            if db_contains_key(nullifiers_db, &serialize(&input.nullifier))? {
+                msg!("[Fee] Error: Duplicate nullifier found");
+                return Err(ContractError::Custom(22))
+            }
+
+            // Whatever the input holds and doesn't go to the change output is
+            // the revealed fee.
+            let fee_commit = pedersen_commitment_u64(params.fee_value, params.fee_value_blind);
+            if input.value_commit - output.value_commit != fee_commit {
+                msg!("[Fee] Error: Value commitments do not match the paid fee");
+                return Err(ContractError::Custom(28))
+            }
+
+            // Create a state update
+            let update = MoneyFeeUpdate {
+                nullifiers: vec![input.nullifier],
+                coins: vec![Coin::from(output.coin)],
+                fee: params.fee_value,
+            };
+            let mut update_data = vec![];
+            update_data.write_u8(MoneyFunction::Fee as u8)?;
+            update.encode(&mut update_data)?;
+            set_return_data(&update_data)?;
+            msg!("[Fee] State update set!");
+
+            Ok(())
+        }
+
         MoneyFunction::Mint => {
             msg!("[Mint] Entered match arm");
             unimplemented!();
@@ -797,6 +879,29 @@ fn process_update(cid: ContractId, update_data: &[u8]) -> ContractResult {
             Ok(())
         }
 
+        MoneyFunction::Fee => {
+            let update: MoneyFeeUpdate = deserialize(&update_data[1..])?;
+
+            let info_db = db_lookup(cid, MONEY_CONTRACT_INFO_TREE)?;
+            let nullifiers_db = db_lookup(cid, MONEY_CONTRACT_NULLIFIERS_TREE)?;
+            let coin_roots_db = db_lookup(cid, MONEY_CONTRACT_COIN_ROOTS_TREE)?;
+
+            for nullifier in update.nullifiers {
+                db_set(nullifiers_db, &serialize(&nullifier), &[])?;
+            }
+
+            msg!("Adding change coins {:?} to Merkle tree", update.coins);
+            let coins: Vec<_> = update.coins.iter().map(|x| MerkleNode::from(x.inner())).collect();
+            merkle_add(
+                info_db,
+                coin_roots_db,
+                &serialize(&MONEY_CONTRACT_COIN_MERKLE_TREE),
+                &coins,
+            )?;
+
+            Ok(())
+        }
+
         MoneyFunction::Mint => {
             msg!("[Mint] Entered match arm");
             unimplemented!();
@@ -91,6 +91,32 @@ pub struct MoneyTransferUpdate {
     pub coins: Vec<Coin>,
 }
 
+/// Input and change output for paying a transaction fee
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct MoneyFeeParams {
+    /// Anonymous input paying the fee
+    pub input: Input,
+    /// Anonymous output returning the change
+    pub output: Output,
+    /// Paid fee
+    pub fee_value: u64,
+    /// Blinding factor for `fee_value`
+    pub fee_value_blind: ValueBlind,
+    /// Token blind to reveal the native token ID
+    pub token_blind: ValueBlind,
+}
+
+/// State update produced by a fee payment
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct MoneyFeeUpdate {
+    /// Revealed nullifiers
+    pub nullifiers: Vec<Nullifier>,
+    /// Minted coins
+    pub coins: Vec<Coin>,
+    /// Paid fee
+    pub fee: u64,
+}
+
 /// State update produced by a staking
 #[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
 pub struct MoneyStakeUpdate {
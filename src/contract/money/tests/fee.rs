@@ -0,0 +1,176 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Integration test for transaction fees.
+//!
+//! The faucet airdrops Alice some native tokens and some of her own token,
+//! which airdrops are exempt from paying fees for. Alice then sends her token to Bob, and attaches a
+//! fee call paying with her native tokens. A transaction paying less than
+//! the gas it uses times the gas price gets rejected, and so does one paying
+//! with the same coin twice. One paying the estimated fee gets accepted.
+
+use darkfi::{consensus::constants::GAS_PRICE, tx::Transaction, Error, Result};
+use darkfi_sdk::{
+    crypto::{
+        merkle_prelude::*, pallas, pasta_prelude::*, poseidon_hash, MerkleNode, Nullifier, TokenId,
+        DARK_TOKEN_ID,
+    },
+    ContractCall,
+};
+use darkfi_serial::Encodable;
+use log::info;
+use rand::rngs::OsRng;
+
+use darkfi_money_contract::{
+    client::{build_fee_tx, build_transfer_tx, Coin, EncryptedNote, OwnCoin},
+    model::MoneyTransferParams,
+    MoneyFunction,
+};
+
+mod harness;
+use harness::{init_logger, MoneyTestHarness};
+
+/// Build the `OwnCoin` for the given output of an airdrop to Alice
+fn alice_owncoin(th: &mut MoneyTestHarness, params: &MoneyTransferParams) -> Result<OwnCoin> {
+    th.alice_merkle_tree.append(&MerkleNode::from(params.outputs[0].coin));
+    let leaf_position = th.alice_merkle_tree.witness().unwrap();
+
+    let ciphertext = params.outputs[0].ciphertext.clone();
+    let ephem_public = params.outputs[0].ephem_public;
+    let e_note = EncryptedNote { ciphertext, ephem_public };
+    let note = e_note.decrypt(&th.alice_kp.secret)?;
+
+    Ok(OwnCoin {
+        coin: Coin::from(params.outputs[0].coin),
+        note: note.clone(),
+        secret: th.alice_kp.secret,
+        nullifier: Nullifier::from(poseidon_hash([th.alice_kp.secret.inner(), note.serial])),
+        leaf_position,
+    })
+}
+
+#[async_std::test]
+async fn money_contract_fee() -> Result<()> {
+    init_logger()?;
+
+    const ALICE_NATIVE: u64 = 1_000_000_000;
+    const ALICE_TOKEN: u64 = 100;
+
+    let mut th = MoneyTestHarness::new().await?;
+    th.alice_state.write().await.gas_price = GAS_PRICE;
+
+    let alice_token_id = TokenId::from(pallas::Base::random(&mut OsRng));
+
+    info!(target: "money", "[Alice] ===================================");
+    info!(target: "money", "[Alice] Executing fee-exempt airdrop txs");
+    info!(target: "money", "[Alice] ===================================");
+    let (native_tx, native_params) =
+        th.airdrop(ALICE_NATIVE, *DARK_TOKEN_ID, &th.alice_kp.public)?;
+    let (token_tx, token_params) = th.airdrop(ALICE_TOKEN, alice_token_id, &th.alice_kp.public)?;
+    th.alice_state.read().await.verify_transactions(&[native_tx], true).await?;
+    let native_oc = alice_owncoin(&mut th, &native_params)?;
+    th.alice_state.read().await.verify_transactions(&[token_tx], true).await?;
+    let token_oc = alice_owncoin(&mut th, &token_params)?;
+
+    info!(target: "money", "[Alice] ===================================");
+    info!(target: "money", "[Alice] Building payment tx to Bob");
+    info!(target: "money", "[Alice] ===================================");
+    let (xfer_params, xfer_proofs, xfer_secrets, _spent_coins) = build_transfer_tx(
+        &th.alice_kp,
+        &th.bob_kp.public,
+        ALICE_TOKEN,
+        alice_token_id,
+        pallas::Base::zero(),
+        pallas::Base::zero(),
+        pallas::Base::random(&mut OsRng),
+        &[token_oc],
+        &th.alice_merkle_tree,
+        &th.mint_zkbin,
+        &th.mint_pk,
+        &th.burn_zkbin,
+        &th.burn_pk,
+        false,
+    )?;
+
+    let mut data = vec![MoneyFunction::Transfer as u8];
+    xfer_params.encode(&mut data)?;
+    let xfer_call = ContractCall { contract_id: th.money_contract_id, data };
+
+    // Build the payment with fee calls paying the given fees out of the
+    // same native coin, signed by all of its calls.
+    let build_tx = |fees: &[u64]| -> Result<Transaction> {
+        let mut calls = vec![xfer_call.clone()];
+        let mut proofs = vec![xfer_proofs.clone()];
+        let mut secrets = vec![xfer_secrets.clone()];
+        for fee in fees {
+            let (fee_params, fee_proofs, fee_secrets, _fee_coin) = build_fee_tx(
+                &th.alice_kp,
+                *fee,
+                &[native_oc.clone()],
+                &th.alice_merkle_tree,
+                &th.mint_zkbin,
+                &th.mint_pk,
+                &th.burn_zkbin,
+                &th.burn_pk,
+            )?;
+
+            let mut data = vec![MoneyFunction::Fee as u8];
+            fee_params.encode(&mut data)?;
+            calls.push(ContractCall { contract_id: th.money_contract_id, data });
+            proofs.push(fee_proofs);
+            secrets.push(fee_secrets);
+        }
+
+        let mut tx = Transaction { calls, proofs, signatures: vec![] };
+        for call_secrets in &secrets {
+            let sigs = tx.create_sigs(&mut OsRng, call_secrets)?;
+            tx.signatures.push(sigs);
+        }
+        Ok(tx)
+    };
+
+    let gas = th.alice_state.read().await.estimate_gas(&build_tx(&[0])?).await?;
+    let required_fee = gas * GAS_PRICE;
+    assert!(required_fee > 1);
+
+    info!(target: "money", "[Alice] ===================================");
+    info!(target: "money", "[Alice] Executing under-paying payment tx");
+    info!(target: "money", "[Alice] ===================================");
+    let tx = build_tx(&[required_fee - 1])?;
+    let res = th.alice_state.read().await.verify_transactions(&[tx], true).await;
+    assert!(
+        matches!(res, Err(Error::InsufficientFee(fee, req)) if fee == required_fee - 1 && req == required_fee)
+    );
+
+    info!(target: "money", "[Alice] ===================================");
+    info!(target: "money", "[Alice] Executing double-spending payment tx");
+    info!(target: "money", "[Alice] ===================================");
+    let tx = build_tx(&[required_fee, required_fee])?;
+    let res = th.alice_state.read().await.verify_transactions(&[tx], true).await;
+    assert!(matches!(res, Err(Error::DuplicateNullifier)));
+
+    info!(target: "money", "[Alice] ===================================");
+    info!(target: "money", "[Alice] Executing fully paying payment tx");
+    info!(target: "money", "[Alice] ===================================");
+    let tx = build_tx(&[required_fee])?;
+    let verified = th.alice_state.read().await.verify_transactions(&[tx], true).await?;
+    assert_eq!(verified[0].gas_used, gas);
+    assert_eq!(verified[0].fee, required_fee);
+
+    Ok(())
+}
@@ -154,6 +154,12 @@ impl MoneyTestHarness {
         )
         .await?;
 
+        // Transaction fees are tested separately in `fee.rs`, so the other
+        // tests can build transactions without fee calls.
+        for state in [&faucet_state, &alice_state, &bob_state, &charlie_state] {
+            state.write().await.gas_price = 0;
+        }
+
         let money_contract_id = *MONEY_CONTRACT_ID;
 
         let alice_sled = alice_state.read().await.blockchain.sled_db.clone();
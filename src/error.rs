@@ -142,6 +142,12 @@ pub enum Error {
     #[error("Malformed packet")]
     MalformedPacket,
 
+    #[error("Message {0} payload too large: {1} bytes")]
+    MessageTooLarge(String, usize),
+
+    #[error("Rate limit exceeded for message: {0}")]
+    RateLimited(String),
+
     #[error("Socks proxy error: {0}")]
     SocksError(String),
 
@@ -158,6 +164,21 @@ pub enum Error {
     #[error("Tor error: {0}")]
     TorError(String),
 
+    #[error("Nym error: {0}")]
+    NymError(String),
+
+    #[error("Noise handshake error: {0}")]
+    NoiseError(String),
+
+    #[error("Peer {0} is not in the allowed peers list")]
+    PeerNotAllowed(String),
+
+    #[error("Packet decompression failed: {0}")]
+    CompressionError(String),
+
+    #[error("Gossip topic {0} is not registered")]
+    GossipTopicNotFound(String),
+
     #[error("Node is not connected to other nodes.")]
     NetworkNotConnected,
 
@@ -272,6 +293,30 @@ pub enum Error {
     #[error("Proposer is not eligible to produce proposals")]
     ProposalProposerNotEligible,
 
+    #[error("Block transactions don't match the header's Merkle root")]
+    InvalidTxsRoot,
+
+    #[error("Transaction is already in the mempool")]
+    TxAlreadyInMempool,
+
+    #[error("Transaction spends a nullifier already spent by mempool transaction {0}")]
+    TxConflictsWithMempool(String),
+
+    #[error("Mempool is full and transaction priority is too low")]
+    MempoolFull,
+
+    #[error("Transaction fee {0} doesn't cover the required {1}")]
+    InsufficientFee(u64, u64),
+
+    #[error("Transaction spends the same input more than once")]
+    DuplicateNullifier,
+
+    #[error("Invalid sync response from peer: {0}")]
+    InvalidSyncResponse(String),
+
+    #[error("No peers left to sync the blockchain from")]
+    SyncPeersExhausted,
+
     // ===============
     // Database errors
     // ===============
@@ -295,6 +340,9 @@ pub enum Error {
     #[error("Block in slot {0} not found in database")]
     SlotNotFound(u64),
 
+    #[error("Transactions of the block in slot {0} have been pruned")]
+    BlockPruned(u64),
+
     #[error("Slot checkpoint {0} not found in database")]
     SlotCheckpointNotFound(u64),
 
@@ -307,6 +355,27 @@ pub enum Error {
     #[error("Contract already initialized")]
     ContractAlreadyInitialized,
 
+    #[error("Contract {0} is already deployed")]
+    ContractAlreadyDeployed(String),
+
+    #[error("Contract {0} is locked and can't be upgraded")]
+    ContractLocked(String),
+
+    #[error("Deploy call of contract {0} has nonce {1}, expected {2}")]
+    ContractNonceMismatch(String, u64, u64),
+
+    #[error("Contract {0} is targeted by more than one deploy call in the transaction")]
+    DuplicateDeployCall(String),
+
+    #[error("Can't roll back to slot {0}, its state diffs are gone or shared by later blocks")]
+    RollbackNotPossible(u64),
+
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("Snapshots can only be imported into a blockchain holding just the genesis block")]
+    SnapshotImportNotEmpty,
+
     #[error("zkas bincode not found in sled database")]
     ZkasBincodeNotFound,
 
@@ -366,6 +435,10 @@ pub enum Error {
     #[error("contract execution error")]
     ContractExecError(u64),
 
+    #[cfg(feature = "wasm-runtime")]
+    #[error("Gas limit exceeded: {0}/{1}")]
+    GasLimitExceeded(u64, u64),
+
     // ====================
     // Miscellaneous errors
     // ====================
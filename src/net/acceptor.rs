@@ -19,12 +19,16 @@
 use std::{env, fs};
 
 use async_std::sync::{Arc, Mutex};
-use log::{error, info};
+use log::{error, info, warn};
 use smol::Executor;
 use url::Url;
 
+#[cfg(feature = "websockets")]
+use super::transport::NymTransport;
 use super::{
-    transport::{TcpTransport, TorTransport, Transport, TransportListener, TransportName},
+    transport::{
+        MemoryTransport, TcpTransport, TorTransport, Transport, TransportListener, TransportName,
+    },
     Channel, ChannelPtr, SessionWeakPtr,
 };
 use crate::{
@@ -132,6 +136,48 @@ impl Acceptor {
 
                 accept!(listener, transport, upgrade);
             }
+            #[cfg(feature = "websockets")]
+            TransportName::Nym(upgrade) => {
+                let transport = NymTransport::new(NymTransport::get_dialer_env()?);
+                let listener = transport.clone().listen_on(accept_url.clone());
+
+                if let Err(err) = listener {
+                    error!(target: "net::acceptor", "Setup for {} failed: {}", accept_url, err);
+                    return Err(Error::BindFailed(accept_url.as_str().into()))
+                }
+
+                let listener = match listener?.await {
+                    Ok(l) => l,
+                    Err(err) => {
+                        error!(target: "net::acceptor", "Bind listener to {} failed: {}", accept_url, err);
+                        return Err(Error::BindFailed(accept_url.as_str().into()))
+                    }
+                };
+
+                info!(target: "net::acceptor", "Nym address: {}", listener.address()?);
+
+                match upgrade {
+                    None => {
+                        self.accept(Box::new(listener), executor);
+                    }
+                    Some(u) if u == "tls" => {
+                        let tls_listener = transport.upgrade_listener(listener)?.await?;
+                        self.accept(Box::new(tls_listener), executor);
+                    }
+                    Some(u) => return Err(Error::UnsupportedTransportUpgrade(u)),
+                }
+            }
+            TransportName::Memory(upgrade) => {
+                let session = self.session.lock().await.as_ref().and_then(|s| s.upgrade());
+                let memory_node = match session.and_then(|s| s.p2p().settings().memory_node.clone())
+                {
+                    Some(node) => node,
+                    None => return Err(Error::UnsupportedTransport("memory".into())),
+                };
+                let transport = MemoryTransport::new(memory_node);
+                let listener = transport.clone().listen_on(accept_url.clone());
+                accept!(listener, transport, upgrade);
+            }
             _ => unimplemented!(),
         }
         Ok(())
@@ -165,8 +211,17 @@ impl Acceptor {
         loop {
             match listener.next().await {
                 Ok((stream, url)) => {
-                    let channel =
-                        Channel::new(stream, url, self.session.lock().await.clone().unwrap()).await;
+                    let session = self.session.lock().await.clone().unwrap();
+
+                    // Drop connections from banned hosts right away
+                    if let Some(s) = session.upgrade() {
+                        if s.p2p().ban_manager().is_banned(&url).await {
+                            warn!(target: "net::acceptor", "Rejecting banned peer {}", url);
+                            continue
+                        }
+                    }
+
+                    let channel = Channel::new(stream, url, session).await;
                     self.channel_subscriber.notify(Ok(channel)).await;
                 }
                 Err(e) => {
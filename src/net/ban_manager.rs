@@ -0,0 +1,307 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use async_std::sync::{Arc, Mutex};
+use darkfi_serial::{deserialize, serialize, SerialDecodable, SerialEncodable};
+use log::{error, info, warn};
+use serde_json::json;
+use url::{Host, Url};
+
+use super::constants::{BAN_DURATION_SECONDS, BAN_SCORE_DECAY_SECONDS, BAN_THRESHOLD};
+use crate::{util::time::unix_timestamp, Result};
+
+/// Atomic pointer to the ban manager.
+pub type BanManagerPtr = Arc<BanManager>;
+
+/// A ban on a single host.
+#[derive(Clone, Debug, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct BanEntry {
+    /// UNIX timestamp after which the ban is lifted
+    pub expiry: u64,
+    /// Why the host was banned
+    pub reason: String,
+}
+
+/// Misbehaviour points accumulated by a host that is not banned (yet).
+struct Score {
+    points: u32,
+    last_report: u64,
+}
+
+/// Keeps track of misbehaving peers. Protocols report misbehaviour points
+/// against a channel, and once a host crosses `BAN_THRESHOLD` it is banned
+/// for `BAN_DURATION_SECONDS`. Bans are keyed by host rather than by full
+/// URL, so a peer can't dodge them by reconnecting from another port.
+/// Nym peers are keyed by their nym address, since many of them share a
+/// gateway. Loopback addresses are never banned: every inbound Tor peer
+/// reaches us through the local Tor daemon, so they would all share a ban.
+pub struct BanManager {
+    bans: Mutex<HashMap<String, BanEntry>>,
+    scores: Mutex<HashMap<String, Score>>,
+    ban_file: Option<PathBuf>,
+}
+
+impl BanManager {
+    /// Create a new ban manager, loading any bans persisted in `ban_file`.
+    pub fn new(ban_file: Option<PathBuf>) -> Arc<Self> {
+        let bans = match &ban_file {
+            Some(path) => load_ban_file(path),
+            None => HashMap::new(),
+        };
+
+        Arc::new(Self { bans: Mutex::new(bans), scores: Mutex::new(HashMap::new()), ban_file })
+    }
+
+    /// Key used to index bans and scores for the given address.
+    /// Returns `None` for addresses that can't be told apart from other
+    /// peers, and so must not be banned.
+    fn ban_key(addr: &Url) -> Option<String> {
+        let host = match addr.host() {
+            Some(Host::Ipv4(ip)) if ip.is_loopback() => return None,
+            Some(Host::Ipv6(ip)) if ip.is_loopback() => return None,
+            Some(Host::Domain("localhost")) => return None,
+            Some(_) => addr.host_str().unwrap(),
+            None => return Some(addr.to_string()),
+        };
+
+        match addr.scheme() {
+            "nym" | "nym+tls" if !addr.username().is_empty() => {
+                Some(format!("{}@{}", addr.username(), host))
+            }
+            _ => Some(host.to_string()),
+        }
+    }
+
+    /// Check if the given address is currently banned. Expired bans are
+    /// dropped on the way.
+    pub async fn is_banned(&self, addr: &Url) -> bool {
+        let Some(key) = Self::ban_key(addr) else { return false };
+        let mut bans = self.bans.lock().await;
+        match bans.get(&key) {
+            Some(entry) if entry.expiry > unix_timestamp().unwrap_or(0) => true,
+            Some(_) => {
+                info!(target: "net::ban_manager", "Ban on {} expired", key);
+                bans.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Add misbehaviour points against the given address. Points are
+    /// forgotten if the host behaves for `BAN_SCORE_DECAY_SECONDS`.
+    /// Returns `true` if the host got banned.
+    pub async fn misbehaving(&self, addr: &Url, points: u32, reason: &str) -> bool {
+        let Some(key) = Self::ban_key(addr) else {
+            warn!(target: "net::ban_manager", "Peer {} misbehaving (unbannable): {}", addr, reason);
+            return false
+        };
+        let now = unix_timestamp().unwrap_or(0);
+
+        let total = {
+            let mut scores = self.scores.lock().await;
+            let score = scores.entry(key.clone()).or_insert(Score { points: 0, last_report: now });
+            if now.saturating_sub(score.last_report) > BAN_SCORE_DECAY_SECONDS {
+                score.points = 0;
+            }
+            score.points = score.points.saturating_add(points);
+            score.last_report = now;
+
+            let total = score.points;
+            if total >= BAN_THRESHOLD {
+                scores.remove(&key);
+            }
+            total
+        };
+
+        warn!(
+            target: "net::ban_manager",
+            "Peer {} misbehaving (+{} = {}): {}", key, points, total, reason
+        );
+
+        if total < BAN_THRESHOLD {
+            return false
+        }
+
+        self.ban(addr, reason).await;
+        true
+    }
+
+    /// Ban the given address for `BAN_DURATION_SECONDS`.
+    pub async fn ban(&self, addr: &Url, reason: &str) {
+        let Some(key) = Self::ban_key(addr) else {
+            warn!(target: "net::ban_manager", "Not banning local address {}: {}", addr, reason);
+            return
+        };
+        let expiry = unix_timestamp().unwrap_or(0) + BAN_DURATION_SECONDS;
+        warn!(target: "net::ban_manager", "Banning {} until {}: {}", key, expiry, reason);
+
+        self.bans.lock().await.insert(key, BanEntry { expiry, reason: reason.to_string() });
+
+        if let Err(e) = self.save().await {
+            error!(target: "net::ban_manager", "Failed saving ban list: {}", e);
+        }
+    }
+
+    /// Lift the ban on the given address, if any.
+    pub async fn unban(&self, addr: &Url) {
+        let Some(key) = Self::ban_key(addr) else { return };
+        if self.bans.lock().await.remove(&key).is_none() {
+            return
+        }
+
+        info!(target: "net::ban_manager", "Lifted ban on {}", key);
+        if let Err(e) = self.save().await {
+            error!(target: "net::ban_manager", "Failed saving ban list: {}", e);
+        }
+    }
+
+    /// Return all active bans.
+    pub async fn load_all(&self) -> Vec<(String, BanEntry)> {
+        let now = unix_timestamp().unwrap_or(0);
+        let mut bans = self.bans.lock().await;
+        bans.retain(|_, entry| entry.expiry > now);
+        bans.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    pub async fn get_info(&self) -> serde_json::Value {
+        let bans: Vec<serde_json::Value> = self
+            .load_all()
+            .await
+            .into_iter()
+            .map(|(host, entry)| {
+                json!({
+                    "host": host,
+                    "expiry": entry.expiry,
+                    "reason": entry.reason,
+                })
+            })
+            .collect();
+
+        json!(bans)
+    }
+
+    /// Write the active bans to the configured ban file, if any.
+    pub async fn save(&self) -> Result<()> {
+        let path = match &self.ban_file {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let bans = self.load_all().await;
+
+        if let Some(parent) = path.parent() {
+            async_std::fs::create_dir_all(parent).await?;
+        }
+
+        info!(target: "net::ban_manager::save()", "Saving {} bans to {:?}", bans.len(), path);
+        async_std::fs::write(path, serialize(&bans)).await?;
+        Ok(())
+    }
+}
+
+/// Auxiliary function to load a previously saved ban list.
+/// Errors are logged and result in an empty ban list.
+fn load_ban_file(path: &Path) -> HashMap<String, BanEntry> {
+    if !path.exists() {
+        return HashMap::new()
+    }
+
+    let bytes = match fs::read(path) {
+        Ok(v) => v,
+        Err(e) => {
+            error!(target: "net::ban_manager::load_ban_file()", "Failed reading ban file {:?}: {}", path, e);
+            return HashMap::new()
+        }
+    };
+
+    match deserialize::<Vec<(String, BanEntry)>>(&bytes) {
+        Ok(bans) => {
+            let now = unix_timestamp().unwrap_or(0);
+            let bans: HashMap<String, BanEntry> =
+                bans.into_iter().filter(|(_, entry)| entry.expiry > now).collect();
+            info!(target: "net::ban_manager::load_ban_file()", "Loaded {} bans from {:?}", bans.len(), path);
+            bans
+        }
+        Err(e) => {
+            error!(target: "net::ban_manager::load_ban_file()", "Failed decoding ban file {:?}: {}", path, e);
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_ban_manager() {
+        // Unique directory, so concurrent test runs don't share the file
+        let dir = std::env::temp_dir().join(format!("darkfi_test_bans_{}", rand::random::<u64>()));
+        let path = dir.join("bans");
+
+        let banman = BanManager::new(Some(path.clone()));
+        let addr = Url::parse("tcp://192.168.10.1:8333").unwrap();
+        // Same host, different port
+        let addr2 = Url::parse("tcp://192.168.10.1:41234").unwrap();
+
+        assert!(!banman.misbehaving(&addr, BAN_THRESHOLD / 2, "test").await);
+        assert!(!banman.is_banned(&addr).await);
+        assert!(banman.misbehaving(&addr2, BAN_THRESHOLD / 2, "test").await);
+        assert!(banman.is_banned(&addr).await);
+        assert!(banman.is_banned(&addr2).await);
+
+        // Bans survive a restart
+        let banman = BanManager::new(Some(path.clone()));
+        assert!(banman.is_banned(&addr).await);
+        assert_eq!(banman.load_all().await.len(), 1);
+
+        banman.unban(&addr).await;
+        assert!(!banman.is_banned(&addr).await);
+
+        // So do unbans
+        let banman = BanManager::new(Some(path.clone()));
+        assert!(!banman.is_banned(&addr).await);
+        assert!(banman.load_all().await.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_ban_manager_shared_addresses() {
+        let banman = BanManager::new(None);
+
+        // Inbound Tor peers all come in through the local Tor daemon
+        let tor = Url::parse("tcp://127.0.0.1:41234").unwrap();
+        assert!(!banman.misbehaving(&tor, BAN_THRESHOLD, "test").await);
+        assert!(!banman.is_banned(&tor).await);
+
+        // Nym peers behind the same gateway are banned separately
+        let nym = Url::parse("nym://id1.enc1@gateway1").unwrap();
+        let nym2 = Url::parse("nym://id2.enc2@gateway1").unwrap();
+        assert!(banman.misbehaving(&nym, BAN_THRESHOLD, "test").await);
+        assert!(banman.is_banned(&nym).await);
+        assert!(!banman.is_banned(&nym2).await);
+    }
+}
@@ -16,7 +16,10 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::{collections::HashMap, time::Instant};
+
 use async_std::sync::{Arc, Mutex};
+use ed25519_compact::x25519::{KeyPair, PublicKey};
 use futures::{
     io::{ReadHalf, WriteHalf},
     AsyncReadExt,
@@ -28,8 +31,14 @@ use smol::Executor;
 use url::Url;
 
 use super::{
-    message,
+    compression,
+    constants::{
+        BAN_SCORE_MALFORMED_PACKET, BAN_SCORE_RATE_LIMITED, BAN_SCORE_UNDECODABLE_MESSAGE,
+    },
+    gossip, message,
     message_subscriber::{MessageSubscription, MessageSubsystem},
+    metrics::MetricsPtr,
+    noise::{self, CipherState},
     transport::TransportStream,
     Session, SessionBitflag, SessionWeakPtr,
 };
@@ -45,8 +54,13 @@ pub type ChannelPtr = Arc<Channel>;
 struct ChannelInfo {
     random_id: u32,
     remote_node_id: String,
+    remote_public_key: String,
     last_msg: String,
     last_status: String,
+    // Whether the remote node accepts compressed packets
+    compression: bool,
+    // Number of messages dropped by the rate limiter, per command
+    rate_limited: HashMap<String, u64>,
     // Message log which is cleared on querying get_info
     log: Option<Mutex<Vec<(NanoTimestamp, String, String)>>>,
 }
@@ -61,8 +75,11 @@ impl ChannelInfo {
         Self {
             random_id: rand::thread_rng().gen(),
             remote_node_id: String::new(),
+            remote_public_key: String::new(),
             last_msg: String::new(),
             last_status: String::new(),
+            compression: false,
+            rate_limited: HashMap::new(),
             log,
         }
     }
@@ -82,8 +99,11 @@ impl ChannelInfo {
         json!({
             "random_id": self.random_id,
             "remote_node_id": self.remote_node_id,
+            "remote_public_key": self.remote_public_key,
             "last_msg": self.last_msg,
             "last_status": self.last_status,
+            "compression": self.compression,
+            "rate_limited": self.rate_limited,
             "log": log,
         })
     }
@@ -94,12 +114,17 @@ impl ChannelInfo {
 pub struct Channel {
     reader: Mutex<ReadHalf<Box<dyn TransportStream>>>,
     writer: Mutex<WriteHalf<Box<dyn TransportStream>>>,
+    send_cipher: Mutex<Option<CipherState>>,
+    recv_cipher: Mutex<Option<CipherState>>,
+    remote_public_key: Mutex<Option<PublicKey>>,
     address: Url,
     message_subsystem: MessageSubsystem,
     stop_subscriber: SubscriberPtr<Error>,
     receive_task: StoppableTaskPtr,
     stopped: Mutex<bool>,
     info: Mutex<ChannelInfo>,
+    metrics: MetricsPtr,
+    created: Instant,
     session: SessionWeakPtr,
 }
 
@@ -119,17 +144,25 @@ impl Channel {
         let message_subsystem = MessageSubsystem::new();
         Self::setup_dispatchers(&message_subsystem).await;
 
-        let channel_log = session.upgrade().unwrap().p2p().settings().channel_log;
+        let p2p = session.upgrade().unwrap().p2p();
+        let channel_log = p2p.settings().channel_log;
+        let metrics = p2p.metrics();
+        metrics.channel_opened().await;
 
         Arc::new(Self {
             reader,
             writer,
+            send_cipher: Mutex::new(None),
+            recv_cipher: Mutex::new(None),
+            remote_public_key: Mutex::new(None),
             address,
             message_subsystem,
             stop_subscriber: Subscriber::new(),
             receive_task: StoppableTask::new(),
             stopped: Mutex::new(false),
             info: Mutex::new(ChannelInfo::new(channel_log)),
+            metrics,
+            created: Instant::now(),
             session,
         })
     }
@@ -152,6 +185,31 @@ impl Channel {
         debug!(target: "net::channel::start()", "END, address={}", self.address());
     }
 
+    /// Performs the Noise handshake on the raw stream using our static
+    /// `identity`. Must run before the channel is started, since the
+    /// receive loop takes over the reader. On success all further packets
+    /// are encrypted, and the remote static public key is returned.
+    pub async fn noise_handshake(&self, identity: &KeyPair, initiator: bool) -> Result<PublicKey> {
+        debug!(target: "net::channel::noise_handshake()", "START, address={}", self.address());
+
+        let session = {
+            let reader = &mut *self.reader.lock().await;
+            let writer = &mut *self.writer.lock().await;
+            match initiator {
+                true => noise::initiator(reader, writer, identity).await?,
+                false => noise::responder(reader, writer, identity).await?,
+            }
+        };
+
+        *self.send_cipher.lock().await = Some(session.send);
+        *self.recv_cipher.lock().await = Some(session.recv);
+        *self.remote_public_key.lock().await = Some(session.remote_static);
+        self.info.lock().await.remote_public_key = noise::encode_public_key(&session.remote_static);
+
+        debug!(target: "net::channel::noise_handshake()", "END, address={}", self.address());
+        Ok(session.remote_static)
+    }
+
     /// Stops the channel. Steps through each component of the channel
     /// connection and sends a stop signal. Notifies all subscribers that
     /// the channel has been closed.
@@ -163,6 +221,7 @@ impl Channel {
             self.stop_subscriber.notify(Error::ChannelStopped).await;
             self.receive_task.stop().await;
             self.message_subsystem.trigger_error(Error::ChannelStopped).await;
+            self.metrics.channel_closed(self.created.elapsed()).await;
             debug!(target: "net::channel::stop()", "END, address={}", self.address());
         }
     }
@@ -230,8 +289,8 @@ impl Channel {
 
     /// Implements send message functionality. Creates a new payload and encodes
     /// it. Then creates a message packet- the base type of the network- and
-    /// copies the payload into it. Then we send the packet over the TCP
-    /// stream.
+    /// copies the payload into it. Large payloads are compressed if the remote
+    /// node supports it. Then we send the packet over the TCP stream.
     async fn send_message<M: message::Message>(&self, message: M) -> Result<()> {
         let mut payload = Vec::new();
         message.encode(&mut payload)?;
@@ -239,15 +298,30 @@ impl Channel {
         let time = NanoTimestamp::current_time();
         //let time = time::unix_timestamp()?;
 
-        {
+        let compress = {
             let info = &mut *self.info.lock().await;
             if let Some(l) = &info.log {
                 l.lock().await.push((time, "send".to_string(), packet.command.clone()));
             };
-        }
+            info.compression
+        };
+
+        let packet = match compress {
+            true => compression::compress_packet(packet)?,
+            false => packet,
+        };
 
+        // Hold the writer lock while sealing so nonces follow wire order
         let stream = &mut *self.writer.lock().await;
-        message::send_packet(stream, packet).await
+        let packet = match &mut *self.send_cipher.lock().await {
+            Some(cipher) => cipher.seal_packet(packet)?,
+            None => packet,
+        };
+        let bytes = packet.payload.len();
+        message::send_packet(stream, packet).await?;
+
+        self.metrics.message_sent(M::name(), bytes).await;
+        Ok(())
     }
 
     /// Subscribe to a messages on the message subsystem.
@@ -280,6 +354,26 @@ impl Channel {
         self.info.lock().await.remote_node_id = remote_node_id;
     }
 
+    /// Start compressing large payloads sent over this channel. Called once
+    /// the remote node advertised support for it in the version exchange.
+    pub async fn enable_compression(&self) {
+        self.info.lock().await.compression = true;
+    }
+
+    /// Report misbehaviour points against this channel's peer to the
+    /// p2p ban manager. The channel is stopped if the peer gets banned.
+    pub async fn misbehaving(&self, points: u32, reason: &str) {
+        if let Some(session) = self.session.upgrade() {
+            session.p2p().misbehaving(self, points, reason).await;
+        }
+    }
+
+    /// Static public key of the remote node, verified by the Noise handshake.
+    /// `None` if the network does not use Noise.
+    pub async fn remote_public_key(&self) -> Option<PublicKey> {
+        *self.remote_public_key.lock().await
+    }
+
     /// End of file error. Triggered when unexpected end of file occurs.
     fn is_eof_error(err: Error) -> bool {
         match err {
@@ -297,6 +391,9 @@ impl Channel {
         message_subsystem.add_dispatch::<message::GetAddrsMessage>().await;
         message_subsystem.add_dispatch::<message::AddrsMessage>().await;
         message_subsystem.add_dispatch::<message::ExtAddrsMessage>().await;
+        message_subsystem.add_dispatch::<gossip::GossipMessage>().await;
+        message_subsystem.add_dispatch::<gossip::GossipHaveMessage>().await;
+        message_subsystem.add_dispatch::<gossip::GossipWantMessage>().await;
     }
 
     /// Convenience function that returns the Message Subsystem.
@@ -312,7 +409,7 @@ impl Channel {
         let reader = &mut *self.reader.lock().await;
 
         loop {
-            let packet = match message::read_packet(reader).await {
+            let packet = match self.read_packet(reader).await {
                 Ok(packet) => packet,
                 Err(err) => {
                     if Self::is_eof_error(err.clone()) {
@@ -328,6 +425,15 @@ impl Channel {
                             self.address(),
                             err
                         );
+                        if matches!(
+                            err,
+                            Error::MalformedPacket |
+                                Error::MessageTooLarge(..) |
+                                Error::NoiseError(_) |
+                                Error::CompressionError(_)
+                        ) {
+                            self.misbehaving(BAN_SCORE_MALFORMED_PACKET, "malformed packet").await;
+                        }
                     }
                     debug!(
                         target: "net::channel::main_receive_loop()",
@@ -350,10 +456,50 @@ impl Channel {
             }
 
             // Send result to our subscribers
-            self.message_subsystem.notify(&packet.command, packet.payload).await;
+            match self.message_subsystem.notify(&packet.command, packet.payload).await {
+                Ok(()) => {}
+                Err(Error::RateLimited(command)) => {
+                    debug!(
+                        target: "net::channel::main_receive_loop()",
+                        "Rate limit exceeded for {} on channel {}, dropping",
+                        command,
+                        self.address()
+                    );
+                    *self.info.lock().await.rate_limited.entry(command.clone()).or_insert(0) += 1;
+                    let reason = format!("{} rate limit exceeded", command);
+                    self.misbehaving(BAN_SCORE_RATE_LIMITED, &reason).await;
+                }
+                Err(e) => {
+                    let reason = format!("undecodable {} message: {}", packet.command, e);
+                    self.misbehaving(BAN_SCORE_UNDECODABLE_MESSAGE, &reason).await;
+                }
+            }
         }
     }
 
+    /// Read a packet from the stream, decrypting it if the channel
+    /// completed a Noise handshake and decompressing it if needed.
+    /// Received traffic is recorded under the inner message name, if it has
+    /// a dispatcher, so peers can't make up metrics keys.
+    /// We always advertise compression support, so compressed packets
+    /// are accepted regardless of what the remote node advertised.
+    async fn read_packet(
+        &self,
+        reader: &mut ReadHalf<Box<dyn TransportStream>>,
+    ) -> Result<message::Packet> {
+        let packet = message::read_packet(reader).await?;
+        let bytes = packet.payload.len();
+        let packet = match &mut *self.recv_cipher.lock().await {
+            Some(cipher) => cipher.open_packet(packet)?,
+            None => packet,
+        };
+        let packet = compression::decompress_packet(packet)?;
+
+        let command = self.message_subsystem.dispatched_name(&packet.command).await;
+        self.metrics.message_received(command, bytes).await;
+        Ok(packet)
+    }
+
     /// Handle network errors. Panic if error passes silently, otherwise
     /// broadcast the error.
     async fn handle_stop(self: Arc<Self>, result: Result<()>) {
@@ -0,0 +1,78 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use futures::{
+    future::{select, Either},
+    pin_mut,
+};
+use smol::Timer;
+
+use super::{transport::MemoryNetworkPtr, Settings};
+
+/// Source of time for the timers of a node: handshake timeouts, connection
+/// retries, pings and gossip requests. Nodes on an in-memory network follow
+/// its virtual clock, so a simulation decides when their timers fire.
+#[derive(Clone)]
+pub enum Clock {
+    /// Wall clock time, measured from when the clock was created
+    System(Instant),
+    /// Virtual clock of an in-memory network
+    Virtual(MemoryNetworkPtr),
+}
+
+impl Clock {
+    /// Clock used by a node with the given settings.
+    pub fn new(settings: &Settings) -> Self {
+        match &settings.memory_node {
+            Some(node) => Self::Virtual(node.network.clone()),
+            None => Self::System(Instant::now()),
+        }
+    }
+
+    /// Time elapsed since the clock was created.
+    pub fn now(&self) -> Duration {
+        match self {
+            Self::System(start) => start.elapsed(),
+            Self::Virtual(network) => network.clock().now(),
+        }
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        match self {
+            Self::System(_) => {
+                Timer::after(duration).await;
+            }
+            Self::Virtual(network) => network.clock().sleep(duration).await,
+        }
+    }
+
+    /// Run a future for at most `duration`. Returns `None` if it timed out.
+    pub async fn timeout<F: Future>(&self, duration: Duration, future: F) -> Option<F::Output> {
+        let sleep = self.sleep(duration);
+        pin_mut!(future, sleep);
+        match select(future, sleep).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
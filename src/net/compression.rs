@@ -0,0 +1,108 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::{Read, Write};
+
+use darkfi_serial::{deserialize, serialize};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use super::message::{Packet, MAX_PACKET_SIZE};
+use crate::{Error, Result};
+
+/// Packet command used to carry deflate-compressed packets.
+pub const COMPRESSED_COMMAND: &str = "deflate";
+
+/// Payloads smaller than this are sent as they are, since compressing
+/// them saves next to nothing.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Compress a packet if its payload is above `COMPRESSION_THRESHOLD`.
+/// The packet is returned untouched if compression does not make it
+/// smaller.
+pub fn compress_packet(packet: Packet) -> Result<Packet> {
+    if packet.payload.len() < COMPRESSION_THRESHOLD {
+        return Ok(packet)
+    }
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&packet.payload)?;
+    let compressed = encoder.finish()?;
+
+    if compressed.len() >= packet.payload.len() {
+        return Ok(packet)
+    }
+
+    let payload = serialize(&(packet.command, compressed));
+    Ok(Packet { command: COMPRESSED_COMMAND.to_string(), payload })
+}
+
+/// Unwrap a compressed packet. Other packets are returned untouched.
+/// Decompressed payloads are capped at `MAX_PACKET_SIZE`, so a peer can't
+/// make us inflate an arbitrarily large buffer.
+pub fn decompress_packet(packet: Packet) -> Result<Packet> {
+    if packet.command != COMPRESSED_COMMAND {
+        return Ok(packet)
+    }
+
+    let (command, compressed): (String, Vec<u8>) = deserialize(&packet.payload)?;
+    if command == COMPRESSED_COMMAND {
+        return Err(Error::MalformedPacket)
+    }
+
+    let mut payload = vec![];
+    DeflateDecoder::new(&compressed[..])
+        .take(MAX_PACKET_SIZE as u64 + 1)
+        .read_to_end(&mut payload)
+        .map_err(|e| Error::CompressionError(e.to_string()))?;
+
+    if payload.len() > MAX_PACKET_SIZE {
+        return Err(Error::MessageTooLarge(command, payload.len()))
+    }
+
+    Ok(Packet { command, payload })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_roundtrip() {
+        // Small payloads are left alone
+        let packet = Packet { command: "ping".to_string(), payload: vec![0u8; 4] };
+        let packet = compress_packet(packet).unwrap();
+        assert_eq!(packet.command, "ping");
+
+        let payload: Vec<u8> = (0..64 * 1024).map(|i| (i % 7) as u8).collect();
+        let packet = Packet { command: "blockinfo".to_string(), payload: payload.clone() };
+        let compressed = compress_packet(packet).unwrap();
+        assert_eq!(compressed.command, COMPRESSED_COMMAND);
+        assert!(compressed.payload.len() < payload.len());
+
+        let packet = decompress_packet(compressed).unwrap();
+        assert_eq!(packet.command, "blockinfo");
+        assert_eq!(packet.payload, payload);
+
+        // Garbage is rejected
+        let packet = Packet {
+            command: COMPRESSED_COMMAND.to_string(),
+            payload: serialize(&("blockinfo".to_string(), vec![0xffu8; 64])),
+        };
+        assert!(decompress_packet(packet).is_err());
+    }
+}
@@ -16,14 +16,19 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{env, time::Duration};
+use std::{
+    env,
+    time::{Duration, Instant},
+};
 
 use async_std::sync::Arc;
 use log::error;
 use url::Url;
 
+#[cfg(feature = "websockets")]
+use super::transport::NymTransport;
 use super::{
-    transport::{TcpTransport, TorTransport, Transport, TransportName},
+    transport::{MemoryTransport, TcpTransport, TorTransport, Transport, TransportName},
     Channel, ChannelPtr, SessionWeakPtr, SettingsPtr,
 };
 use crate::{Error, Result};
@@ -43,12 +48,24 @@ impl Connector {
     /// Establish an outbound connection.
     pub async fn connect(&self, connect_url: Url) -> Result<ChannelPtr> {
         let transport_name = TransportName::try_from(connect_url.clone())?;
-        self.connect_channel(
-            connect_url,
-            transport_name,
-            Duration::from_secs(self.settings.connect_timeout_seconds.into()),
-        )
-        .await
+        let start = Instant::now();
+        let result = self
+            .connect_channel(
+                connect_url,
+                transport_name,
+                Duration::from_secs(self.settings.connect_timeout_seconds.into()),
+            )
+            .await;
+
+        if let Some(session) = self.session.upgrade() {
+            let metrics = session.p2p().metrics();
+            match &result {
+                Ok(_) => metrics.connected(start.elapsed()).await,
+                Err(_) => metrics.connect_failed().await,
+            }
+        }
+
+        result
     }
 
     async fn connect_channel(
@@ -106,6 +123,21 @@ impl Connector {
 
                 connect!(stream, transport, upgrade)
             }
+            #[cfg(feature = "websockets")]
+            TransportName::Nym(upgrade) => {
+                let transport = NymTransport::new(NymTransport::get_dialer_env()?);
+                let stream = transport.clone().dial(connect_url.clone(), Some(timeout));
+                connect!(stream, transport, upgrade)
+            }
+            TransportName::Memory(upgrade) => {
+                let memory_node = match &self.settings.memory_node {
+                    Some(node) => node.clone(),
+                    None => return Err(Error::UnsupportedTransport("memory".into())),
+                };
+                let transport = MemoryTransport::new(memory_node);
+                let stream = transport.clone().dial(connect_url.clone(), Some(timeout));
+                connect!(stream, transport, upgrade)
+            }
             _ => unimplemented!(),
         }
     }
@@ -72,3 +72,74 @@ pub const IP4_PRIV_RANGES: [&str; 47] = [
     "233.252.0.0/24",
     "255.255.255.255/32",
 ];
+
+/// Score awarded to a host on a successful connection
+pub const HOST_SCORE_SUCCESS: i64 = 10;
+
+/// Score deducted from a host on a failed connection attempt
+pub const HOST_SCORE_FAILURE: i64 = 20;
+
+/// Maximum score a host can reach
+pub const HOST_SCORE_MAX: i64 = 100;
+
+/// Minimum score a host can reach
+pub const HOST_SCORE_MIN: i64 = -100;
+
+/// Consecutive failed connection attempts after which a host is dropped
+pub const HOST_MAX_FAILURES: u32 = 5;
+
+/// Interval at which known hosts are saved to the hosts file
+pub const HOSTS_SAVE_INTERVAL_SECONDS: u64 = 300;
+
+/// Misbehaviour points after which a peer gets banned
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// How long a ban lasts
+pub const BAN_DURATION_SECONDS: u64 = 60 * 60 * 24;
+
+/// Misbehaviour points are forgotten after this long without new reports
+pub const BAN_SCORE_DECAY_SECONDS: u64 = 60 * 60;
+
+/// Points for a packet that fails to parse or decrypt
+pub const BAN_SCORE_MALFORMED_PACKET: u32 = 50;
+
+/// Points for a message payload that fails to decode
+pub const BAN_SCORE_UNDECODABLE_MESSAGE: u32 = 20;
+
+/// Points for each message dropped by a channel's rate limiter
+pub const BAN_SCORE_RATE_LIMITED: u32 = 2;
+
+/// Points for a malformed version in the version exchange
+pub const BAN_SCORE_MALFORMED_VERSION: u32 = 50;
+
+/// Points for an addrs message above `MAX_ADDRS_PER_MESSAGE`
+pub const BAN_SCORE_ADDRS_FLOOD: u32 = 20;
+
+/// Maximum number of addresses accepted in a single addrs message
+pub const MAX_ADDRS_PER_MESSAGE: usize = 10000;
+
+/// Points for a gossip message with a bad id, or oversized have/want lists
+pub const BAN_SCORE_INVALID_GOSSIP: u32 = 20;
+
+/// Maximum number of ids accepted in a single gossip have or want message
+pub const MAX_GOSSIP_IDS_PER_MESSAGE: usize = 1000;
+
+/// Number of gossip message ids remembered for deduplication
+pub const GOSSIP_SEEN_CACHE_SIZE: usize = 65536;
+
+/// Number of recent gossip messages kept to answer want messages
+pub const GOSSIP_STORE_SIZE: usize = 1024;
+
+/// A wanted gossip message is requested from the next peer that announced
+/// it after this long
+pub const GOSSIP_WANT_TIMEOUT_SECONDS: u64 = 10;
+
+/// Number of peers that announced a wanted gossip message remembered, to
+/// request it from when the previous ones don't send it
+pub const GOSSIP_MAX_ANNOUNCERS: usize = 8;
+
+/// Points for a block proposal that fails validation checks
+pub const BAN_SCORE_INVALID_PROPOSAL: u32 = 50;
+
+/// Points for a block sync response that fails validation checks
+pub const BAN_SCORE_INVALID_SYNC_RESPONSE: u32 = 50;
@@ -0,0 +1,513 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    iter,
+    time::Duration,
+};
+
+use async_std::sync::{Arc, Mutex, Weak};
+use darkfi_serial::{serialize, Encodable, SerialDecodable, SerialEncodable};
+use log::{debug, error};
+use rand::seq::SliceRandom;
+use serde_json::json;
+use smol::Executor;
+use url::Url;
+
+use super::{
+    constants::{
+        BAN_SCORE_INVALID_GOSSIP, GOSSIP_MAX_ANNOUNCERS, GOSSIP_SEEN_CACHE_SIZE,
+        GOSSIP_STORE_SIZE, GOSSIP_WANT_TIMEOUT_SECONDS, MAX_GOSSIP_IDS_PER_MESSAGE,
+    },
+    message::Message,
+    Channel, ChannelPtr, Clock, P2p, RateLimit,
+};
+use crate::{
+    system::{StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr, Subscription},
+    Error, Result,
+};
+
+/// Atomic pointer to the gossip layer.
+pub type GossipPtr = Arc<Gossip>;
+
+/// Identifier of a gossiped message, the hash of its topic and payload.
+pub type GossipId = [u8; 32];
+
+/// Compute the id of a message published on `topic`.
+pub fn gossip_id(topic: &str, payload: &[u8]) -> GossipId {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&serialize(&topic.to_string()));
+    hasher.update(payload);
+    *hasher.finalize().as_bytes()
+}
+
+/// Per-topic relay configuration.
+#[derive(Clone, Debug)]
+pub struct GossipConfig {
+    /// Number of random peers a message is relayed to
+    pub fanout: usize,
+    /// Maximum number of hops a message travels from its publisher
+    pub ttl: u8,
+    /// Payloads of at least this size are announced with a have message
+    /// and only sent to peers asking for them
+    pub lazy_threshold: usize,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self { fanout: 6, ttl: 8, lazy_threshold: 16 * 1024 }
+    }
+}
+
+/// A message relayed on a gossip topic.
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct GossipMessage {
+    pub topic: String,
+    pub id: GossipId,
+    /// Remaining hops this message may be relayed
+    pub hops: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Announces messages we have, without their payloads.
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct GossipHaveMessage {
+    pub topic: String,
+    pub ids: Vec<GossipId>,
+}
+
+/// Requests announced messages we have not seen yet.
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct GossipWantMessage {
+    pub ids: Vec<GossipId>,
+}
+
+impl Message for GossipMessage {
+    fn name() -> &'static str {
+        "gossip"
+    }
+}
+
+impl Message for GossipHaveMessage {
+    fn name() -> &'static str {
+        "gossiphave"
+    }
+
+    fn max_payload_size() -> usize {
+        64 * 1024
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(100, 20.0))
+    }
+}
+
+impl Message for GossipWantMessage {
+    fn name() -> &'static str {
+        "gossipwant"
+    }
+
+    fn max_payload_size() -> usize {
+        64 * 1024
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(100, 20.0))
+    }
+}
+
+/// A message delivered to the subscribers of a topic.
+#[derive(Clone, Debug)]
+pub struct GossipPayload {
+    pub id: GossipId,
+    pub payload: Vec<u8>,
+}
+
+struct Topic {
+    config: GossipConfig,
+    subscriber: SubscriberPtr<GossipPayload>,
+}
+
+/// Set of recently seen message ids, forgetting the oldest ones once full.
+struct SeenCache {
+    ids: HashSet<GossipId>,
+    order: VecDeque<GossipId>,
+    capacity: usize,
+}
+
+impl SeenCache {
+    fn new(capacity: usize) -> Self {
+        Self { ids: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+
+    fn contains(&self, id: &GossipId) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Returns false if the id was already seen.
+    fn insert(&mut self, id: GossipId) -> bool {
+        if !self.ids.insert(id) {
+            return false
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.ids.remove(&oldest);
+        }
+        true
+    }
+}
+
+/// A message we asked a peer for, along with the other peers that announced
+/// it, asked in turn when it doesn't arrive in time.
+struct Want {
+    /// Peer we asked for the message
+    peer: Weak<Channel>,
+    /// When we asked for it
+    asked: Duration,
+    /// Peers that announced the message, not asked for it yet
+    announcers: VecDeque<Weak<Channel>>,
+}
+
+/// Reusable gossip layer. Applications register a topic and get its
+/// messages through a subscription, while the network takes care of
+/// deduplicating them and relaying them to a random subset of peers.
+/// Messages above the topic's lazy threshold are announced with their
+/// id first, and only sent to peers that want them.
+pub struct Gossip {
+    p2p: Weak<P2p>,
+    topics: Mutex<HashMap<String, Topic>>,
+    seen: Mutex<SeenCache>,
+    /// Recent messages, kept to answer want messages
+    store: Mutex<(HashMap<GossipId, GossipMessage>, VecDeque<GossipId>)>,
+    /// Messages we asked for, so we don't ask every peer at once
+    wanted: Mutex<HashMap<GossipId, Want>>,
+    /// Task asking other peers for the wanted messages that time out
+    retry_task: StoppableTaskPtr,
+    /// Clock the want timeouts run on
+    clock: Clock,
+}
+
+impl Gossip {
+    pub fn new(p2p: Weak<P2p>, clock: Clock) -> Arc<Self> {
+        Arc::new(Self {
+            p2p,
+            clock,
+            topics: Mutex::new(HashMap::new()),
+            seen: Mutex::new(SeenCache::new(GOSSIP_SEEN_CACHE_SIZE)),
+            store: Mutex::new((HashMap::new(), VecDeque::new())),
+            wanted: Mutex::new(HashMap::new()),
+            retry_task: StoppableTask::new(),
+        })
+    }
+
+    /// Start asking other peers for the wanted messages that time out.
+    pub(crate) fn start(self: Arc<Self>, executor: Arc<Executor<'_>>) {
+        self.retry_task.clone().start(
+            self.clone().retry_wants_loop(),
+            // Ignore stop handler
+            |_| async {},
+            Error::NetworkServiceStopped,
+            executor,
+        );
+    }
+
+    pub(crate) async fn stop(&self) {
+        self.retry_task.stop().await;
+    }
+
+    pub async fn get_info(&self) -> serde_json::Value {
+        let topics: Vec<String> = self.topics.lock().await.keys().cloned().collect();
+        json!({ "topics": topics })
+    }
+
+    /// Register a topic, or update its configuration, and subscribe to its
+    /// messages. Messages on topics we have not registered are dropped and
+    /// not relayed.
+    pub async fn subscribe(
+        &self,
+        topic: &str,
+        config: GossipConfig,
+    ) -> Subscription<GossipPayload> {
+        let mut topics = self.topics.lock().await;
+        let subscriber = match topics.get_mut(topic) {
+            Some(t) => {
+                t.config = config;
+                t.subscriber.clone()
+            }
+            None => {
+                let subscriber = Subscriber::new();
+                topics.insert(topic.to_string(), Topic { config, subscriber: subscriber.clone() });
+                subscriber
+            }
+        };
+        subscriber.subscribe().await
+    }
+
+    /// Publish a payload on a registered topic. Our own subscribers are not
+    /// notified.
+    pub async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<GossipId> {
+        let config = match self.topics.lock().await.get(topic) {
+            Some(t) => t.config.clone(),
+            None => return Err(Error::GossipTopicNotFound(topic.to_string())),
+        };
+
+        let id = gossip_id(topic, &payload);
+        if !self.seen.lock().await.insert(id) {
+            debug!(target: "net::gossip", "Message {} already published", hex::encode(id));
+            return Ok(id)
+        }
+
+        let message = GossipMessage { topic: topic.to_string(), id, hops: config.ttl, payload };
+        self.store(message.clone()).await;
+        self.relay(message, &config, None).await;
+        Ok(id)
+    }
+
+    /// Serialize and publish a message on a registered topic.
+    pub async fn publish_msg<M: Encodable>(&self, topic: &str, message: &M) -> Result<GossipId> {
+        self.publish(topic, serialize(message)).await
+    }
+
+    /// Handle a message received from a peer.
+    pub(crate) async fn handle_message(&self, channel: &ChannelPtr, message: GossipMessage) {
+        let (config, subscriber) = match self.topics.lock().await.get(&message.topic) {
+            Some(t) => (t.config.clone(), t.subscriber.clone()),
+            None => return,
+        };
+
+        if gossip_id(&message.topic, &message.payload) != message.id {
+            channel.misbehaving(BAN_SCORE_INVALID_GOSSIP, "gossip id mismatch").await;
+            return
+        }
+
+        if !self.seen.lock().await.insert(message.id) {
+            return
+        }
+        self.wanted.lock().await.remove(&message.id);
+
+        subscriber.notify(GossipPayload { id: message.id, payload: message.payload.clone() }).await;
+
+        // Peers can't make a message travel further than we allow
+        let hops = message.hops.min(config.ttl);
+        if hops == 0 {
+            return
+        }
+
+        let message = GossipMessage { hops: hops - 1, ..message };
+        self.store(message.clone()).await;
+        self.relay(message, &config, Some(&channel.address())).await;
+    }
+
+    /// Handle a have message, asking the peer for messages we are missing.
+    pub(crate) async fn handle_have(&self, channel: &ChannelPtr, have: GossipHaveMessage) {
+        if have.ids.len() > MAX_GOSSIP_IDS_PER_MESSAGE {
+            channel.misbehaving(BAN_SCORE_INVALID_GOSSIP, "oversized gossip have").await;
+            return
+        }
+
+        if !self.topics.lock().await.contains_key(&have.topic) {
+            return
+        }
+
+        let ids = {
+            let seen = self.seen.lock().await;
+            let mut wanted = self.wanted.lock().await;
+            let announcer = Arc::downgrade(channel);
+
+            let mut ids = vec![];
+            for id in have.ids {
+                if seen.contains(&id) {
+                    continue
+                }
+
+                match wanted.get_mut(&id) {
+                    // Already asked for, remember the peer in case that times out
+                    Some(want) => {
+                        if want.announcers.len() < GOSSIP_MAX_ANNOUNCERS &&
+                            !want.peer.ptr_eq(&announcer) &&
+                            !want.announcers.iter().any(|x| x.ptr_eq(&announcer))
+                        {
+                            want.announcers.push_back(announcer.clone());
+                        }
+                    }
+                    None => {
+                        let want = Want {
+                            peer: announcer.clone(),
+                            asked: self.clock.now(),
+                            announcers: VecDeque::new(),
+                        };
+                        wanted.insert(id, want);
+                        ids.push(id);
+                    }
+                }
+            }
+            ids
+        };
+
+        if ids.is_empty() {
+            return
+        }
+
+        if let Err(e) = channel.send(GossipWantMessage { ids }).await {
+            error!(target: "net::gossip", "Failed sending gossip want to {}: {}", channel.address(), e);
+        }
+    }
+
+    async fn retry_wants_loop(self: Arc<Self>) -> Result<()> {
+        loop {
+            self.clock.sleep(Duration::from_secs(1)).await;
+            self.retry_wants().await;
+        }
+    }
+
+    /// Ask the next peer that announced them for the wanted messages that
+    /// didn't arrive in time, and forget the ones no peer is left to ask.
+    async fn retry_wants(&self) {
+        let now = self.clock.now();
+        let timeout = Duration::from_secs(GOSSIP_WANT_TIMEOUT_SECONDS);
+        let mut requests: Vec<(ChannelPtr, Vec<GossipId>)> = vec![];
+        self.wanted.lock().await.retain(|id, want| {
+            if now - want.asked < timeout {
+                return true
+            }
+
+            // Skip the announcers that disconnected meanwhile
+            let next = iter::from_fn(|| want.announcers.pop_front()).find_map(|x| x.upgrade());
+            let Some(channel) = next else { return false };
+
+            want.peer = Arc::downgrade(&channel);
+            want.asked = now;
+            match requests.iter_mut().find(|(x, _)| Arc::ptr_eq(x, &channel)) {
+                Some((_, ids)) => ids.push(*id),
+                None => requests.push((channel, vec![*id])),
+            }
+            true
+        });
+
+        for (channel, ids) in requests {
+            debug!(
+                target: "net::gossip",
+                "Asking {} for {} timed out messages", channel.address(), ids.len(),
+            );
+            for ids in ids.chunks(MAX_GOSSIP_IDS_PER_MESSAGE) {
+                if let Err(e) = channel.send(GossipWantMessage { ids: ids.to_vec() }).await {
+                    error!(
+                        target: "net::gossip",
+                        "Failed sending gossip want to {}: {}", channel.address(), e,
+                    );
+                    break
+                }
+            }
+        }
+    }
+
+    /// Handle a want message, sending the peer the messages we still have.
+    pub(crate) async fn handle_want(&self, channel: &ChannelPtr, want: GossipWantMessage) {
+        if want.ids.len() > MAX_GOSSIP_IDS_PER_MESSAGE {
+            channel.misbehaving(BAN_SCORE_INVALID_GOSSIP, "oversized gossip want").await;
+            return
+        }
+
+        let messages: Vec<GossipMessage> = {
+            let store = self.store.lock().await;
+            want.ids.iter().filter_map(|id| store.0.get(id).cloned()).collect()
+        };
+
+        for message in messages {
+            if let Err(e) = channel.send(message).await {
+                error!(target: "net::gossip", "Failed sending gossip to {}: {}", channel.address(), e);
+                return
+            }
+        }
+    }
+
+    async fn store(&self, message: GossipMessage) {
+        let mut store = self.store.lock().await;
+        let (messages, order) = &mut *store;
+        if messages.insert(message.id, message.clone()).is_none() {
+            order.push_back(message.id);
+        }
+        if order.len() > GOSSIP_STORE_SIZE {
+            let oldest = order.pop_front().unwrap();
+            messages.remove(&oldest);
+        }
+    }
+
+    /// Send a message, or announce it if it is big, to `fanout` random peers.
+    async fn relay(&self, message: GossipMessage, config: &GossipConfig, exclude: Option<&Url>) {
+        let p2p = match self.p2p.upgrade() {
+            Some(p2p) => p2p,
+            None => return,
+        };
+
+        let channels: Vec<ChannelPtr> = p2p
+            .channels()
+            .lock()
+            .await
+            .values()
+            .filter(|c| Some(&c.address()) != exclude)
+            .cloned()
+            .collect();
+
+        let peers: Vec<ChannelPtr> =
+            channels.choose_multiple(&mut rand::thread_rng(), config.fanout).cloned().collect();
+
+        debug!(
+            target: "net::gossip",
+            "Relaying {} on {} to {} peers",
+            hex::encode(message.id), message.topic, peers.len()
+        );
+
+        let lazy = message.payload.len() >= config.lazy_threshold;
+        for channel in peers {
+            let result = if lazy {
+                let have =
+                    GossipHaveMessage { topic: message.topic.clone(), ids: vec![message.id] };
+                channel.send(have).await
+            } else {
+                channel.send(message.clone()).await
+            };
+
+            if let Err(e) = result {
+                error!(target: "net::gossip", "Failed relaying gossip to {}: {}", channel.address(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seen_cache() {
+        let mut seen = SeenCache::new(2);
+        assert!(seen.insert([0u8; 32]));
+        assert!(!seen.insert([0u8; 32]));
+        assert!(seen.insert([1u8; 32]));
+        assert!(seen.insert([2u8; 32]));
+
+        // Oldest id got evicted
+        assert!(!seen.contains(&[0u8; 32]));
+        assert!(seen.contains(&[2u8; 32]));
+
+        assert_ne!(gossip_id("a", b"bc"), gossip_id("ab", b"c"));
+    }
+}
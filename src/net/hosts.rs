@@ -17,33 +17,61 @@
  */
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
+    fs,
     net::IpAddr,
+    path::{Path, PathBuf},
 };
 
 use async_std::sync::{Arc, Mutex};
+use darkfi_serial::{deserialize, serialize, SerialDecodable, SerialEncodable};
+use futures::AsyncWriteExt;
 use ipnet::{Ipv4Net, Ipv6Net};
 use iprange::IpRange;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
+use rand::seq::SliceRandom;
 use url::Url;
 
-use super::constants::{IP4_PRIV_RANGES, IP6_PRIV_RANGES, LOCALNET};
-use crate::util::encoding::base32;
+use super::constants::{
+    HOST_MAX_FAILURES, HOST_SCORE_FAILURE, HOST_SCORE_MAX, HOST_SCORE_MIN, HOST_SCORE_SUCCESS,
+    IP4_PRIV_RANGES, IP6_PRIV_RANGES, LOCALNET,
+};
+use crate::{
+    util::{encoding::base32, time::unix_timestamp},
+    Result,
+};
 
 /// Pointer to hosts class.
 pub type HostsPtr = Arc<Hosts>;
 
+/// Information we keep about every known host address.
+#[derive(Clone, Debug, Default, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct HostInfo {
+    /// Last time (UNIX timestamp) the host was advertised to us or we
+    /// successfully connected to it
+    pub last_seen: u64,
+    /// Last time (UNIX timestamp) we tried to connect to the host
+    pub last_attempt: u64,
+    /// Number of consecutive failed connection attempts
+    pub failures: u32,
+    /// Host score, higher scored hosts are preferred for outbound connections
+    pub score: i64,
+}
+
 /// Manages a store of network addresses.
 pub struct Hosts {
-    addrs: Mutex<HashSet<Url>>,
+    addrs: Mutex<HashMap<Url, HostInfo>>,
     localnet: bool,
     ipv4_range: IpRange<Ipv4Net>,
     ipv6_range: IpRange<Ipv6Net>,
+    /// Optional file the host list is persisted to
+    hosts_file: Option<PathBuf>,
 }
 
 impl Hosts {
-    /// Create a new host list.
-    pub fn new(localnet: bool) -> Arc<Self> {
+    /// Create a new host list. If `hosts_file` is provided, the previously
+    /// saved hosts are loaded from it.
+    pub fn new(localnet: bool, hosts_file: Option<PathBuf>) -> Arc<Self> {
         // Initialize ipv4_range and ipv6_range if needed
         let mut ipv4_range: IpRange<Ipv4Net> =
             IP4_PRIV_RANGES.iter().map(|s| s.parse().unwrap()).collect();
@@ -54,7 +82,12 @@ impl Hosts {
         ipv4_range.simplify();
         ipv6_range.simplify();
 
-        Arc::new(Self { addrs: Mutex::new(HashSet::new()), localnet, ipv4_range, ipv6_range })
+        let addrs = match &hosts_file {
+            Some(path) => load_hosts_file(path),
+            None => HashMap::new(),
+        };
+
+        Arc::new(Self { addrs: Mutex::new(addrs), localnet, ipv4_range, ipv6_range, hosts_file })
     }
 
     /// Add a new host to the host list, after filtering.
@@ -68,10 +101,7 @@ impl Hosts {
             debug!(target: "net::hosts::store()", "hosts::store() [Localnet mode, skipping filterring.]");
             input_addrs
         };
-        let mut addrs_map = self.addrs.lock().await;
-        for addr in addrs {
-            addrs_map.insert(addr);
-        }
+        self.insert(addrs).await;
         debug!(target: "net::hosts::store()", "hosts::store() [End]");
     }
 
@@ -87,27 +117,146 @@ impl Hosts {
             debug!(target: "net::hosts::store_ext()", "hosts::store_ext() [Localnet mode, skipping filterring.]");
             input_addrs
         };
+        self.insert(addrs).await;
+        debug!(target: "net::hosts::store_ext()", "hosts::store_ext() [End]");
+    }
+
+    /// Insert already filtered addresses into the host list, refreshing
+    /// the last seen time of the ones we already know.
+    async fn insert(&self, addrs: Vec<Url>) {
+        let now = unix_timestamp().unwrap_or(0);
         let mut addrs_map = self.addrs.lock().await;
         for addr in addrs {
-            addrs_map.insert(addr);
+            addrs_map.entry(addr).or_default().last_seen = now;
         }
-        debug!(target: "net::hosts::store_ext()", "hosts::store_ext() [End]");
     }
 
     /// Return the list of hosts.
     pub async fn load_all(&self) -> Vec<Url> {
-        self.addrs.lock().await.iter().cloned().collect()
+        self.addrs.lock().await.keys().cloned().collect()
+    }
+
+    /// Return the list of hosts, ordered by descending score.
+    /// Hosts with equal scores are returned in random order.
+    pub async fn load_scored(&self) -> Vec<Url> {
+        let mut hosts: Vec<(Url, i64)> =
+            self.addrs.lock().await.iter().map(|(addr, info)| (addr.clone(), info.score)).collect();
+
+        // Shuffle first so the stable sort keeps ties randomized
+        hosts.shuffle(&mut rand::thread_rng());
+        hosts.sort_by(|a, b| b.1.cmp(&a.1));
+
+        hosts.into_iter().map(|(addr, _)| addr).collect()
+    }
+
+    /// Return the stored information of a host, if it exists.
+    pub async fn get(&self, url: &Url) -> Option<HostInfo> {
+        self.addrs.lock().await.get(url).cloned()
+    }
+
+    /// Mark that we are attempting to connect to the given host.
+    pub async fn mark_attempt(&self, url: &Url) {
+        if let Some(info) = self.addrs.lock().await.get_mut(url) {
+            info.last_attempt = unix_timestamp().unwrap_or(0);
+        }
+    }
+
+    /// Mark a successful connection to the given host, resetting its
+    /// failures counter and increasing its score.
+    pub async fn mark_connected(&self, url: &Url) {
+        if let Some(info) = self.addrs.lock().await.get_mut(url) {
+            info.last_seen = unix_timestamp().unwrap_or(0);
+            info.failures = 0;
+            info.score = (info.score + HOST_SCORE_SUCCESS).min(HOST_SCORE_MAX);
+        }
+    }
+
+    /// Mark a failed connection to the given host, decreasing its score.
+    /// Hosts that reach [`HOST_MAX_FAILURES`] consecutive failures get removed.
+    /// Returns `true` if the host was removed.
+    pub async fn mark_failed(&self, url: &Url) -> bool {
+        let mut addrs_map = self.addrs.lock().await;
+        let info = match addrs_map.get_mut(url) {
+            Some(i) => i,
+            None => return false,
+        };
+
+        info.failures += 1;
+        info.score = (info.score - HOST_SCORE_FAILURE).max(HOST_SCORE_MIN);
+
+        if info.failures >= HOST_MAX_FAILURES {
+            debug!(target: "net::hosts::mark_failed()", "hosts::mark_failed() [Removing {} after {} failures]", url, info.failures);
+            addrs_map.remove(url);
+            return true
+        }
+
+        false
     }
 
     /// Remove an Url from the list
     pub async fn remove(&self, url: &Url) -> bool {
-        self.addrs.lock().await.remove(url)
+        self.addrs.lock().await.remove(url).is_some()
     }
 
     /// Check if the host list is empty.
     pub async fn is_empty(&self) -> bool {
         self.addrs.lock().await.is_empty()
     }
+
+    /// Persist the host list to the configured hosts file, if any.
+    pub async fn save(&self) -> Result<()> {
+        let path = match &self.hosts_file {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let hosts: Vec<(Url, HostInfo)> =
+            self.addrs.lock().await.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+
+        if let Some(parent) = path.parent() {
+            async_std::fs::create_dir_all(parent).await?;
+        }
+
+        info!(target: "net::hosts::save()", "Saving {} hosts to {:?}", hosts.len(), path);
+
+        // Write a temporary file and move it over the hosts file, so a crash
+        // while writing doesn't leave a truncated hosts file behind.
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut file = async_std::fs::File::create(&tmp_path).await?;
+        file.write_all(&serialize(&hosts)).await?;
+        file.sync_all().await?;
+        async_std::fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+}
+
+/// Auxiliary function to load a previously saved host list.
+/// Errors are logged and result in an empty host list.
+fn load_hosts_file(path: &Path) -> HashMap<Url, HostInfo> {
+    if !path.exists() {
+        info!(target: "net::hosts::load_hosts_file()", "No hosts file found at {:?}", path);
+        return HashMap::new()
+    }
+
+    let bytes = match fs::read(path) {
+        Ok(v) => v,
+        Err(e) => {
+            error!(target: "net::hosts::load_hosts_file()", "Failed reading hosts file {:?}: {}", path, e);
+            return HashMap::new()
+        }
+    };
+
+    match deserialize::<Vec<(Url, HostInfo)>>(&bytes) {
+        Ok(hosts) => {
+            info!(target: "net::hosts::load_hosts_file()", "Loaded {} hosts from {:?}", hosts.len(), path);
+            hosts.into_iter().collect()
+        }
+        Err(e) => {
+            error!(target: "net::hosts::load_hosts_file()", "Failed decoding hosts file {:?}: {}", path, e);
+            HashMap::new()
+        }
+    }
 }
 
 /// Auxiliary function to filter localnet hosts.
@@ -308,8 +457,8 @@ mod tests {
     use url::Url;
 
     use crate::net::{
-        constants::{IP4_PRIV_RANGES, IP6_PRIV_RANGES},
-        hosts::{filter_invalid, filter_localnet, filter_non_resolving, is_valid_onion},
+        constants::{HOST_MAX_FAILURES, IP4_PRIV_RANGES, IP6_PRIV_RANGES},
+        hosts::{filter_invalid, filter_localnet, filter_non_resolving, is_valid_onion, Hosts},
     };
 
     #[test]
@@ -482,4 +631,39 @@ mod tests {
         // Invalid onion
         assert!(!is_valid_onion("facebook.com"));
     }
+
+    #[async_std::test]
+    async fn test_hosts_scoring_and_persistence() {
+        // Unique directory, so concurrent test runs don't share the file
+        let dir = std::env::temp_dir().join(format!("darkfi_test_hosts_{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let hosts_file = dir.join("hosts");
+
+        let good = Url::parse("tcp://127.0.0.1:13333").unwrap();
+        let bad = Url::parse("tcp://127.0.0.1:13334").unwrap();
+
+        let hosts = Hosts::new(true, Some(hosts_file.clone()));
+        hosts.store(vec![good.clone(), bad.clone()]).await;
+
+        // Successful connections rank a host first
+        hosts.mark_connected(&good).await;
+        hosts.mark_failed(&bad).await;
+        assert_eq!(hosts.load_scored().await, vec![good.clone(), bad.clone()]);
+
+        // Persisted hosts keep their scores on reload
+        hosts.save().await.unwrap();
+        assert!(!dir.join("hosts.tmp").exists());
+        let reloaded = Hosts::new(true, Some(hosts_file.clone()));
+        assert_eq!(reloaded.get(&good).await, hosts.get(&good).await);
+        assert_eq!(reloaded.get(&bad).await.unwrap().failures, 1);
+
+        // Hosts that keep failing get dropped
+        for _ in 1..HOST_MAX_FAILURES - 1 {
+            assert!(!reloaded.mark_failed(&bad).await);
+        }
+        assert!(reloaded.mark_failed(&bad).await);
+        assert_eq!(reloaded.load_all().await, vec![good]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
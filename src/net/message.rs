@@ -16,18 +16,69 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use darkfi_serial::{Decodable, Encodable, SerialDecodable, SerialEncodable, VarInt};
+use std::io::Cursor;
+
+use darkfi_serial::{
+    decode_bounded, Decodable, DecodeLimits, Encodable, SerialDecodable, SerialEncodable, VarInt,
+};
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use log::debug;
 use url::Url;
 
+use super::rate_limit::RateLimit;
 use crate::{Error, Result};
 
 const MAGIC_BYTES: [u8; 4] = [0xd9, 0xef, 0xb6, 0x7d];
 
+/// Maximum length of a packet command.
+const MAX_COMMAND_LEN: usize = 64;
+
+/// Maximum payload size of any packet, checked before we know the message
+/// type. Must not be smaller than any `Message::max_payload_size()`.
+pub const MAX_PACKET_SIZE: usize = 64 * 1024 * 1024;
+
+/// Default maximum payload size of a message.
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 32 * 1024 * 1024;
+
+/// Default receive rate limit of a message.
+pub const DEFAULT_RATE_LIMIT: RateLimit = RateLimit::new(1000, 200.0);
+
+/// Payload buffers are grown as data arrives past this size, so a peer
+/// declaring a huge length can't make us allocate it upfront.
+const PAYLOAD_PREALLOC_SIZE: usize = 1024 * 1024;
+
 /// Generic message template.
 pub trait Message: 'static + Encodable + Decodable + Send + Sync {
     fn name() -> &'static str;
+
+    /// Maximum accepted payload size for this message, in bytes.
+    fn max_payload_size() -> usize {
+        DEFAULT_MAX_PAYLOAD_SIZE
+    }
+
+    /// Rate limit for receiving this message on a single channel.
+    /// `None` disables rate limiting.
+    fn rate_limit() -> Option<RateLimit> {
+        Some(DEFAULT_RATE_LIMIT)
+    }
+}
+
+/// Decode a message payload received from the network, enforcing the
+/// message's maximum payload size along with collection decode limits.
+/// Every collection element takes at least a byte, so no collection can
+/// have more elements than the payload has bytes.
+pub fn decode_payload<M: Message>(payload: &[u8]) -> Result<M> {
+    let max_size = M::max_payload_size();
+    if payload.len() > max_size {
+        return Err(Error::MessageTooLarge(M::name().to_string(), payload.len()))
+    }
+
+    let limits = DecodeLimits {
+        max_bytes: max_size,
+        max_collection_len: payload.len() as u64,
+        ..Default::default()
+    };
+    Ok(decode_bounded(Cursor::new(payload), &limits)?)
 }
 
 /// Outbound keep-alive message.
@@ -59,10 +110,40 @@ pub struct ExtAddrsMessage {
     pub ext_addrs: Vec<Url>,
 }
 
+/// Feature bit advertised by nodes able to receive compressed packets.
+pub const FEATURE_COMPRESSION: u64 = 1 << 0;
+
+/// Features supported by this node, advertised in the version message.
+pub const LOCAL_FEATURES: u64 = FEATURE_COMPRESSION;
+
 /// Requests version information of outbound connection.
-#[derive(SerialEncodable, SerialDecodable)]
 pub struct VersionMessage {
     pub node_id: String,
+    /// Bitmask of supported `FEATURE_*` flags
+    pub features: u64,
+}
+
+impl Encodable for VersionMessage {
+    fn encode<S: std::io::Write>(&self, mut s: S) -> std::result::Result<usize, std::io::Error> {
+        let mut len = 0;
+        len += self.node_id.encode(&mut s)?;
+        len += self.features.encode(&mut s)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for VersionMessage {
+    /// Older nodes only send `node_id` and ignore trailing data, so a
+    /// missing `features` field means the peer supports none.
+    fn decode<D: std::io::Read>(mut d: D) -> std::result::Result<Self, std::io::Error> {
+        let node_id = String::decode(&mut d)?;
+        let features = match u64::decode(&mut d) {
+            Ok(features) => features,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(Self { node_id, features })
+    }
 }
 
 /// Sends version information to inbound connection. Response to VersionMessage.
@@ -76,42 +157,98 @@ impl Message for PingMessage {
     fn name() -> &'static str {
         "ping"
     }
+
+    fn max_payload_size() -> usize {
+        1024
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(5, 1.0))
+    }
 }
 
 impl Message for PongMessage {
     fn name() -> &'static str {
         "pong"
     }
+
+    fn max_payload_size() -> usize {
+        1024
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(5, 1.0))
+    }
 }
 
 impl Message for GetAddrsMessage {
     fn name() -> &'static str {
         "getaddr"
     }
+
+    fn max_payload_size() -> usize {
+        1024
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(5, 0.1))
+    }
 }
 
 impl Message for AddrsMessage {
     fn name() -> &'static str {
         "addr"
     }
+
+    fn max_payload_size() -> usize {
+        1024 * 1024
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(10, 1.0))
+    }
 }
 
 impl Message for ExtAddrsMessage {
     fn name() -> &'static str {
         "extaddr"
     }
+
+    fn max_payload_size() -> usize {
+        1024 * 1024
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(10, 1.0))
+    }
 }
 
 impl Message for VersionMessage {
     fn name() -> &'static str {
         "version"
     }
+
+    fn max_payload_size() -> usize {
+        4096
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(2, 0.1))
+    }
 }
 
 impl Message for VerackMessage {
     fn name() -> &'static str {
         "verack"
     }
+
+    fn max_payload_size() -> usize {
+        4096
+    }
+
+    fn rate_limit() -> Option<RateLimit> {
+        Some(RateLimit::new(2, 0.1))
+    }
 }
 
 /// Packets are the base type read from the network. Converted to messages and
@@ -137,6 +274,9 @@ pub async fn read_packet<R: AsyncRead + Unpin + Sized>(stream: &mut R) -> Result
 
     // The type of the message
     let command_len = VarInt::decode_async(stream).await?.0 as usize;
+    if command_len > MAX_COMMAND_LEN {
+        return Err(Error::MalformedPacket)
+    }
     let mut cmd = vec![0u8; command_len];
     if command_len > 0 {
         stream.read_exact(&mut cmd).await?;
@@ -145,11 +285,17 @@ pub async fn read_packet<R: AsyncRead + Unpin + Sized>(stream: &mut R) -> Result
     debug!(target: "net::message", "read command: {}", cmd);
 
     let payload_len = VarInt::decode_async(stream).await?.0 as usize;
+    if payload_len > MAX_PACKET_SIZE {
+        return Err(Error::MessageTooLarge(cmd, payload_len))
+    }
 
     // The message-dependent data (see message types)
-    let mut payload = vec![0u8; payload_len];
+    let mut payload = Vec::with_capacity(payload_len.min(PAYLOAD_PREALLOC_SIZE));
     if payload_len > 0 {
-        stream.read_exact(&mut payload).await?;
+        (&mut *stream).take(payload_len as u64).read_to_end(&mut payload).await?;
+        if payload.len() != payload_len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+        }
     }
     debug!(target: "net::message", "read payload {} bytes", payload_len);
 
@@ -180,3 +326,56 @@ pub async fn send_packet<W: AsyncWrite + Unpin + Sized>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkfi_serial::{deserialize, serialize};
+
+    #[derive(SerialEncodable, SerialDecodable)]
+    struct BlobMessage {
+        data: Vec<u8>,
+    }
+
+    impl Message for BlobMessage {
+        fn name() -> &'static str {
+            "blob"
+        }
+    }
+
+    #[test]
+    fn decode_payload_limits() {
+        // Collections are only bounded by the payload size
+        let blob = BlobMessage { data: vec![7; 2 * 1024 * 1024] };
+        let decoded: BlobMessage = decode_payload(&serialize(&blob)).unwrap();
+        assert_eq!(decoded.data, blob.data);
+
+        // But can't claim more elements than the payload has bytes
+        let mut payload = serialize(&VarInt(1024));
+        payload.extend_from_slice(&[7; 512]);
+        assert!(decode_payload::<BlobMessage>(&payload).is_err());
+
+        let payload = vec![0; DEFAULT_MAX_PAYLOAD_SIZE + 1];
+        assert!(matches!(
+            decode_payload::<BlobMessage>(&payload),
+            Err(Error::MessageTooLarge(..))
+        ));
+    }
+
+    #[test]
+    fn version_message_compat() {
+        // Version message as sent by nodes predating feature flags
+        let old = serialize(&String::from("node"));
+        let version: VersionMessage = deserialize(&old).unwrap();
+        assert_eq!(version.node_id, "node");
+        assert_eq!(version.features, 0);
+
+        let new = VersionMessage { node_id: String::from("node"), features: LOCAL_FEATURES };
+        let version: VersionMessage = deserialize(&serialize(&new)).unwrap();
+        assert_eq!(version.features, LOCAL_FEATURES);
+
+        // Older nodes decode the new message, ignoring the trailing features
+        let node_id: String = Decodable::decode(Cursor::new(serialize(&new))).unwrap();
+        assert_eq!(node_id, "node");
+    }
+}
@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{any::Any, collections::HashMap, io::Cursor, sync::Arc};
+use std::{any::Any, collections::HashMap, sync::Arc};
 
 use async_std::sync::Mutex;
 use async_trait::async_trait;
@@ -25,7 +25,10 @@ use rand::Rng;
 
 use crate::{Error, Result};
 
-use super::message::Message;
+use super::{
+    message::{self, Message},
+    rate_limit::TokenBucket,
+};
 
 /// 64bit identifier for message subscription.
 pub type MessageSubscriptionId = u64;
@@ -59,7 +62,7 @@ impl<M: Message> MessageSubscription<M> {
 #[async_trait]
 /// Generic interface for message dispatcher.
 trait MessageDispatcherInterface: Send + Sync {
-    async fn trigger(&self, payload: Vec<u8>);
+    async fn trigger(&self, payload: Vec<u8>) -> Result<()>;
 
     async fn trigger_error(&self, err: Error);
 
@@ -69,12 +72,16 @@ trait MessageDispatcherInterface: Send + Sync {
 /// A dispatchers that is unique to every Message. Maintains a list of subscribers that are subscribed to that unique Message type and handles sending messages across these subscriptions.
 struct MessageDispatcher<M: Message> {
     subs: Mutex<HashMap<MessageSubscriptionId, smol::channel::Sender<MessageResult<M>>>>,
+    rate_limiter: Mutex<Option<TokenBucket>>,
 }
 
 impl<M: Message> MessageDispatcher<M> {
     /// Create a new message dispatcher.
     fn new() -> Self {
-        MessageDispatcher { subs: Mutex::new(HashMap::new()) }
+        MessageDispatcher {
+            subs: Mutex::new(HashMap::new()),
+            rate_limiter: Mutex::new(M::rate_limit().map(TokenBucket::new)),
+        }
     }
 
     /// Create a random ID.
@@ -147,14 +154,21 @@ impl<M: Message> MessageDispatcher<M> {
 // Local implementation of the Message Dispatcher Interface.
 impl<M: Message> MessageDispatcherInterface for MessageDispatcher<M> {
     /// Internal function to deserialize data into a message type and dispatch it across subscriber channels.
-    async fn trigger(&self, payload: Vec<u8>) {
+    async fn trigger(&self, payload: Vec<u8>) -> Result<()> {
+        // drop the message if the peer is sending too fast
+        if let Some(bucket) = &mut *self.rate_limiter.lock().await {
+            if !bucket.try_take() {
+                return Err(Error::RateLimited(M::name().to_string()))
+            }
+        }
+
         // deserialize data into type
         // send down the pipes
-        let cursor = Cursor::new(payload);
-        match M::decode(cursor) {
+        match message::decode_payload::<M>(&payload) {
             Ok(message) => {
                 let message = Ok(Arc::new(message));
-                self._trigger_all(message).await
+                self._trigger_all(message).await;
+                Ok(())
             }
             Err(err) => {
                 debug!(
@@ -162,6 +176,7 @@ impl<M: Message> MessageDispatcherInterface for MessageDispatcher<M> {
                     "Unable to decode data. Dropping...: {}",
                     err
                 );
+                Err(err)
             }
         }
     }
@@ -218,21 +233,26 @@ impl MessageSubsystem {
         Ok(sub)
     }
 
+    /// Return the name of the dispatched Message matching the given command,
+    /// if there's a dispatcher for it.
+    pub async fn dispatched_name(&self, command: &str) -> Option<&'static str> {
+        self.dispatchers.lock().await.get_key_value(command).map(|(name, _)| *name)
+    }
+
     /// Transmits a payload to a dispatcher. Returns an error if the payload
-    /// fails to transmit.
-    pub async fn notify(&self, command: &str, payload: Vec<u8>) {
+    /// fails to decode or exceeds the message's rate limit.
+    pub async fn notify(&self, command: &str, payload: Vec<u8>) -> Result<()> {
         let dispatcher = self.dispatchers.lock().await.get(command).cloned();
 
         match dispatcher {
-            Some(dispatcher) => {
-                dispatcher.trigger(payload).await;
-            }
+            Some(dispatcher) => dispatcher.trigger(payload).await,
             None => {
                 warn!(
                     target: "net::message_subscriber::notify()",
                     "Command '{}' did not find a dispatcher",
                     command
                 );
+                Ok(())
             }
         }
     }
@@ -260,7 +280,8 @@ impl Default for MessageSubsystem {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use darkfi_serial::{Decodable, Encodable};
+    use crate::net::RateLimit;
+    use darkfi_serial::{serialize, Decodable, Encodable, SerialDecodable, SerialEncodable};
     use std::io;
 
     #[async_std::test]
@@ -306,7 +327,10 @@ mod tests {
         // receive message and publish
         //   1. based on string, lookup relevant dispatcher interface
         //   2. publish data there
-        subsystem.notify("verver", payload).await;
+        subsystem.notify("verver", payload).await.unwrap();
+
+        // undecodable payloads are reported back
+        assert!(subsystem.notify("verver", vec![1]).await.is_err());
 
         // receive
         //    1. do a get easy
@@ -321,4 +345,34 @@ mod tests {
 
         sub.unsubscribe().await;
     }
+
+    #[async_std::test]
+    async fn message_rate_limit_test() {
+        #[derive(SerialEncodable, SerialDecodable)]
+        struct LimitedMessage {
+            x: u32,
+        }
+
+        impl Message for LimitedMessage {
+            fn name() -> &'static str {
+                "limited"
+            }
+
+            fn rate_limit() -> Option<RateLimit> {
+                Some(RateLimit::new(2, 0.0))
+            }
+        }
+
+        let subsystem = MessageSubsystem::new();
+        subsystem.add_dispatch::<LimitedMessage>().await;
+        let sub = subsystem.subscribe::<LimitedMessage>().await.unwrap();
+
+        let payload = serialize(&LimitedMessage { x: 42 });
+        subsystem.notify("limited", payload.clone()).await.unwrap();
+        subsystem.notify("limited", payload.clone()).await.unwrap();
+        assert!(matches!(subsystem.notify("limited", payload).await, Err(Error::RateLimited(_))));
+
+        assert_eq!(sub.receive().await.unwrap().x, 42);
+        assert_eq!(sub.receive().await.unwrap().x, 42);
+    }
 }
@@ -0,0 +1,412 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Traffic metrics of a P2P network, exported in the Prometheus text
+//! exposition format over a minimal HTTP `/metrics` listener.
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+use async_std::sync::{Arc, Mutex};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use log::{debug, error, info};
+use url::Url;
+
+use super::transport::{TcpTransport, Transport, TransportListener, TransportStream};
+use crate::{Error, Result};
+
+/// Atomic pointer to network metrics.
+pub type MetricsPtr = Arc<Metrics>;
+
+/// Bucket bounds of the connect latency histogram, in seconds.
+const CONNECT_LATENCY_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Bucket bounds of the channel lifetime histogram, in seconds.
+const CHANNEL_LIFETIME_BUCKETS: &[f64] = &[1.0, 10.0, 60.0, 300.0, 900.0, 3600.0, 21600.0, 86400.0];
+
+/// Maximum size of an HTTP request head we accept on the metrics listener.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Time a client has to send its HTTP request head to the metrics listener.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Name received messages without a dispatcher are recorded under.
+const UNKNOWN_COMMAND: &str = "unknown";
+
+/// Histogram with fixed bucket bounds.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if let Some(i) = self.bounds.iter().position(|b| value <= *b) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// Message and byte counters of a single message name.
+#[derive(Clone, Copy, Debug, Default)]
+struct Traffic {
+    messages: u64,
+    bytes: u64,
+}
+
+#[derive(Clone)]
+struct MetricsInner {
+    // Keyed by message name
+    sent: BTreeMap<&'static str, Traffic>,
+    received: BTreeMap<&'static str, Traffic>,
+    // Keyed by session name
+    handshake_failures: BTreeMap<String, u64>,
+    connect_failures: u64,
+    channels_opened: u64,
+    connect_latency: Histogram,
+    channel_lifetime: Histogram,
+}
+
+/// Counters and histograms collected by channels and sessions of a
+/// single P2P network.
+pub struct Metrics {
+    inner: Mutex<MetricsInner>,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(MetricsInner {
+                sent: BTreeMap::new(),
+                received: BTreeMap::new(),
+                handshake_failures: BTreeMap::new(),
+                connect_failures: 0,
+                channels_opened: 0,
+                connect_latency: Histogram::new(CONNECT_LATENCY_BUCKETS),
+                channel_lifetime: Histogram::new(CHANNEL_LIFETIME_BUCKETS),
+            }),
+        })
+    }
+
+    /// Record a sent message of `bytes` payload bytes on the wire.
+    pub async fn message_sent(&self, command: &'static str, bytes: usize) {
+        let inner = &mut *self.inner.lock().await;
+        let traffic = inner.sent.entry(command).or_default();
+        traffic.messages += 1;
+        traffic.bytes += bytes as u64;
+    }
+
+    /// Record a received message of `bytes` payload bytes on the wire.
+    /// Messages of unknown commands, given as `None`, share a single entry,
+    /// since the command names are chosen by the remote node.
+    pub async fn message_received(&self, command: Option<&'static str>, bytes: usize) {
+        let inner = &mut *self.inner.lock().await;
+        let traffic = inner.received.entry(command.unwrap_or(UNKNOWN_COMMAND)).or_default();
+        traffic.messages += 1;
+        traffic.bytes += bytes as u64;
+    }
+
+    /// Record a failed handshake in the given session.
+    pub async fn handshake_failed(&self, session: &str) {
+        *self.inner.lock().await.handshake_failures.entry(session.to_string()).or_insert(0) += 1;
+    }
+
+    /// Record the time it took to establish an outbound connection.
+    pub async fn connected(&self, latency: Duration) {
+        self.inner.lock().await.connect_latency.observe(latency.as_secs_f64());
+    }
+
+    /// Record a failed outbound connection attempt.
+    pub async fn connect_failed(&self) {
+        self.inner.lock().await.connect_failures += 1;
+    }
+
+    /// Record a newly created channel.
+    pub async fn channel_opened(&self) {
+        self.inner.lock().await.channels_opened += 1;
+    }
+
+    /// Record a stopped channel along with how long it lived.
+    pub async fn channel_closed(&self, lifetime: Duration) {
+        self.inner.lock().await.channel_lifetime.observe(lifetime.as_secs_f64());
+    }
+
+    pub async fn get_info(&self) -> serde_json::Value {
+        let inner = self.inner.lock().await;
+        let traffic = |map: &BTreeMap<&'static str, Traffic>| -> serde_json::Value {
+            map.iter()
+                .map(|(k, v)| (k.to_string(), serde_json::json!([v.messages, v.bytes])))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        };
+
+        serde_json::json!({
+            "sent": traffic(&inner.sent),
+            "received": traffic(&inner.received),
+            "handshake_failures": inner.handshake_failures,
+            "connect_failures": inner.connect_failures,
+            "channels_opened": inner.channels_opened,
+            "channels_closed": inner.channel_lifetime.count(),
+        })
+    }
+}
+
+/// Write a metric family header.
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_histogram(out: &mut String, name: &str, network: &str, histogram: &Histogram) {
+    let mut cumulative = 0;
+    for (bound, count) in histogram.bounds.iter().zip(histogram.counts.iter()) {
+        cumulative += count;
+        let _ = writeln!(
+            out,
+            "{}_bucket{{network=\"{}\",le=\"{}\"}} {}",
+            name, network, bound, cumulative
+        );
+    }
+    let _ =
+        writeln!(out, "{}_bucket{{network=\"{}\",le=\"+Inf\"}} {}", name, network, histogram.count);
+    let _ = writeln!(out, "{}_sum{{network=\"{}\"}} {}", name, network, histogram.sum);
+    let _ = writeln!(out, "{}_count{{network=\"{}\"}} {}", name, network, histogram.count);
+}
+
+/// Write the message and byte counters of one traffic direction.
+fn write_traffic(
+    out: &mut String,
+    snapshots: &[(&str, MetricsInner)],
+    direction: &str,
+    select: impl Fn(&MetricsInner) -> &BTreeMap<&'static str, Traffic>,
+) {
+    let name = format!("darkfi_net_messages_{}_total", direction);
+    let help = format!("Messages {}, by message name", direction);
+    write_header(out, &name, "counter", &help);
+    for (network, inner) in snapshots {
+        for (command, traffic) in select(inner) {
+            let _ = writeln!(
+                out,
+                "{}{{network=\"{}\",command=\"{}\"}} {}",
+                name, network, command, traffic.messages
+            );
+        }
+    }
+
+    let name = format!("darkfi_net_bytes_{}_total", direction);
+    let help = format!("Payload bytes {}, by message name", direction);
+    write_header(out, &name, "counter", &help);
+    for (network, inner) in snapshots {
+        for (command, traffic) in select(inner) {
+            let _ = writeln!(
+                out,
+                "{}{{network=\"{}\",command=\"{}\"}} {}",
+                name, network, command, traffic.bytes
+            );
+        }
+    }
+}
+
+/// Render the metrics of the given networks in the Prometheus text format.
+/// Every sample is labeled with the network name it belongs to.
+pub async fn encode(networks: &[(String, MetricsPtr)]) -> String {
+    let mut snapshots = vec![];
+    for (network, metrics) in networks {
+        snapshots.push((network.as_str(), metrics.inner.lock().await.clone()));
+    }
+
+    let mut out = String::new();
+
+    write_traffic(&mut out, &snapshots, "sent", |m| &m.sent);
+    write_traffic(&mut out, &snapshots, "received", |m| &m.received);
+
+    let name = "darkfi_net_handshake_failures_total";
+    write_header(&mut out, name, "counter", "Failed channel handshakes, by session");
+    for (network, inner) in &snapshots {
+        for (session, count) in &inner.handshake_failures {
+            let _ = writeln!(
+                out,
+                "{}{{network=\"{}\",session=\"{}\"}} {}",
+                name, network, session, count
+            );
+        }
+    }
+
+    let name = "darkfi_net_connect_failures_total";
+    write_header(&mut out, name, "counter", "Failed outbound connection attempts");
+    for (network, inner) in &snapshots {
+        let _ = writeln!(out, "{}{{network=\"{}\"}} {}", name, network, inner.connect_failures);
+    }
+
+    let name = "darkfi_net_channels_opened_total";
+    write_header(&mut out, name, "counter", "Channels created");
+    for (network, inner) in &snapshots {
+        let _ = writeln!(out, "{}{{network=\"{}\"}} {}", name, network, inner.channels_opened);
+    }
+
+    let name = "darkfi_net_connect_latency_seconds";
+    write_header(&mut out, name, "histogram", "Time to establish outbound connections");
+    for (network, inner) in &snapshots {
+        write_histogram(&mut out, name, network, &inner.connect_latency);
+    }
+
+    let name = "darkfi_net_channel_lifetime_seconds";
+    write_header(&mut out, name, "histogram", "Lifetime of stopped channels");
+    for (network, inner) in &snapshots {
+        write_histogram(&mut out, name, network, &inner.channel_lifetime);
+    }
+
+    out
+}
+
+/// Serve a single HTTP request on the metrics listener.
+async fn handle_request(
+    mut stream: Box<dyn TransportStream>,
+    networks: &[(String, MetricsPtr)],
+) -> Result<()> {
+    let mut buf = vec![];
+    let read_head = async {
+        let mut chunk = [0u8; 1024];
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(false)
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            if buf.len() > MAX_REQUEST_SIZE {
+                break
+            }
+        }
+        Ok::<bool, Error>(true)
+    };
+
+    // Slow clients would otherwise hold the connection open forever
+    if !async_std::future::timeout(REQUEST_TIMEOUT, read_head).await?? {
+        return Ok(())
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", encode(networks).await),
+        _ => ("404 Not Found", String::from("Not Found\n")),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Start an HTTP listener bound to the given TCP accept URL, serving the
+/// metrics of the given networks on `/metrics`.
+pub async fn listen_and_serve(
+    accept_url: Url,
+    networks: Vec<(String, MetricsPtr)>,
+    ex: Arc<smol::Executor<'_>>,
+) -> Result<()> {
+    debug!(target: "net::metrics", "Trying to bind metrics listener on {}", accept_url);
+
+    let listener = match TcpTransport::new(None, 1024).listen_on(accept_url.clone()) {
+        Ok(listener) => listener.await,
+        Err(e) => Err(e),
+    };
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(target: "net::metrics", "Metrics listener bind to {} failed: {}", accept_url, e);
+            return Err(Error::BindFailed(accept_url.as_str().into()))
+        }
+    };
+    info!(target: "net::metrics", "Metrics listener bound to {}", accept_url);
+
+    let networks = Arc::new(networks);
+    while let Ok((stream, peer_addr)) = listener.next().await {
+        let networks = networks.clone();
+        ex.spawn(async move {
+            if let Err(e) = handle_request(stream, &networks).await {
+                debug!(target: "net::metrics", "Failed serving metrics to {}: {}", peer_addr, e);
+            }
+        })
+        .detach();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn metrics_encode() {
+        let metrics = Metrics::new();
+        metrics.message_sent("ping", 4).await;
+        metrics.message_sent("ping", 4).await;
+        metrics.message_received(Some("pong"), 4).await;
+        metrics.message_received(None, 3).await;
+        metrics.message_received(None, 5).await;
+        metrics.handshake_failed("outbound").await;
+        metrics.connected(Duration::from_millis(30)).await;
+        metrics.connected(Duration::from_secs(60)).await;
+
+        let out = encode(&[("sync".to_string(), metrics)]).await;
+        assert!(
+            out.contains("darkfi_net_messages_sent_total{network=\"sync\",command=\"ping\"} 2\n")
+        );
+        assert!(out.contains("darkfi_net_bytes_sent_total{network=\"sync\",command=\"ping\"} 8\n"));
+        assert!(out
+            .contains("darkfi_net_messages_received_total{network=\"sync\",command=\"pong\"} 1\n"));
+        assert!(out
+            .contains("darkfi_net_bytes_received_total{network=\"sync\",command=\"unknown\"} 8\n"));
+        assert!(out.contains(
+            "darkfi_net_handshake_failures_total{network=\"sync\",session=\"outbound\"} 1\n"
+        ));
+        assert!(out.contains(
+            "darkfi_net_connect_latency_seconds_bucket{network=\"sync\",le=\"0.05\"} 1\n"
+        ));
+        assert!(out
+            .contains("darkfi_net_connect_latency_seconds_bucket{network=\"sync\",le=\"30\"} 1\n"));
+        assert!(out.contains(
+            "darkfi_net_connect_latency_seconds_bucket{network=\"sync\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(out.contains("darkfi_net_connect_latency_seconds_count{network=\"sync\"} 2\n"));
+    }
+}
@@ -21,6 +21,11 @@
 /// connections and to handle network errors.
 pub mod acceptor;
 
+/// Tracks misbehaviour points reported by protocols against channels, and
+/// bans hosts that cross the threshold. Bans are persisted to disk with
+/// an expiry time and checked before accepting or dialing a peer.
+pub mod ban_manager;
+
 /// Async channel that handles the sending of messages across the network.
 /// Public interface is used to create new channels, to stop and start
 /// a channel, and to send messages.
@@ -28,10 +33,23 @@ pub mod acceptor;
 /// Implements message functionality and the message subscriber subsystem.
 pub mod channel;
 
+/// Source of time for the timers of a node, following the virtual clock
+/// of the in-memory network in simulations.
+pub mod clock;
+
+/// Deflate compression of large packets, used on channels where both
+/// sides advertised support for it in the version exchange.
+pub mod compression;
+
 /// Handles the creation of outbound connections. Used to establish an outbound
 /// connection.
 pub mod connector;
 
+/// Gossip layer relaying messages on registered topics to a random subset
+/// of peers, with message deduplication, hop limits and lazy push of big
+/// payloads.
+pub mod gossip;
+
 /// Hosts are a list of network addresses used when establishing an outbound
 /// connection. Hosts are shared across the network through the address
 /// protocol. When attempting to connect, a node will loop through addresses in
@@ -66,6 +84,15 @@ pub mod message_subscriber;
 /// converted into messages and passed to an event loop.
 pub mod message;
 
+/// Traffic counters and histograms of a network, collected by channels and
+/// sessions. Can be served over an optional HTTP `/metrics` listener.
+pub mod metrics;
+
+/// Optional Noise XX handshake run on a fresh connection before the version
+/// exchange. Gives each channel a verified static public key of the remote
+/// node and encrypts all packets sent over the channel afterwards.
+pub mod noise;
+
 /// P2P provides all core functionality to interact with the peer-to-peer
 /// network.
 ///
@@ -76,6 +103,10 @@ pub mod message;
 /// remove channels or check whether a channel is already is in the store.
 pub mod p2p;
 
+/// Token bucket rate limiting of received messages. Each channel keeps a
+/// bucket per message type, configured by `Message::rate_limit()`.
+pub mod rate_limit;
+
 /// Defines the networking protocol used at each stage in a connection. Consists
 /// of a series of messages that are sent across the network at the different
 /// connection stages.
@@ -103,6 +134,10 @@ pub mod session;
 /// Network configuration settings.
 pub mod settings;
 
+/// Harness running several nodes in one process over an in-memory
+/// transport, with injectable latency, loss and partitions.
+pub mod simulation;
+
 /// Network transport implementations.
 pub mod transport;
 
@@ -110,13 +145,18 @@ pub mod transport;
 pub mod constants;
 
 pub use acceptor::{Acceptor, AcceptorPtr};
+pub use ban_manager::{BanManager, BanManagerPtr};
 pub use channel::{Channel, ChannelPtr};
+pub use clock::Clock;
 pub use connector::Connector;
+pub use gossip::{Gossip, GossipConfig, GossipPtr};
 pub use hosts::{Hosts, HostsPtr};
 pub use message::Message;
 pub use message_subscriber::MessageSubscription;
+pub use metrics::{Metrics, MetricsPtr};
 pub use p2p::{P2p, P2pPtr};
 pub use protocol::{ProtocolBase, ProtocolBasePtr, ProtocolJobsManager, ProtocolJobsManagerPtr};
+pub use rate_limit::RateLimit;
 pub use session::{
     Session, SessionBitflag, SessionWeakPtr, SESSION_ALL, SESSION_INBOUND, SESSION_MANUAL,
     SESSION_OUTBOUND, SESSION_SEED,
@@ -0,0 +1,378 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Noise XX handshake over X25519, ChaCha20-Poly1305 and BLAKE3.
+//!
+//! ```text
+//! -> e
+//! <- e, ee, s, es
+//! -> s, se
+//! ```
+//!
+//! After the three messages both sides know each other's static public
+//! key, and the chaining key is split into one cipher per direction.
+//! HKDF is instantiated with BLAKE3 in keyed mode instead of HMAC.
+
+use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt, path::Path};
+
+use crypto_api_chachapoly::ChachaPolyIetf;
+use darkfi_serial::{deserialize, serialize, Decodable, Encodable, VarInt};
+use ed25519_compact::x25519::{KeyPair, PublicKey, SecretKey};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use log::debug;
+
+use super::message::Packet;
+use crate::{Error, Result};
+
+const PROTOCOL_NAME: &[u8] = b"Noise_XX_25519_ChaChaPoly_BLAKE3";
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+/// Handshake messages are tiny, anything larger is garbage.
+const MAX_HANDSHAKE_MSG_LEN: usize = 256;
+
+/// Packet command used to carry encrypted packets once the handshake is done.
+pub const ENCRYPTED_COMMAND: &str = "noise";
+
+/// Encodes a static public key for display and allowlists.
+pub fn encode_public_key(pk: &PublicKey) -> String {
+    hex::encode(&pk[..])
+}
+
+/// Decodes a hex-encoded static public key.
+pub fn decode_public_key(s: &str) -> Result<PublicKey> {
+    let bytes = hex::decode(s)?;
+    PublicKey::from_slice(&bytes).map_err(|e| Error::NoiseError(e.to_string()))
+}
+
+/// Load the node's static identity from `path`, generating and writing a
+/// new one if the file does not exist yet. The file holds the hex-encoded
+/// secret key, and is only readable by its owner.
+pub async fn load_or_create_identity(path: &Path) -> Result<KeyPair> {
+    if path.exists() {
+        let contents = async_std::fs::read_to_string(path).await?;
+        let bytes = hex::decode(contents.trim())?;
+        let sk = SecretKey::from_slice(&bytes).map_err(|e| Error::NoiseError(e.to_string()))?;
+        let pk = sk.recover_public_key().map_err(|e| Error::NoiseError(e.to_string()))?;
+        return Ok(KeyPair { pk, sk })
+    }
+
+    let keypair = KeyPair::generate();
+    if let Some(parent) = path.parent() {
+        async_std::fs::create_dir_all(parent).await?;
+    }
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    file.write_all(hex::encode(&keypair.sk[..]).as_bytes())?;
+    debug!(target: "net::noise", "Generated new p2p identity {}", encode_public_key(&keypair.pk));
+
+    Ok(keypair)
+}
+
+/// One direction of an established session.
+pub struct CipherState {
+    key: [u8; KEY_LEN],
+    nonce: u64,
+}
+
+impl CipherState {
+    fn new(key: [u8; KEY_LEN]) -> Self {
+        Self { key, nonce: 0 }
+    }
+
+    fn next_nonce(&mut self) -> Result<[u8; 12]> {
+        if self.nonce == u64::MAX {
+            return Err(Error::NoiseError("Nonce exhausted".to_string()))
+        }
+
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        Ok(nonce)
+    }
+
+    pub fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let mut ciphertext = vec![0u8; plaintext.len() + TAG_LEN];
+        ChachaPolyIetf::aead_cipher()
+            .seal_to(&mut ciphertext, plaintext, ad, &self.key, &nonce)
+            .map_err(|e| Error::NoiseError(e.to_string()))?;
+        Ok(ciphertext)
+    }
+
+    pub fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if ciphertext.len() < TAG_LEN {
+            return Err(Error::NoiseError("Ciphertext too short".to_string()))
+        }
+
+        let nonce = self.next_nonce()?;
+        let mut plaintext = vec![0u8; ciphertext.len()];
+        let len = ChachaPolyIetf::aead_cipher()
+            .open_to(&mut plaintext, ciphertext, ad, &self.key, &nonce)
+            .map_err(|_| Error::NoiseError("Decryption failed".to_string()))?;
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+
+    /// Wrap a plaintext packet into an encrypted one.
+    pub fn seal_packet(&mut self, packet: Packet) -> Result<Packet> {
+        let plaintext = serialize(&(packet.command, packet.payload));
+        let payload = self.encrypt(&[], &plaintext)?;
+        Ok(Packet { command: ENCRYPTED_COMMAND.to_string(), payload })
+    }
+
+    /// Unwrap an encrypted packet. Plaintext packets are rejected.
+    pub fn open_packet(&mut self, packet: Packet) -> Result<Packet> {
+        if packet.command != ENCRYPTED_COMMAND {
+            return Err(Error::MalformedPacket)
+        }
+
+        let plaintext = self.decrypt(&[], &packet.payload)?;
+        let (command, payload): (String, Vec<u8>) = deserialize(&plaintext)?;
+        Ok(Packet { command, payload })
+    }
+}
+
+/// Result of a completed handshake.
+pub struct NoiseSession {
+    pub remote_static: PublicKey,
+    pub send: CipherState,
+    pub recv: CipherState,
+}
+
+fn hkdf(ck: &[u8; KEY_LEN], ikm: &[u8]) -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+    let temp = *blake3::keyed_hash(ck, ikm).as_bytes();
+    let out1 = *blake3::keyed_hash(&temp, &[1]).as_bytes();
+    let mut input = out1.to_vec();
+    input.push(2);
+    let out2 = *blake3::keyed_hash(&temp, &input).as_bytes();
+    (out1, out2)
+}
+
+fn dh(sk: &SecretKey, pk: &PublicKey) -> Result<[u8; KEY_LEN]> {
+    let shared = pk.dh(sk).map_err(|e| Error::NoiseError(e.to_string()))?;
+    Ok(*shared)
+}
+
+fn public_key(bytes: &[u8]) -> Result<PublicKey> {
+    PublicKey::from_slice(bytes).map_err(|e| Error::NoiseError(e.to_string()))
+}
+
+struct SymmetricState {
+    ck: [u8; KEY_LEN],
+    h: [u8; KEY_LEN],
+    cipher: Option<CipherState>,
+}
+
+impl SymmetricState {
+    fn new() -> Self {
+        let h = *blake3::hash(PROTOCOL_NAME).as_bytes();
+        Self { ck: h, h, cipher: None }
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.h);
+        hasher.update(data);
+        self.h = *hasher.finalize().as_bytes();
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) {
+        let (ck, k) = hkdf(&self.ck, ikm);
+        self.ck = ck;
+        self.cipher = Some(CipherState::new(k));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let ciphertext = match &mut self.cipher {
+            Some(cipher) => cipher.encrypt(&self.h, plaintext)?,
+            None => plaintext.to_vec(),
+        };
+        self.mix_hash(&ciphertext);
+        Ok(ciphertext)
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let plaintext = match &mut self.cipher {
+            Some(cipher) => cipher.decrypt(&self.h, ciphertext)?,
+            None => ciphertext.to_vec(),
+        };
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    fn split(&self) -> (CipherState, CipherState) {
+        let (k1, k2) = hkdf(&self.ck, &[]);
+        (CipherState::new(k1), CipherState::new(k2))
+    }
+}
+
+async fn write_msg<W: AsyncWrite + Unpin + Sized>(stream: &mut W, msg: &[u8]) -> Result<()> {
+    VarInt(msg.len() as u64).encode_async(stream).await?;
+    stream.write_all(msg).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_msg<R: AsyncRead + Unpin + Sized>(stream: &mut R) -> Result<Vec<u8>> {
+    let len = VarInt::decode_async(stream).await?.0 as usize;
+    if len > MAX_HANDSHAKE_MSG_LEN {
+        return Err(Error::NoiseError(format!("Handshake message too large: {}", len)))
+    }
+
+    let mut msg = vec![0u8; len];
+    stream.read_exact(&mut msg).await?;
+    Ok(msg)
+}
+
+/// Run the initiator side of the handshake with our static `identity`.
+pub async fn initiator<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    identity: &KeyPair,
+) -> Result<NoiseSession>
+where
+    R: AsyncRead + Unpin + Sized,
+    W: AsyncWrite + Unpin + Sized,
+{
+    let mut ss = SymmetricState::new();
+    let e = KeyPair::generate();
+
+    // -> e
+    ss.mix_hash(&e.pk[..]);
+    let mut msg = e.pk.to_vec();
+    msg.extend(ss.encrypt_and_hash(&[])?);
+    write_msg(writer, &msg).await?;
+
+    // <- e, ee, s, es
+    let msg = read_msg(reader).await?;
+    if msg.len() < KEY_LEN + KEY_LEN + TAG_LEN {
+        return Err(Error::NoiseError("Short handshake message".to_string()))
+    }
+    let re = public_key(&msg[..KEY_LEN])?;
+    ss.mix_hash(&re[..]);
+    ss.mix_key(&dh(&e.sk, &re)?);
+    let rs = ss.decrypt_and_hash(&msg[KEY_LEN..KEY_LEN * 2 + TAG_LEN])?;
+    let rs = public_key(&rs)?;
+    ss.mix_key(&dh(&e.sk, &rs)?);
+    ss.decrypt_and_hash(&msg[KEY_LEN * 2 + TAG_LEN..])?;
+
+    // -> s, se
+    let mut msg = ss.encrypt_and_hash(&identity.pk[..])?;
+    ss.mix_key(&dh(&identity.sk, &re)?);
+    msg.extend(ss.encrypt_and_hash(&[])?);
+    write_msg(writer, &msg).await?;
+
+    let (send, recv) = ss.split();
+    Ok(NoiseSession { remote_static: rs, send, recv })
+}
+
+/// Run the responder side of the handshake with our static `identity`.
+pub async fn responder<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    identity: &KeyPair,
+) -> Result<NoiseSession>
+where
+    R: AsyncRead + Unpin + Sized,
+    W: AsyncWrite + Unpin + Sized,
+{
+    let mut ss = SymmetricState::new();
+
+    // -> e
+    let msg = read_msg(reader).await?;
+    if msg.len() < KEY_LEN {
+        return Err(Error::NoiseError("Short handshake message".to_string()))
+    }
+    let re = public_key(&msg[..KEY_LEN])?;
+    ss.mix_hash(&re[..]);
+    ss.decrypt_and_hash(&msg[KEY_LEN..])?;
+
+    // <- e, ee, s, es
+    let e = KeyPair::generate();
+    ss.mix_hash(&e.pk[..]);
+    let mut msg = e.pk.to_vec();
+    ss.mix_key(&dh(&e.sk, &re)?);
+    msg.extend(ss.encrypt_and_hash(&identity.pk[..])?);
+    ss.mix_key(&dh(&identity.sk, &re)?);
+    msg.extend(ss.encrypt_and_hash(&[])?);
+    write_msg(writer, &msg).await?;
+
+    // -> s, se
+    let msg = read_msg(reader).await?;
+    if msg.len() < KEY_LEN + TAG_LEN {
+        return Err(Error::NoiseError("Short handshake message".to_string()))
+    }
+    let rs = ss.decrypt_and_hash(&msg[..KEY_LEN + TAG_LEN])?;
+    let rs = public_key(&rs)?;
+    ss.mix_key(&dh(&e.sk, &rs)?);
+    ss.decrypt_and_hash(&msg[KEY_LEN + TAG_LEN..])?;
+
+    let (recv, send) = ss.split();
+    Ok(NoiseSession { remote_static: rs, send, recv })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::os::unix::net::UnixStream;
+
+    #[async_std::test]
+    async fn noise_xx_handshake() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+
+        let (mut a_reader, mut a_writer) = (a.clone(), a);
+        let (mut b_reader, mut b_writer) = (b.clone(), b);
+
+        let (a_session, b_session) = futures::join!(
+            initiator(&mut a_reader, &mut a_writer, &alice),
+            responder(&mut b_reader, &mut b_writer, &bob),
+        );
+        let mut a_session = a_session.unwrap();
+        let mut b_session = b_session.unwrap();
+
+        assert_eq!(a_session.remote_static, bob.pk);
+        assert_eq!(b_session.remote_static, alice.pk);
+
+        let packet = Packet { command: "ping".to_string(), payload: vec![1, 2, 3] };
+        let sealed = a_session.send.seal_packet(packet).unwrap();
+        assert_eq!(sealed.command, ENCRYPTED_COMMAND);
+        let opened = b_session.recv.open_packet(sealed).unwrap();
+        assert_eq!(opened.command, "ping");
+        assert_eq!(opened.payload, vec![1, 2, 3]);
+
+        let packet = Packet { command: "pong".to_string(), payload: vec![] };
+        let sealed = b_session.send.seal_packet(packet).unwrap();
+        let opened = a_session.recv.open_packet(sealed).unwrap();
+        assert_eq!(opened.command, "pong");
+
+        // Replaying a packet must fail since the nonce moved on
+        let packet = Packet { command: "ping".to_string(), payload: vec![] };
+        let sealed = a_session.send.seal_packet(packet).unwrap();
+        let replay = Packet { command: sealed.command.clone(), payload: sealed.payload.clone() };
+        assert!(b_session.recv.open_packet(sealed).is_ok());
+        assert!(b_session.recv.open_packet(replay).is_err());
+    }
+
+    #[async_std::test]
+    async fn allowed_peers_require_identity() {
+        let peer = encode_public_key(&KeyPair::generate().pk);
+        let settings = crate::net::Settings { allowed_peers: vec![peer], ..Default::default() };
+        assert!(crate::net::P2p::new(settings).await.is_err());
+    }
+}
@@ -19,9 +19,11 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt,
+    time::Duration,
 };
 
 use async_std::sync::{Arc, Mutex};
+use ed25519_compact::x25519::{KeyPair, PublicKey};
 use futures::{select, stream::FuturesUnordered, try_join, FutureExt, StreamExt, TryFutureExt};
 use log::{debug, error, warn};
 use rand::Rng;
@@ -30,16 +32,19 @@ use smol::Executor;
 use url::Url;
 
 use crate::{
-    system::{Subscriber, SubscriberPtr, Subscription},
-    util::async_util::sleep,
-    Result,
+    system::{StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr, Subscription},
+    util::{async_util::sleep, path::expand_path},
+    Error, Result,
 };
 
 use super::{
+    constants::HOSTS_SAVE_INTERVAL_SECONDS,
     message::Message,
+    noise,
     protocol::{register_default_protocols, ProtocolRegistry},
     session::{InboundSession, ManualSession, OutboundSession, SeedSyncSession, Session},
-    Channel, ChannelPtr, Hosts, HostsPtr, Settings, SettingsPtr,
+    BanManager, BanManagerPtr, Channel, ChannelPtr, Clock, Gossip, GossipPtr, Hosts, HostsPtr,
+    Metrics, MetricsPtr, Settings, SettingsPtr,
 };
 
 /// List of channels that are awaiting connection.
@@ -83,6 +88,8 @@ pub struct P2p {
     // Used both internally and externally
     stop_subscriber: SubscriberPtr<()>,
     hosts: HostsPtr,
+    ban_manager: BanManagerPtr,
+    metrics: MetricsPtr,
     protocol_registry: ProtocolRegistry,
 
     // We keep a reference to the sessions used for get info
@@ -90,9 +97,21 @@ pub struct P2p {
     session_inbound: Mutex<Option<Arc<InboundSession>>>,
     session_outbound: Mutex<Option<Arc<OutboundSession>>>,
 
+    gossip: Mutex<Option<GossipPtr>>,
+
+    /// Task periodically saving the known hosts
+    save_hosts_task: StoppableTaskPtr,
+
     state: Mutex<P2pState>,
 
     settings: SettingsPtr,
+    /// Source of time for the timers of the network
+    clock: Clock,
+
+    /// Static identity used for the Noise handshake, if enabled
+    identity: Option<KeyPair>,
+    /// Static public keys of peers we accept, empty allows anyone
+    allowed_peers: Vec<PublicKey>,
 
     /// Flag to check if on discovery mode
     discovery: Mutex<bool>,
@@ -106,21 +125,84 @@ impl P2p {
     /// address protocols.
     ///
     /// Creates a weak pointer to self that is used by all sessions to access the p2p parent class.
-    pub async fn new(settings: Settings) -> Arc<Self> {
+    ///
+    /// Fails if the identity file can't be loaded or created, if an allowed peer key is invalid,
+    /// or if allowed peers are set without an identity file.
+    pub async fn new(settings: Settings) -> Result<Arc<Self>> {
         let settings = Arc::new(settings);
 
+        let hosts_file = match &settings.hosts_file {
+            Some(path) => match expand_path(path) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    error!(target: "net::p2p::new()", "Failed expanding hosts file path {}: {}", path, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let ban_file = match &settings.ban_file {
+            Some(path) => match expand_path(path) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    error!(target: "net::p2p::new()", "Failed expanding ban file path {}: {}", path, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        // Peers can only be told apart by their Noise identity
+        if !settings.allowed_peers.is_empty() && settings.identity_file.is_none() {
+            error!(target: "net::p2p::new()", "allowed_peers is set but identity_file is not");
+            return Err(Error::NoiseError("allowed_peers requires an identity_file".to_string()))
+        }
+
+        let identity = match &settings.identity_file {
+            Some(path) => {
+                let path = expand_path(path)?;
+                match noise::load_or_create_identity(&path).await {
+                    Ok(keypair) => Some(keypair),
+                    Err(e) => {
+                        error!(target: "net::p2p::new()", "Failed loading p2p identity {:?}: {}", path, e);
+                        return Err(e)
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let mut allowed_peers = Vec::with_capacity(settings.allowed_peers.len());
+        for pk in &settings.allowed_peers {
+            match noise::decode_public_key(pk) {
+                Ok(pk) => allowed_peers.push(pk),
+                Err(e) => {
+                    error!(target: "net::p2p::new()", "Invalid allowed peer key {}: {}", pk, e);
+                    return Err(e)
+                }
+            }
+        }
+
         let self_ = Arc::new(Self {
             pending: Mutex::new(HashSet::new()),
             channels: Mutex::new(HashMap::new()),
             channel_subscriber: Subscriber::new(),
             stop_subscriber: Subscriber::new(),
-            hosts: Hosts::new(settings.localnet),
+            hosts: Hosts::new(settings.localnet, hosts_file),
+            ban_manager: BanManager::new(ban_file),
+            metrics: Metrics::new(),
             protocol_registry: ProtocolRegistry::new(),
             session_manual: Mutex::new(None),
             session_inbound: Mutex::new(None),
             session_outbound: Mutex::new(None),
+            gossip: Mutex::new(None),
+            save_hosts_task: StoppableTask::new(),
             state: Mutex::new(P2pState::Open),
+            clock: Clock::new(&settings),
             settings,
+            identity,
+            allowed_peers,
             discovery: Mutex::new(false),
         });
 
@@ -128,11 +210,12 @@ impl P2p {
 
         *self_.session_manual.lock().await = Some(ManualSession::new(parent.clone()));
         *self_.session_inbound.lock().await = Some(InboundSession::new(parent.clone()).await);
-        *self_.session_outbound.lock().await = Some(OutboundSession::new(parent));
+        *self_.session_outbound.lock().await = Some(OutboundSession::new(parent.clone()));
+        *self_.gossip.lock().await = Some(Gossip::new(parent, self_.clock.clone()));
 
         register_default_protocols(self_.clone()).await;
 
-        self_
+        Ok(self_)
     }
 
     // ANCHOR: get_info
@@ -149,6 +232,9 @@ impl P2p {
             "session_inbound": self.session_inbound().await.get_info().await,
             "session_outbound": self.session_outbound().await.get_info().await,
             "state": self.state.lock().await.to_string(),
+            "banned": self.ban_manager.get_info().await,
+            "metrics": self.metrics.get_info().await,
+            "gossip": self.gossip().await.get_info().await,
         })
     }
     // ANCHOR_END: get_info
@@ -182,6 +268,11 @@ impl P2p {
         self.session_outbound.lock().await.as_ref().unwrap().clone()
     }
 
+    /// Gossip layer, used to register topics and publish on them.
+    pub async fn gossip(&self) -> GossipPtr {
+        self.gossip.lock().await.as_ref().unwrap().clone()
+    }
+
     /// Runs the network. Starts inbound, outbound and manual sessions.
     /// Waits for a stop signal and stops the network if received.
     // ANCHOR: run
@@ -201,6 +292,18 @@ impl P2p {
         let outbound = self.session_outbound().await;
         outbound.clone().start(executor.clone()).await?;
 
+        let gossip = self.gossip().await;
+        gossip.clone().start(executor.clone());
+
+        // Save the hosts every once in a while, so they survive crashes
+        self.save_hosts_task.clone().start(
+            save_hosts_loop(self.hosts.clone(), self.clock.clone()),
+            // Ignore stop handler
+            |_| async {},
+            Error::NetworkServiceStopped,
+            executor.clone(),
+        );
+
         let stop_sub = self.subscribe_stop().await;
         // Wait for stop signal
         stop_sub.receive().await;
@@ -209,6 +312,8 @@ impl P2p {
         manual.stop().await;
         inbound.stop().await;
         outbound.stop().await;
+        gossip.stop().await;
+        self.save_hosts_task.stop().await;
 
         debug!(target: "net::p2p::run()", "P2p::run() [END]");
         Ok(())
@@ -332,7 +437,16 @@ impl P2p {
 
     // ANCHOR: stop
     pub async fn stop(&self) {
-        self.stop_subscriber.notify(()).await
+        self.stop_subscriber.notify(()).await;
+
+        // Persist known hosts so we can reconnect quickly on restart
+        if let Err(e) = self.hosts.save().await {
+            error!(target: "net::p2p::stop()", "Failed saving hosts: {}", e);
+        }
+
+        if let Err(e) = self.ban_manager.save().await {
+            error!(target: "net::p2p::stop()", "Failed saving ban list: {}", e);
+        }
     }
     // ANCHOR_END: stop
 
@@ -455,6 +569,42 @@ impl P2p {
         self.settings.clone()
     }
 
+    /// Clock the network's timers run on.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Return our static identity, if channels are encrypted with Noise.
+    pub fn identity(&self) -> Option<&KeyPair> {
+        self.identity.as_ref()
+    }
+
+    /// Check a remote static public key against the configured allowlist.
+    pub fn is_peer_allowed(&self, pk: &PublicKey) -> bool {
+        self.allowed_peers.is_empty() || self.allowed_peers.contains(pk)
+    }
+
+    /// Return an atomic pointer to the ban manager.
+    pub fn ban_manager(&self) -> BanManagerPtr {
+        self.ban_manager.clone()
+    }
+
+    /// Return an atomic pointer to the network metrics.
+    pub fn metrics(&self) -> MetricsPtr {
+        self.metrics.clone()
+    }
+
+    /// Report misbehaviour points against a channel. If the peer crosses
+    /// the ban threshold, the channel is stopped and its address is
+    /// dropped from our hosts.
+    pub async fn misbehaving(&self, channel: &Channel, points: u32, reason: &str) {
+        let addr = channel.address();
+        if self.ban_manager.misbehaving(&addr, points, reason).await {
+            self.hosts.remove(&addr).await;
+            channel.stop().await;
+        }
+    }
+
     /// Return an atomic pointer to the list of hosts.
     pub fn hosts(&self) -> HostsPtr {
         self.hosts.clone()
@@ -507,3 +657,13 @@ impl P2p {
         Some(values.nth(rand::thread_rng().gen_range(0..values.len())).unwrap().clone())
     }
 }
+
+/// Save the known hosts to the hosts file every `HOSTS_SAVE_INTERVAL_SECONDS`.
+async fn save_hosts_loop(hosts: HostsPtr, clock: Clock) -> Result<()> {
+    loop {
+        clock.sleep(Duration::from_secs(HOSTS_SAVE_INTERVAL_SECONDS)).await;
+        if let Err(e) = hosts.save().await {
+            error!(target: "net::p2p::save_hosts_loop()", "Failed saving hosts: {}", e);
+        }
+    }
+}
@@ -27,6 +27,11 @@
 /// address information to their local store.
 pub mod protocol_address;
 
+/// Protocol relaying gossip messages. Hands gossip, have and want messages
+/// received on a channel to the node's gossip layer, which deduplicates them
+/// and relays them further.
+pub mod protocol_gossip;
+
 /// Manages the tasks for the network protocol. Used by other connection
 /// protocols to handle asynchronous task execution across the network. Runs all
 /// tasks that are handed to it on an executor that has stopping functionality.
@@ -68,6 +73,7 @@ pub mod protocol_base;
 pub mod protocol_registry;
 
 pub use protocol_address::ProtocolAddress;
+pub use protocol_gossip::ProtocolGossip;
 pub use protocol_jobs_manager::{ProtocolJobsManager, ProtocolJobsManagerPtr};
 pub use protocol_ping::ProtocolPing;
 pub use protocol_seed::ProtocolSeed;
@@ -85,5 +91,6 @@ pub async fn register_default_protocols(p2p: P2pPtr) {
     let registry = p2p.protocol_registry();
     registry.register(SESSION_ALL, ProtocolPing::init).await;
     registry.register(!SESSION_SEED, ProtocolAddress::init).await;
+    registry.register(!SESSION_SEED, ProtocolGossip::init).await;
     registry.register(SESSION_SEED, ProtocolSeed::init).await;
 }
@@ -16,19 +16,21 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use log::debug;
 use rand::seq::SliceRandom;
 use smol::Executor;
 
-use crate::{util::async_util, Result};
+use crate::Result;
 
 use super::{
     super::{
-        message, message_subscriber::MessageSubscription, ChannelPtr, HostsPtr, P2pPtr,
-        SettingsPtr, SESSION_OUTBOUND,
+        constants::{BAN_SCORE_ADDRS_FLOOD, MAX_ADDRS_PER_MESSAGE},
+        message,
+        message_subscriber::MessageSubscription,
+        ChannelPtr, Clock, HostsPtr, P2pPtr, SettingsPtr, SESSION_OUTBOUND,
     },
     ProtocolBase, ProtocolBasePtr, ProtocolJobsManager, ProtocolJobsManagerPtr,
 };
@@ -44,6 +46,7 @@ pub struct ProtocolAddress {
     hosts: HostsPtr,
     jobsman: ProtocolJobsManagerPtr,
     settings: SettingsPtr,
+    clock: Clock,
 }
 
 impl ProtocolAddress {
@@ -82,6 +85,7 @@ impl ProtocolAddress {
             hosts,
             jobsman: ProtocolJobsManager::new("ProtocolAddress", channel),
             settings,
+            clock: p2p.clock().clone(),
         })
     }
 
@@ -97,6 +101,10 @@ impl ProtocolAddress {
                 "received {} addrs",
                 addrs_msg.addrs.len()
             );
+            if addrs_msg.addrs.len() > MAX_ADDRS_PER_MESSAGE {
+                self.channel.misbehaving(BAN_SCORE_ADDRS_FLOOD, "oversized addrs message").await;
+                continue
+            }
             self.hosts.store(addrs_msg.addrs.clone()).await;
         }
     }
@@ -113,6 +121,12 @@ impl ProtocolAddress {
                 "ProtocolAddress::handle_receive_ext_addrs() received {} addrs",
                 ext_addrs_msg.ext_addrs.len()
             );
+            if ext_addrs_msg.ext_addrs.len() > MAX_ADDRS_PER_MESSAGE {
+                self.channel
+                    .misbehaving(BAN_SCORE_ADDRS_FLOOD, "oversized ext_addrs message")
+                    .await;
+                continue
+            }
             self.hosts.store_ext(self.channel.address(), ext_addrs_msg.ext_addrs.clone()).await;
         }
     }
@@ -152,7 +166,7 @@ impl ProtocolAddress {
             let ext_addrs = self.settings.external_addr.clone();
             let ext_addr_msg = message::ExtAddrsMessage { ext_addrs };
             self.channel.clone().send(ext_addr_msg).await?;
-            async_util::sleep(SEND_ADDR_SLEEP_SECONDS).await;
+            self.clock.sleep(Duration::from_secs(SEND_ADDR_SLEEP_SECONDS)).await;
         }
     }
 }
@@ -0,0 +1,121 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::debug;
+use smol::Executor;
+
+use crate::Result;
+
+use super::{
+    super::{
+        gossip::{GossipHaveMessage, GossipMessage, GossipWantMessage},
+        message_subscriber::MessageSubscription,
+        ChannelPtr, GossipPtr, P2pPtr,
+    },
+    ProtocolBase, ProtocolBasePtr, ProtocolJobsManager, ProtocolJobsManagerPtr,
+};
+
+/// Hands gossip, have and want messages received on a channel to the
+/// node's gossip layer.
+pub struct ProtocolGossip {
+    channel: ChannelPtr,
+    gossip_sub: MessageSubscription<GossipMessage>,
+    have_sub: MessageSubscription<GossipHaveMessage>,
+    want_sub: MessageSubscription<GossipWantMessage>,
+    gossip: GossipPtr,
+    jobsman: ProtocolJobsManagerPtr,
+}
+
+impl ProtocolGossip {
+    /// Create a new gossip protocol, subscribing to gossip, have and want
+    /// messages on the channel.
+    pub async fn init(channel: ChannelPtr, p2p: P2pPtr) -> ProtocolBasePtr {
+        let gossip_sub = channel
+            .clone()
+            .subscribe_msg::<GossipMessage>()
+            .await
+            .expect("Missing gossip dispatcher!");
+
+        let have_sub = channel
+            .clone()
+            .subscribe_msg::<GossipHaveMessage>()
+            .await
+            .expect("Missing gossiphave dispatcher!");
+
+        let want_sub = channel
+            .clone()
+            .subscribe_msg::<GossipWantMessage>()
+            .await
+            .expect("Missing gossipwant dispatcher!");
+
+        Arc::new(Self {
+            channel: channel.clone(),
+            gossip_sub,
+            have_sub,
+            want_sub,
+            gossip: p2p.gossip().await,
+            jobsman: ProtocolJobsManager::new("ProtocolGossip", channel),
+        })
+    }
+
+    async fn handle_receive_gossip(self: Arc<Self>) -> Result<()> {
+        debug!(target: "net::protocol_gossip::handle_receive_gossip()", "START");
+        loop {
+            let message = self.gossip_sub.receive().await?;
+            self.gossip.handle_message(&self.channel, (*message).clone()).await;
+        }
+    }
+
+    async fn handle_receive_have(self: Arc<Self>) -> Result<()> {
+        debug!(target: "net::protocol_gossip::handle_receive_have()", "START");
+        loop {
+            let have = self.have_sub.receive().await?;
+            self.gossip.handle_have(&self.channel, (*have).clone()).await;
+        }
+    }
+
+    async fn handle_receive_want(self: Arc<Self>) -> Result<()> {
+        debug!(target: "net::protocol_gossip::handle_receive_want()", "START");
+        loop {
+            let want = self.want_sub.receive().await?;
+            self.gossip.handle_want(&self.channel, (*want).clone()).await;
+        }
+    }
+}
+
+#[async_trait]
+impl ProtocolBase for ProtocolGossip {
+    /// Starts the gossip protocol, handling incoming gossip, have and want
+    /// messages on the protocol task manager.
+    async fn start(self: Arc<Self>, executor: Arc<Executor<'_>>) -> Result<()> {
+        debug!(target: "net::protocol_gossip::start()", "START");
+        self.jobsman.clone().start(executor.clone());
+        self.jobsman.clone().spawn(self.clone().handle_receive_gossip(), executor.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_receive_have(), executor.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_receive_want(), executor).await;
+        debug!(target: "net::protocol_gossip::start()", "END");
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "ProtocolGossip"
+    }
+}
@@ -16,17 +16,19 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{sync::Arc, time::Instant};
+use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use log::{debug, error};
 use rand::Rng;
 use smol::Executor;
 
-use crate::{util::async_util::sleep, Error, Result};
+use crate::{Error, Result};
 
 use super::{
-    super::{message, message_subscriber::MessageSubscription, ChannelPtr, P2pPtr, SettingsPtr},
+    super::{
+        message, message_subscriber::MessageSubscription, ChannelPtr, Clock, P2pPtr, SettingsPtr,
+    },
     ProtocolBase, ProtocolBasePtr, ProtocolJobsManager, ProtocolJobsManagerPtr,
 };
 
@@ -36,6 +38,7 @@ pub struct ProtocolPing {
     ping_sub: MessageSubscription<message::PingMessage>,
     pong_sub: MessageSubscription<message::PongMessage>,
     settings: SettingsPtr,
+    clock: Clock,
     jobsman: ProtocolJobsManagerPtr,
 }
 
@@ -63,6 +66,7 @@ impl ProtocolPing {
             ping_sub,
             pong_sub,
             settings,
+            clock: p2p.clock().clone(),
             jobsman: ProtocolJobsManager::new("ProtocolPing", channel),
         })
     }
@@ -75,7 +79,8 @@ impl ProtocolPing {
         debug!(target: "net::protocol_ping::run_ping_pong()", "START");
         loop {
             // Wait channel_heartbeat amount of time.
-            sleep(self.settings.channel_heartbeat_seconds.into()).await;
+            let heartbeat = self.settings.channel_heartbeat_seconds.into();
+            self.clock.sleep(Duration::from_secs(heartbeat)).await;
 
             // Create a random nonce.
             let nonce = Self::random_nonce();
@@ -85,7 +90,7 @@ impl ProtocolPing {
             self.channel.clone().send(ping).await?;
             debug!(target: "net::protocol_ping::run_ping_pong()", "Send Ping message");
             // Start the timer for ping timer.
-            let start = Instant::now();
+            let start = self.clock.now();
 
             // Wait for pong, check nonce matches.
             let pong_msg = self.pong_sub.receive().await?;
@@ -98,7 +103,7 @@ impl ProtocolPing {
                 self.channel.stop().await;
                 return Err(Error::ChannelStopped)
             }
-            let duration = start.elapsed().as_millis();
+            let duration = (self.clock.now() - start).as_millis();
             debug!(
                 target: "net::protocol_ping::run_ping_pong()",
                 "Received Pong message {}ms from [{:?}]",
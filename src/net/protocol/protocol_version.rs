@@ -16,7 +16,6 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use async_std::future::timeout;
 use std::{sync::Arc, time::Duration};
 
 use log::*;
@@ -25,7 +24,8 @@ use smol::Executor;
 use crate::{Error, Result};
 
 use super::super::{
-    message, message_subscriber::MessageSubscription, ChannelPtr, HostsPtr, SettingsPtr,
+    constants::BAN_SCORE_MALFORMED_VERSION, message, message_subscriber::MessageSubscription,
+    ChannelPtr, Clock, HostsPtr, SettingsPtr,
 };
 
 /// Implements the protocol version handshake sent out by nodes at the beginning
@@ -36,13 +36,19 @@ pub struct ProtocolVersion {
     verack_sub: MessageSubscription<message::VerackMessage>,
     settings: SettingsPtr,
     hosts: HostsPtr,
+    clock: Clock,
 }
 
 impl ProtocolVersion {
     /// Create a new version protocol. Makes a version and version
     /// acknowledgement subscription, then adds them to a version protocol
     /// instance.
-    pub async fn new(channel: ChannelPtr, settings: SettingsPtr, hosts: HostsPtr) -> Arc<Self> {
+    pub async fn new(
+        channel: ChannelPtr,
+        settings: SettingsPtr,
+        hosts: HostsPtr,
+        clock: Clock,
+    ) -> Arc<Self> {
         // Creates a version subscription.
         let version_sub = channel
             .clone()
@@ -57,7 +63,7 @@ impl ProtocolVersion {
             .await
             .expect("Missing verack dispatcher!");
 
-        Arc::new(Self { channel, version_sub, verack_sub, settings, hosts })
+        Arc::new(Self { channel, version_sub, verack_sub, settings, hosts, clock })
     }
 
     /// Start version information exchange. Start the timer. Send version info
@@ -69,13 +75,15 @@ impl ProtocolVersion {
         // Send version, wait for verack
         // Wait for version, send verack
         // Fin.
-        let result = timeout(
-            Duration::from_secs(self.settings.channel_handshake_seconds.into()),
-            self.clone().exchange_versions(executor),
-        )
-        .await;
-
-        if let Err(_e) = result {
+        let result = self
+            .clock
+            .timeout(
+                Duration::from_secs(self.settings.channel_handshake_seconds.into()),
+                self.clone().exchange_versions(executor),
+            )
+            .await;
+
+        if result.is_none() {
             return Err(Error::ChannelTimeout)
         }
 
@@ -102,7 +110,10 @@ impl ProtocolVersion {
     async fn send_version(self: Arc<Self>) -> Result<()> {
         debug!(target: "net::protocol_version::send_version()", "START");
 
-        let version = message::VersionMessage { node_id: self.settings.node_id.clone() };
+        let version = message::VersionMessage {
+            node_id: self.settings.node_id.clone(),
+            features: message::LOCAL_FEATURES,
+        };
 
         self.channel.clone().send(version).await?;
 
@@ -129,6 +140,9 @@ impl ProtocolVersion {
                             target: "net::protocol_version::send_version()",
                             "Malformed version detected. Disconnecting from channel."
                         );
+                        self.channel
+                            .misbehaving(BAN_SCORE_MALFORMED_VERSION, "malformed app version")
+                            .await;
                         self.hosts.remove(&self.channel.address()).await;
                         self.channel.stop().await;
                         return Err(Error::ChannelStopped)
@@ -167,6 +181,14 @@ impl ProtocolVersion {
         // Receive version message
         let version = self.version_sub.receive().await?;
         self.channel.set_remote_node_id(version.node_id.clone()).await;
+        if version.features & message::FEATURE_COMPRESSION != 0 {
+            debug!(
+                target: "net::protocol_version::recv_version()",
+                "Enabling compression for channel {}",
+                self.channel.address()
+            );
+            self.channel.enable_compression().await;
+        }
 
         // Send version acknowledgement
         let verack =
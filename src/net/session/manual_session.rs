@@ -16,6 +16,8 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::time::Duration;
+
 use async_std::sync::{Arc, Mutex, Weak};
 
 use async_trait::async_trait;
@@ -27,7 +29,6 @@ use url::Url;
 use crate::{
     net::transport::TransportName,
     system::{StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr, Subscription},
-    util::async_util::sleep,
     Error, Result,
 };
 
@@ -158,7 +159,8 @@ impl ManualSession {
                 self.channel_subscriber.notify(Err(Error::ConnectFailed)).await;
             }
 
-            sleep(settings.connect_timeout_seconds.into()).await;
+            let retry = Duration::from_secs(settings.connect_timeout_seconds.into());
+            self.p2p().clock().sleep(retry).await;
         }
 
         warn!(
@@ -16,15 +16,18 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::{Arc, Weak};
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
 
 use async_trait::async_trait;
-use log::debug;
+use log::{debug, error, warn};
 use smol::Executor;
 
-use crate::Result;
+use crate::{Error, Result};
 
-use super::{p2p::P2pPtr, protocol::ProtocolVersion, ChannelPtr};
+use super::{noise, p2p::P2pPtr, protocol::ProtocolVersion, ChannelPtr};
 
 /// Seed sync session creates a connection to the seed nodes specified in settings.
 /// A new seed sync session is created every time we call p2p::start(). The seed
@@ -83,6 +86,17 @@ pub use seedsync_session::SeedSyncSession;
 
 pub type SessionWeakPtr = Arc<Weak<dyn Session + Send + Sync + 'static>>;
 
+/// Returns the name of a session type, used to label metrics.
+pub fn session_name(type_id: SessionBitflag) -> &'static str {
+    match type_id {
+        SESSION_INBOUND => "inbound",
+        SESSION_OUTBOUND => "outbound",
+        SESSION_MANUAL => "manual",
+        SESSION_SEED => "seed",
+        _ => "unknown",
+    }
+}
+
 /// Removes channel from the list of connected channels when a stop signal is
 /// received.
 async fn remove_sub_on_stop(p2p: P2pPtr, channel: ChannelPtr) {
@@ -131,17 +145,21 @@ pub trait Session: Sync {
             p2p.protocol_registry().attach(self.type_id(), channel.clone(), p2p.clone()).await;
 
         // Perform the handshake protocol
-        let protocol_version =
-            ProtocolVersion::new(channel.clone(), p2p.settings().clone(), p2p.hosts().clone())
-                .await;
-        let handshake_task =
-            self.perform_handshake_protocols(protocol_version, channel.clone(), executor.clone());
-
-        // Switch on the channel
-        channel.start(executor.clone());
-
-        // Wait for handshake to finish.
-        handshake_task.await?;
+        let protocol_version = ProtocolVersion::new(
+            channel.clone(),
+            p2p.settings().clone(),
+            p2p.hosts().clone(),
+            p2p.clock().clone(),
+        )
+        .await;
+        // Wait for handshake to finish. This also switches on the channel.
+        if let Err(e) = self
+            .perform_handshake_protocols(protocol_version, channel.clone(), executor.clone())
+            .await
+        {
+            p2p.metrics().handshake_failed(session_name(self.type_id())).await;
+            return Err(e)
+        }
 
         // Now the channel is ready
         debug!(target: "net", "Session handshake complete. Activating remaining protocols");
@@ -158,15 +176,54 @@ pub trait Session: Sync {
         Ok(())
     }
 
-    /// Performs network handshake to initialize channel. Adds the channel to
-    /// the list of connected channels, and prepares to remove the channel
-    /// when a stop signal is received.
+    /// Performs network handshake to initialize channel. If a p2p identity
+    /// is configured, runs the Noise handshake on the raw stream first and
+    /// checks the remote static key against the allowlist. Then starts the
+    /// channel and exchanges versions. Adds the channel to the list of
+    /// connected channels, and prepares to remove the channel when a stop
+    /// signal is received.
     async fn perform_handshake_protocols(
         &self,
         protocol_version: Arc<ProtocolVersion>,
         channel: ChannelPtr,
         executor: Arc<Executor<'_>>,
     ) -> Result<()> {
+        let p2p = self.p2p();
+
+        if let Some(identity) = p2p.identity() {
+            // Whoever dialed the connection initiates
+            let initiator = self.type_id() != SESSION_INBOUND;
+            let timeout = Duration::from_secs(p2p.settings().channel_handshake_seconds.into());
+
+            let remote_pk = match p2p
+                .clock()
+                .timeout(timeout, channel.noise_handshake(identity, initiator))
+                .await
+            {
+                Some(Ok(pk)) => pk,
+                Some(Err(e)) => {
+                    error!(target: "net", "Noise handshake with {} failed: {}", channel.address(), e);
+                    channel.stop().await;
+                    return Err(e)
+                }
+                None => {
+                    error!(target: "net", "Noise handshake with {} timed out", channel.address());
+                    channel.stop().await;
+                    return Err(Error::ChannelTimeout)
+                }
+            };
+
+            if !p2p.is_peer_allowed(&remote_pk) {
+                let remote_pk = noise::encode_public_key(&remote_pk);
+                warn!(target: "net", "Rejecting channel {}: peer {} not allowed", channel.address(), remote_pk);
+                channel.stop().await;
+                return Err(Error::PeerNotAllowed(remote_pk))
+            }
+        }
+
+        // Switch on the channel
+        channel.clone().start(executor.clone());
+
         // Perform handshake
         protocol_version.run(executor.clone()).await?;
 
@@ -16,12 +16,11 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::fmt;
+use std::{fmt, time::Duration};
 
 use async_std::sync::{Arc, Mutex, Weak};
 use async_trait::async_trait;
 use log::{debug, error, info, warn};
-use rand::seq::SliceRandom;
 use serde_json::{json, Value};
 use smol::Executor;
 use url::Url;
@@ -29,7 +28,6 @@ use url::Url;
 use crate::{
     net::{message, transport::TransportName},
     system::{StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr, Subscription},
-    util::async_util,
     Error, Result,
 };
 
@@ -176,7 +174,8 @@ impl OutboundSession {
                 }
             }
 
-            async_util::sleep(self.p2p().settings().outbound_retry_seconds).await;
+            let p2p = self.p2p();
+            p2p.clock().sleep(Duration::from_secs(p2p.settings().outbound_retry_seconds)).await;
         }
     }
 
@@ -208,6 +207,8 @@ impl OutboundSession {
             outbound_transports.clone()
         };
 
+        self.p2p().hosts().mark_attempt(&addr).await;
+
         for transport in transports {
             // Replace addr transport
             let mut transport_addr = addr.clone();
@@ -217,6 +218,7 @@ impl OutboundSession {
                 Ok(channel) => {
                     // Blacklist goes here
                     info!(target: "net::outbound_session", "#{} connected to outbound [{}]", slot_number, transport_addr);
+                    self.p2p().hosts().mark_connected(&addr).await;
 
                     let stop_sub = channel.subscribe_stop().await;
                     if stop_sub.is_err() {
@@ -251,8 +253,11 @@ impl OutboundSession {
             }
         }
 
-        // Remove url from hosts
-        self.p2p().hosts().remove(&addr).await;
+        // Penalize the url, removing it from hosts if it keeps failing
+        if self.p2p().hosts().mark_failed(&addr).await {
+            info!(target: "net::outbound_session", "#{} removed failing host [{}]", slot_number, addr);
+        }
+        self.p2p().remove_pending(&addr).await;
 
         {
             let info = &mut self.slot_info.lock().await[slot_number as usize];
@@ -279,14 +284,8 @@ impl OutboundSession {
             let p2p = self.p2p();
             let self_inbound_addr = p2p.settings().external_addr.clone();
 
-            let mut addrs;
-
-            {
-                let hosts = p2p.hosts().load_all().await;
-                addrs = hosts;
-            }
-
-            addrs.shuffle(&mut rand::thread_rng());
+            // Hosts are ordered by score, so better peers are tried first
+            let addrs = p2p.hosts().load_scored().await;
 
             for addr in addrs {
                 if p2p.exists(&addr).await? {
@@ -298,6 +297,11 @@ impl OutboundSession {
                     continue
                 }
 
+                // Skip banned hosts
+                if p2p.ban_manager().is_banned(&addr).await {
+                    continue
+                }
+
                 // Obtain a lock on this address to prevent duplicate connections
                 if !p2p.add_pending(addr.clone()).await {
                     continue
@@ -319,7 +323,7 @@ impl OutboundSession {
 
             // Sleep and then retry
             debug!(target: "net::outbound_session", "Retrying connect slot #{}", slot_number);
-            async_util::sleep(p2p.settings().outbound_retry_seconds).await;
+            p2p.clock().sleep(Duration::from_secs(p2p.settings().outbound_retry_seconds)).await;
         }
     }
 
@@ -18,10 +18,7 @@
 
 use std::time::Duration;
 
-use async_std::{
-    future::timeout,
-    sync::{Arc, Weak},
-};
+use async_std::sync::{Arc, Weak};
 use async_trait::async_trait;
 use futures::future::join_all;
 use log::*;
@@ -77,12 +74,11 @@ impl SeedSyncSession {
             tasks.push(async move {
                 let task = self2.clone().start_seed(i, seed.clone(), ex2.clone());
 
-                let result =
-                    timeout(Duration::from_secs(sett2.seed_query_timeout_seconds.into()), task)
-                        .await;
+                let timeout = Duration::from_secs(sett2.seed_query_timeout_seconds.into());
+                let result = self2.p2p().clock().timeout(timeout, task).await;
 
                 match result {
-                    Ok(t) => match t {
+                    Some(t) => match t {
                         Ok(()) => {
                             info!(target: "net::seedsync_session", "Seed #{} connected successfully", i)
                         }
@@ -90,7 +86,7 @@ impl SeedSyncSession {
                             warn!(target: "net::seedsync_session", "Seed #{} failed for reason {}", i, err)
                         }
                     },
-                    Err(_err) => error!(target: "net::seedsync_session", "Seed #{} timed out", i),
+                    None => error!(target: "net::seedsync_session", "Seed #{} timed out", i),
                 }
             });
         }
@@ -23,7 +23,7 @@ use structopt::StructOpt;
 use structopt_toml::StructOptToml;
 use url::Url;
 
-use crate::net::transport::TransportName;
+use crate::net::transport::{MemoryNode, TransportName};
 
 /// Atomic pointer to network settings.
 pub type SettingsPtr = Arc<Settings>;
@@ -67,6 +67,19 @@ pub struct Settings {
     pub peer_discovery: bool,
     /// Enable channel logging
     pub channel_log: bool,
+    /// Path to the file used to persist known hosts across restarts
+    pub hosts_file: Option<String>,
+    /// Path to the file used to persist banned hosts across restarts
+    pub ban_file: Option<String>,
+    /// Path to the node's static p2p identity key. When set, every channel
+    /// performs a Noise handshake and is encrypted afterwards
+    pub identity_file: Option<String>,
+    /// Hex-encoded static public keys of peers we accept channels from.
+    /// Empty allows any peer. Requires `identity_file`
+    pub allowed_peers: Vec<String>,
+    /// In-memory network node used by `memory://` URLs. Only set by the
+    /// network simulator.
+    pub memory_node: Option<MemoryNode>,
 }
 
 impl Default for Settings {
@@ -89,6 +102,11 @@ impl Default for Settings {
             localnet: false,
             peer_discovery: true,
             channel_log: false,
+            hosts_file: None,
+            ban_file: None,
+            identity_file: None,
+            allowed_peers: Vec::new(),
+            memory_node: None,
         }
     }
 }
@@ -176,6 +194,28 @@ pub struct SettingsOpt {
     #[serde(default)]
     #[structopt(long)]
     pub channel_log: bool,
+
+    /// Path to the file used to persist known hosts across restarts
+    #[serde(default)]
+    #[structopt(long)]
+    pub hosts_file: Option<String>,
+
+    /// Path to the file used to persist banned hosts across restarts
+    #[serde(default)]
+    #[structopt(long)]
+    pub ban_file: Option<String>,
+
+    /// Path to the node's static p2p identity key. When set, every channel
+    /// performs a Noise handshake and is encrypted afterwards
+    #[serde(default)]
+    #[structopt(long)]
+    pub identity_file: Option<String>,
+
+    /// Hex-encoded static public keys of peers we accept channels from.
+    /// Empty allows any peer. Requires `identity_file`
+    #[serde(default)]
+    #[structopt(long)]
+    pub allowed_peers: Vec<String>,
 }
 
 impl From<SettingsOpt> for Settings {
@@ -198,6 +238,11 @@ impl From<SettingsOpt> for Settings {
             localnet: settings_opt.localnet,
             peer_discovery: settings_opt.peer_discovery,
             channel_log: settings_opt.channel_log,
+            hosts_file: settings_opt.hosts_file,
+            ban_file: settings_opt.ban_file,
+            identity_file: settings_opt.identity_file,
+            allowed_peers: settings_opt.allowed_peers,
+            memory_node: None,
         }
     }
 }
@@ -0,0 +1,267 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::time::Duration;
+
+use async_std::sync::{Arc, Mutex};
+use log::{debug, error};
+use smol::Executor;
+use url::Url;
+
+use super::{
+    transport::{LinkConfig, MemoryNetwork, MemoryNetworkPtr, MemoryNode, TransportName},
+    P2p, P2pPtr, Settings,
+};
+use crate::{Error, Result};
+
+/// How long `start_node()` waits for a node to start accepting connections,
+/// on the virtual clock.
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Steps the virtual clock moves by while `start_node()` waits.
+const LISTEN_STEP: Duration = Duration::from_millis(10);
+
+/// Runs a set of `P2p` instances in a single process, connected over an
+/// in-memory network. Latency, loss and partitions are injected through
+/// the network's links, and pass according to its virtual clock.
+///
+/// The nodes' timers (handshake timeouts, outbound retries, pings, gossip
+/// requests) run on the same virtual clock, and the nodes are run on a
+/// single-threaded executor that's only driven, until idle, while the
+/// simulation is stepped. Link behaviour is drawn from an RNG seeded on
+/// creation, so reusing the seed reproduces it. Peer selection still draws
+/// from the OS RNG though, so runs may differ in which peers nodes pick.
+pub struct Simulation {
+    network: MemoryNetworkPtr,
+    executor: Arc<Executor<'static>>,
+    nodes: Mutex<Vec<P2pPtr>>,
+}
+
+impl Simulation {
+    /// Create a new simulation.
+    pub fn new(seed: u64) -> Arc<Self> {
+        Arc::new(Self {
+            network: MemoryNetwork::new(seed),
+            executor: Arc::new(Executor::new()),
+            nodes: Mutex::new(vec![]),
+        })
+    }
+
+    /// Executor the nodes run on. It's driven by the simulation.
+    pub fn executor(&self) -> Arc<Executor<'static>> {
+        self.executor.clone()
+    }
+
+    pub fn network(&self) -> MemoryNetworkPtr {
+        self.network.clone()
+    }
+
+    /// Name of the i-th node on the network.
+    pub fn node_name(i: usize) -> String {
+        format!("node-{}", i)
+    }
+
+    /// URL the i-th node accepts connections on.
+    pub fn node_url(i: usize) -> Url {
+        Url::parse(&format!("memory://{}", Self::node_name(i))).unwrap()
+    }
+
+    /// Default settings for the i-th node: it listens on and advertises
+    /// `memory://node-i`, only dials over the in-memory network and uses
+    /// short timeouts so simulations converge quickly.
+    pub fn settings(&self, i: usize) -> Settings {
+        let name = Self::node_name(i);
+        Settings {
+            inbound: vec![Self::node_url(i)],
+            external_addr: vec![Self::node_url(i)],
+            node_id: name.clone(),
+            outbound_transports: vec![TransportName::Memory(None)],
+            localnet: true,
+            seed_query_timeout_seconds: 2,
+            connect_timeout_seconds: 2,
+            channel_handshake_seconds: 2,
+            outbound_retry_seconds: 1,
+            memory_node: Some(MemoryNode { network: self.network.clone(), name }),
+            ..Default::default()
+        }
+    }
+
+    /// Create a node and add it to the simulation. Protocols can be
+    /// registered on the returned instance before starting it.
+    pub async fn add_node(&self, settings: Settings) -> Result<P2pPtr> {
+        let p2p = P2p::new(settings).await?;
+        self.nodes.lock().await.push(p2p.clone());
+        Ok(p2p)
+    }
+
+    /// Start and run a node. Returns once its seed sync is done and it
+    /// accepts connections, so nodes started afterwards can use it as a seed.
+    /// The virtual clock moves forward while waiting.
+    pub async fn start_node(&self, p2p: P2pPtr) -> Result<()> {
+        let (started_s, started_r) = smol::channel::bounded(1);
+        let ex = self.executor.clone();
+        let p2p2 = p2p.clone();
+        self.executor
+            .spawn(async move {
+                let started = p2p2.clone().start(ex.clone()).await;
+                let failed = started.is_err();
+                started_s.send(started).await.unwrap_or(());
+                if failed {
+                    return
+                }
+
+                if let Err(e) = p2p2.run(ex).await {
+                    error!(target: "net::simulation", "Node failed running: {}", e);
+                }
+            })
+            .detach();
+
+        let settings = p2p.settings();
+        let listening = || match &settings.memory_node {
+            Some(node) if !settings.inbound.is_empty() => self.network.is_listening(&node.name),
+            _ => true,
+        };
+
+        let end = self.now() + LISTEN_TIMEOUT;
+        let mut started = false;
+        loop {
+            self.run_until_idle();
+            if let Ok(result) = started_r.try_recv() {
+                result?;
+                started = true;
+            }
+            if started && listening() {
+                return Ok(())
+            }
+            if self.now() >= end {
+                let addr = settings.inbound.first().map(Url::to_string);
+                return Err(Error::BindFailed(addr.unwrap_or_else(|| settings.node_id.clone())))
+            }
+            self.advance(LISTEN_STEP);
+        }
+    }
+
+    /// Create and start `count` nodes. The first one acts as the seed of
+    /// the others, which all open `outbound` connections.
+    pub async fn spawn_nodes(&self, count: usize, outbound: u32) -> Result<Vec<P2pPtr>> {
+        let mut nodes = vec![];
+        for i in 0..count {
+            let mut settings = self.settings(i);
+            if i > 0 {
+                settings.seeds = vec![Self::node_url(0)];
+                settings.outbound_connections = outbound;
+            }
+            let p2p = self.add_node(settings).await?;
+            self.start_node(p2p.clone()).await?;
+            nodes.push(p2p);
+        }
+        Ok(nodes)
+    }
+
+    pub async fn nodes(&self) -> Vec<P2pPtr> {
+        self.nodes.lock().await.clone()
+    }
+
+    /// Set the link configuration used between all nodes.
+    pub fn set_default_link(&self, config: LinkConfig) {
+        self.network.set_default_link(config);
+    }
+
+    /// Set the link configuration between the i-th and j-th nodes.
+    pub fn set_link(&self, i: usize, j: usize, config: LinkConfig) {
+        self.network.set_link(&Self::node_name(i), &Self::node_name(j), config);
+    }
+
+    /// Partition two groups of nodes, given by index, from each other.
+    pub fn partition(&self, group_a: &[usize], group_b: &[usize]) {
+        let group_a: Vec<String> = group_a.iter().map(|i| Self::node_name(*i)).collect();
+        let group_b: Vec<String> = group_b.iter().map(|i| Self::node_name(*i)).collect();
+        let group_a: Vec<&str> = group_a.iter().map(|n| n.as_str()).collect();
+        let group_b: Vec<&str> = group_b.iter().map(|n| n.as_str()).collect();
+        self.network.partition(&group_a, &group_b);
+    }
+
+    /// Remove all partitions.
+    pub fn heal(&self) {
+        self.network.heal();
+    }
+
+    /// Current time on the virtual clock.
+    pub fn now(&self) -> Duration {
+        self.network.clock().now()
+    }
+
+    /// Advance the virtual clock in one go, without running the nodes.
+    pub fn advance(&self, duration: Duration) {
+        self.network.clock().advance(duration);
+    }
+
+    /// Run the nodes until none of them has anything left to do before the
+    /// virtual clock moves.
+    pub fn run_until_idle(&self) {
+        while self.executor.try_tick() {}
+    }
+
+    /// Advance the virtual clock by `duration`, `step` at a time, running
+    /// the nodes until idle in between steps.
+    pub async fn run_for(&self, duration: Duration, step: Duration) {
+        assert!(!step.is_zero());
+        let end = self.now() + duration;
+        self.run_until_idle();
+        while self.now() < end {
+            self.advance(step.min(end - self.now()));
+            self.run_until_idle();
+        }
+        debug!(target: "net::simulation", "Virtual clock at {:?}", self.now());
+    }
+
+    /// Advance the virtual clock until `condition` holds for every node,
+    /// or until `timeout` of virtual time passed. Returns whether the
+    /// condition was met.
+    pub async fn run_until<F, Fut>(&self, condition: F, timeout: Duration, step: Duration) -> bool
+    where
+        F: Fn(P2pPtr) -> Fut,
+        Fut: std::future::Future<Output = bool>,
+    {
+        let end = self.now() + timeout;
+        loop {
+            let mut done = true;
+            for node in self.nodes().await {
+                if !condition(node).await {
+                    done = false;
+                    break
+                }
+            }
+            if done {
+                return true
+            }
+            if self.now() >= end {
+                return false
+            }
+            self.run_for(step, step).await;
+        }
+    }
+
+    /// Stop all nodes.
+    pub async fn stop(&self) {
+        for node in self.nodes().await {
+            node.stop().await;
+        }
+        self.run_until_idle();
+    }
+}
@@ -37,6 +37,17 @@ pub use tor::TorTransport;
 mod unix;
 pub use unix::UnixTransport;
 
+#[cfg(feature = "websockets")]
+mod nym;
+#[cfg(feature = "websockets")]
+pub use nym::{NymListener, NymStream, NymTransport};
+
+mod memory;
+pub use memory::{
+    LinkConfig, MemoryListener, MemoryNetwork, MemoryNetworkPtr, MemoryNode, MemoryStream,
+    MemoryTransport, VirtualClock,
+};
+
 /// A helper function to convert SocketAddr to Url and add scheme
 pub(crate) fn socket_addr_to_url(addr: SocketAddr, scheme: &str) -> Result<Url> {
     let url = Url::parse(&format!("{}://{}", scheme, addr))?;
@@ -58,6 +69,7 @@ pub enum TransportName {
     Tor(Option<String>),
     Nym(Option<String>),
     Unix,
+    Memory(Option<String>),
 }
 
 impl TransportName {
@@ -70,6 +82,8 @@ impl TransportName {
             Self::Nym(None) => "nym".into(),
             Self::Nym(Some(opt)) => format!("nym+{}", opt),
             Self::Unix => "unix".into(),
+            Self::Memory(None) => "memory".into(),
+            Self::Memory(Some(opt)) => format!("memory+{}", opt),
         }
     }
 }
@@ -86,6 +100,8 @@ impl TryFrom<&str> for TransportName {
             "nym" => Self::Nym(None),
             "nym+tls" => Self::Nym(Some("tls".into())),
             "unix" => Self::Unix,
+            "memory" => Self::Memory(None),
+            "memory+tls" => Self::Memory(Some("tls".into())),
             n => return Err(crate::Error::UnsupportedTransport(n.into())),
         };
         Ok(transport_name)
@@ -0,0 +1,557 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use futures_rustls::{TlsAcceptor, TlsStream};
+use log::debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use url::Url;
+
+use super::{TlsUpgrade, Transport, TransportListener, TransportStream};
+use crate::{Error, Result};
+
+/// Extra delay added each time a write is "lost" on a lossy link,
+/// standing in for a TCP retransmission timeout.
+const RETRANSMIT_DELAY: Duration = Duration::from_millis(200);
+
+/// Maximum number of retransmissions of a single write.
+const MAX_RETRANSMITS: u32 = 8;
+
+/// Atomic pointer to an in-memory network.
+pub type MemoryNetworkPtr = Arc<MemoryNetwork>;
+
+/// Virtual clock of an in-memory network. Data written to a link becomes
+/// readable once the clock reaches its delivery time, and the timers of the
+/// nodes on the network fire once it reaches their deadline, so time only
+/// passes when the clock is advanced.
+pub struct VirtualClock {
+    now: Mutex<Duration>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl VirtualClock {
+    fn new() -> Self {
+        Self { now: Mutex::new(Duration::ZERO), wakers: Mutex::new(vec![]) }
+    }
+
+    /// Time elapsed on the virtual clock since the network was created.
+    pub fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    /// Move the clock forward, waking up readers waiting for delayed data.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+        let wakers: Vec<Waker> = self.wakers.lock().unwrap().drain(..).collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Wait until the clock moved forward by `duration`.
+    pub async fn sleep(&self, duration: Duration) {
+        let deadline = self.now() + duration;
+        future::poll_fn(|cx| {
+            if self.now() >= deadline {
+                return Poll::Ready(())
+            }
+            self.wake_on_advance(cx.waker());
+            // The clock might have moved before the waker got registered
+            if self.now() >= deadline {
+                return Poll::Ready(())
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn wake_on_advance(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+/// Behaviour of the link between two nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkConfig {
+    /// One-way delay of every write
+    pub latency: Duration,
+    /// Random extra delay, up to this value, added to every write
+    pub jitter: Duration,
+    /// Probability of a write getting lost. Streams are reliable, so lost
+    /// writes are retransmitted after `RETRANSMIT_DELAY`, like TCP would.
+    pub loss: f64,
+}
+
+/// One direction of a connection.
+struct Pipe {
+    state: Mutex<PipeState>,
+}
+
+struct PipeState {
+    /// Written chunks along with their delivery time
+    chunks: VecDeque<(Duration, Vec<u8>)>,
+    /// Read position in the front chunk
+    offset: usize,
+    /// Delivery time of the last chunk, so data is never reordered
+    last_delivery: Duration,
+    closed: bool,
+    reader: Option<Waker>,
+}
+
+impl Pipe {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(PipeState {
+                chunks: VecDeque::new(),
+                offset: 0,
+                last_delivery: Duration::ZERO,
+                closed: false,
+                reader: None,
+            }),
+        })
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.reader.take() {
+            waker.wake();
+        }
+    }
+
+    /// Close the pipe, dropping data still in flight.
+    fn cut(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.chunks.clear();
+        state.offset = 0;
+        state.closed = true;
+        if let Some(waker) = state.reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A live connection, kept so partitions can cut it.
+struct Connection {
+    nodes: (String, String),
+    pipes: (Weak<Pipe>, Weak<Pipe>),
+}
+
+/// An in-process network that nodes reach through `memory://<name>` URLs.
+/// Links between nodes can be given latency, jitter and loss, and groups
+/// of nodes can be partitioned from each other. Random decisions come from
+/// a seeded RNG, so a run can be reproduced by reusing the seed.
+pub struct MemoryNetwork {
+    clock: VirtualClock,
+    listeners: Mutex<HashMap<String, smol::channel::Sender<(MemoryStream, Url)>>>,
+    default_link: Mutex<LinkConfig>,
+    links: Mutex<HashMap<(String, String), LinkConfig>>,
+    partitions: Mutex<HashSet<(String, String)>>,
+    connections: Mutex<Vec<Connection>>,
+    rng: Mutex<StdRng>,
+    next_port: Mutex<u16>,
+}
+
+impl fmt::Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let listeners: Vec<String> = self.listeners.lock().unwrap().keys().cloned().collect();
+        f.debug_struct("MemoryNetwork").field("listeners", &listeners).finish()
+    }
+}
+
+impl MemoryNetwork {
+    pub fn new(seed: u64) -> Arc<Self> {
+        Arc::new(Self {
+            clock: VirtualClock::new(),
+            listeners: Mutex::new(HashMap::new()),
+            default_link: Mutex::new(LinkConfig::default()),
+            links: Mutex::new(HashMap::new()),
+            partitions: Mutex::new(HashSet::new()),
+            connections: Mutex::new(vec![]),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            next_port: Mutex::new(1024),
+        })
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Set the link configuration used between nodes without a specific one.
+    pub fn set_default_link(&self, config: LinkConfig) {
+        *self.default_link.lock().unwrap() = config;
+    }
+
+    /// Set the link configuration between two nodes, in both directions.
+    pub fn set_link(&self, a: &str, b: &str, config: LinkConfig) {
+        let mut links = self.links.lock().unwrap();
+        links.insert((a.to_string(), b.to_string()), config);
+        links.insert((b.to_string(), a.to_string()), config);
+    }
+
+    /// Partition two groups of nodes from each other. Connections between
+    /// them are cut, and new ones are refused until `heal()` is called.
+    pub fn partition(&self, group_a: &[&str], group_b: &[&str]) {
+        {
+            let mut partitions = self.partitions.lock().unwrap();
+            for a in group_a {
+                for b in group_b {
+                    partitions.insert((a.to_string(), b.to_string()));
+                    partitions.insert((b.to_string(), a.to_string()));
+                }
+            }
+        }
+
+        let partitions = self.partitions.lock().unwrap();
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|conn| {
+            if !partitions.contains(&conn.nodes) {
+                return conn.pipes.0.strong_count() > 0
+            }
+            debug!(target: "net::memory", "Cutting connection {} <-> {}", conn.nodes.0, conn.nodes.1);
+            if let Some(pipe) = conn.pipes.0.upgrade() {
+                pipe.cut();
+            }
+            if let Some(pipe) = conn.pipes.1.upgrade() {
+                pipe.cut();
+            }
+            false
+        });
+    }
+
+    /// Whether a node is currently accepting connections.
+    pub fn is_listening(&self, name: &str) -> bool {
+        self.listeners.lock().unwrap().contains_key(name)
+    }
+
+    /// Remove all partitions.
+    pub fn heal(&self) {
+        self.partitions.lock().unwrap().clear();
+    }
+
+    fn is_partitioned(&self, a: &str, b: &str) -> bool {
+        self.partitions.lock().unwrap().contains(&(a.to_string(), b.to_string()))
+    }
+
+    /// Time at which a write from `from` to `to` made now gets delivered.
+    fn delivery_time(&self, from: &str, to: &str) -> Duration {
+        let link = match self.links.lock().unwrap().get(&(from.to_string(), to.to_string())) {
+            Some(link) => *link,
+            None => *self.default_link.lock().unwrap(),
+        };
+
+        let mut delay = link.latency;
+        let mut rng = self.rng.lock().unwrap();
+        if !link.jitter.is_zero() {
+            delay += link.jitter.mul_f64(rng.gen::<f64>());
+        }
+        let mut retransmits = 0;
+        while link.loss > 0.0 && retransmits < MAX_RETRANSMITS && rng.gen::<f64>() < link.loss {
+            delay += RETRANSMIT_DELAY;
+            retransmits += 1;
+        }
+
+        self.clock.now() + delay
+    }
+
+    fn listen(self: &Arc<Self>, url: &Url) -> Result<MemoryListener> {
+        let name = url_to_node_name(url)?;
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(&name) {
+            return Err(Error::BindFailed(url.to_string()))
+        }
+
+        let (sender, incoming) = smol::channel::unbounded();
+        listeners.insert(name.clone(), sender);
+        debug!(target: "net::memory", "Listening on {}", url);
+        Ok(MemoryListener { name, network: self.clone(), incoming })
+    }
+
+    fn dial(self: &Arc<Self>, from: &str, url: &Url) -> Result<MemoryStream> {
+        let to = url_to_node_name(url)?;
+        if self.is_partitioned(from, &to) {
+            debug!(target: "net::memory", "{} can't reach {}: partitioned", from, to);
+            return Err(Error::ConnectFailed)
+        }
+
+        let listener = match self.listeners.lock().unwrap().get(&to) {
+            Some(listener) => listener.clone(),
+            None => return Err(Error::ConnectFailed),
+        };
+
+        let port = {
+            let mut next_port = self.next_port.lock().unwrap();
+            let port = *next_port;
+            *next_port = next_port.checked_add(1).unwrap_or(1024);
+            port
+        };
+
+        let outgoing = Pipe::new();
+        let incoming = Pipe::new();
+
+        self.connections.lock().unwrap().push(Connection {
+            nodes: (from.to_string(), to.clone()),
+            pipes: (Arc::downgrade(&outgoing), Arc::downgrade(&incoming)),
+        });
+
+        let dialer = MemoryStream {
+            local: from.to_string(),
+            remote: to.clone(),
+            read: incoming.clone(),
+            write: outgoing.clone(),
+            network: self.clone(),
+        };
+        let accepted = MemoryStream {
+            local: to,
+            remote: from.to_string(),
+            read: outgoing,
+            write: incoming,
+            network: self.clone(),
+        };
+
+        let peer_url = Url::parse(&format!("memory://{}:{}", from, port))?;
+        if listener.try_send((accepted, peer_url)).is_err() {
+            return Err(Error::ConnectFailed)
+        }
+
+        Ok(dialer)
+    }
+}
+
+/// Extract the node name out of a `memory://<name>` URL.
+fn url_to_node_name(url: &Url) -> Result<String> {
+    match url.host_str() {
+        Some(name) => Ok(name.to_string()),
+        None => Err(Error::InvalidDialerScheme),
+    }
+}
+
+/// A node attached to an in-memory network. Set in the network settings
+/// to let a P2p instance use `memory://` URLs.
+#[derive(Clone)]
+pub struct MemoryNode {
+    pub network: MemoryNetworkPtr,
+    /// Name other nodes see as our address when we dial them
+    pub name: String,
+}
+
+impl fmt::Debug for MemoryNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryNode").field("name", &self.name).finish()
+    }
+}
+
+/// One end of an in-memory connection.
+pub struct MemoryStream {
+    local: String,
+    remote: String,
+    read: Arc<Pipe>,
+    write: Arc<Pipe>,
+    network: MemoryNetworkPtr,
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.read.state.lock().unwrap();
+        loop {
+            let delivery = match state.chunks.front() {
+                Some((delivery, _)) => *delivery,
+                // Writer is gone, signal EOF
+                None if state.closed => return Poll::Ready(Ok(0)),
+                None => {
+                    state.reader = Some(cx.waker().clone());
+                    return Poll::Pending
+                }
+            };
+
+            if delivery > self.network.clock.now() {
+                // Register before checking again, so an advance in
+                // between does not get lost.
+                self.network.clock.wake_on_advance(cx.waker());
+                if delivery > self.network.clock.now() {
+                    state.reader = Some(cx.waker().clone());
+                    return Poll::Pending
+                }
+                continue
+            }
+
+            let offset = state.offset;
+            let (_, chunk) = state.chunks.front().unwrap();
+            let n = buf.len().min(chunk.len() - offset);
+            buf[..n].copy_from_slice(&chunk[offset..offset + n]);
+            if offset + n == chunk.len() {
+                state.chunks.pop_front();
+                state.offset = 0;
+            } else {
+                state.offset += n;
+            }
+            return Poll::Ready(Ok(n))
+        }
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let delivery = self.network.delivery_time(&self.local, &self.remote);
+
+        let mut state = self.write.state.lock().unwrap();
+        if state.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        }
+
+        state.last_delivery = state.last_delivery.max(delivery);
+        let delivery = state.last_delivery;
+        state.chunks.push_back((delivery, buf.to_vec()));
+        if let Some(waker) = state.reader.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.write.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.write.close();
+        self.read.close();
+    }
+}
+
+impl TransportStream for MemoryStream {}
+
+/// Listener accepting connections dialed to a `memory://<name>` URL.
+pub struct MemoryListener {
+    name: String,
+    network: MemoryNetworkPtr,
+    incoming: smol::channel::Receiver<(MemoryStream, Url)>,
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.network.listeners.lock().unwrap().remove(&self.name);
+    }
+}
+
+#[async_trait]
+impl TransportListener for MemoryListener {
+    async fn next(&self) -> Result<(Box<dyn TransportStream>, Url)> {
+        match self.incoming.recv().await {
+            Ok((stream, url)) => Ok((Box::new(stream), url)),
+            Err(_) => Err(Error::AcceptConnectionFailed(self.name.clone())),
+        }
+    }
+}
+
+#[async_trait]
+impl TransportListener for (TlsAcceptor, MemoryListener) {
+    async fn next(&self) -> Result<(Box<dyn TransportStream>, Url)> {
+        let (stream, url) = match self.1.incoming.recv().await {
+            Ok(s) => s,
+            Err(_) => return Err(Error::AcceptConnectionFailed(self.1.name.clone())),
+        };
+
+        match self.0.accept(stream).await {
+            Ok(stream) => Ok((Box::new(TlsStream::Server(stream)), url)),
+            Err(_) => Err(Error::AcceptTlsConnectionFailed(self.1.name.clone())),
+        }
+    }
+}
+
+/// Transport over an in-process [`MemoryNetwork`], used to simulate
+/// networks of nodes in tests without touching the OS network stack.
+#[derive(Clone)]
+pub struct MemoryTransport {
+    node: MemoryNode,
+}
+
+impl MemoryTransport {
+    pub fn new(node: MemoryNode) -> Self {
+        Self { node }
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Acceptor = MemoryListener;
+    type Connector = MemoryStream;
+
+    type Listener = Pin<Box<dyn Future<Output = Result<Self::Acceptor>> + Send>>;
+    type Dial = Pin<Box<dyn Future<Output = Result<Self::Connector>> + Send>>;
+
+    type TlsListener = Pin<Box<dyn Future<Output = Result<(TlsAcceptor, Self::Acceptor)>> + Send>>;
+    type TlsDialer = Pin<Box<dyn Future<Output = Result<TlsStream<Self::Connector>>> + Send>>;
+
+    fn listen_on(self, url: Url) -> Result<Self::Listener> {
+        match url.scheme() {
+            "memory" | "memory+tls" => {}
+            x => return Err(Error::UnsupportedTransport(x.to_string())),
+        }
+
+        let listener = self.node.network.listen(&url);
+        Ok(Box::pin(async move { listener }))
+    }
+
+    fn upgrade_listener(self, acceptor: Self::Acceptor) -> Result<Self::TlsListener> {
+        let tlsupgrade = TlsUpgrade::new();
+        Ok(Box::pin(tlsupgrade.upgrade_listener_tls(acceptor)))
+    }
+
+    fn dial(self, url: Url, _timeout: Option<Duration>) -> Result<Self::Dial> {
+        match url.scheme() {
+            "memory" | "memory+tls" => {}
+            x => return Err(Error::UnsupportedTransport(x.to_string())),
+        }
+
+        let stream = self.node.network.dial(&self.node.name, &url);
+        Ok(Box::pin(async move { stream }))
+    }
+
+    fn upgrade_dialer(self, connector: Self::Connector) -> Result<Self::TlsDialer> {
+        let tlsupgrade = TlsUpgrade::new();
+        Ok(Box::pin(tlsupgrade.upgrade_dialer_tls(connector)))
+    }
+}
@@ -0,0 +1,980 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Mutex, Weak},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_std::{net::TcpStream, sync::Arc};
+use async_trait::async_trait;
+use async_tungstenite::tungstenite::Message;
+use darkfi_serial::{deserialize, serialize, SerialDecodable, SerialEncodable};
+use futures::{prelude::*, SinkExt, StreamExt};
+use futures_rustls::{TlsAcceptor, TlsStream};
+use lazy_static::lazy_static;
+use log::{debug, error, warn};
+use rand::Rng;
+use serde_json::{json, Value};
+use url::Url;
+
+use super::{TlsUpgrade, Transport, TransportListener, TransportStream};
+use crate::{Error, Result};
+
+/// Maximum amount of bytes we put in a single mixnet message.
+/// The nym client splits messages into sphinx packets on its own,
+/// this only bounds how much we buffer per frame.
+const NYM_MAX_PAYLOAD: usize = 64 * 1024;
+
+/// Maximum amount of data frames a stream buffers ahead of its reader,
+/// either queued or received out of order.
+const NYM_MAX_PENDING_FRAMES: u64 = 256;
+
+/// Maximum amount of inbound connections waiting for their dialer to
+/// complete the handshake.
+const NYM_MAX_HANDSHAKES: usize = 64;
+
+/// Time an inbound connection is given to complete the handshake before
+/// its slot is handed to another one. Mixnet roundtrips take seconds.
+const NYM_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum amount of websocket messages queued for the nym-client. Writers
+/// wait for room in the queue once it's full.
+const NYM_MAX_OUTGOING_FRAMES: usize = 64;
+
+lazy_static! {
+    /// Open websocket sessions, keyed by nym-client address. A nym-client
+    /// serves a single websocket connection, so all the streams dialed or
+    /// accepted through it share one session.
+    static ref SESSIONS: async_std::sync::Mutex<HashMap<SocketAddr, Weak<NymSession>>> =
+        async_std::sync::Mutex::new(HashMap::new());
+}
+
+/// Frame types exchanged between darkfi nodes through the mixnet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum FrameKind {
+    Connect = 0x00,
+    Data = 0x01,
+    Close = 0x02,
+    Accept = 0x03,
+    Ack = 0x04,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = Error;
+
+    fn try_from(kind: u8) -> Result<Self> {
+        match kind {
+            0x00 => Ok(Self::Connect),
+            0x01 => Ok(Self::Data),
+            0x02 => Ok(Self::Close),
+            0x03 => Ok(Self::Accept),
+            0x04 => Ok(Self::Ack),
+            _ => Err(Error::NymError(format!("Unknown frame kind {}", kind))),
+        }
+    }
+}
+
+/// A frame carried inside a mixnet message. Since the mixnet is
+/// message-oriented and can reorder messages, frames carry a connection
+/// id and a sequence number so we can build ordered streams on top.
+///
+/// The mixnet doesn't authenticate senders, so connections start with a
+/// handshake: the dialer sends a `Connect` frame with a random connection
+/// id, which only the owner of the dialed address receives. The listener
+/// replies with an `Accept` frame holding a random token, which only the
+/// owner of the claimed sender address receives, and the dialer echoes it
+/// back in an `Ack` frame. All the frames of the connection then carry the
+/// token, and frames with a wrong one are dropped. As the dialer writes
+/// right after sending the `Ack`, its first frames can arrive before it,
+/// so any frame with the right token completes the handshake.
+///
+/// `Close` frames carry the amount of data frames sent, so the stream
+/// ends once all of them are read, even if some arrive after the `Close`.
+#[derive(SerialEncodable, SerialDecodable)]
+struct NymFrame {
+    kind: u8,
+    /// Connection id, picked at random by the dialing side
+    conn_id: u64,
+    /// Connection token, picked at random by the listening side
+    token: u64,
+    /// Nym address of the sender, used to reply
+    sender: String,
+    /// Sequence number of data frames, or amount of data frames sent
+    /// for `Close` frames
+    seq: u64,
+    payload: Vec<u8>,
+}
+
+/// Frame queued to a stream: a data frame with its sequence number, or
+/// the amount of data frames sent with no data once the remote closed.
+type InboundFrame = (u64, Option<Vec<u8>>);
+
+/// Sender half of a connection's inbound frame queue
+type FrameSender = smol::channel::Sender<InboundFrame>;
+
+/// Queueing of a websocket message waiting for room in the outgoing queue,
+/// resolving to `false` if the session got closed meanwhile.
+type PendingSend = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+/// An open stream of a session.
+struct Connection {
+    /// Token the frames of the connection must carry
+    token: u64,
+    /// Inbound frame queue of the stream
+    frames: FrameSender,
+}
+
+/// A websocket session with a local nym-client. Multiplexes all the
+/// streams opened over it, and accepts inbound streams while listening.
+struct NymSession {
+    /// Our own nym address, as reported by the nym-client
+    self_address: String,
+    /// Queue of websocket messages to write to the nym-client
+    outgoing: smol::channel::Sender<Message>,
+    /// Open streams, keyed by remote address and conn id
+    connections: Mutex<HashMap<(String, u64), Connection>>,
+    /// Dials waiting for the connection token, keyed the same
+    dials: Mutex<HashMap<(String, u64), smol::channel::Sender<u64>>>,
+    /// Tokens sent to inbound connections waiting for the dialer's `Ack`,
+    /// along with the deadline for it
+    handshakes: Mutex<HashMap<(String, u64), (u64, Instant)>>,
+    /// Queue of accepted streams, only set while listening
+    accept: Mutex<Option<smol::channel::Sender<NymStream>>>,
+}
+
+impl NymSession {
+    /// Get the session with the nym-client at `client_url`, opening one if
+    /// there is none yet.
+    async fn get(client_url: &Url) -> Result<Arc<Self>> {
+        let socket_addr = client_url.socket_addrs(|| None)?[0];
+
+        let mut sessions = SESSIONS.lock().await;
+        if let Some(session) = sessions.get(&socket_addr).and_then(|x| x.upgrade()) {
+            if !session.outgoing.is_closed() {
+                return Ok(session)
+            }
+        }
+
+        sessions.retain(|_, x| x.strong_count() > 0);
+        let session = Self::connect(socket_addr).await?;
+        sessions.insert(socket_addr, Arc::downgrade(&session));
+        Ok(session)
+    }
+
+    /// Open a websocket session with the nym-client at `socket_addr`, query our
+    /// own address and spawn the background reader and writer tasks. The session
+    /// gets closed once all its streams and listener are dropped.
+    async fn connect(socket_addr: SocketAddr) -> Result<Arc<Self>> {
+        let ws_url = format!("ws://{}", socket_addr);
+        debug!(target: "net::nym", "Connecting to nym-client at {}", ws_url);
+
+        let stream = TcpStream::connect(socket_addr).await?;
+        let (ws, _) = async_tungstenite::client_async(ws_url, stream).await?;
+        let (mut sink, mut stream) = ws.split();
+
+        // Ask the client for our own address
+        sink.send(Message::Text(json!({"type": "selfAddress"}).to_string())).await?;
+        let self_address = loop {
+            let reply = match stream.next().await {
+                Some(msg) => msg?,
+                None => return Err(Error::NymError("nym-client closed the connection".into())),
+            };
+
+            if let Message::Text(text) = reply {
+                let reply: Value = serde_json::from_str(&text)?;
+                match reply["type"].as_str() {
+                    Some("selfAddress") => match reply["address"].as_str() {
+                        Some(addr) => break addr.to_string(),
+                        None => return Err(Error::NymError("Invalid selfAddress reply".into())),
+                    },
+                    Some("error") => return Err(Error::NymError(reply["message"].to_string())),
+                    _ => continue,
+                }
+            }
+        };
+        debug!(target: "net::nym", "Our nym address: {}", self_address);
+
+        let (outgoing, outgoing_recv) = smol::channel::bounded(NYM_MAX_OUTGOING_FRAMES);
+
+        let session = Arc::new(Self {
+            self_address,
+            outgoing,
+            connections: Mutex::new(HashMap::new()),
+            dials: Mutex::new(HashMap::new()),
+            handshakes: Mutex::new(HashMap::new()),
+            accept: Mutex::new(None),
+        });
+
+        // Writer task, ends (closing the websocket) when the session is closed
+        // or dropped
+        smol::spawn(async move {
+            while let Ok(msg) = outgoing_recv.recv().await {
+                if let Err(e) = sink.send(msg).await {
+                    error!(target: "net::nym", "Failed writing to nym-client: {}", e);
+                    break
+                }
+            }
+            let _ = sink.close().await;
+        })
+        .detach();
+
+        // Reader task, ends when the websocket is closed. It doesn't keep the
+        // session alive.
+        let session_ = Arc::downgrade(&session);
+        smol::spawn(async move {
+            while let Some(msg) = stream.next().await {
+                let text = match msg {
+                    Ok(Message::Text(text)) => text,
+                    Ok(Message::Close(_)) => break,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        error!(target: "net::nym", "Failed reading from nym-client: {}", e);
+                        break
+                    }
+                };
+
+                let Some(session) = session_.upgrade() else { break };
+                if let Err(e) = session.handle_message(&text).await {
+                    warn!(target: "net::nym", "Dropping invalid mixnet message: {}", e);
+                }
+            }
+
+            // Signal EOF to all the streams and pending dials of this session
+            if let Some(session) = session_.upgrade() {
+                session.connections.lock().unwrap().clear();
+                session.dials.lock().unwrap().clear();
+                session.outgoing.close();
+            }
+        })
+        .detach();
+
+        Ok(session)
+    }
+
+    /// Handle a websocket message received from the nym-client.
+    async fn handle_message(self: Arc<Self>, text: &str) -> Result<()> {
+        let msg: Value = serde_json::from_str(text)?;
+        match msg["type"].as_str() {
+            Some("received") => {}
+            Some("error") => return Err(Error::NymError(msg["message"].to_string())),
+            _ => return Ok(()),
+        }
+
+        let message = match msg["message"].as_str() {
+            Some(m) => m,
+            None => return Err(Error::NymError("Received message without content".into())),
+        };
+
+        let frame: NymFrame = deserialize(&hex::decode(message)?)?;
+        let key = (frame.sender.clone(), frame.conn_id);
+
+        match FrameKind::try_from(frame.kind)? {
+            FrameKind::Connect => {
+                if self.accept.lock().unwrap().is_none() {
+                    return Ok(())
+                }
+
+                let token = rand::thread_rng().gen();
+                {
+                    let mut handshakes = self.handshakes.lock().unwrap();
+                    // Handshakes never completed don't hold their slot forever
+                    let now = Instant::now();
+                    handshakes.retain(|_, (_, deadline)| *deadline > now);
+                    if handshakes.len() >= NYM_MAX_HANDSHAKES && !handshakes.contains_key(&key) {
+                        return Err(Error::NymError("Too many pending inbound connections".into()))
+                    }
+                    handshakes.insert(key, (token, now + NYM_HANDSHAKE_TIMEOUT));
+                }
+
+                debug!(target: "net::nym", "Inbound connection {} from {}", frame.conn_id, frame.sender);
+                let accept = self.frame(FrameKind::Accept, frame.conn_id, token);
+                self.send_frame(&frame.sender, &accept)?;
+            }
+            FrameKind::Accept => {
+                let dial = self.dials.lock().unwrap().remove(&key);
+                match dial {
+                    Some(d) => {
+                        let _ = d.try_send(frame.token);
+                    }
+                    None => {
+                        debug!(target: "net::nym", "Accept for unknown connection {}", frame.conn_id)
+                    }
+                }
+            }
+            FrameKind::Ack => {
+                // Frames sent after the Ack might have completed the handshake
+                if !self.complete_handshake(&frame).await? {
+                    let connections = self.connections.lock().unwrap();
+                    if connections.get(&key).map_or(true, |conn| conn.token != frame.token) {
+                        return Err(Error::NymError("Invalid connection token".into()))
+                    }
+                }
+            }
+            kind @ (FrameKind::Data | FrameKind::Close) => {
+                // The mixnet can deliver the dialer's first frames before its Ack
+                let known = self.connections.lock().unwrap().contains_key(&key);
+                if !known && !self.complete_handshake(&frame).await? {
+                    debug!(target: "net::nym", "Frame for unknown connection {}", frame.conn_id);
+                    return Ok(())
+                }
+
+                let mut connections = self.connections.lock().unwrap();
+                let Some(conn) = connections.get(&key) else { return Ok(()) };
+
+                if conn.token != frame.token {
+                    return Err(Error::NymError("Invalid connection token".into()))
+                }
+
+                // The stream ends once it has read all the data frames sent
+                // before the Close, which might still be on their way.
+                let payload = match kind {
+                    FrameKind::Close => None,
+                    _ => Some(frame.payload),
+                };
+
+                // We don't wait on slow readers, which would stall all the
+                // streams of the session, and drop their connection instead.
+                if conn.frames.try_send((frame.seq, payload)).is_err() {
+                    connections.remove(&key);
+                    return Err(Error::NymError(format!("Connection {} is full", frame.conn_id)))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Complete the handshake of the inbound connection the given frame
+    /// belongs to, if it's pending, and hand its stream to the listener.
+    /// Returns `false` if there is no such handshake.
+    async fn complete_handshake(self: &Arc<Self>, frame: &NymFrame) -> Result<bool> {
+        let key = (frame.sender.clone(), frame.conn_id);
+        {
+            let mut handshakes = self.handshakes.lock().unwrap();
+            match handshakes.remove(&key) {
+                Some((token, deadline)) if token == frame.token => {
+                    if deadline <= Instant::now() {
+                        return Err(Error::NymError("Handshake timed out".into()))
+                    }
+                }
+                Some(handshake) => {
+                    handshakes.insert(key, handshake);
+                    return Err(Error::NymError("Invalid connection token".into()))
+                }
+                None => return Ok(false),
+            }
+        }
+
+        let accept = self.accept.lock().unwrap().clone();
+        let Some(accept) = accept else { return Ok(false) };
+        let stream = self.clone().open_stream(frame.sender.clone(), frame.conn_id, frame.token);
+        if accept.send(stream).await.is_err() {
+            return Err(Error::NymError("Listener is closed".into()))
+        }
+
+        Ok(true)
+    }
+
+    /// Build a handshake frame of the given connection.
+    fn frame(&self, kind: FrameKind, conn_id: u64, token: u64) -> NymFrame {
+        NymFrame {
+            kind: kind as u8,
+            conn_id,
+            token,
+            sender: self.self_address.clone(),
+            seq: 0,
+            payload: vec![],
+        }
+    }
+
+    /// Register a new stream with the given remote, connection id and token.
+    fn open_stream(self: Arc<Self>, remote: String, conn_id: u64, token: u64) -> NymStream {
+        let (frames, inbound) = smol::channel::bounded(NYM_MAX_PENDING_FRAMES as usize);
+        self.connections
+            .lock()
+            .unwrap()
+            .insert((remote.clone(), conn_id), Connection { token, frames });
+
+        NymStream {
+            session: self,
+            remote,
+            conn_id,
+            token,
+            inbound,
+            pending: BTreeMap::new(),
+            read_buf: Vec::new(),
+            read_pos: 0,
+            next_recv_seq: 0,
+            end_seq: None,
+            next_send_seq: 0,
+            sending: Mutex::new(None),
+        }
+    }
+
+    /// Build the websocket message sending a frame to the given recipient
+    /// through the mixnet.
+    fn frame_message(recipient: &str, frame: &NymFrame) -> Message {
+        let request = json!({
+            "type": "send",
+            "recipient": recipient,
+            "message": hex::encode(serialize(frame)),
+            "withReplySurb": false,
+        });
+
+        Message::Text(request.to_string())
+    }
+
+    /// Queue a frame to be sent to the given recipient through the mixnet.
+    /// Fails if the outgoing queue is full, see [`NymStream`] for writes
+    /// waiting for room instead.
+    fn send_frame(&self, recipient: &str, frame: &NymFrame) -> io::Result<()> {
+        self.outgoing
+            .try_send(Self::frame_message(recipient, frame))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Stream of a connection routed through the mixnet.
+pub struct NymStream {
+    session: Arc<NymSession>,
+    /// Nym address of the remote end
+    remote: String,
+    conn_id: u64,
+    token: u64,
+    inbound: smol::channel::Receiver<InboundFrame>,
+    /// Data frames received ahead of order
+    pending: BTreeMap<u64, Vec<u8>>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    next_recv_seq: u64,
+    /// Amount of data frames the remote sent before closing, once it did
+    end_seq: Option<u64>,
+    next_send_seq: u64,
+    /// Data frame waiting for room in the session's outgoing queue. Further
+    /// writes are held back until it's queued. The mutex only keeps the
+    /// stream `Sync`, it's always accessed through `&mut self`.
+    sending: Mutex<Option<PendingSend>>,
+}
+
+impl NymStream {
+    fn frame(&self, kind: FrameKind, seq: u64, payload: Vec<u8>) -> NymFrame {
+        NymFrame {
+            kind: kind as u8,
+            conn_id: self.conn_id,
+            token: self.token,
+            sender: self.session.self_address.clone(),
+            seq,
+            payload,
+        }
+    }
+
+    /// Send a `Close` frame with the amount of data frames sent.
+    fn send_close(&mut self) {
+        // A data frame still held back never gets sent
+        let held_back = self.sending.get_mut().unwrap().is_some();
+        let sent = self.next_send_seq - held_back as u64;
+        let frame = self.frame(FrameKind::Close, sent, vec![]);
+        let _ = self.session.send_frame(&self.remote, &frame);
+    }
+
+    /// Wait for the data frame held back by a full outgoing queue, if any,
+    /// to get queued.
+    fn poll_sending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let sending = self.sending.get_mut().unwrap();
+        if let Some(send) = sending {
+            let sent = ready!(send.as_mut().poll(cx));
+            *sending = None;
+            if !sent {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)))
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for NymStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            // Serve buffered data first
+            if self.read_pos < self.read_buf.len() {
+                let n = buf.len().min(self.read_buf.len() - self.read_pos);
+                let start = self.read_pos;
+                buf[..n].copy_from_slice(&self.read_buf[start..start + n]);
+                self.read_pos += n;
+                return Poll::Ready(Ok(n))
+            }
+
+            // Then data that arrived ahead of order
+            let next = self.next_recv_seq;
+            if let Some(data) = self.pending.remove(&next) {
+                self.read_buf = data;
+                self.read_pos = 0;
+                self.next_recv_seq += 1;
+                continue
+            }
+
+            // The remote closed and we read all it sent, signal EOF
+            if self.end_seq.map_or(false, |end| self.next_recv_seq >= end) {
+                return Poll::Ready(Ok(0))
+            }
+
+            match self.inbound.poll_next_unpin(cx) {
+                Poll::Ready(Some((seq, None))) => {
+                    self.end_seq = Some(self.end_seq.map_or(seq, |end| end.min(seq)));
+                }
+                Poll::Ready(Some((seq, Some(data)))) => {
+                    // Frames too far ahead would have us buffer without bound
+                    if seq >= self.next_recv_seq + NYM_MAX_PENDING_FRAMES {
+                        let e = io::Error::new(io::ErrorKind::InvalidData, "Frame too far ahead");
+                        return Poll::Ready(Err(e))
+                    }
+                    if seq >= self.next_recv_seq && self.end_seq.map_or(true, |end| seq < end) {
+                        self.pending.insert(seq, data);
+                    }
+                }
+                // Connection closed, signal EOF
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for NymStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Backpressure: we hold at most one frame the queue had no room for
+        ready!(this.poll_sending(cx))?;
+
+        let n = buf.len().min(NYM_MAX_PAYLOAD);
+        let frame = this.frame(FrameKind::Data, this.next_send_seq, buf[..n].to_vec());
+        let msg = NymSession::frame_message(&this.remote, &frame);
+        match this.session.outgoing.try_send(msg) {
+            Ok(()) => {}
+            Err(smol::channel::TrySendError::Full(msg)) => {
+                let outgoing = this.session.outgoing.clone();
+                let send = Box::pin(async move { outgoing.send(msg).await.is_ok() });
+                *this.sending.get_mut().unwrap() = Some(send);
+            }
+            Err(smol::channel::TrySendError::Closed(_)) => {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)))
+            }
+        }
+
+        this.next_send_seq += 1;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_sending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // The session might already be gone, which is fine when closing.
+        let _ = ready!(this.poll_sending(cx));
+        this.send_close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for NymStream {
+    fn drop(&mut self) {
+        self.send_close();
+        self.session.connections.lock().unwrap().remove(&(self.remote.clone(), self.conn_id));
+    }
+}
+
+impl TransportStream for NymStream {}
+
+/// Listener accepting connections routed to our nym address.
+pub struct NymListener {
+    session: Arc<NymSession>,
+    incoming: smol::channel::Receiver<NymStream>,
+}
+
+impl NymListener {
+    /// Returns the nym address peers can dial to reach this listener.
+    pub fn address(&self) -> Result<Url> {
+        nym_address_to_url(&self.session.self_address, "nym")
+    }
+}
+
+impl Drop for NymListener {
+    fn drop(&mut self) {
+        *self.session.accept.lock().unwrap() = None;
+        self.session.handshakes.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl TransportListener for NymListener {
+    async fn next(&self) -> Result<(Box<dyn TransportStream>, Url)> {
+        let stream = match self.incoming.recv().await {
+            Ok(s) => s,
+            Err(_) => {
+                error!(target: "net::nym", "nym-client session closed");
+                return Err(Error::AcceptConnectionFailed(self.session.self_address.clone()))
+            }
+        };
+
+        let url = nym_address_to_url(&stream.remote, "nym")?;
+        Ok((Box::new(stream), url))
+    }
+}
+
+#[async_trait]
+impl TransportListener for (TlsAcceptor, NymListener) {
+    async fn next(&self) -> Result<(Box<dyn TransportStream>, Url)> {
+        let stream = match self.1.incoming.recv().await {
+            Ok(s) => s,
+            Err(_) => {
+                error!(target: "net::nym", "nym-client session closed");
+                return Err(Error::AcceptConnectionFailed(self.1.session.self_address.clone()))
+            }
+        };
+
+        let url = nym_address_to_url(&stream.remote, "nym+tls")?;
+
+        let stream = self.0.accept(stream).await;
+        if let Err(err) = stream {
+            error!(target: "net::nym", "Error wrapping the connection {} with tls: {}", url, err);
+            return Err(Error::AcceptTlsConnectionFailed(self.1.session.self_address.clone()))
+        }
+
+        Ok((Box::new(TlsStream::Server(stream?)), url))
+    }
+}
+
+/// Converts a nym address (`<identity>.<encryption>@<gateway>`) into an Url
+fn nym_address_to_url(address: &str, scheme: &str) -> Result<Url> {
+    Ok(Url::parse(&format!("{}://{}", scheme, address))?)
+}
+
+/// Recovers the nym address (`<identity>.<encryption>@<gateway>`) out of an Url
+fn url_to_nym_address(url: &Url) -> Result<String> {
+    match url.host_str() {
+        Some(gateway) if !url.username().is_empty() => {
+            Ok(format!("{}@{}", url.username(), gateway))
+        }
+        _ => Err(Error::NymError(format!("Invalid nym address: {}", url))),
+    }
+}
+
+/// Implements communication through the Nym mixnet.
+///
+/// A local [nym-client](https://nymtech.net/docs/clients/websocket-client.html)
+/// must be running, we talk to it through its websocket interface.
+///
+/// ## Dialing
+///
+/// Dialing opens a stream over the websocket session with the nym-client
+/// passed to the constructor, which is shared by all the streams through
+/// that client. Remote addresses are nym addresses in the form of
+/// `nym://<identity>.<encryption>@<gateway>`.
+///
+/// ## Listening
+///
+/// The Url passed to [listen_on][transportlisten] is the websocket address of
+/// the nym-client used for inbound connections, e.g. `nym://127.0.0.1:1977`.
+/// Once listening, [`NymListener::address`] returns the nym address that
+/// peers should dial to reach us.
+///
+/// [transportlisten]: Transport
+#[derive(Clone)]
+pub struct NymTransport {
+    /// Websocket address of the nym-client used for dialing
+    client_url: Url,
+}
+
+impl NymTransport {
+    /// Creates a new NymTransport
+    ///
+    /// # Arguments
+    ///
+    /// * `client_url` - websocket url of the nym-client used for dialing.
+    /// For example ws://127.0.0.1:1977
+    pub fn new(client_url: Url) -> Self {
+        Self { client_url }
+    }
+
+    /// Query the environment for the nym-client websocket url, or fallback to defaults
+    pub fn get_dialer_env() -> Result<Url> {
+        Ok(Url::parse(
+            &std::env::var("DARKFI_NYM_CLIENT_URL")
+                .unwrap_or_else(|_| "ws://127.0.0.1:1977".to_string()),
+        )?)
+    }
+
+    pub async fn do_dial(self, url: Url) -> Result<NymStream> {
+        let recipient = url_to_nym_address(&url)?;
+        let session = NymSession::get(&self.client_url).await?;
+
+        // Wait for the recipient to send us the connection token, see NymFrame
+        let conn_id = rand::thread_rng().gen();
+        let (accepted, accepted_recv) = smol::channel::bounded(1);
+        {
+            let mut dials = session.dials.lock().unwrap();
+            // Drop the dials that were given up on
+            dials.retain(|_, x| !x.is_closed());
+            dials.insert((recipient.clone(), conn_id), accepted);
+        }
+
+        session.send_frame(&recipient, &session.frame(FrameKind::Connect, conn_id, 0))?;
+        let token = match accepted_recv.recv().await {
+            Ok(token) => token,
+            Err(_) => return Err(Error::NymError("nym-client session closed".into())),
+        };
+
+        let stream = session.open_stream(recipient.clone(), conn_id, token);
+        stream.session.send_frame(&recipient, &stream.frame(FrameKind::Ack, 0, vec![]))?;
+        debug!(target: "net::nym", "Opened connection {} to {}", conn_id, recipient);
+
+        Ok(stream)
+    }
+
+    pub async fn do_listen(self, url: Url) -> Result<NymListener> {
+        let session = NymSession::get(&url).await?;
+
+        let (accept, incoming) = smol::channel::unbounded();
+        {
+            let mut session_accept = session.accept.lock().unwrap();
+            if session_accept.is_some() {
+                return Err(Error::NymError("Already listening on this nym-client".into()))
+            }
+            *session_accept = Some(accept);
+        }
+
+        Ok(NymListener { session, incoming })
+    }
+}
+
+impl Transport for NymTransport {
+    type Acceptor = NymListener;
+    type Connector = NymStream;
+
+    type Listener = Pin<Box<dyn Future<Output = Result<Self::Acceptor>> + Send>>;
+    type Dial = Pin<Box<dyn Future<Output = Result<Self::Connector>> + Send>>;
+
+    type TlsListener = Pin<Box<dyn Future<Output = Result<(TlsAcceptor, Self::Acceptor)>> + Send>>;
+    type TlsDialer = Pin<Box<dyn Future<Output = Result<TlsStream<Self::Connector>>> + Send>>;
+
+    fn listen_on(self, url: Url) -> Result<Self::Listener> {
+        match url.scheme() {
+            "nym" | "nym+tls" => {}
+            x => return Err(Error::UnsupportedTransport(x.to_string())),
+        }
+        Ok(Box::pin(self.do_listen(url)))
+    }
+
+    fn upgrade_listener(self, acceptor: Self::Acceptor) -> Result<Self::TlsListener> {
+        let tlsupgrade = TlsUpgrade::new();
+        Ok(Box::pin(tlsupgrade.upgrade_listener_tls(acceptor)))
+    }
+
+    fn dial(self, url: Url, timeout: Option<Duration>) -> Result<Self::Dial> {
+        match url.scheme() {
+            "nym" | "nym+tls" => {}
+            x => return Err(Error::UnsupportedTransport(x.to_string())),
+        }
+
+        match timeout {
+            Some(t) => Ok(Box::pin(async move {
+                match async_std::future::timeout(t, self.do_dial(url)).await {
+                    Ok(r) => r,
+                    Err(_) => Err(Error::ConnectTimeout),
+                }
+            })),
+            None => Ok(Box::pin(self.do_dial(url))),
+        }
+    }
+
+    fn upgrade_dialer(self, connector: Self::Connector) -> Result<Self::TlsDialer> {
+        let tlsupgrade = TlsUpgrade::new();
+        Ok(Box::pin(tlsupgrade.upgrade_dialer_tls(connector)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nym_address_url_roundtrip() {
+        let address = "DguTcdkWWtDyUFLvQxRdcA8qZhardhE1ZXy1YCC7Zfmq.\
+                       Dxreouj5RhQqMb3ZaAxgXFdGpRzgnn3Pg6gmwW1Z9XJH@\
+                       62F81C9GrHDRja9WCqozemRFSzFPMecY85MbGwn6efve";
+
+        let url = nym_address_to_url(address, "nym").unwrap();
+        assert_eq!(url.scheme(), "nym");
+        assert_eq!(url_to_nym_address(&url).unwrap(), address);
+
+        let url = Url::parse("nym://127.0.0.1:1977").unwrap();
+        assert!(url_to_nym_address(&url).is_err());
+    }
+
+    /// Build a listening session that isn't connected to a nym-client,
+    /// along with its outgoing websocket messages and accepted streams.
+    fn test_session() -> (
+        Arc<NymSession>,
+        smol::channel::Receiver<Message>,
+        smol::channel::Receiver<NymStream>,
+    ) {
+        let (outgoing, outgoing_recv) = smol::channel::bounded(NYM_MAX_OUTGOING_FRAMES);
+        let (accept, incoming) = smol::channel::unbounded();
+        let session = Arc::new(NymSession {
+            self_address: "self".to_string(),
+            outgoing,
+            connections: Mutex::new(HashMap::new()),
+            dials: Mutex::new(HashMap::new()),
+            handshakes: Mutex::new(HashMap::new()),
+            accept: Mutex::new(Some(accept)),
+        });
+
+        (session, outgoing_recv, incoming)
+    }
+
+    /// Build the websocket message of a frame received from the mixnet.
+    fn received_message(
+        kind: FrameKind,
+        sender: &str,
+        conn_id: u64,
+        token: u64,
+        seq: u64,
+        payload: &[u8],
+    ) -> String {
+        let frame = NymFrame {
+            kind: kind as u8,
+            conn_id,
+            token,
+            sender: sender.to_string(),
+            seq,
+            payload: payload.to_vec(),
+        };
+
+        json!({"type": "received", "message": hex::encode(serialize(&frame))}).to_string()
+    }
+
+    fn connect_message(sender: &str, conn_id: u64) -> String {
+        received_message(FrameKind::Connect, sender, conn_id, 0, 0, &[])
+    }
+
+    /// Read the frame of a websocket message sent to the nym-client.
+    fn sent_frame(msg: Message) -> NymFrame {
+        let Message::Text(text) = msg else { panic!("Unexpected message {:?}", msg) };
+        let request: Value = serde_json::from_str(&text).unwrap();
+        deserialize(&hex::decode(request["message"].as_str().unwrap()).unwrap()).unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_nym_handshake_expiry() {
+        let (session, outgoing_recv, _) = test_session();
+
+        for conn_id in 0..NYM_MAX_HANDSHAKES as u64 {
+            session.clone().handle_message(&connect_message("spoofed", conn_id)).await.unwrap();
+            outgoing_recv.try_recv().unwrap();
+        }
+        assert!(session.clone().handle_message(&connect_message("peer", 0)).await.is_err());
+
+        // Handshakes past their deadline make room for new ones
+        for (_, deadline) in session.handshakes.lock().unwrap().values_mut() {
+            *deadline = Instant::now();
+        }
+        session.clone().handle_message(&connect_message("peer", 0)).await.unwrap();
+        assert_eq!(session.handshakes.lock().unwrap().len(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_nym_write_backpressure() {
+        let (session, outgoing_recv, _) = test_session();
+        let mut stream = session.open_stream("peer".to_string(), 0, 0);
+
+        // Fill the outgoing queue, plus the frame held back by the stream
+        for _ in 0..=NYM_MAX_OUTGOING_FRAMES {
+            stream.write(b"data").await.unwrap();
+        }
+        assert_eq!(outgoing_recv.len(), NYM_MAX_OUTGOING_FRAMES);
+        assert!(stream.write(b"data").now_or_never().is_none());
+        assert!(stream.flush().now_or_never().is_none());
+
+        // Writes resume once the nym-client gets the queued frames
+        outgoing_recv.recv().await.unwrap();
+        stream.flush().await.unwrap();
+        outgoing_recv.recv().await.unwrap();
+        stream.write(b"data").await.unwrap();
+        assert_eq!(outgoing_recv.len(), NYM_MAX_OUTGOING_FRAMES);
+    }
+    #[async_std::test]
+    async fn test_nym_reordered_frames() {
+        let (session, outgoing_recv, incoming) = test_session();
+
+        session.clone().handle_message(&connect_message("peer", 0)).await.unwrap();
+        let token = sent_frame(outgoing_recv.try_recv().unwrap()).token;
+
+        // The mixnet delivers the frames in any order, the Ack and the first
+        // data frame last. The stream only ends once all the data is read.
+        let frames = [
+            received_message(FrameKind::Data, "peer", 0, token, 1, b" nym"),
+            received_message(FrameKind::Close, "peer", 0, token, 3, &[]),
+            received_message(FrameKind::Data, "peer", 0, token, 2, b"!"),
+            received_message(FrameKind::Data, "peer", 0, token, 0, b"ohai"),
+            received_message(FrameKind::Ack, "peer", 0, token, 0, &[]),
+        ];
+        for frame in frames {
+            session.clone().handle_message(&frame).await.unwrap();
+        }
+
+        let mut stream = incoming.try_recv().unwrap();
+        assert!(incoming.try_recv().is_err());
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"ohai nym!");
+
+        // Frames with a wrong token still don't complete handshakes
+        session.clone().handle_message(&connect_message("peer", 1)).await.unwrap();
+        let data = received_message(FrameKind::Data, "peer", 1, token + 1, 0, b"ohai");
+        assert!(session.clone().handle_message(&data).await.is_err());
+        assert!(incoming.try_recv().is_err());
+
+        sent_frame(outgoing_recv.try_recv().unwrap());
+
+        // Closing a stream tells the remote how many data frames it was sent
+        stream.write(b"data").await.unwrap();
+        stream.close().await.unwrap();
+        assert_eq!(sent_frame(outgoing_recv.try_recv().unwrap()).seq, 0);
+        let close = sent_frame(outgoing_recv.try_recv().unwrap());
+        assert_eq!((close.kind, close.seq), (FrameKind::Close as u8, 1));
+    }
+}
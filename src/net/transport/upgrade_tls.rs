@@ -18,7 +18,7 @@
 
 use std::time::SystemTime;
 
-use async_std::{net::TcpListener, sync::Arc};
+use async_std::sync::Arc;
 use futures::prelude::*;
 use futures_rustls::{
     rustls,
@@ -132,10 +132,7 @@ impl TlsUpgrade {
         Self { server_config, client_config }
     }
 
-    pub async fn upgrade_listener_tls(
-        self,
-        listener: TcpListener,
-    ) -> Result<(TlsAcceptor, TcpListener)> {
+    pub async fn upgrade_listener_tls<L>(self, listener: L) -> Result<(TlsAcceptor, L)> {
         Ok((TlsAcceptor::from(self.server_config), listener))
     }
 
@@ -0,0 +1,144 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use wasmer::wasmparser::Operator;
+
+/// Gas limit for a contract
+pub const GAS_LIMIT: u64 = 200000000;
+
+/// Base cost of calling any host function
+pub const GAS_HOST_CALL: u64 = 100;
+/// Cost per byte copied between the guest memory and the host
+pub const GAS_PER_BYTE_COPY: u64 = 1;
+/// Cost of a lookup in a sled tree
+pub const GAS_DB_READ: u64 = 1000;
+/// Cost per byte read from a sled tree
+pub const GAS_PER_BYTE_DB_READ: u64 = 2;
+/// Cost per byte written to a sled tree batch
+pub const GAS_PER_BYTE_DB_WRITE: u64 = 20;
+/// Cost of opening or creating a sled tree
+pub const GAS_DB_OPEN: u64 = 10000;
+/// Cost of appending a leaf to a Merkle tree, which involves hashing
+/// along the tree's depth
+pub const GAS_MERKLE_APPEND: u64 = 5000;
+/// Cost of invoking another contract, which involves compiling its wasm
+/// code, on top of the gas the invoked contract uses
+pub const GAS_INVOKE: u64 = 100000;
+
+/// Cost of a single wasm operator. Called by the metering middleware for
+/// each operator encountered when compiling the module.
+/// https://docs.rs/wasmparser/latest/wasmparser/enum.Operator.html
+pub fn op_cost(operator: &Operator) -> u64 {
+    match operator {
+        // Structural markers don't do any work on their own
+        Operator::Nop { .. } |
+        Operator::Block { .. } |
+        Operator::Loop { .. } |
+        Operator::Else { .. } |
+        Operator::End { .. } |
+        Operator::Drop { .. } => 0,
+
+        // Locals, globals and constants
+        Operator::LocalGet { .. } |
+        Operator::LocalSet { .. } |
+        Operator::LocalTee { .. } |
+        Operator::GlobalGet { .. } |
+        Operator::GlobalSet { .. } |
+        Operator::I32Const { .. } |
+        Operator::I64Const { .. } => 1,
+
+        // Branching
+        Operator::If { .. } |
+        Operator::Br { .. } |
+        Operator::BrIf { .. } |
+        Operator::Return { .. } |
+        Operator::Select { .. } |
+        Operator::TypedSelect { .. } => 2,
+        Operator::BrTable { .. } => 5,
+
+        // Function calls
+        Operator::Call { .. } => 10,
+        Operator::CallIndirect { .. } => 20,
+
+        // Memory access
+        Operator::I32Load { .. } |
+        Operator::I64Load { .. } |
+        Operator::I32Load8S { .. } |
+        Operator::I32Load8U { .. } |
+        Operator::I32Load16S { .. } |
+        Operator::I32Load16U { .. } |
+        Operator::I64Load8S { .. } |
+        Operator::I64Load8U { .. } |
+        Operator::I64Load16S { .. } |
+        Operator::I64Load16U { .. } |
+        Operator::I64Load32S { .. } |
+        Operator::I64Load32U { .. } => 3,
+        Operator::I32Store { .. } |
+        Operator::I64Store { .. } |
+        Operator::I32Store8 { .. } |
+        Operator::I32Store16 { .. } |
+        Operator::I64Store8 { .. } |
+        Operator::I64Store16 { .. } |
+        Operator::I64Store32 { .. } => 4,
+        Operator::MemorySize { .. } => 1,
+        Operator::MemoryGrow { .. } => 10000,
+
+        // Integer multiplication and division
+        Operator::I32Mul { .. } | Operator::I64Mul { .. } => 3,
+        Operator::I32DivS { .. } |
+        Operator::I32DivU { .. } |
+        Operator::I32RemS { .. } |
+        Operator::I32RemU { .. } |
+        Operator::I64DivS { .. } |
+        Operator::I64DivU { .. } |
+        Operator::I64RemS { .. } |
+        Operator::I64RemU { .. } => 10,
+
+        // Floating point shouldn't be used by contracts, and is priced
+        // accordingly.
+        Operator::F32Add { .. } |
+        Operator::F32Sub { .. } |
+        Operator::F32Mul { .. } |
+        Operator::F32Div { .. } |
+        Operator::F32Sqrt { .. } |
+        Operator::F64Add { .. } |
+        Operator::F64Sub { .. } |
+        Operator::F64Mul { .. } |
+        Operator::F64Div { .. } |
+        Operator::F64Sqrt { .. } => 20,
+
+        // Remaining integer arithmetic, bitwise, comparison and conversion
+        // operators
+        _ => 1,
+    }
+}
+
+/// Cost of a host function copying `bytes` from or to the guest memory.
+pub fn host_call_cost(bytes: usize) -> u64 {
+    GAS_HOST_CALL + bytes as u64 * GAS_PER_BYTE_COPY
+}
+
+/// Cost of reading a value of `bytes` from a sled tree.
+pub fn db_read_cost(bytes: usize) -> u64 {
+    GAS_DB_READ + bytes as u64 * GAS_PER_BYTE_DB_READ
+}
+
+/// Cost of writing `bytes` of keys and values to a sled tree.
+pub fn db_write_cost(bytes: usize) -> u64 {
+    bytes as u64 * GAS_PER_BYTE_DB_WRITE
+}
@@ -16,63 +16,83 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::io::Cursor;
+use std::{io::Cursor, ops::Bound};
 
 use darkfi_sdk::{
     crypto::ContractId,
     db::{
-        CALLER_ACCESS_DENIED, DB_CONTAINS_KEY_FAILED, DB_DEL_FAILED, DB_GET_FAILED, DB_INIT_FAILED,
-        DB_LOOKUP_FAILED, DB_SET_FAILED, DB_SUCCESS,
+        DbPage, CALLER_ACCESS_DENIED, DB_CONTAINS_KEY_FAILED, DB_DEL_FAILED, DB_GET_FAILED,
+        DB_INIT_FAILED, DB_LOOKUP_FAILED, DB_SCAN_FAILED, DB_SCAN_MAX_LIMIT, DB_SET_FAILED,
+        DB_SUCCESS,
     },
 };
-use darkfi_serial::Decodable;
+use darkfi_serial::{serialize, Decodable};
 use log::{debug, error};
 use wasmer::{FunctionEnvMut, WasmPtr};
 
 use crate::{
-    runtime::vm_runtime::{ContractSection, Env},
+    blockchain::{writes_batch, StateOverlay, TreeWrites},
+    runtime::{
+        gas::{db_read_cost, db_write_cost, host_call_cost, GAS_DB_OPEN},
+        vm_runtime::{ContractSection, Env},
+    },
     Result,
 };
 
-/// Internal wasm runtime API for sled trees
+/// Internal wasm runtime API for sled trees.
+/// Reads go through the blockchain's [`StateOverlay`], so they see the state
+/// changes that are pending to be written.
 pub struct DbHandle {
     pub contract_id: ContractId,
     tree: sled::Tree,
+    overlay: StateOverlay,
 }
 
 impl DbHandle {
-    pub fn new(contract_id: ContractId, tree: sled::Tree) -> Self {
-        Self { contract_id, tree }
+    pub fn new(contract_id: ContractId, tree: sled::Tree, overlay: StateOverlay) -> Self {
+        Self { contract_id, tree, overlay }
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if let Some(v) = self.tree.get(key)? {
-            return Ok(Some(v.to_vec()))
-        };
-
-        Ok(None)
+        self.overlay.get(&self.tree, key)
     }
 
     pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
-        Ok(self.tree.contains_key(key)?)
+        self.overlay.contains_key(&self.tree, key)
+    }
+
+    pub fn range(
+        &self,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.overlay.range(&self.tree, range, limit)
     }
 
-    pub fn apply_batch(&self, batch: sled::Batch) -> Result<()> {
-        Ok(self.tree.apply_batch(batch)?)
+    /// Stage the given writes in the overlay, to be written along with
+    /// the block they belong to.
+    pub fn stage(&self, writes: TreeWrites) {
+        self.overlay.insert(&self.tree, writes)
     }
 
-    pub fn flush(&self) -> Result<()> {
+    /// Write the given writes straight to the tree.
+    pub fn apply(&self, writes: &TreeWrites) -> Result<()> {
+        self.tree.apply_batch(writes_batch(writes))?;
         let _ = self.tree.flush()?;
         Ok(())
     }
 }
 
 /// Only deploy() can call this. Creates a new database instance for this contract.
-pub(crate) fn db_init(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
-    let env = ctx.data();
+pub(crate) fn db_init(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Deploy => {
-            let memory_view = env.memory_view(&ctx);
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + GAS_DB_OPEN) {
+                return DB_INIT_FAILED
+            }
+
+            let memory_view = env.memory_view(&store);
             let db = &env.blockchain.sled_db;
             let contracts = &env.blockchain.contracts;
             let contract_id = &env.contract_id;
@@ -118,24 +138,32 @@ pub(crate) fn db_init(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i
                 return CALLER_ACCESS_DENIED
             }
 
-            let tree_handle = match contracts.init(db, &cid, &db_name) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!(target: "runtime::db::db_init()", "Failed to init db: {}", e);
-                    return DB_INIT_FAILED
-                }
-            };
+            // The pointer registering the tree is kept with the pending
+            // writes, so it's written along with the rest of the deployment.
+            let mut state_pointers = env.state_pointers.borrow_mut();
+            let overlay = &env.blockchain.overlay;
+            let tree_handle =
+                match contracts.init_writes(db, overlay, &state_pointers, &cid, &db_name) {
+                    Ok((tree, writes)) => {
+                        state_pointers.extend(writes);
+                        tree
+                    }
+                    Err(e) => {
+                        error!(target: "runtime::db::db_init()", "Failed to init db: {}", e);
+                        return DB_INIT_FAILED
+                    }
+                };
 
             // TODO: Make sure we don't duplicate the DbHandle in the vec.
             //       It should behave like an ordered set.
-            // In `lookup()` we also create a `sled::Batch`. This is done for
+            // In `lookup()` we also create a batch of writes. This is done for
             // some simplicity reasons, and also for possible future changes.
             // However, we make sure that unauthorized writes are not available
             // from other functions that interface with the databases.
             let mut db_handles = env.db_handles.borrow_mut();
             let mut db_batches = env.db_batches.borrow_mut();
-            db_handles.push(DbHandle::new(cid, tree_handle));
-            db_batches.push(sled::Batch::default());
+            db_handles.push(DbHandle::new(cid, tree_handle, env.blockchain.overlay.clone()));
+            db_batches.push(TreeWrites::new());
             (db_handles.len() - 1) as i32
         }
         _ => {
@@ -146,14 +174,18 @@ pub(crate) fn db_init(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i
 }
 
 /// Everyone can call this. Lookups up a database handle from its name.
-pub(crate) fn db_lookup(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
-    let env = ctx.data();
+pub(crate) fn db_lookup(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Deploy |
         ContractSection::Exec |
         ContractSection::Update |
         ContractSection::Metadata => {
-            let memory_view = env.memory_view(&ctx);
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + GAS_DB_OPEN) {
+                return DB_LOOKUP_FAILED
+            }
+
+            let memory_view = env.memory_view(&store);
             let db = &env.blockchain.sled_db;
             let contracts = &env.blockchain.contracts;
 
@@ -193,24 +225,27 @@ pub(crate) fn db_lookup(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) ->
                 return DB_LOOKUP_FAILED
             }*/
 
-            let tree_handle = match contracts.lookup(db, &cid, &db_name) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!(target: "runtime::db::db_lookup()", "Failed to lookup db: {}", e);
-                    return DB_LOOKUP_FAILED
-                }
-            };
+            let state_pointers = env.state_pointers.borrow();
+            let overlay = &env.blockchain.overlay;
+            let tree_handle =
+                match contracts.lookup_pending(db, overlay, &state_pointers, &cid, &db_name) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!(target: "runtime::db::db_lookup()", "Failed to lookup db: {}", e);
+                        return DB_LOOKUP_FAILED
+                    }
+                };
 
             // TODO: Make sure we don't duplicate the DbHandle in the vec.
             //       It should behave like an ordered set.
-            // In `lookup()` we also create a `sled::Batch`. This is done for
+            // In `lookup()` we also create a batch of writes. This is done for
             // some simplicity reasons, and also for possible future changes.
             // However, we make sure that unauthorized writes are not available
             // from other functions that interface with the databases.
             let mut db_handles = env.db_handles.borrow_mut();
             let mut db_batches = env.db_batches.borrow_mut();
-            db_handles.push(DbHandle::new(cid, tree_handle));
-            db_batches.push(sled::Batch::default());
+            db_handles.push(DbHandle::new(cid, tree_handle, env.blockchain.overlay.clone()));
+            db_batches.push(TreeWrites::new());
             (db_handles.len() - 1) as i32
         }
         _ => {
@@ -221,11 +256,11 @@ pub(crate) fn db_lookup(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) ->
 }
 
 /// Only update() can call this. Set a value within the transaction.
-pub(crate) fn db_set(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
-    let env = ctx.data();
+pub(crate) fn db_set(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Deploy | ContractSection::Update => {
-            let memory_view = env.memory_view(&ctx);
+            let memory_view = env.memory_view(&store);
 
             let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
                 error!(target: "runtime::db::db_set()", "Failed to make slice from ptr");
@@ -273,6 +308,11 @@ pub(crate) fn db_set(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i3
                 return DB_DEL_FAILED
             }*/
 
+            let written = key.len() + value.len();
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + db_write_cost(written)) {
+                return DB_SET_FAILED
+            }
+
             let db_handles = env.db_handles.borrow();
             let mut db_batches = env.db_batches.borrow_mut();
 
@@ -290,7 +330,7 @@ pub(crate) fn db_set(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i3
                 return CALLER_ACCESS_DENIED
             }
 
-            db_batch.insert(key, value);
+            db_batch.insert(key, Some(value));
 
             DB_SUCCESS
         }
@@ -299,11 +339,11 @@ pub(crate) fn db_set(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i3
 }
 
 /// Only update() can call this. Remove a key from the database.
-pub(crate) fn db_del(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
-    let env = ctx.data();
+pub(crate) fn db_del(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Deploy | ContractSection::Update => {
-            let memory_view = env.memory_view(&ctx);
+            let memory_view = env.memory_view(&store);
 
             let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
                 error!(target: "runtime::db::db_del()", "Failed to make slice from ptr");
@@ -343,6 +383,11 @@ pub(crate) fn db_del(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i3
                 return DB_DEL_FAILED
             }*/
 
+            let gas = host_call_cost(len as usize) + db_write_cost(key.len());
+            if !env.charge_gas(&mut store, gas) {
+                return DB_DEL_FAILED
+            }
+
             let db_handles = env.db_handles.borrow();
             let mut db_batches = env.db_batches.borrow_mut();
 
@@ -360,7 +405,7 @@ pub(crate) fn db_del(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i3
                 return CALLER_ACCESS_DENIED
             }
 
-            db_batch.remove(key);
+            db_batch.insert(key, None);
 
             DB_SUCCESS
         }
@@ -369,11 +414,11 @@ pub(crate) fn db_del(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i3
 }
 
 /// Everyone can call this. Will read a key from the key-value store.
-pub(crate) fn db_get(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
-    let env = ctx.data();
+pub(crate) fn db_get(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Deploy | ContractSection::Exec | ContractSection::Metadata => {
-            let memory_view = env.memory_view(&ctx);
+            let memory_view = env.memory_view(&store);
 
             let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
                 error!(target: "runtime::db::db_get()", "Failed to make slice from ptr");
@@ -431,6 +476,11 @@ pub(crate) fn db_get(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i6
                 }
             };
 
+            let read = ret.as_ref().map_or(0, |v| v.len());
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + db_read_cost(read)) {
+                return DB_GET_FAILED.into()
+            }
+
             let Some(return_data) = ret else {
                 debug!(target: "runtime::db::db_get()", "returned empty vec");
                 return -127
@@ -446,14 +496,14 @@ pub(crate) fn db_get(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i6
 }
 
 /// Everyone can call this. Will check if a given db contains given key.
-pub(crate) fn db_contains_key(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
-    let env = ctx.data();
+pub(crate) fn db_contains_key(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Deploy |
         ContractSection::Exec |
         ContractSection::Update |
         ContractSection::Metadata => {
-            let memory_view = env.memory_view(&ctx);
+            let memory_view = env.memory_view(&store);
 
             let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
                 error!(target: "runtime::db::db_contains_key()", "Failed to make slice from ptr");
@@ -493,6 +543,10 @@ pub(crate) fn db_contains_key(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u
                 return DB_CONTAINS_KEY_FAILED
             }*/
 
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + db_read_cost(0)) {
+                return DB_CONTAINS_KEY_FAILED
+            }
+
             let db_handles = env.db_handles.borrow();
 
             if db_handles.len() <= db_handle {
@@ -514,3 +568,148 @@ pub(crate) fn db_contains_key(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u
         _ => CALLER_ACCESS_DENIED,
     }
 }
+
+/// Everyone can call this. Will read a page of the records with keys in a
+/// given range from the key-value store.
+pub(crate) fn db_range(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
+    db_scan(ctx, ptr, len, false)
+}
+
+/// Everyone can call this. Will read a page of the records with keys
+/// starting with a given prefix from the key-value store.
+pub(crate) fn db_prefix(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
+    db_scan(ctx, ptr, len, true)
+}
+
+/// Smallest key greater than all the keys starting with the given prefix,
+/// or `None` if there's no such key.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end)
+        }
+    }
+
+    None
+}
+
+/// Shared implementation of `db_range` and `db_prefix`, reading the range
+/// bounds, or the prefix, followed by the cursor and the page limit.
+/// Returns the index of the object holding the serialized [`DbPage`].
+fn db_scan(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32, prefix: bool) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    match env.contract_section {
+        ContractSection::Deploy | ContractSection::Exec | ContractSection::Metadata => {
+            if !env.charge_gas(&mut store, host_call_cost(len as usize)) {
+                return DB_SCAN_FAILED.into()
+            }
+
+            let memory_view = env.memory_view(&store);
+
+            let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
+                error!(target: "runtime::db::db_scan()", "Failed to make slice from ptr");
+                return DB_SCAN_FAILED.into()
+            };
+
+            let mut buf = vec![0_u8; len as usize];
+            if let Err(e) = mem_slice.read_slice(&mut buf) {
+                error!(target: "runtime::db::db_scan()", "Failed to read from memory slice: {}", e);
+                return DB_SCAN_FAILED.into()
+            };
+
+            let mut buf_reader = Cursor::new(buf);
+
+            let db_handle: u32 = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::db::db_scan()", "Failed to decode DbHandle: {}", e);
+                    return DB_SCAN_FAILED.into()
+                }
+            };
+            let db_handle = db_handle as usize;
+
+            let start: Vec<u8> = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::db::db_scan()", "Failed to decode start key: {}", e);
+                    return DB_SCAN_FAILED.into()
+                }
+            };
+
+            let end: Option<Vec<u8>> = if prefix {
+                prefix_end(&start)
+            } else {
+                match Decodable::decode(&mut buf_reader) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!(target: "runtime::db::db_scan()", "Failed to decode end key: {}", e);
+                        return DB_SCAN_FAILED.into()
+                    }
+                }
+            };
+
+            let cursor: Option<Vec<u8>> = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::db::db_scan()", "Failed to decode cursor: {}", e);
+                    return DB_SCAN_FAILED.into()
+                }
+            };
+
+            let limit: u32 = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::db::db_scan()", "Failed to decode limit: {}", e);
+                    return DB_SCAN_FAILED.into()
+                }
+            };
+            let limit = limit.clamp(1, DB_SCAN_MAX_LIMIT) as usize;
+
+            let db_handles = env.db_handles.borrow();
+
+            if db_handles.len() <= db_handle {
+                error!(target: "runtime::db::db_scan()", "Requested DbHandle that is out of bounds");
+                return DB_SCAN_FAILED.into()
+            }
+
+            let handle_idx = db_handle;
+            let db_handle = &db_handles[handle_idx];
+
+            // The cursor is the last key of the previous page
+            let lower = match cursor {
+                Some(cursor) if cursor >= start => Bound::Excluded(cursor),
+                _ => Bound::Included(start),
+            };
+            let upper = end.map_or(Bound::Unbounded, Bound::Excluded);
+
+            // One more record is read to know if there's a following page
+            let mut records = match db_handle.range((lower, upper), limit + 1) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::db::db_scan()", "Internal error iterating tree: {}", e);
+                    return DB_SCAN_FAILED.into()
+                }
+            };
+
+            let read: u64 = records.iter().map(|(k, v)| db_read_cost(k.len() + v.len())).sum();
+            if !env.charge_gas(&mut store, read) {
+                return DB_SCAN_FAILED.into()
+            }
+
+            let cursor = if records.len() > limit {
+                records.truncate(limit);
+                records.last().map(|(key, _)| key.clone())
+            } else {
+                None
+            };
+
+            // Copy the page to the VM
+            let mut objects = env.objects.borrow_mut();
+            objects.push(serialize(&DbPage { records, cursor }));
+            (objects.len() - 1) as i64
+        }
+        _ => CALLER_ACCESS_DENIED.into(),
+    }
+}
@@ -0,0 +1,84 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::Cursor;
+
+use darkfi_sdk::{
+    error::{CALLER_ACCESS_DENIED, INTERNAL_ERROR},
+    event::ContractEvent,
+};
+use darkfi_serial::Decodable;
+use log::error;
+use wasmer::{FunctionEnvMut, WasmPtr};
+
+use crate::runtime::{
+    gas::{db_write_cost, host_call_cost},
+    vm_runtime::{ContractSection, Env},
+};
+
+/// Host function for emitting a contract event.
+/// The event is kept in the runtime along with the state update, and gets
+/// stored by the validator once the transaction is verified.
+pub(crate) fn emit_event(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    match env.contract_section {
+        ContractSection::Exec | ContractSection::Update => {
+            let memory_view = env.memory_view(&store);
+
+            let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
+                error!(target: "runtime::event::emit_event()", "Failed to make slice from ptr");
+                return INTERNAL_ERROR
+            };
+
+            let mut buf = vec![0_u8; len as usize];
+            if let Err(e) = mem_slice.read_slice(&mut buf) {
+                error!(target: "runtime::event::emit_event()", "Failed to read from memory slice: {}", e);
+                return INTERNAL_ERROR
+            };
+
+            let mut buf_reader = Cursor::new(buf);
+
+            let topic: String = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::event::emit_event()", "Failed to decode topic: {}", e);
+                    return INTERNAL_ERROR
+                }
+            };
+
+            let data: Vec<u8> = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::event::emit_event()", "Failed to decode data: {}", e);
+                    return INTERNAL_ERROR
+                }
+            };
+
+            // Events end up in the database, so they're charged like writes
+            let written = topic.len() + data.len();
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + db_write_cost(written)) {
+                return INTERNAL_ERROR
+            }
+
+            let event = ContractEvent { contract_id: env.contract_id, topic, data };
+            env.events.borrow_mut().push(event);
+            0
+        }
+        _ => CALLER_ACCESS_DENIED,
+    }
+}
@@ -0,0 +1,509 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::Cursor;
+
+use darkfi_sdk::{crypto::ContractId, error::INVOKE_FAILED};
+use darkfi_serial::{serialize, Decodable};
+use log::{debug, error};
+use wasmer::{FunctionEnvMut, WasmPtr};
+
+use crate::{
+    blockchain::Blockchain,
+    runtime::{
+        gas::{db_read_cost, host_call_cost, GAS_HOST_CALL, GAS_INVOKE},
+        vm_runtime::{ContractSection, Env, Runtime},
+    },
+};
+
+/// Maximum number of nested contract invocations, so the call stack can't
+/// grow unbounded.
+const MAX_INVOKE_DEPTH: usize = 4;
+
+/// Only exec() can call this. Runs the `__invoke` function of another
+/// contract in a nested runtime, followed by its `__update` with the returned
+/// state update, which is then handed back to the caller as an object. Only
+/// contracts exporting `__invoke` can be invoked, since their `__metadata`
+/// isn't run, so they must not rely on any signatures or ZK proofs.
+/// The gas used by the nested runtime is charged to the caller, so it's
+/// limited to what the caller has left, counting both its wasm code and its
+/// host functions.
+/// The nested runtime's state changes are staged in an overlay on top of
+/// the caller's, so the following invocations see them, and they're staged
+/// along with the caller's when its runtime is committed.
+/// Contracts can't invoke themselves or any contract below them in the call
+/// stack, so a contract never runs twice at the same time.
+pub(crate) fn invoke_contract(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    match env.contract_section {
+        ContractSection::Exec => {
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + GAS_INVOKE) {
+                return INVOKE_FAILED
+            }
+
+            let memory_view = env.memory_view(&store);
+
+            let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
+                error!(target: "runtime::invoke", "Failed to make slice from ptr");
+                return INVOKE_FAILED
+            };
+
+            let mut buf = vec![0_u8; len as usize];
+            if let Err(e) = mem_slice.read_slice(&mut buf) {
+                error!(target: "runtime::invoke", "Failed to read from memory slice: {}", e);
+                return INVOKE_FAILED
+            };
+
+            let mut buf_reader = Cursor::new(buf);
+
+            let contract_id: ContractId = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::invoke", "Failed to decode ContractId: {}", e);
+                    return INVOKE_FAILED
+                }
+            };
+
+            let data: Vec<u8> = match Decodable::decode(&mut buf_reader) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::invoke", "Failed to decode call data: {}", e);
+                    return INVOKE_FAILED
+                }
+            };
+
+            // Re-entrancy is forbidden, so the callee can't observe the caller
+            // in the middle of its execution.
+            if contract_id == env.contract_id || env.call_stack.contains(&contract_id) {
+                error!(target: "runtime::invoke", "Contract {} is already in the call stack", contract_id);
+                return darkfi_sdk::error::CALLER_ACCESS_DENIED
+            }
+
+            if env.call_stack.len() + 1 >= MAX_INVOKE_DEPTH {
+                error!(target: "runtime::invoke", "Maximum invocation depth reached");
+                return INVOKE_FAILED
+            }
+
+            let blockchain = &env.blockchain;
+            let wasm = match blockchain.wasm_bincode.get_pending(&blockchain.overlay, contract_id) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::invoke", "Could not find wasm bincode for contract {}: {}", contract_id, e);
+                    return INVOKE_FAILED
+                }
+            };
+
+            if !env.charge_gas(&mut store, db_read_cost(wasm.len())) {
+                return INVOKE_FAILED
+            }
+
+            // The invoked contract reads and stages its state changes through
+            // the overlay holding the ones of the previous invocations.
+            let overlay = env
+                .invoke_overlay
+                .borrow_mut()
+                .get_or_insert_with(|| blockchain.overlay.child())
+                .clone();
+            let blockchain = Blockchain { overlay, ..blockchain.clone() };
+
+            debug!(target: "runtime::invoke", "Invoking contract {}", contract_id);
+            let mut runtime = match Runtime::new(&wasm, blockchain, contract_id) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::invoke", "Failed to instantiate runtime for contract {}: {}", contract_id, e);
+                    return INVOKE_FAILED
+                }
+            };
+
+            if !runtime.is_invocable() {
+                error!(target: "runtime::invoke", "Contract {} can't be invoked", contract_id);
+                return INVOKE_FAILED
+            }
+
+            let mut call_stack = env.call_stack.clone();
+            call_stack.push(env.contract_id);
+            runtime.set_invoker(call_stack, env.gas_left(&mut store));
+            runtime.set_block_context(env.block_context);
+
+            let result = runtime.invoke(&data).and_then(|update| {
+                runtime.update(&update)?;
+                Ok(update)
+            });
+
+            // The gas is charged whether the invocation succeeded or not
+            if !env.charge_gas(&mut store, runtime.gas_used()) {
+                return INVOKE_FAILED
+            }
+
+            let update = match result {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "runtime::invoke", "Failed to invoke contract {}: {}", contract_id, e);
+                    return INVOKE_FAILED
+                }
+            };
+
+            if let Err(e) = runtime.commit() {
+                error!(target: "runtime::invoke", "Failed to stage state changes of contract {}: {}", contract_id, e);
+                return INVOKE_FAILED
+            }
+
+            env.events.borrow_mut().extend(runtime.take_events());
+
+            // Copy the state update to the VM
+            let mut objects = env.objects.borrow_mut();
+            objects.push(update);
+            (objects.len() - 1) as i64
+        }
+        _ => {
+            error!(target: "runtime::invoke", "invoke_contract called in unauthorized section");
+            darkfi_sdk::error::CALLER_ACCESS_DENIED
+        }
+    }
+}
+
+/// Everyone can call this. Returns the index of an object holding the
+/// `ContractId` of the contract that invoked the one being executed, or
+/// `-127` if it's executed as a regular contract call.
+pub(crate) fn get_caller(mut ctx: FunctionEnvMut<Env>) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    if !env.charge_gas(&mut store, GAS_HOST_CALL) {
+        return darkfi_sdk::error::INTERNAL_ERROR
+    }
+
+    let Some(caller) = env.call_stack.last() else { return -127 };
+
+    // Copy the ContractId to the VM
+    let mut objects = env.objects.borrow_mut();
+    objects.push(serialize(caller));
+    (objects.len() - 1) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use darkfi_sdk::{error::ContractError, pasta::pallas};
+
+    use super::*;
+    use crate::{blockchain::tests::test_blockchain, runtime::gas::GAS_LIMIT, Error, Result};
+
+    /// Imports and helpers shared by the test contracts
+    const PRELUDE: &str = r#"
+        (import "env" "invoke_contract_" (func $invoke_contract (param i32 i32) (result i64)))
+        (import "env" "get_caller_" (func $get_caller (result i64)))
+        (import "env" "get_object_bytes_" (func $get_object_bytes (param i32 i32) (result i64)))
+        (import "env" "get_object_size_" (func $get_object_size (param i32) (result i64)))
+        (import "env" "set_return_data_" (func $set_return_data (param i32 i32) (result i64)))
+        (import "env" "db_init_" (func $db_init (param i32 i32) (result i32)))
+        (import "env" "db_lookup_" (func $db_lookup (param i32 i32) (result i32)))
+        (import "env" "db_contains_key_" (func $db_contains_key (param i32 i32) (result i32)))
+        (import "env" "db_set_" (func $db_set (param i32 i32) (result i32)))
+        (memory (export "memory") 1)
+
+        ;; Set the object with the given index as return data
+        (func $return_object (param $idx i32) (result i64)
+            (drop (call $get_object_bytes (i32.const 8192) (local.get $idx)))
+            (call $set_return_data
+                (i32.const 8192)
+                (i32.wrap_i64 (call $get_object_size (local.get $idx)))))
+
+        (func (export "__metadata") (param i32) (result i64) (i64.const 0))
+    "#;
+
+    /// `__initialize` of the test contracts that have no db
+    const NO_INIT: &str =
+        r#"(func (export "__initialize") (param i32) (result i64) (i64.const 0))"#;
+
+    /// Bytes as a wat string literal
+    fn wat_bytes(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("\\{:02x}", b)).collect()
+    }
+
+    fn contract_id(n: u64) -> ContractId {
+        ContractId::from(pallas::Base::from(n))
+    }
+
+    /// Contract invoking the given contracts in order, with empty call data,
+    /// and returning the state update of the last one. It does so from its
+    /// `__entrypoint`, and from its `__invoke` if it's `invocable`.
+    fn invoker(calls: &[ContractId], invocable: bool) -> String {
+        let mut data = String::new();
+        let mut body = String::new();
+        for (i, contract_id) in calls.iter().enumerate() {
+            let args = [serialize(contract_id), serialize(&Vec::<u8>::new())].concat();
+            let offset = 4096 + i * 64;
+            data += &format!(r#"(data (i32.const {}) "{}")"#, offset, wat_bytes(&args));
+            body += &format!(
+                "(local.set $ret (call $invoke_contract (i32.const {}) (i32.const {})))
+                 (if (i64.lt_s (local.get $ret) (i64.const 0)) (then (return (local.get $ret))))",
+                offset,
+                args.len()
+            );
+        }
+
+        let invoke = if invocable {
+            r#"(func (export "__invoke") (param i32) (result i64) (call $run))"#
+        } else {
+            ""
+        };
+
+        format!(
+            r#"(module {PRELUDE} {NO_INIT} {data}
+                (func $run (result i64)
+                    (local $ret i64)
+                    (local.set $ret (i64.const -1))
+                    {body}
+                    (if (i64.ge_s (local.get $ret) (i64.const 0))
+                        (then (return (call $return_object (i32.wrap_i64 (local.get $ret))))))
+                    (i64.const 0))
+                (func (export "__entrypoint") (param i32) (result i64) (call $run))
+                {invoke}
+                (func (export "__update") (param i32) (result i64) (i64.const 0)))"#
+        )
+    }
+
+    /// Contract whose state update says if the key `key` is set in its
+    /// `counter` db, and whose `__update` sets it.
+    fn counter(contract_id: &ContractId) -> String {
+        let db = serialize(&(*contract_id, "counter".to_string()));
+        let key = serialize(&b"key".to_vec());
+        let key_value = [key.clone(), serialize(&b"value".to_vec())].concat();
+
+        format!(
+            r#"(module {PRELUDE}
+                (data (i32.const 4096) "{}")
+                (data (i32.const 6148) "{}")
+                (data (i32.const 7172) "{}")
+                (func $lookup (result i32) (call $db_lookup (i32.const 4096) (i32.const {})))
+                (func (export "__initialize") (param i32) (result i64)
+                    (i64.extend_i32_s (call $db_init (i32.const 4096) (i32.const {}))))
+                (func (export "__entrypoint") (param i32) (result i64) (i64.const 0))
+                (func (export "__invoke") (param i32) (result i64)
+                    (i32.store (i32.const 6144) (call $lookup))
+                    (i32.store8
+                        (i32.const 8192)
+                        (call $db_contains_key (i32.const 6144) (i32.const {})))
+                    (call $set_return_data (i32.const 8192) (i32.const 1)))
+                (func (export "__update") (param i32) (result i64)
+                    (i32.store (i32.const 7168) (call $lookup))
+                    (i64.extend_i32_s (call $db_set (i32.const 7168) (i32.const {})))))"#,
+            wat_bytes(&db),
+            wat_bytes(&key),
+            wat_bytes(&key_value),
+            db.len(),
+            db.len(),
+            4 + key.len(),
+            4 + key_value.len(),
+        )
+    }
+
+    /// Contract returning the serialized ID of the contract that invoked it
+    fn whoami() -> String {
+        format!(
+            r#"(module {PRELUDE} {NO_INIT}
+                (func $run (result i64)
+                    (local $ret i64)
+                    (local.set $ret (call $get_caller))
+                    (if (i64.lt_s (local.get $ret) (i64.const 0)) (then (return (local.get $ret))))
+                    (call $return_object (i32.wrap_i64 (local.get $ret))))
+                (func (export "__entrypoint") (param i32) (result i64) (call $run))
+                (func (export "__invoke") (param i32) (result i64) (call $run))
+                (func (export "__update") (param i32) (result i64) (i64.const 0)))"#
+        )
+    }
+
+    /// Contract looping until it runs out of gas when invoked
+    fn looper() -> String {
+        format!(
+            r#"(module {PRELUDE} {NO_INIT}
+                (func (export "__entrypoint") (param i32) (result i64) (i64.const 0))
+                (func (export "__invoke") (param i32) (result i64) (loop $l (br $l)) (i64.const 0))
+                (func (export "__update") (param i32) (result i64) (i64.const 0)))"#
+        )
+    }
+
+    /// Contract looping `iterations` times before invoking `callee`
+    fn spend_then_invoke(callee: &ContractId, iterations: u32) -> String {
+        let args = [serialize(callee), serialize(&Vec::<u8>::new())].concat();
+        format!(
+            r#"(module {PRELUDE} {NO_INIT}
+                (data (i32.const 4096) "{}")
+                (func (export "__entrypoint") (param i32) (result i64)
+                    (local $i i32)
+                    (loop $l
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $l (i32.lt_u (local.get $i) (i32.const {iterations}))))
+                    (call $invoke_contract (i32.const 4096) (i32.const {})))
+                (func (export "__update") (param i32) (result i64) (i64.const 0)))"#,
+            wat_bytes(&args),
+            args.len(),
+        )
+    }
+
+    fn deploy(blockchain: &Blockchain, contract_id: ContractId, wat: &str) -> Result<()> {
+        let mut runtime = Runtime::new(wat.as_bytes(), blockchain.clone(), contract_id)?;
+        runtime.deploy(&[])
+    }
+
+    /// Execute the given contract, returning its state update and gas used
+    fn exec(blockchain: &Blockchain, contract_id: ContractId) -> Result<(Vec<u8>, u64)> {
+        let wasm = blockchain.wasm_bincode.get(contract_id)?;
+        let mut runtime = Runtime::new(&wasm, blockchain.clone(), contract_id)?;
+        let update = runtime.exec(&[])?;
+        Ok((update, runtime.gas_used()))
+    }
+
+    /// Error returned by the contract, if any
+    fn contract_error(result: Result<(Vec<u8>, u64)>) -> Option<ContractError> {
+        match result {
+            Err(Error::ContractError(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn invoke_charges_callee_gas() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let (a, b, c) = (contract_id(1), contract_id(2), contract_id(3));
+        let (d, looper_id) = (contract_id(4), contract_id(5));
+        deploy(&blockchain, a, &invoker(&[], true))?;
+        deploy(&blockchain, b, &invoker(&[a], true))?;
+        deploy(&blockchain, c, &invoker(&[b], true))?;
+        deploy(&blockchain, d, &invoker(&[looper_id], true))?;
+        deploy(&blockchain, looper_id, &looper())?;
+
+        let (_, alone) = exec(&blockchain, a)?;
+        let (_, nested) = exec(&blockchain, b)?;
+        let (_, nested_twice) = exec(&blockchain, c)?;
+
+        // The caller pays for the callee and its own invocations
+        assert!(nested >= alone + GAS_INVOKE);
+        assert!(nested_twice >= nested + GAS_INVOKE);
+
+        // The callee can only use what the caller has left, and the gas
+        // it used is charged even though it failed.
+        let wasm = blockchain.wasm_bincode.get(d)?;
+        let mut runtime = Runtime::new(&wasm, blockchain.clone(), d)?;
+        assert!(matches!(runtime.exec(&[]), Err(Error::GasLimitExceeded(_, GAS_LIMIT))));
+        assert!(runtime.gas_used() > GAS_LIMIT);
+
+        // What the caller has left excludes the gas its wasm code used, so
+        // the callee and the caller's host functions get at most the rest.
+        let spender = contract_id(6);
+        deploy(&blockchain, spender, &spend_then_invoke(&looper_id, 80_000))?;
+        let wasm = blockchain.wasm_bincode.get(spender)?;
+        let mut runtime = Runtime::new(&wasm, blockchain.clone(), spender)?;
+        runtime.set_invoker(vec![], 1_000_000);
+        assert!(runtime.exec(&[]).is_err());
+        assert!(runtime.gas_used() > 1_000_000);
+        assert!(runtime.ctx.as_ref(&runtime.store).host_gas_used.get() < 400_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invoke_rejects_reentrancy() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let (a, b) = (contract_id(1), contract_id(2));
+
+        // Directly
+        deploy(&blockchain, a, &invoker(&[a], true))?;
+        let err = contract_error(exec(&blockchain, a));
+        assert!(matches!(err, Some(ContractError::CallerAccessDenied)));
+
+        // Through another contract
+        deploy(&blockchain, a, &invoker(&[b], true))?;
+        deploy(&blockchain, b, &invoker(&[a], true))?;
+        let err = contract_error(exec(&blockchain, a));
+        assert!(matches!(err, Some(ContractError::InvokeFailed)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invoke_depth_limit() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let ids: Vec<ContractId> = (0..=MAX_INVOKE_DEPTH as u64).map(contract_id).collect();
+
+        // Every contract invokes the next one, the last one nothing
+        for (i, id) in ids.iter().enumerate() {
+            deploy(&blockchain, *id, &invoker(&ids[i + 1..(i + 2).min(ids.len())], true))?;
+        }
+
+        // Up to MAX_INVOKE_DEPTH contracts deep
+        assert!(exec(&blockchain, ids[1]).is_ok());
+        let err = contract_error(exec(&blockchain, ids[0]));
+        assert!(matches!(err, Some(ContractError::InvokeFailed)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invoke_requires_opt_in() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let (a, b) = (contract_id(1), contract_id(2));
+        deploy(&blockchain, a, &invoker(&[b], true))?;
+        deploy(&blockchain, b, &invoker(&[], false))?;
+
+        let err = contract_error(exec(&blockchain, a));
+        assert!(matches!(err, Some(ContractError::InvokeFailed)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invoke_exposes_caller() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let (a, w) = (contract_id(1), contract_id(2));
+        deploy(&blockchain, a, &invoker(&[w], true))?;
+        deploy(&blockchain, w, &whoami())?;
+
+        assert_eq!(exec(&blockchain, a)?.0, serialize(&a));
+        // Not invoked by anyone
+        assert!(exec(&blockchain, w).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invoke_merges_state() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let (a, b, counter_id) = (contract_id(1), contract_id(2), contract_id(3));
+        deploy(&blockchain, counter_id, &counter(&counter_id))?;
+        deploy(&blockchain, a, &invoker(&[counter_id, counter_id], true))?;
+        deploy(&blockchain, b, &invoker(&[counter_id], true))?;
+        let tree = blockchain.contracts.lookup(&blockchain.sled_db, &counter_id, "counter")?;
+
+        // The second invocation sees the write of the first one
+        let wasm = blockchain.wasm_bincode.get(a)?;
+        let mut runtime = Runtime::new(&wasm, blockchain.clone(), a)?;
+        assert_eq!(runtime.exec(&[])?, vec![1]);
+
+        // But nothing is staged in the blockchain until the caller commits
+        assert_eq!(blockchain.overlay.get(&tree, b"key")?, None);
+        let (update, _) = exec(&blockchain, b)?;
+        assert_eq!(update, vec![0]);
+
+        runtime.update(&[])?;
+        runtime.commit()?;
+        assert_eq!(blockchain.overlay.get(&tree, b"key")?, Some(b"value".to_vec()));
+        assert!(tree.get(b"key")?.is_none());
+        assert_eq!(exec(&blockchain, b)?.0, vec![1]);
+
+        Ok(())
+    }
+}
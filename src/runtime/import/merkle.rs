@@ -26,15 +26,18 @@ use darkfi_serial::{serialize, Decodable, Encodable, WriteExt};
 use log::{debug, error};
 use wasmer::{FunctionEnvMut, WasmPtr};
 
-use crate::runtime::vm_runtime::{ContractSection, Env};
+use crate::runtime::{
+    gas::{db_read_cost, db_write_cost, host_call_cost, GAS_MERKLE_APPEND},
+    vm_runtime::{ContractSection, Env},
+};
 
 type MerkleTree = BridgeTree<MerkleNode, { MERKLE_DEPTH }>;
 
-pub(crate) fn merkle_add(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
-    let env = ctx.data();
+pub(crate) fn merkle_add(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i32 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Update => {
-            let memory_view = env.memory_view(&ctx);
+            let memory_view = env.memory_view(&store);
 
             let Ok(mem_slice) = ptr.slice(&memory_view, len) else {
                 error!(target: "runtime::merkle", "Failed to make slice from ptr");
@@ -113,6 +116,11 @@ pub(crate) fn merkle_add(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -
 
             // TODO: Ensure we've read the entire buffer above.
 
+            let appends = coins.len() as u64 * GAS_MERKLE_APPEND;
+            if !env.charge_gas(&mut store, host_call_cost(len as usize) + appends) {
+                return -2
+            }
+
             // Read the current tree
             let ret = match db_info.get(&key) {
                 Ok(v) => v,
@@ -176,8 +184,16 @@ pub(crate) fn merkle_add(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -
                 error!(target: "runtime::merkle", "Couldn't reserialize modified tree");
                 return -2
             }
+
+            // Reading the tree, and writing it back along with the new roots
+            let written = tree_data.len() + key.len() + new_roots.len() * 32;
+            let gas = db_read_cost(return_data.len()) + db_write_cost(written);
+            if !env.charge_gas(&mut store, gas) {
+                return -2
+            }
+
             let db_info_batch = &mut db_batches[info_handle_idx];
-            db_info_batch.insert(key, tree_data);
+            db_info_batch.insert(key, Some(tree_data));
 
             // Here we add the Merkle root to our set of roots
             // TODO: We should probably make sure that this root isn't in the set
@@ -191,7 +207,7 @@ pub(crate) fn merkle_add(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -
                 // FIXME: This assert can be used to DoS nodes from contracts
                 assert_eq!(root_value.len(), 32);
                 //db_roots_batch.insert(root_index, root_value);
-                db_roots_batch.insert(root_value, &[]);
+                db_roots_batch.insert(root_value, Some(vec![]));
             }
 
             0
@@ -19,6 +19,12 @@
 /// Host functions for interacting with db backend
 pub(crate) mod db;
 
+/// Host functions for contract events
+pub(crate) mod event;
+
+/// Host functions for cross-contract calls
+pub(crate) mod invoke;
+
 /// Host functions for merkle tree functions
 pub(crate) mod merkle;
 
@@ -19,13 +19,20 @@
 use log::error;
 use wasmer::{FunctionEnvMut, WasmPtr};
 
-use crate::runtime::vm_runtime::{ContractSection, Env};
+use crate::runtime::{
+    gas::{host_call_cost, GAS_HOST_CALL},
+    vm_runtime::{ContractSection, Env},
+};
 
 /// Host function for logging strings.
 /// This is injected into the runtime with wasmer's `imports!` macro.
-pub(crate) fn drk_log(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) {
-    let env = ctx.data();
-    let memory_view = env.memory_view(&ctx);
+pub(crate) fn drk_log(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) {
+    let (env, mut store) = ctx.data_and_store_mut();
+    if !env.charge_gas(&mut store, host_call_cost(len as usize)) {
+        return
+    }
+
+    let memory_view = env.memory_view(&store);
 
     match ptr.read_utf8_string(&memory_view, len) {
         Ok(msg) => {
@@ -39,11 +46,15 @@ pub(crate) fn drk_log(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) {
     }
 }
 
-pub(crate) fn set_return_data(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
-    let env = ctx.data();
+pub(crate) fn set_return_data(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
     match env.contract_section {
         ContractSection::Exec | ContractSection::Metadata => {
-            let memory_view = env.memory_view(&ctx);
+            if !env.charge_gas(&mut store, host_call_cost(len as usize)) {
+                return darkfi_sdk::error::INTERNAL_ERROR
+            }
+
+            let memory_view = env.memory_view(&store);
 
             let Ok(slice) = ptr.slice(&memory_view, len) else {
                 return darkfi_sdk::error::INTERNAL_ERROR
@@ -64,9 +75,13 @@ pub(crate) fn set_return_data(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u
     }
 }
 
-pub(crate) fn put_object_bytes(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
-    let env = ctx.data();
-    let memory_view = env.memory_view(&ctx);
+pub(crate) fn put_object_bytes(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len: u32) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    if !env.charge_gas(&mut store, host_call_cost(len as usize)) {
+        return -2
+    }
+
+    let memory_view = env.memory_view(&store);
 
     //debug!(target: "runtime::util", "diagnostic:");
     //let pages = memory_view.size().0;
@@ -99,11 +114,10 @@ pub(crate) fn put_object_bytes(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, len:
     obj_idx as i64
 }
 
-pub(crate) fn get_object_bytes(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, idx: u32) -> i64 {
+pub(crate) fn get_object_bytes(mut ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, idx: u32) -> i64 {
     // Get the slice, where we will read the size of the buffer
 
-    let env = ctx.data();
-    let memory_view = env.memory_view(&ctx);
+    let (env, mut store) = ctx.data_and_store_mut();
 
     // Get the object from env
 
@@ -113,6 +127,11 @@ pub(crate) fn get_object_bytes(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, idx:
         return -5
     }
     let obj = &objects[idx as usize];
+    if !env.charge_gas(&mut store, host_call_cost(obj.len())) {
+        return -2
+    }
+
+    let memory_view = env.memory_view(&store);
 
     // Read N bytes from the object and write onto the ptr.
 
@@ -131,11 +150,14 @@ pub(crate) fn get_object_bytes(ctx: FunctionEnvMut<Env>, ptr: WasmPtr<u8>, idx:
     0
 }
 
-pub(crate) fn get_object_size(ctx: FunctionEnvMut<Env>, idx: u32) -> i64 {
+pub(crate) fn get_object_size(mut ctx: FunctionEnvMut<Env>, idx: u32) -> i64 {
     // Get the slice, where we will read the size of the buffer
 
-    let env = ctx.data();
-    //let memory_view = env.memory_view(&ctx);
+    let (env, mut store) = ctx.data_and_store_mut();
+    if !env.charge_gas(&mut store, GAS_HOST_CALL) {
+        return -2
+    }
+    //let memory_view = env.memory_view(&store);
 
     // Get the object from env
 
@@ -148,3 +170,146 @@ pub(crate) fn get_object_size(ctx: FunctionEnvMut<Env>, idx: u32) -> i64 {
     let obj = &objects[idx as usize];
     obj.len() as i64
 }
+
+/// Host function returning the slot of the block the contract calls
+/// belong to.
+pub(crate) fn get_current_slot(mut ctx: FunctionEnvMut<Env>) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    if !env.charge_gas(&mut store, GAS_HOST_CALL) {
+        return darkfi_sdk::error::INTERNAL_ERROR
+    }
+
+    env.block_context.slot as i64
+}
+
+/// Host function returning the epoch of the block the contract calls
+/// belong to.
+pub(crate) fn get_current_epoch(mut ctx: FunctionEnvMut<Env>) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    if !env.charge_gas(&mut store, GAS_HOST_CALL) {
+        return darkfi_sdk::error::INTERNAL_ERROR
+    }
+
+    env.block_context.epoch as i64
+}
+
+/// Host function returning the timestamp of the block before the one the
+/// contract calls belong to, in seconds since the UNIX epoch.
+pub(crate) fn get_last_block_timestamp(mut ctx: FunctionEnvMut<Env>) -> i64 {
+    let (env, mut store) = ctx.data_and_store_mut();
+    if !env.charge_gas(&mut store, GAS_HOST_CALL) {
+        return darkfi_sdk::error::INTERNAL_ERROR
+    }
+
+    env.block_context.last_block_timestamp.0
+}
+
+#[cfg(test)]
+mod tests {
+    use darkfi_sdk::{crypto::ContractId, pasta::pallas};
+
+    use crate::{
+        blockchain::tests::test_blockchain,
+        runtime::vm_runtime::{BlockContext, Runtime},
+        util::time::Timestamp,
+        Result,
+    };
+
+    /// Contract looping `iterations` times before logging a 5000 bytes message
+    fn spend_then_log(iterations: u32) -> String {
+        format!(
+            r#"(module
+                (import "env" "drk_log_" (func $drk_log (param i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "__initialize") (param i32) (result i64) (i64.const 0))
+                (func (export "__metadata") (param i32) (result i64) (i64.const 0))
+                (func (export "__entrypoint") (param i32) (result i64)
+                    (local $i i32)
+                    (loop $l
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $l (i32.lt_u (local.get $i) (i32.const {iterations}))))
+                    (call $drk_log (i32.const 4096) (i32.const 5000))
+                    (i64.const 0))
+                (func (export "__update") (param i32) (result i64) (i64.const 0)))"#
+        )
+    }
+
+    /// Contract returning the slot, epoch and last block timestamp it reads
+    const CONTEXT_WAT: &str = r#"
+        (module
+            (import "env" "get_current_slot_" (func $get_current_slot (result i64)))
+            (import "env" "get_current_epoch_" (func $get_current_epoch (result i64)))
+            (import "env" "get_last_block_timestamp_" (func $get_last_block_timestamp (result i64)))
+            (import "env" "set_return_data_" (func $set_return_data (param i32 i32) (result i64)))
+            (memory (export "memory") 1)
+            (func (export "__initialize") (param i32) (result i64) (i64.const 0))
+            (func (export "__metadata") (param i32) (result i64) (i64.const 0))
+            (func (export "__entrypoint") (param i32) (result i64)
+                (i64.store (i32.const 4096) (call $get_current_slot))
+                (i64.store (i32.const 4104) (call $get_current_epoch))
+                (i64.store (i32.const 4112) (call $get_last_block_timestamp))
+                (call $set_return_data (i32.const 4096) (i32.const 24)))
+            (func (export "__update") (param i32) (result i64) (i64.const 0)))
+    "#;
+
+    #[test]
+    fn block_context_host_functions() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let contract_id = ContractId::from(pallas::Base::from(1));
+        let mut runtime = Runtime::new(CONTEXT_WAT.as_bytes(), blockchain, contract_id)?;
+
+        let read = |runtime: &mut Runtime| -> Result<Vec<i64>> {
+            let data = runtime.exec(&[])?;
+            Ok(data.chunks(8).map(|x| i64::from_le_bytes(x.try_into().unwrap())).collect())
+        };
+
+        // Contracts executed outside of a block see the default context
+        assert_eq!(read(&mut runtime)?, vec![0, 0, 0]);
+
+        let block_context =
+            BlockContext { slot: 42, epoch: 4, last_block_timestamp: Timestamp(1_234_567) };
+        runtime.set_block_context(block_context);
+        assert_eq!(read(&mut runtime)?, vec![42, 4, 1_234_567]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn host_and_wasm_gas_share_the_limit() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let contract_id = ContractId::from(pallas::Base::from(1));
+
+        // The loop and the log fit in the limit separately, so the log
+        // only gets refused if the gas the loop used is accounted for.
+        for (iterations, fits) in [(100, true), (1000, false)] {
+            let wat = spend_then_log(iterations);
+            let mut runtime = Runtime::new(wat.as_bytes(), blockchain.clone(), contract_id)?;
+            runtime.set_invoker(vec![], 10_000);
+
+            assert_eq!(runtime.exec(&[]).is_ok(), fits);
+            assert_eq!(runtime.gas_used() <= 10_000, fits);
+            let logs = runtime.ctx.as_ref(&runtime.store).logs.borrow().len();
+            assert_eq!(logs, fits as usize);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_memory_is_rejected() -> Result<()> {
+        let blockchain = test_blockchain()?;
+        let contract_id = ContractId::from(pallas::Base::from(1));
+
+        // Its cost depends on the size, which the metering can't charge for
+        let wat = r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "__entrypoint") (param i32) (result i64)
+                    (memory.fill (i32.const 0) (i32.const 0) (i32.const 65536))
+                    (i64.const 0)))
+        "#;
+        assert!(Runtime::new(wat.as_bytes(), blockchain, contract_id).is_err());
+
+        Ok(())
+    }
+}
@@ -19,6 +19,9 @@
 /// Main wasm vm runtime implementation
 pub mod vm_runtime;
 
+/// Gas costs of wasm operators and host functions
+pub mod gas;
+
 /// VM memory access (read/write)
 pub(crate) mod memory;
 
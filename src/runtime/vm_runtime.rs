@@ -21,27 +21,39 @@ use std::{
     sync::Arc,
 };
 
-use darkfi_sdk::{crypto::ContractId, entrypoint};
+use darkfi_sdk::{
+    crypto::ContractId, db::SMART_CONTRACT_ZKAS_DB_NAME, entrypoint, event::ContractEvent,
+};
 use darkfi_serial::serialize;
 use log::{debug, error, info};
 use wasmer::{
-    imports, wasmparser::Operator, AsStoreRef, CompilerConfig, Function, FunctionEnv, Instance,
-    Memory, MemoryView, Module, Pages, Store, Value, WASM_PAGE_SIZE,
+    imports, AsStoreMut, AsStoreRef, CompilerConfig, EngineBuilder, Features, Function,
+    FunctionEnv, Instance, Memory, MemoryView, Module, Pages, Store, Value, WASM_PAGE_SIZE,
 };
 use wasmer_compiler_singlepass::Singlepass;
 use wasmer_middlewares::{
-    metering::{get_remaining_points, MeteringPoints},
+    metering::{get_remaining_points, set_remaining_points, MeteringPoints},
     Metering,
 };
 
-use super::{import, import::db::DbHandle, memory::MemoryManipulation};
-use crate::{blockchain::Blockchain, Error, Result};
+use super::{
+    gas::{op_cost, GAS_LIMIT},
+    import,
+    import::db::DbHandle,
+    memory::MemoryManipulation,
+};
+use crate::{
+    blockchain::{writes_batch, Blockchain, StateOverlay, TreeWrites},
+    util::time::Timestamp,
+    Error, Result,
+};
 
 /// Name of the wasm linear memory in our guest module
 const MEMORY: &str = "memory";
 
-/// Gas limit for a contract
-const GAS_LIMIT: u64 = 200000000;
+/// Function run by `invoke_contract`. Only the contracts exporting it can be
+/// invoked by other contracts.
+const INVOKE_ENTRYPOINT: &str = "__invoke";
 
 #[derive(Clone, Copy)]
 pub enum ContractSection {
@@ -69,14 +81,37 @@ impl ContractSection {
     }
 }
 
+/// Blockchain context a contract is executed in, which it can read with
+/// the `get_current_slot`, `get_current_epoch` and `get_last_block_timestamp`
+/// host functions. Filled in by the validator, see
+/// [`Runtime::set_block_context`].
+#[derive(Clone, Copy, Debug)]
+pub struct BlockContext {
+    /// Slot of the block the contract calls belong to
+    pub slot: u64,
+    /// Epoch of that slot
+    pub epoch: u64,
+    /// Timestamp of the block before it
+    pub last_block_timestamp: Timestamp,
+}
+
+impl Default for BlockContext {
+    fn default() -> Self {
+        Self { slot: 0, epoch: 0, last_block_timestamp: Timestamp(0) }
+    }
+}
+
 /// The wasm vm runtime instantiated for every smart contract that runs.
 pub struct Env {
     /// Blockchain access
     pub blockchain: Blockchain,
     /// sled tree handles used with `db_*`
     pub db_handles: RefCell<Vec<DbHandle>>,
-    /// sled tree batches, indexed the same as `db_handles`.
-    pub db_batches: RefCell<Vec<sled::Batch>>,
+    /// sled tree writes, indexed the same as `db_handles`.
+    pub db_batches: RefCell<Vec<TreeWrites>>,
+    /// Writes to the `ContractStateStore` registering the trees created
+    /// with `db_init`, written along with `db_batches`.
+    pub state_pointers: RefCell<TreeWrites>,
     /// The contract ID being executed
     pub contract_id: ContractId,
     /// The compiled wasm bincode being executed,
@@ -87,10 +122,28 @@ pub struct Env {
     pub contract_return_data: Cell<Option<Vec<u8>>>,
     /// Logs produced by the contract
     pub logs: RefCell<Vec<String>>,
+    /// Events emitted by the contract, and by the contracts it invoked
+    pub events: RefCell<Vec<ContractEvent>>,
     /// Direct memory access to the VM
     pub memory: Option<Memory>,
+    /// The instance being executed, holding the metering points
+    pub instance: Option<Instance>,
     /// Object store for transferring memory from the host to VM
     pub objects: RefCell<Vec<Vec<u8>>>,
+    /// Gas charged by host functions
+    pub host_gas_used: Cell<u64>,
+    /// Set once a host function exceeded the gas limit
+    pub gas_exhausted: Cell<bool>,
+    /// Gas limit of the runtime, lower than `GAS_LIMIT` for invoked contracts
+    pub gas_limit: u64,
+    /// Contracts that invoked the one being executed, outermost first
+    pub call_stack: Vec<ContractId>,
+    /// State changes of the contracts invoked with `invoke_contract`, staged
+    /// on top of the blockchain's overlay so the following invocations see
+    /// them, and moved into it when the runtime is committed.
+    pub invoke_overlay: RefCell<Option<StateOverlay>>,
+    /// Blockchain context exposed to the contract
+    pub block_context: BlockContext,
 }
 
 impl Env {
@@ -109,6 +162,39 @@ impl Env {
     pub fn memory(&self) -> &Memory {
         self.memory.as_ref().unwrap()
     }
+
+    /// Gas left to the runtime, for both the wasm code and host functions.
+    pub fn gas_left(&self, store: &mut impl AsStoreMut) -> u64 {
+        if self.gas_exhausted.get() {
+            return 0
+        }
+
+        match get_remaining_points(store, self.instance.as_ref().unwrap()) {
+            MeteringPoints::Remaining(rem) => rem,
+            MeteringPoints::Exhausted => 0,
+        }
+    }
+
+    /// Charge gas for work done by a host function. It's taken from the
+    /// metering points the wasm code uses, so the gas limit applies to
+    /// their sum. Returns `false` if the gas limit got exceeded, in which
+    /// case the host function should fail.
+    pub fn charge_gas(&self, store: &mut impl AsStoreMut, gas: u64) -> bool {
+        self.host_gas_used.set(self.host_gas_used.get().saturating_add(gas));
+        let gas_left = self.gas_left(store);
+        let instance = self.instance.as_ref().unwrap();
+
+        if gas > gas_left {
+            error!(target: "runtime::vm_runtime", "Host functions exceeded the gas limit");
+            // Nothing is left for the wasm code either
+            set_remaining_points(store, instance, 0);
+            self.gas_exhausted.set(true);
+            return false
+        }
+
+        set_remaining_points(store, instance, gas_left - gas);
+        true
+    }
 }
 
 pub struct Runtime {
@@ -121,28 +207,21 @@ impl Runtime {
     /// Create a new wasm runtime instance that contains the given wasm module.
     pub fn new(wasm_bytes: &[u8], blockchain: Blockchain, contract_id: ContractId) -> Result<Self> {
         info!(target: "runtime::vm_runtime", "Instantiating a new runtime");
-        // This function will be called for each `Operator` encountered during
-        // the wasm module execution. It should return the cost of the operator
-        // that it received as its first argument.
-        // https://docs.rs/wasmparser/latest/wasmparser/enum.Operator.html
-        let cost_function = |operator: &Operator| -> u64 {
-            match operator {
-                Operator::LocalGet { .. } => 1,
-                Operator::I32Const { .. } => 1,
-                Operator::I32Add { .. } => 2,
-                _ => 0,
-            }
-        };
-
         // `Metering` needs to be conigured with a limit and a cost function.
         // For each `Operator`, the metering middleware will call the cost
         // function and subtract the cost from the remaining points.
-        let metering = Arc::new(Metering::new(GAS_LIMIT, cost_function));
+        let metering = Arc::new(Metering::new(GAS_LIMIT, op_cost));
 
         // Define the compiler and middleware, engine, and store
         let mut compiler_config = Singlepass::new();
         compiler_config.push_middleware(metering);
-        let mut store = Store::new(compiler_config);
+
+        // The cost of bulk memory operations depends on sizes only known at
+        // runtime, which the metering can't charge for, so they're disabled.
+        let mut features = Features::new();
+        features.bulk_memory(false);
+        let engine = EngineBuilder::new(compiler_config).set_features(Some(features)).engine();
+        let mut store = Store::new(engine);
 
         debug!(target: "runtime::vm_runtime", "Compiling module");
         let module = Module::new(&store, wasm_bytes)?;
@@ -160,13 +239,22 @@ impl Runtime {
                 blockchain,
                 db_handles,
                 db_batches,
+                state_pointers: RefCell::new(TreeWrites::new()),
                 contract_id,
                 contract_bincode: wasm_bytes.to_vec(),
                 contract_section: ContractSection::Null,
                 contract_return_data: Cell::new(None),
                 logs,
+                events: RefCell::new(vec![]),
                 memory: None,
+                instance: None,
                 objects: RefCell::new(vec![]),
+                host_gas_used: Cell::new(0),
+                gas_exhausted: Cell::new(false),
+                gas_limit: GAS_LIMIT,
+                call_stack: vec![],
+                invoke_overlay: RefCell::new(None),
+                block_context: BlockContext::default(),
             },
         );
 
@@ -220,6 +308,18 @@ impl Runtime {
                     import::db::db_del,
                 ),
 
+                "db_range_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::db::db_range,
+                ),
+
+                "db_prefix_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::db::db_prefix,
+                ),
+
                 "put_object_bytes_" => Function::new_typed_with_env(
                     &mut store,
                     &ctx,
@@ -243,6 +343,42 @@ impl Runtime {
                     &ctx,
                     import::merkle::merkle_add,
                 ),
+
+                "invoke_contract_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::invoke::invoke_contract,
+                ),
+
+                "get_caller_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::invoke::get_caller,
+                ),
+
+                "emit_event_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::event::emit_event,
+                ),
+
+                "get_current_slot_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::util::get_current_slot,
+                ),
+
+                "get_current_epoch_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::util::get_current_epoch,
+                ),
+
+                "get_last_block_timestamp_" => Function::new_typed_with_env(
+                    &mut store,
+                    &ctx,
+                    import::util::get_last_block_timestamp,
+                ),
             }
         };
 
@@ -251,12 +387,24 @@ impl Runtime {
 
         let mut env_mut = ctx.as_mut(&mut store);
         env_mut.memory = Some(instance.exports.get_with_generics(MEMORY)?);
+        env_mut.instance = Some(instance.clone());
 
         Ok(Self { instance, store, ctx })
     }
 
     fn call(&mut self, section: ContractSection, payload: &[u8]) -> Result<Vec<u8>> {
-        debug!(target: "runtime::vm_runtime", "Calling {} method", section.name());
+        self.call_function(section, section.name(), payload)
+    }
+
+    /// Call the exported function with the given name, running it as the
+    /// given contract section.
+    fn call_function(
+        &mut self,
+        section: ContractSection,
+        name: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>> {
+        debug!(target: "runtime::vm_runtime", "Calling {} method", name);
 
         let mut env_mut = self.ctx.as_mut(&mut self.store);
         env_mut.contract_section = section;
@@ -273,8 +421,8 @@ impl Runtime {
         self.set_memory_page_size(pages_required as u32)?;
         self.copy_to_memory(&payload)?;
 
-        debug!(target: "runtime::vm_runtime", "Getting {} function", section.name());
-        let entrypoint = self.instance.exports.get_function(section.name())?;
+        debug!(target: "runtime::vm_runtime", "Getting {} function", name);
+        let entrypoint = self.instance.exports.get_function(name)?;
 
         debug!(target: "runtime::vm_runtime", "Executing wasm");
         let ret = match entrypoint.call(&mut self.store, &[Value::I32(0_i32)]) {
@@ -292,6 +440,14 @@ impl Runtime {
             }
         };
 
+        // Host functions refuse to work once the limit is hit, but the
+        // contract might still have returned successfully.
+        let gas_limit = self.gas_limit();
+        if self.gas_used() > gas_limit {
+            error!(target: "runtime::vm_runtime", "{}", self.gas_info());
+            return Err(Error::GasLimitExceeded(self.gas_used(), gas_limit))
+        }
+
         debug!(target: "runtime::vm_runtime", "wasm executed successfully");
         debug!(target: "runtime::vm_runtime", "Contract returned: {:?}", ret[0]);
 
@@ -331,21 +487,82 @@ impl Runtime {
         debug!(target: "runtime::vm_runtime", "[wasm-runtime] payload: {:?}", payload);
         let _ = self.call(ContractSection::Deploy, payload)?;
 
-        // If the above didn't fail, we write the batches.
-        // TODO: Make all the writes atomic in a transaction over all trees.
+        // If the above didn't fail, we write the batches. Deployments don't
+        // belong to a block, so they're written straight to the database.
         let env_mut = self.ctx.as_mut(&mut self.store);
-        for (idx, db) in env_mut.db_handles.get_mut().iter().enumerate() {
-            let batch = env_mut.db_batches.borrow()[idx].clone();
-            db.apply_batch(batch)?;
-            db.flush()?;
+        for (db, writes) in env_mut.db_handles.get_mut().iter().zip(env_mut.db_batches.get_mut()) {
+            db.apply(&std::mem::take(writes))?;
         }
 
+        let state_pointers = std::mem::take(env_mut.state_pointers.get_mut());
+        env_mut.blockchain.contracts.0.apply_batch(writes_batch(&state_pointers))?;
+
         // Update the wasm bincode in the WasmStore
+        let env_mut = self.ctx.as_mut(&mut self.store);
         env_mut.blockchain.wasm_bincode.insert(env_mut.contract_id, &env_mut.contract_bincode)?;
 
         Ok(())
     }
 
+    /// This function runs when a smart contract gets deployed or upgraded on-chain.
+    /// Like `deploy()`, it runs the `INITIALIZE` symbol of the wasm code with the
+    /// given payload, but nothing gets written to the database: the state changes
+    /// are kept pending like the ones of `exec` and `update`, and get staged with
+    /// `commit()`. The given zkas circuits, pairs of namespace and bincode, are
+    /// set up in the contract's zkas db along with them.
+    /// Storing the wasm bincode is up to the caller.
+    pub fn initialize(
+        &mut self,
+        zkas_bincodes: &[(String, Vec<u8>)],
+        payload: &[u8],
+    ) -> Result<()> {
+        info!(target: "runtime::vm_runtime", "[wasm-runtime] Running initialize");
+        debug!(target: "runtime::vm_runtime", "[wasm-runtime] payload: {:?}", payload);
+
+        if !zkas_bincodes.is_empty() {
+            let env = self.ctx.as_ref(&self.store);
+            let blockchain = &env.blockchain;
+            let mut state_pointers = env.state_pointers.borrow_mut();
+            let (db, overlay) = (&blockchain.sled_db, &blockchain.overlay);
+
+            let zkas_db = match blockchain.contracts.lookup_pending(
+                db,
+                overlay,
+                &state_pointers,
+                &env.contract_id,
+                SMART_CONTRACT_ZKAS_DB_NAME,
+            ) {
+                Ok(v) => v,
+                Err(_) => {
+                    let (tree, writes) = blockchain.contracts.init_writes(
+                        db,
+                        overlay,
+                        &state_pointers,
+                        &env.contract_id,
+                        SMART_CONTRACT_ZKAS_DB_NAME,
+                    )?;
+                    state_pointers.extend(writes);
+                    tree
+                }
+            };
+
+            let writes: TreeWrites = zkas_bincodes
+                .iter()
+                .map(|(namespace, bincode)| (serialize(namespace), Some(bincode.clone())))
+                .collect();
+
+            env.db_handles.borrow_mut().push(DbHandle::new(
+                env.contract_id,
+                zkas_db,
+                overlay.clone(),
+            ));
+            env.db_batches.borrow_mut().push(writes);
+        }
+
+        let _ = self.call(ContractSection::Deploy, payload)?;
+        Ok(())
+    }
+
     /// This funcion runs when someone wants to execute a smart contract.
     /// The runtime will look for an `ENTRYPOINT` symbol in the wasm code, and
     /// execute it if found. A payload is also passed as an instruction that can
@@ -355,22 +572,56 @@ impl Runtime {
         self.call(ContractSection::Exec, payload)
     }
 
+    /// This function runs when another contract invokes this one with
+    /// `invoke_contract`. It's executed like `exec`, but runs the `INVOKE`
+    /// symbol of the wasm code, with the call data as payload.
+    /// Contracts opt in to being invoked by exporting it, since their
+    /// `metadata` isn't run, so no signatures or ZK proofs get verified.
+    pub fn invoke(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        debug!(target: "runtime::vm_runtime", "invoke: {:?}", payload);
+        self.call_function(ContractSection::Exec, INVOKE_ENTRYPOINT, payload)
+    }
+
+    /// Returns `true` if the contract exports the `INVOKE` symbol, and can
+    /// therefore be invoked by other contracts.
+    pub fn is_invocable(&self) -> bool {
+        self.instance.exports.get_function(INVOKE_ENTRYPOINT).is_ok()
+    }
+
     /// This function runs after successful execution of `exec` and tries to
     /// apply the state change to the sled databases.
     /// The runtime will lok for an `UPDATE` symbol in the wasm code, and execute
     /// it if found. The function does not take an arbitrary payload, but just takes
     /// a state update from `env` and passes it into the wasm runtime.
     pub fn apply(&mut self, update: &[u8]) -> Result<()> {
-        debug!(target: "runtime::vm_runtime", "apply: {:?}", update);
+        self.update(update)?;
+        self.commit()
+    }
+
+    /// Run the `UPDATE` section of the contract without writing anything,
+    /// so the gas it uses is known before deciding to apply the state change.
+    /// The changes can then be written with `commit`.
+    pub fn update(&mut self, update: &[u8]) -> Result<()> {
+        debug!(target: "runtime::vm_runtime", "update: {:?}", update);
         let _ = self.call(ContractSection::Update, update)?;
+        Ok(())
+    }
 
-        // If the above didn't fail, we write the batches.
-        // TODO: Make all the writes atomic in a transaction over all trees.
+    /// Stage the database writes of the previously executed sections in the
+    /// blockchain's `StateOverlay`. They're written to the database along with
+    /// the block they belong to, in `Blockchain::add()`.
+    pub fn commit(&mut self) -> Result<()> {
         let env_mut = self.ctx.as_mut(&mut self.store);
-        for (idx, db) in env_mut.db_handles.get_mut().iter().enumerate() {
-            let batch = env_mut.db_batches.borrow()[idx].clone();
-            db.apply_batch(batch)?;
-            db.flush()?;
+        for (db, writes) in env_mut.db_handles.get_mut().iter().zip(env_mut.db_batches.get_mut()) {
+            db.stage(std::mem::take(writes));
+        }
+
+        let state_pointers = std::mem::take(env_mut.state_pointers.get_mut());
+        env_mut.blockchain.overlay.insert(&env_mut.blockchain.contracts.0, state_pointers);
+
+        // The state changes of the invoked contracts are staged along with ours
+        if let Some(overlay) = env_mut.invoke_overlay.get_mut().take() {
+            overlay.merge();
         }
 
         Ok(())
@@ -380,6 +631,13 @@ impl Runtime {
         self.call(ContractSection::Metadata, payload)
     }
 
+    /// Take the events emitted by the previously executed sections, in the
+    /// order they were emitted. The events of invoked contracts come right
+    /// where they were invoked.
+    pub fn take_events(&mut self) -> Vec<ContractEvent> {
+        std::mem::take(self.ctx.as_mut(&mut self.store).events.get_mut())
+    }
+
     fn print_logs(&self) {
         let logs = self.ctx.as_ref(&self.store).logs.borrow();
         for msg in logs.iter() {
@@ -387,17 +645,47 @@ impl Runtime {
         }
     }
 
-    fn gas_info(&mut self) -> String {
-        let remaining_points = get_remaining_points(&mut self.store, &self.instance);
+    /// Gas used by all calls made on this runtime so far, both by the wasm
+    /// code and by the host functions it invoked.
+    pub fn gas_used(&mut self) -> u64 {
+        let gas_limit = self.gas_limit();
+        if self.ctx.as_ref(&self.store).gas_exhausted.get() {
+            return gas_limit + 1
+        }
 
-        match remaining_points {
-            MeteringPoints::Remaining(rem) => {
-                format!("Gas used: {}/{}", GAS_LIMIT - rem, GAS_LIMIT)
-            }
-            MeteringPoints::Exhausted => {
-                format!("Gas fully exhausted: {}/{}", GAS_LIMIT + 1, GAS_LIMIT)
-            }
+        match get_remaining_points(&mut self.store, &self.instance) {
+            MeteringPoints::Remaining(rem) => gas_limit - rem,
+            MeteringPoints::Exhausted => gas_limit + 1,
+        }
+    }
+
+    /// Gas limit of all calls made on this runtime.
+    pub fn gas_limit(&self) -> u64 {
+        self.ctx.as_ref(&self.store).gas_limit
+    }
+
+    /// Prepare the runtime for running a contract invoked by another one, with
+    /// the given contracts above it in the call stack and a lower gas limit.
+    /// Must be called before any call is made on the runtime.
+    pub(crate) fn set_invoker(&mut self, call_stack: Vec<ContractId>, gas_limit: u64) {
+        set_remaining_points(&mut self.store, &self.instance, gas_limit);
+        let env_mut = self.ctx.as_mut(&mut self.store);
+        env_mut.call_stack = call_stack;
+        env_mut.gas_limit = gas_limit;
+    }
+
+    /// Set the blockchain context exposed to the contract.
+    /// Must be called before any call is made on the runtime.
+    pub fn set_block_context(&mut self, block_context: BlockContext) {
+        self.ctx.as_mut(&mut self.store).block_context = block_context;
+    }
+
+    fn gas_info(&mut self) -> String {
+        let (gas_used, gas_limit) = (self.gas_used(), self.gas_limit());
+        if gas_used > gas_limit {
+            return format!("Gas fully exhausted: {}/{}", gas_used, gas_limit)
         }
+        format!("Gas used: {}/{}", gas_used, gas_limit)
     }
 
     /// Set the memory page size
@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use darkfi_serial::Encodable;
+use darkfi_serial::{deserialize, Encodable, SerialDecodable, SerialEncodable};
 
 use super::{
     crypto::ContractId,
@@ -37,6 +37,22 @@ pub const DB_GET_FAILED: i32 = -4;
 pub const DB_CONTAINS_KEY_FAILED: i32 = -5;
 pub const DB_SET_FAILED: i32 = -6;
 pub const DB_DEL_FAILED: i32 = -7;
+pub const DB_SCAN_FAILED: i32 = -8;
+
+/// Maximum number of records returned by a single [`db_range`] or
+/// [`db_prefix`] call. Larger limits get lowered to it, and a limit of zero
+/// is taken as one.
+pub const DB_SCAN_MAX_LIMIT: u32 = 1000;
+
+/// A page of records returned by [`db_range`] and [`db_prefix`], in key order.
+#[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
+pub struct DbPage {
+    /// Key-value pairs of the page
+    pub records: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Cursor to pass to the next call to get the following page, or `None`
+    /// if this is the last one
+    pub cursor: Option<Vec<u8>>,
+}
 
 /// Only deploy() can call this. Creates a new database instance for this contract.
 ///
@@ -65,6 +81,14 @@ pub fn db_init(contract_id: ContractId, db_name: &str) -> GenericResult<DbHandle
     }
 }
 
+/// Everyone can call this. Looks up a database handle from its name.
+/// The databases of other contracts can be looked up as well, but only
+/// read from, since writes are restricted to the executing contract.
+///
+/// ```
+///     type DbHandle = u32;
+///     db_lookup(contract_id, db_name) -> DbHandle
+/// ```
 pub fn db_lookup(contract_id: ContractId, db_name: &str) -> GenericResult<DbHandle> {
     unsafe {
         let mut len = 0;
@@ -140,6 +164,133 @@ pub fn db_contains_key(db_handle: DbHandle, key: &[u8]) -> GenericResult<bool> {
     }
 }
 
+/// Everyone can call this. Reads up to `limit` records with keys from `start`
+/// (inclusive) to `end` (exclusive, or unbounded if `None`), in key order.
+/// Records are charged gas like with `db_get`. To read the following records,
+/// call this again with the cursor of the returned page.
+///
+/// ```
+///     page = db_range(db_handle, start, end, None, limit);
+///     next = db_range(db_handle, start, end, page.cursor, limit);
+/// ```
+pub fn db_range(
+    db_handle: DbHandle,
+    start: &[u8],
+    end: Option<&[u8]>,
+    cursor: Option<&[u8]>,
+    limit: u32,
+) -> GenericResult<DbPage> {
+    let mut len = 0;
+    let mut buf = vec![];
+    len += db_handle.encode(&mut buf)?;
+    len += start.to_vec().encode(&mut buf)?;
+    len += end.map(|x| x.to_vec()).encode(&mut buf)?;
+    len += cursor.map(|x| x.to_vec()).encode(&mut buf)?;
+    len += limit.encode(&mut buf)?;
+
+    let ret = unsafe { db_range_(buf.as_ptr(), len as u32) };
+    get_page(ret)
+}
+
+/// Everyone can call this. Reads up to `limit` records with keys starting
+/// with `prefix`, in key order. Records are charged gas like with `db_get`.
+/// To read the following records, call this again with the cursor of the
+/// returned page.
+///
+/// ```
+///     page = db_prefix(db_handle, prefix, None, limit);
+///     next = db_prefix(db_handle, prefix, page.cursor, limit);
+/// ```
+pub fn db_prefix(
+    db_handle: DbHandle,
+    prefix: &[u8],
+    cursor: Option<&[u8]>,
+    limit: u32,
+) -> GenericResult<DbPage> {
+    let mut len = 0;
+    let mut buf = vec![];
+    len += db_handle.encode(&mut buf)?;
+    len += prefix.to_vec().encode(&mut buf)?;
+    len += cursor.map(|x| x.to_vec()).encode(&mut buf)?;
+    len += limit.encode(&mut buf)?;
+
+    let ret = unsafe { db_prefix_(buf.as_ptr(), len as u32) };
+    get_page(ret)
+}
+
+/// Iterate over all the records with keys starting with `prefix`, in key
+/// order. The records are fetched with [`db_prefix`] as needed, in pages of
+/// [`DB_SCAN_MAX_LIMIT`] records.
+///
+/// ```
+///     for record in db_iter_prefix(db_handle, prefix) {
+///         let (key, value) = record?;
+///     }
+/// ```
+pub fn db_iter_prefix(db_handle: DbHandle, prefix: &[u8]) -> DbPrefixIter {
+    DbPrefixIter {
+        db_handle,
+        prefix: prefix.to_vec(),
+        records: vec![].into_iter(),
+        cursor: None,
+        done: false,
+    }
+}
+
+/// Iterator returned by [`db_iter_prefix`].
+pub struct DbPrefixIter {
+    db_handle: DbHandle,
+    prefix: Vec<u8>,
+    records: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+    cursor: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl Iterator for DbPrefixIter {
+    type Item = GenericResult<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(record) = self.records.next() {
+            return Some(Ok(record))
+        }
+
+        if self.done {
+            return None
+        }
+
+        match db_prefix(self.db_handle, &self.prefix, self.cursor.as_deref(), DB_SCAN_MAX_LIMIT) {
+            Ok(page) => {
+                self.done = page.cursor.is_none();
+                self.cursor = page.cursor;
+                self.records = page.records.into_iter();
+                self.records.next().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Read the page returned by the `db_range_` and `db_prefix_` host functions.
+fn get_page(ret: i64) -> GenericResult<DbPage> {
+    if ret < 0 {
+        match ret as i32 {
+            CALLER_ACCESS_DENIED => return Err(ContractError::CallerAccessDenied),
+            DB_SCAN_FAILED => return Err(ContractError::DbScanFailed),
+            _ => unimplemented!(),
+        }
+    }
+
+    let obj = ret as u32;
+    let obj_size = get_object_size(obj);
+    let mut buf = vec![0u8; obj_size as usize];
+    get_object_bytes(&mut buf, obj);
+
+    Ok(deserialize(&buf)?)
+}
+
 /// Only update() can call this. Set a value within the transaction.
 ///
 /// ```
@@ -192,4 +343,6 @@ extern "C" {
     fn db_contains_key_(ptr: *const u8, len: u32) -> i32;
     fn db_set_(ptr: *const u8, len: u32) -> i32;
     fn db_del_(ptr: *const u8, len: u32) -> i32;
+    fn db_range_(ptr: *const u8, len: u32) -> i64;
+    fn db_prefix_(ptr: *const u8, len: u32) -> i64;
 }
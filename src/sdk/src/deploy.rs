@@ -0,0 +1,72 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi_serial::{SerialDecodable, SerialEncodable};
+
+use super::{crypto::PublicKey, error::ContractError};
+
+/// Functions of the native deployer, called with `DEPLOYOOOR_CONTRACT_ID`.
+/// The call data is the function byte, followed by its serialized parameters.
+#[repr(u8)]
+pub enum DeployFunction {
+    /// Deploy a new contract, with [`DeployParamsV1`]
+    Deploy = 0x00,
+    /// Replace the code of a deployed contract, with [`DeployParamsV1`]
+    Upgrade = 0x01,
+    /// Permanently forbid upgrades of a deployed contract, with [`LockParamsV1`]
+    Lock = 0x02,
+}
+
+impl TryFrom<u8> for DeployFunction {
+    type Error = ContractError;
+
+    fn try_from(b: u8) -> core::result::Result<DeployFunction, Self::Error> {
+        match b {
+            0x00 => Ok(Self::Deploy),
+            0x01 => Ok(Self::Upgrade),
+            0x02 => Ok(Self::Lock),
+            _ => Err(ContractError::InvalidFunction),
+        }
+    }
+}
+
+/// Parameters for deploying or upgrading a contract. The contract ID is
+/// derived from `public_key`, and the call has to be signed with the
+/// corresponding deploy key.
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct DeployParamsV1 {
+    /// Public key of the deploy key
+    pub public_key: PublicKey,
+    /// Number of times the contract was deployed or upgraded before, so a
+    /// signed deploy call can't be replayed to downgrade the contract
+    pub nonce: u64,
+    /// Compiled wasm bincode of the contract
+    pub wasm_bincode: Vec<u8>,
+    /// Compiled zkas bincodes of the contract's circuits
+    pub zkas_bincodes: Vec<Vec<u8>>,
+    /// Payload passed to the contract's `__initialize` function
+    pub ix: Vec<u8>,
+}
+
+/// Parameters for locking a contract. The call has to be signed with the
+/// contract's deploy key.
+#[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
+pub struct LockParamsV1 {
+    /// Public key of the deploy key
+    pub public_key: PublicKey,
+}
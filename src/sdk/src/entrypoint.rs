@@ -69,6 +69,34 @@ macro_rules! define_contract {
             }
         }
     };
+
+    // Contracts defining an `invoke` function can be invoked by other
+    // contracts, see `invoke_contract()`.
+    (
+        init: $init_func:ident,
+        exec: $exec_func:ident,
+        apply: $apply_func:ident,
+        metadata: $metadata_func:ident,
+        invoke: $invoke_func:ident
+    ) => {
+        $crate::define_contract!(
+            init: $init_func,
+            exec: $exec_func,
+            apply: $apply_func,
+            metadata: $metadata_func
+        );
+
+        /// # Safety
+        #[no_mangle]
+        pub unsafe extern "C" fn __invoke(input: *mut u8) -> i64 {
+            let (contract_id, call_data) = $crate::entrypoint::deserialize(input);
+
+            match $invoke_func(contract_id, &call_data) {
+                Ok(()) => $crate::entrypoint::SUCCESS,
+                Err(e) => e.into(),
+            }
+        }
+    };
 }
 
 /// Deserialize a given payload in `entrypoint`
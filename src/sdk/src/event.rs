@@ -0,0 +1,62 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi_serial::{Encodable, SerialDecodable, SerialEncodable};
+
+use super::{
+    crypto::ContractId,
+    error::{ContractError, GenericResult},
+};
+
+/// An event emitted by a contract with [`emit_event`]. Nodes store the
+/// events along with the transaction that emitted them, so wallets can
+/// follow contract activity without decoding the call data.
+#[derive(Debug, Clone, Eq, PartialEq, SerialEncodable, SerialDecodable)]
+pub struct ContractEvent {
+    /// ID of the contract that emitted the event
+    pub contract_id: ContractId,
+    /// Topic of the event, which events can be filtered by
+    pub topic: String,
+    /// Event data, in a format defined by the contract
+    pub data: Vec<u8>,
+}
+
+/// Only exec() and update() can call this. Emits an event with the given
+/// topic and data. The events are only stored if the transaction gets
+/// verified, and are charged gas like the database writes.
+///
+/// ```
+///     emit_event("proposal", &serialize(&proposal))?;
+/// ```
+pub fn emit_event(topic: &str, data: &[u8]) -> GenericResult<()> {
+    let mut len = 0;
+    let mut buf = vec![];
+    len += topic.to_string().encode(&mut buf)?;
+    len += data.to_vec().encode(&mut buf)?;
+
+    unsafe {
+        match emit_event_(buf.as_ptr(), len as u32) {
+            0 => Ok(()),
+            errcode => Err(ContractError::from(errcode)),
+        }
+    }
+}
+
+extern "C" {
+    fn emit_event_(ptr: *const u8, len: u32) -> i64;
+}
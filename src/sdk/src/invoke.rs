@@ -0,0 +1,95 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi_serial::{deserialize, Encodable};
+
+use super::{
+    crypto::ContractId,
+    error::{ContractError, GenericResult},
+    util::{get_object_bytes, get_object_size},
+};
+
+/// Only exec() can call this. Invokes another contract, running its
+/// `__invoke` function with the given call data, and right after that its
+/// `__update` with the state update it returned, which is also returned
+/// here. Contracts opt in to being invoked by defining an `invoke` function
+/// in [`define_contract!`](crate::define_contract).
+///
+/// * The gas used by the invoked contract is charged to the caller.
+/// * A contract can't invoke itself, nor any contract it was invoked by,
+///   and invocations can only be nested up to a fixed depth.
+/// * The state changes of the invoked contract are written along with the
+///   caller's, once the transaction is verified. The following invocations
+///   see them, but like for regular calls, the caller doesn't see them
+///   during its own execution.
+/// * The invoked contract's metadata isn't run, so it can't require any
+///   signatures or ZK proofs. Those need a regular call in the transaction.
+///   It can check which contract invoked it with [`get_caller`].
+///
+/// ```
+///     update = invoke_contract(contract_id, data);
+/// ```
+pub fn invoke_contract(contract_id: ContractId, data: &[u8]) -> GenericResult<Vec<u8>> {
+    let mut len = 0;
+    let mut buf = vec![];
+    len += contract_id.encode(&mut buf)?;
+    len += data.to_vec().encode(&mut buf)?;
+
+    let ret = unsafe { invoke_contract_(buf.as_ptr(), len as u32) };
+
+    if ret < 0 {
+        return Err(ContractError::from(ret))
+    }
+
+    let obj = ret as u32;
+    let obj_size = get_object_size(obj);
+    let mut buf = vec![0u8; obj_size as usize];
+    get_object_bytes(&mut buf, obj);
+
+    Ok(buf)
+}
+
+/// Everyone can call this. Returns the ID of the contract that invoked the
+/// one being executed, or `None` if it's executed as a regular contract call.
+///
+/// ```
+///     caller = get_caller()?;
+/// ```
+pub fn get_caller() -> GenericResult<Option<ContractId>> {
+    let ret = unsafe { get_caller_() };
+
+    if ret == -127 {
+        return Ok(None)
+    }
+
+    if ret < 0 {
+        return Err(ContractError::from(ret))
+    }
+
+    let obj = ret as u32;
+    let obj_size = get_object_size(obj);
+    let mut buf = vec![0u8; obj_size as usize];
+    get_object_bytes(&mut buf, obj);
+
+    Ok(Some(deserialize(&buf)?))
+}
+
+extern "C" {
+    fn invoke_contract_(ptr: *const u8, len: u32) -> i64;
+    fn get_caller_() -> i64;
+}
@@ -22,18 +22,32 @@ pub use pasta_curves as pasta;
 /// Database functions
 pub mod db;
 
+/// Native contract deployment
+pub mod deploy;
+
 /// Entrypoint used for the wasm binaries
 pub mod entrypoint;
 
 /// Error handling
 pub mod error;
 
+/// Contract events
+pub mod event;
+pub use event::emit_event;
+
 /// Logging infrastructure
 pub mod log;
 
 /// Crypto-related definitions
 pub mod crypto;
 
+/// Cross-contract calls
+pub mod invoke;
+pub use invoke::{get_caller, invoke_contract};
+
+/// Native money contract functions
+pub mod money;
+
 /// Merkle
 pub mod merkle;
 pub use merkle::merkle_add;
@@ -44,4 +58,4 @@ pub use tx::ContractCall;
 
 /// Utilities
 pub mod util;
-pub use util::set_return_data;
+pub use util::{get_current_epoch, get_current_slot, get_last_block_timestamp, set_return_data};
@@ -0,0 +1,48 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::error::ContractError;
+
+/// Functions of the native money contract, called with `MONEY_CONTRACT_ID`.
+/// The call data, and the state updates they produce, start with the
+/// function byte. They're defined here so the node can tell them apart.
+#[repr(u8)]
+pub enum MoneyFunction {
+    Transfer = 0x00,
+    OtcSwap = 0x01,
+    Stake = 0x02,
+    Unstake = 0x03,
+    Mint = 0x04,
+    Fee = 0x05,
+}
+
+impl TryFrom<u8> for MoneyFunction {
+    type Error = ContractError;
+
+    fn try_from(b: u8) -> core::result::Result<MoneyFunction, Self::Error> {
+        match b {
+            0x00 => Ok(Self::Transfer),
+            0x01 => Ok(Self::OtcSwap),
+            0x02 => Ok(Self::Stake),
+            0x03 => Ok(Self::Unstake),
+            0x04 => Ok(Self::Mint),
+            0x05 => Ok(Self::Fee),
+            _ => Err(ContractError::InvalidFunction),
+        }
+    }
+}
@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use super::error::ContractError;
+use super::error::{ContractError, GenericResult};
 
 pub fn set_return_data(data: &[u8]) -> Result<(), ContractError> {
     unsafe {
@@ -39,9 +39,59 @@ pub fn get_object_size(object_index: u32) -> i64 {
     unsafe { get_object_size_(object_index as u32) }
 }
 
+/// Everyone can call this. Returns the slot of the block the transaction
+/// is executed in. When verifying a transaction for the mempool, this is
+/// the current slot.
+///
+/// ```
+///     slot = get_current_slot()?;
+/// ```
+pub fn get_current_slot() -> GenericResult<u64> {
+    let ret = unsafe { get_current_slot_() };
+    if ret < 0 {
+        return Err(ContractError::from(ret))
+    }
+
+    Ok(ret as u64)
+}
+
+/// Everyone can call this. Returns the epoch of the slot returned by
+/// [`get_current_slot`].
+///
+/// ```
+///     epoch = get_current_epoch()?;
+/// ```
+pub fn get_current_epoch() -> GenericResult<u64> {
+    let ret = unsafe { get_current_epoch_() };
+    if ret < 0 {
+        return Err(ContractError::from(ret))
+    }
+
+    Ok(ret as u64)
+}
+
+/// Everyone can call this. Returns the timestamp of the last block of the
+/// canonical chain before the one the transaction is executed in, in
+/// seconds since the UNIX epoch.
+///
+/// ```
+///     timestamp = get_last_block_timestamp()?;
+/// ```
+pub fn get_last_block_timestamp() -> GenericResult<i64> {
+    let ret = unsafe { get_last_block_timestamp_() };
+    if ret < 0 {
+        return Err(ContractError::from(ret))
+    }
+
+    Ok(ret)
+}
+
 extern "C" {
     fn set_return_data_(ptr: *const u8, len: u32) -> i64;
     fn put_object_bytes_(ptr: *const u8, len: u32) -> i64;
     fn get_object_bytes_(ptr: *mut u8, len: u32) -> i64;
     fn get_object_size_(len: u32) -> i64;
+    fn get_current_slot_() -> i64;
+    fn get_current_epoch_() -> i64;
+    fn get_last_block_timestamp_() -> i64;
 }
@@ -25,8 +25,11 @@ pub use darkfi_derive::{SerialDecodable, SerialEncodable};
 mod async_serial;
 
 mod endian;
+mod limits;
 mod types;
 
+pub use limits::{decode_bounded, deserialize_bounded, DecodeLimits};
+
 /// Data which can be encoded in a consensus-consistent way.
 pub trait Encodable {
     /// Encode an object with a well-defined format.
@@ -459,7 +462,8 @@ impl<T: Decodable> Decodable for Vec<T> {
     #[inline]
     fn decode<D: Read>(mut d: D) -> Result<Self, Error> {
         let len = VarInt::decode(&mut d)?.0;
-        let mut ret = Vec::with_capacity(len as usize);
+        let _depth = limits::enter_collection(len)?;
+        let mut ret = Vec::with_capacity(limits::prealloc_len::<T>(len));
         for _ in 0..len {
             ret.push(Decodable::decode(&mut d)?);
         }
@@ -886,4 +890,34 @@ mod tests {
         assert_eq!(ts1, ts1_n);
         assert_eq!(ts1_n, TestStruct1(baz));
     }
+
+    #[test]
+    fn deserialize_bounded_test() {
+        let limits = DecodeLimits::new(64, 8, 2);
+
+        // Within limits
+        let v: Vec<Vec<u8>> = vec![vec![1, 2, 3], vec![4]];
+        let ser = serialize(&v);
+        assert_eq!(deserialize_bounded::<Vec<Vec<u8>>>(&ser, &limits).unwrap(), v);
+
+        // Too many bytes
+        assert!(deserialize_bounded::<Vec<u8>>(&serialize(&vec![0u8; 70]), &limits).is_err());
+
+        // Collection too long
+        assert!(deserialize_bounded::<Vec<u8>>(&serialize(&vec![0u8; 9]), &limits).is_err());
+
+        // Nested too deep
+        let v: Vec<Vec<Vec<u8>>> = vec![vec![vec![1]]];
+        assert!(deserialize_bounded::<Vec<Vec<Vec<u8>>>>(&serialize(&v), &limits).is_err());
+
+        // A huge length prefix with no data must fail cleanly instead of
+        // allocating, even without limits
+        let mut ser = vec![];
+        VarInt(u64::MAX).encode(&mut ser).unwrap();
+        assert!(deserialize::<Vec<u64>>(&ser).is_err());
+        assert!(deserialize_bounded::<Vec<u64>>(&ser, &DecodeLimits::default()).is_err());
+
+        // Limits are lifted once the bounded decode returns
+        assert!(deserialize::<Vec<u8>>(&serialize(&vec![0u8; 70])).is_ok());
+    }
 }
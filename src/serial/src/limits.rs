@@ -0,0 +1,151 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Bounded decoding of untrusted data.
+//!
+//! `Decodable::decode` has no room for extra context, so the active limits
+//! live in a thread-local that collection decoders consult. Decoding is
+//! synchronous, so the limits can't leak into unrelated decodes.
+use std::{
+    cell::RefCell,
+    io::{Cursor, Error, ErrorKind, Read},
+};
+
+use crate::Decodable;
+
+/// Upper bound on memory preallocated by a collection decoder from a length
+/// prefix. Collections longer than this grow as elements are actually read.
+pub(crate) const MAX_PREALLOC_BYTES: usize = 1024 * 1024;
+
+/// Limits applied when decoding untrusted data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum number of bytes consumed from the input
+    pub max_bytes: usize,
+    /// Maximum number of elements in a single collection
+    pub max_collection_len: u64,
+    /// Maximum nesting depth of collections
+    pub max_depth: usize,
+}
+
+impl DecodeLimits {
+    pub const fn new(max_bytes: usize, max_collection_len: u64, max_depth: usize) -> Self {
+        Self { max_bytes, max_collection_len, max_depth }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::new(32 * 1024 * 1024, 1024 * 1024, 32)
+    }
+}
+
+struct ActiveLimits {
+    limits: DecodeLimits,
+    depth: usize,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<ActiveLimits>> = const { RefCell::new(None) };
+}
+
+/// Restores the previously active limits once a bounded decode finishes,
+/// even if it bails out early.
+struct LimitsScope {
+    prev: Option<ActiveLimits>,
+}
+
+impl LimitsScope {
+    fn enter(limits: &DecodeLimits) -> Self {
+        let prev = ACTIVE.with(|a| a.replace(Some(ActiveLimits { limits: *limits, depth: 0 })));
+        Self { prev }
+    }
+}
+
+impl Drop for LimitsScope {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        ACTIVE.with(|a| *a.borrow_mut() = prev);
+    }
+}
+
+/// Tracks one level of collection nesting, released on drop.
+pub(crate) struct DepthGuard {
+    active: bool,
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        if self.active {
+            ACTIVE.with(|a| {
+                if let Some(active) = a.borrow_mut().as_mut() {
+                    active.depth -= 1;
+                }
+            });
+        }
+    }
+}
+
+/// Called by collection decoders after reading the length prefix. Checks the
+/// length and nesting depth against the active limits, if any.
+pub(crate) fn enter_collection(len: u64) -> Result<DepthGuard, Error> {
+    ACTIVE.with(|a| match a.borrow_mut().as_mut() {
+        Some(active) => {
+            if len > active.limits.max_collection_len {
+                return Err(Error::new(ErrorKind::InvalidData, "Collection length exceeds limit"))
+            }
+            if active.depth >= active.limits.max_depth {
+                return Err(Error::new(ErrorKind::InvalidData, "Nesting depth exceeds limit"))
+            }
+            active.depth += 1;
+            Ok(DepthGuard { active: true })
+        }
+        None => Ok(DepthGuard { active: false }),
+    })
+}
+
+/// Number of elements to preallocate for a collection of `len` elements of `T`.
+pub(crate) fn prealloc_len<T>(len: u64) -> usize {
+    let size = std::mem::size_of::<T>().max(1);
+    (len as usize).min(MAX_PREALLOC_BYTES / size)
+}
+
+/// Decode an object from a reader within the given limits. Like
+/// `Decodable::decode`, this does not require the input to be consumed.
+pub fn decode_bounded<T: Decodable, D: Read>(d: D, limits: &DecodeLimits) -> Result<T, Error> {
+    let _scope = LimitsScope::enter(limits);
+    T::decode(d.take(limits.max_bytes as u64))
+}
+
+/// Deserialize an object from a vector within the given limits.
+/// Will error if the data exceeds the limits or is not consumed entirely.
+pub fn deserialize_bounded<T: Decodable>(data: &[u8], limits: &DecodeLimits) -> Result<T, Error> {
+    if data.len() > limits.max_bytes {
+        return Err(Error::new(ErrorKind::InvalidData, "Data exceeds decode limit"))
+    }
+
+    let mut decoder = Cursor::new(data);
+    let rv = decode_bounded(&mut decoder, limits)?;
+
+    // Fail if data is not consumed entirely.
+    if decoder.position() as usize != data.len() {
+        return Err(Error::new(ErrorKind::Other, "Data not consumed fully on deserialization"))
+    }
+
+    Ok(rv)
+}
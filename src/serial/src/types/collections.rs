@@ -38,6 +38,7 @@ impl<T: Encodable> Encodable for HashSet<T> {
 impl<T: Decodable + std::cmp::Eq + std::hash::Hash> Decodable for HashSet<T> {
     fn decode<D: Read>(mut d: D) -> Result<Self, Error> {
         let len = VarInt::decode(&mut d)?.0;
+        let _depth = crate::limits::enter_collection(len)?;
         let mut ret = HashSet::new();
         for _ in 0..len {
             let entry: T = Decodable::decode(&mut d)?;
@@ -62,6 +63,7 @@ impl<T: Encodable, U: Encodable> Encodable for BTreeMap<T, U> {
 impl<T: Decodable + std::cmp::Ord, U: Decodable> Decodable for BTreeMap<T, U> {
     fn decode<D: Read>(mut d: D) -> Result<Self, Error> {
         let len = VarInt::decode(&mut d)?.0;
+        let _depth = crate::limits::enter_collection(len)?;
         let mut ret = BTreeMap::new();
         for _ in 0..len {
             let key: T = Decodable::decode(&mut d)?;
@@ -86,6 +88,7 @@ impl<T: Encodable> Encodable for BTreeSet<T> {
 impl<T: Decodable + std::cmp::Ord> Decodable for BTreeSet<T> {
     fn decode<D: Read>(mut d: D) -> Result<Self, Error> {
         let len = VarInt::decode(&mut d)?.0;
+        let _depth = crate::limits::enter_collection(len)?;
         let mut ret = BTreeSet::new();
         for _ in 0..len {
             let key: T = Decodable::decode(&mut d)?;
@@ -98,6 +101,7 @@ impl<T: Decodable + std::cmp::Ord> Decodable for BTreeSet<T> {
 impl<T: Decodable + std::cmp::Eq + std::hash::Hash, U: Decodable> Decodable for HashMap<T, U> {
     fn decode<D: Read>(mut d: D) -> Result<Self, Error> {
         let len = VarInt::decode(&mut d)?.0;
+        let _depth = crate::limits::enter_collection(len)?;
         let mut ret = HashMap::new();
         for _ in 0..len {
             let key: T = Decodable::decode(&mut d)?;
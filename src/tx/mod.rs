@@ -22,7 +22,7 @@ use async_std::sync::{Arc, RwLock};
 use darkfi_sdk::{
     crypto::{
         schnorr::{SchnorrPublic, SchnorrSecret, Signature},
-        PublicKey, SecretKey,
+        ContractId, PublicKey, SecretKey,
     },
     pasta::pallas,
     tx::ContractCall,
@@ -32,7 +32,7 @@ use log::{debug, error};
 use rand::{CryptoRng, RngCore};
 
 use crate::{
-    zk::{proof::VerifyingKey, Proof},
+    zk::{proof::VerifyingKey, Proof, ProofBatch},
     Error, Result, VerifyFailed,
 };
 
@@ -150,3 +150,136 @@ impl Transaction {
         Ok(buf)
     }
 }
+
+/// A proof in a batch of transactions, given by the indexes of its
+/// transaction, call, and position in the call, along with its public inputs.
+type BatchProof = (usize, usize, usize, Vec<pallas::Base>);
+
+/// Verify the Schnorr signatures and ZK proofs of a batch of transactions.
+/// `sig_tables` and `zkp_tables` hold the metadata of each transaction, in
+/// the same order as `txs`.
+///
+/// The work is done on the blocking thread pool. Signatures are split into
+/// a task per available CPU, and the proofs made with the same circuit are
+/// batch verified in a task of their own. If any of them fails, the entire
+/// batch is rejected.
+pub async fn verify_batch(
+    txs: &[Transaction],
+    sig_tables: Vec<Vec<Vec<PublicKey>>>,
+    zkp_tables: Vec<Vec<Vec<(String, Vec<pallas::Base>)>>>,
+    verifying_keys: VerifyingKeyMap,
+) -> Result<()> {
+    // TODO: Are we sure we should assert here?
+    assert_eq!(txs.len(), sig_tables.len());
+    assert_eq!(txs.len(), zkp_tables.len());
+
+    // Group the proofs of the entire batch by the circuit they were made with
+    let mut circuits: HashMap<([u8; 32], String), (ContractId, Vec<BatchProof>)> = HashMap::new();
+    for (tx_idx, (tx, zkp_table)) in txs.iter().zip(zkp_tables).enumerate() {
+        assert_eq!(tx.calls.len(), tx.proofs.len());
+        assert_eq!(tx.calls.len(), zkp_table.len());
+
+        for (call_idx, (call, (proofs, pubvals))) in
+            zip!(tx.calls, tx.proofs, zkp_table).enumerate()
+        {
+            assert_eq!(proofs.len(), pubvals.len());
+
+            for (proof_idx, (zk_ns, public_vals)) in pubvals.into_iter().enumerate() {
+                circuits
+                    .entry((call.contract_id.to_bytes(), zk_ns))
+                    .or_insert_with(|| (call.contract_id, vec![]))
+                    .1
+                    .push((tx_idx, call_idx, proof_idx, public_vals));
+            }
+        }
+    }
+
+    let txs = Arc::new(txs.to_vec());
+
+    // Spawn the proof tasks first, as they're the heaviest
+    let mut proof_tasks = Vec::with_capacity(circuits.len());
+    {
+        let vks = verifying_keys.read().await;
+        for ((contract_id_bytes, zk_ns), (contract_id, proofs)) in circuits {
+            let Some(vk) =
+                vks.get(&contract_id_bytes).and_then(|x| x.iter().find(|x| x.0 == zk_ns))
+            else {
+                let e = format!("{}:{} circuit VK nonexistent", contract_id, zk_ns);
+                error!("{}", e);
+                return Err(VerifyFailed::ProofVerifyFailed(e).into())
+            };
+
+            let vk = vk.1.clone();
+            let txs = txs.clone();
+            proof_tasks.push(smol::unblock(move || {
+                verify_circuit_proofs(&txs, contract_id, &zk_ns, &vk, &proofs)
+            }));
+        }
+    }
+
+    let workers = std::thread::available_parallelism().map(|x| x.get()).unwrap_or(1);
+    let chunk_size = ((txs.len() + workers - 1) / workers).max(1);
+
+    let mut sig_tasks = Vec::with_capacity(workers);
+    let mut sig_tables = sig_tables.into_iter().enumerate();
+    for _ in (0..txs.len()).step_by(chunk_size) {
+        let chunk: Vec<_> = sig_tables.by_ref().take(chunk_size).collect();
+        let txs = txs.clone();
+        sig_tasks.push(smol::unblock(move || {
+            for (tx_idx, sig_table) in chunk {
+                if let Err(e) = txs[tx_idx].verify_sigs(sig_table) {
+                    error!("Signature verification for tx {} in the batch failed", tx_idx);
+                    return Err(e)
+                }
+            }
+            Ok(())
+        }));
+    }
+
+    for task in sig_tasks {
+        task.await?;
+    }
+    debug!("Successfully verified signatures of {} transactions", txs.len());
+
+    for task in proof_tasks {
+        task.await?;
+    }
+    debug!("Successfully verified ZK proofs of {} transactions", txs.len());
+
+    Ok(())
+}
+
+/// Batch verify proofs made with a single circuit. If the batch fails, the
+/// proofs are verified one by one to find the invalid one.
+fn verify_circuit_proofs(
+    txs: &[Transaction],
+    contract_id: ContractId,
+    zk_ns: &str,
+    vk: &VerifyingKey,
+    proofs: &[BatchProof],
+) -> Result<()> {
+    let mut batch = ProofBatch::new();
+    for (tx_idx, call_idx, proof_idx, public_vals) in proofs {
+        batch.add(&txs[*tx_idx].proofs[*call_idx][*proof_idx], public_vals);
+    }
+
+    if batch.verify(vk) {
+        debug!("Successfully batch verified {} {}::{} ZK proofs", proofs.len(), contract_id, zk_ns);
+        return Ok(())
+    }
+
+    for (tx_idx, call_idx, proof_idx, public_vals) in proofs {
+        if let Err(e) = txs[*tx_idx].proofs[*call_idx][*proof_idx].verify(vk, public_vals) {
+            error!(
+                "Failed verifying {}::{} ZK proof of tx {} in the batch: {:#?}",
+                contract_id, zk_ns, tx_idx, e
+            );
+            return Err(VerifyFailed::ProofVerifyFailed(e.to_string()).into())
+        }
+    }
+
+    // Every proof verifies on its own, so this shouldn't be reachable.
+    let e = format!("{}::{} batch verification failed", contract_id, zk_ns);
+    error!("{}", e);
+    Err(VerifyFailed::ProofVerifyFailed(e).into())
+}
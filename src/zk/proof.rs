@@ -20,7 +20,7 @@ use darkfi_serial::{SerialDecodable, SerialEncodable};
 use halo2_proofs::{
     pasta::{pallas, vesta},
     plonk,
-    plonk::{Circuit, SingleVerifier},
+    plonk::{BatchVerifier, Circuit, SingleVerifier},
     poly::commitment::Params,
     transcript::{Blake2bRead, Blake2bWrite},
 };
@@ -38,6 +38,18 @@ impl VerifyingKey {
         let vk = plonk::keygen_vk(&params, c).unwrap();
         VerifyingKey { params, vk }
     }
+
+    /// Like [`VerifyingKey::build`], but returns an error instead of
+    /// panicking when the circuit can't be synthesized, or doesn't fit
+    /// in `2^k` rows.
+    pub fn try_build(
+        k: u32,
+        c: &impl Circuit<pallas::Base>,
+    ) -> std::result::Result<Self, plonk::Error> {
+        let params = Params::new(k);
+        let vk = plonk::keygen_vk(&params, c)?;
+        Ok(VerifyingKey { params, vk })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -105,3 +117,30 @@ impl Proof {
         Proof(bytes)
     }
 }
+
+/// A set of proofs made with the same circuit, verified together. This is
+/// considerably cheaper than verifying each of the proofs on its own, but
+/// it doesn't tell which proof is invalid in case the batch fails.
+pub struct ProofBatch(BatchVerifier<vesta::Affine>);
+
+impl ProofBatch {
+    pub fn new() -> Self {
+        ProofBatch(BatchVerifier::new())
+    }
+
+    /// Add a proof and its public inputs to the batch.
+    pub fn add(&mut self, proof: &Proof, instances: &[pallas::Base]) {
+        self.0.add_proof(vec![vec![instances.to_vec()]], proof.0.clone());
+    }
+
+    /// Verify all the proofs in the batch using the given verifying key.
+    pub fn verify(self, vk: &VerifyingKey) -> bool {
+        self.0.finalize(&vk.params, &vk.vk)
+    }
+}
+
+impl Default for ProofBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -39,6 +39,11 @@ fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
 
 impl ZkBinary {
     pub fn decode(bytes: &[u8]) -> Result<Self> {
+        // Magic bytes and the binary version
+        if bytes.len() < MAGIC_BYTES.len() + 1 {
+            return Err(ZkasErr("Binary is too short.".to_string()))
+        }
+
         let magic_bytes = &bytes[0..4];
         if magic_bytes != MAGIC_BYTES {
             return Err(ZkasErr("Magic bytes are incorrect.".to_string()))
@@ -102,9 +107,95 @@ impl ZkBinary {
 
         // TODO: Debug info
 
-        Ok(Self { namespace, constants, literals, witnesses, opcodes })
+        let zkbin = Self { namespace, constants, literals, witnesses, opcodes };
+        zkbin.verify()?;
+        Ok(zkbin)
     }
 
+    /// Type-check the decoded circuit against the stack the zkvm builds
+    /// from it, so its synthesis can't reference a variable that doesn't
+    /// exist or has the wrong type. Binaries don't necessarily come from
+    /// our compiler, e.g. the ones found in contract deployments.
+    fn verify(&self) -> Result<()> {
+        let mut stack = Vec::with_capacity(self.constants.len() + self.witnesses.len());
+
+        // The zkvm looks up constants by name, whatever their declared type.
+        for (_, name) in &self.constants {
+            let var_type = match name.as_str() {
+                "VALUE_COMMIT_VALUE" => VarType::EcFixedPointShort,
+                "VALUE_COMMIT_RANDOM" => VarType::EcFixedPoint,
+                "NULLIFIER_K" => VarType::EcFixedPointBase,
+                _ => return Err(ZkasErr(format!("Unknown constant {}", name))),
+            };
+            stack.push(var_type);
+        }
+
+        for witness in &self.witnesses {
+            match witness {
+                VarType::EcPoint |
+                VarType::EcNiPoint |
+                VarType::Base |
+                VarType::Scalar |
+                VarType::MerklePath |
+                VarType::Uint32 |
+                VarType::Uint64 => stack.push(*witness),
+                _ => return Err(ZkasErr(format!("Unsupported witness type {:?}", witness))),
+            }
+        }
+
+        // The zkvm hands out literals in order to the opcodes taking one,
+        // whatever their argument references.
+        let mut literals_used = 0;
+        for (opcode, args) in &self.opcodes {
+            let (ret_types, arg_types) = opcode.arg_types();
+            let arg_types = match opcode {
+                // The zkvm refuses to synthesize these.
+                Opcode::Noop | Opcode::DebugPrint => continue,
+                Opcode::PoseidonHash if args.is_empty() || args.len() > 16 => {
+                    let e = format!("Unsupported poseidon hash of {} elements", args.len());
+                    return Err(ZkasErr(e))
+                }
+                Opcode::PoseidonHash => vec![VarType::Base; args.len()],
+                _ => arg_types,
+            };
+
+            if args.len() != arg_types.len() {
+                return Err(ZkasErr(format!(
+                    "Opcode {:?} takes {} arguments, got {}",
+                    opcode,
+                    arg_types.len(),
+                    args.len()
+                )))
+            }
+
+            for ((stack_type, index), arg_type) in args.iter().zip(arg_types) {
+                match (stack_type, arg_type) {
+                    (StackType::Lit, VarType::Uint64) if *index < self.literals.len() => {}
+                    (StackType::Var, _) if stack.get(*index) == Some(&arg_type) => {}
+                    _ => {
+                        return Err(ZkasErr(format!(
+                            "Invalid argument {:?} {} for opcode {:?}",
+                            stack_type, index, opcode
+                        )))
+                    }
+                }
+            }
+
+            if matches!(opcode, Opcode::WitnessBase | Opcode::RangeCheck) {
+                literals_used += 1;
+            }
+
+            stack.extend(ret_types);
+        }
+
+        if literals_used > self.literals.len() {
+            return Err(ZkasErr("Circuit uses more literals than it declares".to_string()))
+        }
+
+        Ok(())
+    }
+
+
     fn parse_constants(bytes: &[u8]) -> Result<Vec<(VarType, String)>> {
         let mut constants = vec![];
 
@@ -198,7 +289,9 @@ impl ZkBinary {
 
             let mut args = vec![];
             for _ in 0..arg_num.0 {
-                let stack_type = bytes[iter_offset];
+                let Some(&stack_type) = bytes.get(iter_offset) else {
+                    return Err(ZkasErr("Truncated opcode arguments".to_string()))
+                };
                 iter_offset += 1;
                 let (stack_index, offset) = deserialize_partial::<VarInt>(&bytes[iter_offset..])?;
                 iter_offset += offset;
@@ -0,0 +1,161 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use futures::FutureExt;
+
+use darkfi::net::{simulation::Simulation, transport::LinkConfig, GossipConfig};
+
+const STEP: Duration = Duration::from_millis(10);
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Run a simulation. The nodes only run on this thread, while it's stepped.
+fn simulate<F, Fut>(test: F)
+where
+    F: FnOnce(Arc<Simulation>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let sim = Simulation::new(42);
+    smol::future::block_on(async {
+        test(sim.clone()).await;
+        sim.stop().await;
+    });
+}
+
+#[test]
+fn simulation_peer_discovery() {
+    simulate(|sim| async move {
+        sim.set_default_link(LinkConfig {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(20),
+            loss: 0.05,
+        });
+        sim.spawn_nodes(5, 2).await.unwrap();
+
+        // Every node learns about the others through the seed and fills
+        // its outbound slots
+        let connected = sim
+            .run_until(
+                |p2p| async move {
+                    let wanted = p2p.settings().outbound_connections.max(1) as usize;
+                    p2p.channels().lock().await.len() >= wanted
+                },
+                TIMEOUT,
+                STEP,
+            )
+            .await;
+        assert!(connected);
+        assert!(sim.now() >= Duration::from_millis(50));
+    });
+}
+
+#[test]
+fn simulation_partition() {
+    simulate(|sim| async move {
+        let nodes = sim.spawn_nodes(3, 2).await.unwrap();
+        assert!(
+            sim.run_until(
+                |p2p| async move { !p2p.channels().lock().await.is_empty() },
+                TIMEOUT,
+                STEP
+            )
+            .await
+        );
+
+        // Isolate the last node, dropping all its channels
+        sim.partition(&[0, 1], &[2]);
+        let isolated = nodes[2].clone();
+        let mut disconnected = false;
+        for _ in 0..TIMEOUT.as_millis() / STEP.as_millis() {
+            if isolated.channels().lock().await.is_empty() {
+                disconnected = true;
+                break
+            }
+            sim.run_for(STEP, STEP).await;
+        }
+        assert!(disconnected);
+
+        // After healing, it reconnects to the others
+        sim.heal();
+        assert!(
+            sim.run_until(
+                |p2p| async move { !p2p.channels().lock().await.is_empty() },
+                TIMEOUT,
+                STEP
+            )
+            .await
+        );
+    });
+}
+
+#[test]
+fn simulation_gossip() {
+    simulate(|sim| async move {
+        sim.set_default_link(LinkConfig {
+            latency: Duration::from_millis(20),
+            ..Default::default()
+        });
+        let nodes = sim.spawn_nodes(5, 4).await.unwrap();
+
+        // Payloads of 1KB and more are announced first and pulled by peers
+        let config = GossipConfig { lazy_threshold: 1024, ..Default::default() };
+        let mut subs = vec![];
+        for node in &nodes {
+            subs.push(node.gossip().await.subscribe("test", config.clone()).await);
+        }
+
+        let connected = sim
+            .run_until(
+                |p2p| async move {
+                    let wanted = p2p.settings().outbound_connections.max(1) as usize;
+                    p2p.channels().lock().await.len() >= wanted
+                },
+                TIMEOUT,
+                STEP,
+            )
+            .await;
+        assert!(connected);
+
+        for payload in [b"ohai gossip".to_vec(), vec![7u8; 4096]] {
+            let id = nodes[1].gossip().await.publish("test", payload.clone()).await.unwrap();
+
+            for (i, sub) in subs.iter().enumerate() {
+                if i == 1 {
+                    continue
+                }
+
+                let received = loop {
+                    sim.run_for(STEP, STEP).await;
+                    if let Some(received) = sub.receive().now_or_never() {
+                        break received
+                    }
+                    assert!(sim.now() < TIMEOUT * 2, "node {} never got the message", i);
+                };
+                assert_eq!(received.id, id);
+                assert_eq!(received.payload, payload);
+            }
+        }
+
+        // Every node got each message exactly once
+        sim.run_for(Duration::from_secs(1), STEP).await;
+        for sub in &subs {
+            assert!(sub.receive().now_or_never().is_none());
+        }
+    });
+}
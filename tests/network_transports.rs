@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{env::var, fs};
+use std::{env::var, fs, time::Duration};
 
 use async_std::{
     io,
@@ -26,7 +26,10 @@ use async_std::{
 };
 use url::Url;
 
-use darkfi::net::transport::{TcpTransport, TorTransport, Transport, UnixTransport};
+use darkfi::net::transport::{
+    LinkConfig, MemoryNetwork, MemoryNetworkPtr, MemoryNode, MemoryTransport, TcpTransport,
+    TorTransport, Transport, TransportListener, UnixTransport,
+};
 
 #[async_std::test]
 async fn unix_transport() {
@@ -110,6 +113,47 @@ async fn tcp_tls_transport() {
     assert_eq!(buf, payload);
 }
 
+fn memory_node(network: &MemoryNetworkPtr, name: &str) -> MemoryTransport {
+    MemoryTransport::new(MemoryNode { network: network.clone(), name: name.to_string() })
+}
+
+#[async_std::test]
+async fn memory_transport() {
+    let network = MemoryNetwork::new(0);
+    let url = Url::parse("memory://node-0").unwrap();
+
+    let listener = memory_node(&network, "node-0").listen_on(url.clone()).unwrap().await.unwrap();
+    let mut client =
+        memory_node(&network, "node-1").dial(url.clone(), None).unwrap().await.unwrap();
+    let (mut server, peer) = listener.next().await.unwrap();
+    assert_eq!(peer.host_str(), Some("node-1"));
+
+    let payload = b"ohai memory";
+    client.write_all(payload).await.unwrap();
+    let mut buf = vec![0_u8; 11];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, payload);
+
+    // Latency only passes on the virtual clock
+    let link = LinkConfig { latency: Duration::from_millis(100), ..Default::default() };
+    network.set_link("node-0", "node-1", link);
+    server.write_all(b"pong").await.unwrap();
+    let mut buf = vec![0_u8; 4];
+    assert!(io::timeout(Duration::from_millis(50), client.read_exact(&mut buf)).await.is_err());
+    network.clock().advance(Duration::from_millis(100));
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, b"pong");
+
+    // Partitions cut live connections and refuse new ones
+    network.partition(&["node-0"], &["node-1"]);
+    assert_eq!(server.read(&mut buf).await.unwrap(), 0);
+    assert!(client.write_all(b"ping").await.is_err());
+    assert!(memory_node(&network, "node-1").dial(url.clone(), None).unwrap().await.is_err());
+
+    network.heal();
+    assert!(memory_node(&network, "node-1").dial(url, None).unwrap().await.is_ok());
+}
+
 #[async_std::test]
 #[ignore]
 async fn tor_transport_no_control() {
@@ -208,3 +252,113 @@ For example: \'export DARKFI_TOR_COOKIE=\"/var/lib/tor/control_auth_cookie\"\'",
     // Try to reach the host
     let _client = tor_client.dial(hurl, None).unwrap().await.unwrap();
 }
+
+#[cfg(feature = "websockets")]
+mod nym {
+    use std::collections::HashMap;
+
+    use async_std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        task,
+    };
+    use async_tungstenite::tungstenite::Message;
+    use futures::{AsyncReadExt, AsyncWriteExt, SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use url::Url;
+
+    use darkfi::net::transport::{NymTransport, Transport, TransportListener};
+
+    type Sessions = Arc<Mutex<HashMap<String, smol::channel::Sender<String>>>>;
+
+    /// Spawns a mock nym-client websocket endpoint owning the given nym
+    /// address. Like a real nym-client it serves a single websocket at a
+    /// time, and `send` requests are delivered to the mock client owning
+    /// the recipient address through the shared `sessions`.
+    async fn mock_nym_client(addr: &str, address: &str, sessions: Sessions) {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let address = address.to_string();
+
+        task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let ws = async_tungstenite::accept_async(stream).await.unwrap();
+                let (mut sink, mut source) = ws.split();
+
+                if sessions.lock().await.contains_key(&address) {
+                    let _ = sink.send(Message::Close(None)).await;
+                    continue
+                }
+
+                let (tx, rx) = smol::channel::unbounded::<String>();
+                sessions.lock().await.insert(address.clone(), tx.clone());
+
+                task::spawn(async move {
+                    while let Ok(msg) = rx.recv().await {
+                        if sink.send(Message::Text(msg)).await.is_err() {
+                            break
+                        }
+                    }
+                });
+
+                let sessions = sessions.clone();
+                let address = address.clone();
+                task::spawn(async move {
+                    while let Some(Ok(Message::Text(text))) = source.next().await {
+                        let req: Value = serde_json::from_str(&text).unwrap();
+                        match req["type"].as_str().unwrap() {
+                            "selfAddress" => {
+                                let reply = json!({"type": "selfAddress", "address": address});
+                                tx.send(reply.to_string()).await.unwrap();
+                            }
+                            "send" => {
+                                let recipient = req["recipient"].as_str().unwrap();
+                                let reply = json!({"type": "received", "message": req["message"]});
+                                if let Some(s) = sessions.lock().await.get(recipient) {
+                                    let _ = s.send(reply.to_string()).await;
+                                }
+                            }
+                            _ => unimplemented!(),
+                        }
+                    }
+                    sessions.lock().await.remove(&address);
+                    tx.close();
+                });
+            }
+        });
+    }
+
+    #[async_std::test]
+    async fn nym_transport() {
+        let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        mock_nym_client("127.0.0.1:19770", "client0.enc@gateway", sessions.clone()).await;
+        mock_nym_client("127.0.0.1:19771", "client1.enc@gateway", sessions).await;
+
+        let listen_nym = NymTransport::new(Url::parse("ws://127.0.0.1:19770").unwrap());
+        let url = Url::parse("nym://127.0.0.1:19770").unwrap();
+
+        let listener = listen_nym.listen_on(url).unwrap().await.unwrap();
+        let nym_addr = listener.address().unwrap();
+
+        let _ = task::spawn(async move {
+            loop {
+                let (stream, _) = listener.next().await.unwrap();
+                task::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    futures::io::copy(&mut reader, &mut writer).await.unwrap();
+                });
+            }
+        });
+
+        // Both streams go through the single websocket the nym-client serves
+        let nym = NymTransport::new(Url::parse("ws://127.0.0.1:19771").unwrap());
+        let mut client0 = nym.clone().dial(nym_addr.clone(), None).unwrap().await.unwrap();
+        let mut client1 = nym.dial(nym_addr, None).unwrap().await.unwrap();
+
+        for (client, payload) in [(&mut client0, b"ohai nym"), (&mut client1, b"ohai mix")] {
+            client.write_all(payload).await.unwrap();
+            let mut buf = vec![0_u8; 8];
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, payload);
+        }
+    }
+}